@@ -0,0 +1,82 @@
+// Slurm Epilog finalizing the TRO of the job queued in the spool by the SPANK
+// plugin running with finalize=epilog, for sites that forbid long-running work
+// in slurmstepd hooks. Install it as Epilog= (or call it from the site epilog).
+use eyre::{eyre, Report, WrapErr};
+use std::env;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tracing::{error, info, Level};
+
+use tro_core::logging::{self, LogTarget};
+use tro_core::spool;
+
+const USAGE: &str = "usage: spank-tro-epilog [--spool DIR] [--strict]";
+
+struct Options {
+    spool_dir: PathBuf,
+    // fail the epilog, and thus drain the node, when finalization fails
+    strict: bool,
+}
+
+fn parse_options() -> Result<Options, Report> {
+    let mut options = Options {
+        spool_dir: PathBuf::from("/var/spool/spank-tro"),
+        strict: false,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--spool" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| eyre!("{arg} needs a value\n{USAGE}"))?;
+                options.spool_dir = PathBuf::from(value);
+            }
+            "--strict" => options.strict = true,
+            _ => return Err(eyre!("Unknown argument {arg}\n{USAGE}")),
+        }
+    }
+    Ok(options)
+}
+
+fn run(options: &Options) -> Result<(), Report> {
+    let jobid: u32 = env::var("SLURM_JOB_ID")
+        .wrap_err("SLURM_JOB_ID is not set, not running as a Slurm Epilog?")?
+        .parse()
+        .wrap_err("Invalid SLURM_JOB_ID")?;
+    // jobs without --generate-tro, or that ran on another node, have no spec
+    let specs = spool::queued_for(&options.spool_dir, jobid)
+        .wrap_err_with(|| format!("Failed to list {}", options.spool_dir.display()))?;
+    let notify = |msg: &str| info!("{}", msg);
+    let mut result = Ok(());
+    for spec in specs {
+        if let Err(e) = spool::process(&spec, None, &notify) {
+            result = Err(e);
+        }
+    }
+    result
+}
+
+fn main() -> ExitCode {
+    // the epilog's stdout and stderr go nowhere useful
+    logging::init(Level::INFO, LogTarget::Syslog, None);
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(e) => {
+            error!("{:#}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{:#}", e);
+            // a failing epilog drains the node, which is rarely what a missing
+            // TRO deserves
+            match options.strict {
+                true => ExitCode::FAILURE,
+                false => ExitCode::SUCCESS,
+            }
+        }
+    }
+}
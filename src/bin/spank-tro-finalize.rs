@@ -0,0 +1,21 @@
+// Finalize a TRO queued in the spool by the SPANK plugin running with
+// finalize=deferred. Started detached from slurmstepd with the spec path.
+use eyre::{eyre, Report};
+use std::env;
+use std::path::PathBuf;
+use tracing::info;
+
+use tro_core::logging;
+use tro_core::spool::{self, FinalizationSpec};
+
+fn main() -> Result<(), Report> {
+    let path = env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .ok_or_else(|| eyre!("usage: spank-tro-finalize <spec>"))?;
+    let spec = FinalizationSpec::read(&path)?;
+    logging::init(spec.settings.log_level, spec.settings.log_target, None);
+
+    let notify = |msg: &str| info!("{}", msg);
+    spool::process(&path, None, &notify)
+}
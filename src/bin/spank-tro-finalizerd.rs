@@ -0,0 +1,111 @@
+// Node-local daemon finalizing the TROs queued in the spool by the SPANK
+// plugin running with finalize=daemon, so that no heavy work happens inside
+// slurmstepd at all.
+use eyre::{eyre, Report, WrapErr};
+use std::env;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn, Level};
+
+use tro_core::config::ConfigFile;
+use tro_core::logging::{self, LogTarget};
+use tro_core::settings::parse_duration;
+use tro_core::spool;
+
+const USAGE: &str = "usage: spank-tro-finalizerd [--spool DIR] [--poll DURATION] \
+                     [--rate PER_MINUTE] [--config FILE] [--log-target stderr|syslog|journald] \
+                     [--once]";
+
+struct Options {
+    spool_dir: PathBuf,
+    poll: Duration,
+    // minimal delay between two finalizations
+    spacing: Duration,
+    // settings overriding those of the queued specs, re-read when it changes
+    config: Option<PathBuf>,
+    log_target: LogTarget,
+    once: bool,
+}
+
+fn parse_options() -> Result<Options, Report> {
+    let mut options = Options {
+        spool_dir: PathBuf::from("/var/spool/spank-tro"),
+        poll: Duration::from_secs(10),
+        spacing: Duration::ZERO,
+        config: None,
+        log_target: LogTarget::Stderr,
+        once: false,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| eyre!("{arg} needs a value\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--spool" => options.spool_dir = PathBuf::from(value()?),
+            "--poll" => options.poll = parse_duration(&value()?).wrap_err("Invalid --poll")?,
+            "--rate" => {
+                let rate: u32 = value()?.parse().wrap_err("Invalid --rate")?;
+                if rate > 0 {
+                    options.spacing = Duration::from_secs(60) / rate;
+                }
+            }
+            "--config" => options.config = Some(PathBuf::from(value()?)),
+            "--log-target" => options.log_target = value()?.parse()?,
+            "--once" => options.once = true,
+            _ => return Err(eyre!("Unknown argument {arg}\n{USAGE}")),
+        }
+    }
+    Ok(options)
+}
+
+fn main() -> Result<(), Report> {
+    let options = parse_options()?;
+    logging::init(Level::INFO, options.log_target, None);
+    info!("Finalizing TROs queued in {}", options.spool_dir.display());
+
+    let notify = |msg: &str| info!("{}", msg);
+    let mut config = options
+        .config
+        .as_deref()
+        .map(ConfigFile::load)
+        .transpose()?;
+    let mut last_started: Option<Instant> = None;
+    loop {
+        if let Some(config) = &mut config {
+            match config.refresh() {
+                Ok(true) => info!("Reloaded the configuration"),
+                Ok(false) => {}
+                // keep going with the previous configuration
+                Err(e) => warn!("{:#}", e),
+            }
+        }
+        match spool::requeue_abandoned(&options.spool_dir) {
+            Ok(requeued) => {
+                for spec in requeued {
+                    info!("Resuming {}, its finalizer is gone", spec.display());
+                }
+            }
+            Err(e) => warn!("{:#}", e),
+        }
+        let queued = spool::queued(&options.spool_dir)
+            .wrap_err_with(|| format!("Failed to list {}", options.spool_dir.display()))?;
+        for spec in queued {
+            // rate limiting
+            if let Some(elapsed) = last_started.map(|started| started.elapsed()) {
+                thread::sleep(options.spacing.saturating_sub(elapsed));
+            }
+            last_started = Some(Instant::now());
+            // failures are recorded in the spool, keep going with the other jobs
+            if let Err(e) = spool::process(&spec, config.as_ref(), &notify) {
+                warn!("{}: {:#}", spec.display(), e);
+            }
+        }
+        if options.once {
+            return Ok(());
+        }
+        thread::sleep(options.poll);
+    }
+}
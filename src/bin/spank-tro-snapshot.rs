@@ -0,0 +1,25 @@
+// Record the current content of the workdir as a new arrangement of the TRO of
+// the interactive allocation (salloc --generate-tro) it is run from.
+use eyre::{eyre, Report};
+use std::env;
+use std::path::PathBuf;
+
+use tro_core::session::{Session, SESSION_ENV};
+use tro_core::tro_utils::TroUtils;
+
+fn main() -> Result<(), Report> {
+    let path = env::var_os(SESSION_ENV)
+        .map(PathBuf::from)
+        .ok_or_else(|| eyre!("Not in an allocation made with salloc --generate-tro"))?;
+    let session = Session::read(&path)?;
+    let comment = match env::args().nth(1) {
+        Some(comment) => format!("'{}'", comment),
+        None => "'Snapshot'".to_string(),
+    };
+
+    let notify = |msg: &str| eprintln!("{}", msg);
+    let tro = TroUtils::new(&session.settings, &session.job, &notify);
+    let id = tro.add_arrangement(&comment)?;
+    println!("Recorded {} in {}", id, session.job.declaration.display());
+    Ok(())
+}
@@ -0,0 +1,54 @@
+// Record arrangements of a running job's TRO as it reaches its checkpoints,
+// and every snapshot_interval, and the files of its workdir it writes.
+// Started detached from slurmstepd with the spec path, and stopped with
+// SIGINT once the job's tasks are done.
+use eyre::{eyre, Report};
+use std::env;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use tro_core::logging;
+use tro_core::session::Session;
+use tro_core::watch::Watcher;
+
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn stop(_: libc::c_int) {
+    STOPPED.store(true, Ordering::SeqCst);
+}
+
+fn main() -> Result<(), Report> {
+    let path = env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .ok_or_else(|| eyre!("usage: spank-tro-watch <spec>"))?;
+    let spec = Session::read(&path)?;
+    logging::init(spec.settings.log_level, spec.settings.log_target, None);
+    unsafe {
+        libc::signal(libc::SIGINT, stop as *const () as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, stop as *const () as libc::sighandler_t);
+    }
+
+    let notify = |msg: &str| info!("{}", msg);
+    let mut watcher = Watcher::new(spec.settings, spec.job);
+    loop {
+        let next = Instant::now() + watcher.poll_interval();
+        while !STOPPED.load(Ordering::SeqCst) && Instant::now() < next {
+            thread::sleep(Duration::from_millis(100));
+            if let Err(e) = watcher.read_files() {
+                warn!("{:#}", e);
+            }
+        }
+        // one last look at what the job did right before it ended
+        let last = STOPPED.load(Ordering::SeqCst);
+        if let Err(e) = watcher.poll(last, &notify) {
+            warn!("{:#}", e);
+        }
+        if last {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,49 @@
+// Run a task of a job with tracker=wrapper, recording its command line and
+// times in the trace the plugin names in SPANK_TRO_TRACE. The plugin puts it
+// in front of the task's command, the task ends the way its command does.
+use eyre::{eyre, Report, WrapErr};
+use std::env;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{exit, Command};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use tro_core::tracker::{CommandRecord, TRACE_ENV};
+
+fn main() -> Result<(), Report> {
+    let argv: Vec<String> = env::args().skip(1).collect();
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| eyre!("usage: spank-tro-wrap <command> [<arg>...]"))?;
+
+    let start_time = unix_time();
+    let started = Instant::now();
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .wrap_err_with(|| format!("Failed to run {program}"))?;
+    let record = CommandRecord {
+        argv: argv.clone(),
+        start_time,
+        end_time: unix_time(),
+        exit_code: status.code(),
+        elapsed: Some(started.elapsed().as_secs_f64()),
+    };
+    // the task matters more than its record
+    if let Some(trace) = env::var_os(TRACE_ENV).map(PathBuf::from) {
+        if let Err(e) = record.append(&trace) {
+            eprintln!("spank-tro: {:#}", e);
+        }
+    }
+    exit(match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    })
+}
+
+fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64())
+}
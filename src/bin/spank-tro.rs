@@ -0,0 +1,376 @@
+// Administration of TROs outside of job execution: look into declarations and
+// spool entries, verify signatures, finalize queued or failed jobs, sign
+// declarations again e.g. after a key change, and report on the catalog.
+use eyre::{eyre, Report, WrapErr};
+use std::env;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use tro_core::catalog::{self, Query};
+use tro_core::compare;
+use tro_core::config::{Config, ConfigFile};
+use tro_core::cwlprov;
+use tro_core::declaration::Declaration;
+use tro_core::intoto;
+use tro_core::job::Job;
+use tro_core::logging;
+use tro_core::merkle;
+use tro_core::report::{self, Format};
+use tro_core::signing::{self, Signer, SigningKey};
+use tro_core::sigstore;
+use tro_core::slsa;
+use tro_core::spool::{self, FinalizationSpec};
+use tro_core::tro_utils::TroUtils;
+use tro_core::workflow;
+
+const USAGE: &str = "usage: spank-tro [--config FILE] <command> <path>...
+
+commands:
+    inspect <declaration|spec>...  summarize declarations or spool entries
+    verify <declaration>...        check the signatures of declarations
+    finalize <spec>...             finalize queued or failed spool entries,
+                                   or resume those whose finalizer died
+    resign <declaration>...        sign declarations with the configured key
+    cwlprov <declaration>...       export declarations as CWLProv research
+                                   objects, tro-1.cwlprov for tro-1.jsonld
+    intoto <declaration>...        export declarations as in-toto
+                                   attestations, tro-1.intoto.json
+    slsa <declaration>...          export the SLSA provenance of
+                                   declarations, tro-1.slsa.json
+    verify-dir <declaration> <dir>...
+                                   check directories of the workdir, e.g.
+                                   results/run1, against the Merkle tree of
+                                   the last arrangement, rehashing only them
+    compare <declaration> <declaration> [--json]
+                                   list the files, programs and performance
+                                   that differ between two declarations,
+                                   e.g. two runs of the same pipeline
+    aggregate <workflow> <declaration|dir>...
+                                   add the TROs of a workflow's tasks, or
+                                   those found in directories, to the
+                                   workflow-level declaration <workflow>
+    list [<filter>...] [--json]    list the finalizations in the catalog
+    show <jobid> [--json]          show the finalizations of a job
+    report [<filter>...] [--json]  count finalizations per user and partition
+
+filters:
+    --since <date|time|duration>   finalized since 2024-05-01, an RFC 3339
+                                   time, or e.g. 7d ago
+    --user <name>
+    --partition <name>
+
+--config is the file given to the plugin with config=, it provides the
+tro-utils, signing and catalog settings.";
+
+fn main() -> Result<(), Report> {
+    let mut args = env::args().skip(1).peekable();
+    let mut config = Config::default();
+    let mut config_file = None;
+    if args.peek().map(String::as_str) == Some("--config") {
+        args.next();
+        let path = args
+            .next()
+            .ok_or_else(|| eyre!("--config needs a value\n{USAGE}"))?;
+        config = Config::from_args([format!("config={path}").as_str()])?;
+        config_file = Some(ConfigFile::load(Path::new(&path))?);
+    }
+    let command = args.next().ok_or_else(|| eyre!(USAGE))?;
+    if matches!(command.as_str(), "list" | "show" | "report") {
+        logging::init(config.settings.log_level, config.settings.log_target, None);
+        return query_catalog(&config, &command, args.collect());
+    }
+    if command == "compare" {
+        logging::init(config.settings.log_level, config.settings.log_target, None);
+        return compare(args.collect());
+    }
+    let paths: Vec<PathBuf> = args.map(PathBuf::from).collect();
+    if paths.is_empty() {
+        return Err(eyre!(USAGE));
+    }
+    logging::init(config.settings.log_level, config.settings.log_target, None);
+    if command == "aggregate" {
+        return aggregate(&paths);
+    }
+    if command == "verify-dir" {
+        return verify_dirs(&paths);
+    }
+
+    let mut failed = 0;
+    for path in &paths {
+        let result = match command.as_str() {
+            "inspect" => inspect(path),
+            "verify" => verify(&config, path),
+            "finalize" => finalize(config_file.as_ref(), path),
+            "resign" => resign(&config, path),
+            "cwlprov" => export_cwlprov(path),
+            "intoto" => export_intoto(path),
+            "slsa" => export_slsa(path),
+            _ => return Err(eyre!("Unknown command {command}\n{USAGE}")),
+        };
+        if let Err(e) = result {
+            eprintln!("{}: {:#}", path.display(), e);
+            failed += 1;
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(eyre!("{failed} of {} failed", paths.len())),
+    }
+}
+
+fn inspect(path: &Path) -> Result<(), Report> {
+    // spool entries are named <key>.json[.running|.failed]
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if !name.ends_with(".jsonld") {
+        let spec = FinalizationSpec::read(path)?;
+        println!("{}", path.display());
+        println!("  job: {}{}", spec.job.jobid, spec.job.label());
+        println!("  user: {} ({})", spec.job.user, spec.job.uid);
+        println!("  declaration: {}", spec.job.declaration.display());
+        let site_key =
+            SigningKey::site(&spec.settings).map_or_else(|e| e.to_string(), |key| key.fingerprint);
+        match spec.settings.signer {
+            Signer::Site => println!("  signing key: {}", site_key),
+            Signer::User => println!("  signing key: the user's"),
+            Signer::Both => println!("  signing keys: the user's, then {}", site_key),
+            Signer::Sigstore => println!("  signing key: none, keyless with Sigstore"),
+        }
+        return Ok(());
+    }
+
+    let declaration = Declaration::load(path)?;
+    println!("{}", path.display());
+    for (id, comment) in declaration.arrangements() {
+        println!("  arrangement {}: {}", id, comment);
+    }
+    for (id, comment) in declaration.performances() {
+        println!("  performance {}: {}", id, comment);
+    }
+    let signed = signing::signature(path).is_some() || sigstore::bundle_path(path, None).exists();
+    println!("  signed: {}", if signed { "yes" } else { "no" });
+    if signing::detached_signature(path, None).exists() {
+        println!("  detached signature: yes");
+    }
+    if sigstore::bundle_path(path, None).exists() {
+        println!("  sigstore bundle: yes");
+    }
+    Ok(())
+}
+
+// Directories are relative to the workdir, the declaration's directory unless
+// it is absolute and in there
+fn verify_dirs(paths: &[PathBuf]) -> Result<(), Report> {
+    let (path, dirs) = paths
+        .split_first()
+        .filter(|(_, dirs)| !dirs.is_empty())
+        .ok_or_else(|| eyre!(USAGE))?;
+    let declaration = Declaration::load(path)?;
+    let workdir = path.parent().unwrap_or(Path::new("."));
+    let mut failed = 0;
+    for dir in dirs {
+        let relative = dir.strip_prefix(workdir).unwrap_or(dir);
+        match merkle::verify_dir(&declaration, workdir, &relative.to_string_lossy()) {
+            Ok(()) => println!("{}: unchanged", dir.display()),
+            Err(e) => {
+                eprintln!("{}: {:#}", dir.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(eyre!("{failed} of {} changed", dirs.len())),
+    }
+}
+
+// Fails when the declarations differ, to be usable as a reproducibility check
+fn compare(args: Vec<String>) -> Result<(), Report> {
+    let mut format = Format::Table;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--json" => format = Format::Json,
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    let [left, right] = paths.as_slice() else {
+        return Err(eyre!(USAGE));
+    };
+    let differences = compare::compare(&Declaration::load(left)?, &Declaration::load(right)?);
+    print!("{}", report::render(&differences, format)?);
+    match differences.len() {
+        0 => Ok(()),
+        n => Err(eyre!(
+            "{n} differences between {} and {}",
+            left.display(),
+            right.display()
+        )),
+    }
+}
+
+fn verify(config: &Config, path: &Path) -> Result<(), Report> {
+    let job = job_for(path);
+    let notify = |msg: &str| println!("{}", msg);
+    // keyless signatures are cosign's to check
+    match sigstore::bundle_path(path, None).exists() {
+        true => sigstore::verify(&config.settings, path)?,
+        false => TroUtils::new(&config.settings, &job, &notify).verify()?,
+    }
+    println!("{}: signature OK", path.display());
+    Ok(())
+}
+
+// Failed and abandoned entries are put back in the queue first. Settings
+// from --config override those the job was queued with.
+fn finalize(config_file: Option<&ConfigFile>, path: &Path) -> Result<(), Report> {
+    let path = spool::requeue(path)?;
+    let notify = |msg: &str| println!("{}", msg);
+    spool::process(&path, config_file, &notify)
+}
+
+fn resign(config: &Config, path: &Path) -> Result<(), Report> {
+    let job = job_for(path);
+    let notify = |msg: &str| println!("{}", msg);
+    let tro = TroUtils::new(&config.settings, &job, &notify);
+    let keys = signing::keys(&config.settings, (job.uid, job.gid))?;
+    for (i, key) in keys.iter().enumerate() {
+        tro.sign(key).wrap_err("Failed to sign")?;
+        let last = i + 1 == keys.len();
+        if !last {
+            signing::set_aside(path, key.role())?;
+        }
+        if config.settings.detached_signature {
+            let signature = signing::detached_signature(path, (!last).then(|| key.role()));
+            signing::detach_sign(&config.settings, key, path, &signature)?;
+        }
+        println!("{}: signed with {}", path.display(), key.fingerprint);
+    }
+    Ok(())
+}
+
+fn export_intoto(path: &Path) -> Result<(), Report> {
+    let attestation = intoto::default_path(path);
+    intoto::export(path, &attestation)?;
+    println!("{}: exported to {}", path.display(), attestation.display());
+    Ok(())
+}
+
+fn export_slsa(path: &Path) -> Result<(), Report> {
+    let provenance = slsa::default_path(path);
+    slsa::export(path, &provenance, None)?;
+    println!("{}: exported to {}", path.display(), provenance.display());
+    Ok(())
+}
+
+fn export_cwlprov(path: &Path) -> Result<(), Report> {
+    let dir = cwlprov::default_dir(path);
+    cwlprov::export(path, &dir)?;
+    println!("{}: exported to {}", path.display(), dir.display());
+    Ok(())
+}
+
+// Roll the TROs of a workflow's tasks into one declaration, e.g. for runs
+// the plugin did not recognize as a workflow's
+fn aggregate(paths: &[PathBuf]) -> Result<(), Report> {
+    let (aggregate, members) = paths
+        .split_first()
+        .filter(|(_, members)| !members.is_empty())
+        .ok_or_else(|| eyre!(USAGE))?;
+    let mut declarations = Vec::new();
+    for path in members {
+        find_declarations(path, aggregate, &mut declarations)?;
+    }
+    for declaration in &declarations {
+        workflow::aggregate(aggregate, None, declaration)?;
+        println!("{}: added {}", aggregate.display(), declaration.display());
+    }
+    Ok(())
+}
+
+fn query_catalog(config: &Config, command: &str, args: Vec<String>) -> Result<(), Report> {
+    let catalog = catalog::open(&config.settings)
+        .ok_or_else(|| eyre!("No catalog is configured, see catalog= in --config"))?;
+    let mut query = Query::default();
+    let mut format = Format::Table;
+    let mut jobid = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| eyre!("{arg} needs a value\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--json" => format = Format::Json,
+            "--since" if command != "show" => query.since = Some(catalog::parse_since(&value()?)?),
+            "--user" if command != "show" => query.user = Some(value()?),
+            "--partition" if command != "show" => query.partition = Some(value()?),
+            _ if command == "show" && jobid.is_none() => {
+                jobid = Some(arg.parse().map_err(|_| eyre!("{arg} is not a job id"))?)
+            }
+            _ => return Err(eyre!("Unexpected {arg}\n{USAGE}")),
+        }
+    }
+    let output = match command {
+        "show" => {
+            let jobid = jobid.ok_or_else(|| eyre!(USAGE))?;
+            let entries = catalog.find(jobid)?;
+            if entries.is_empty() {
+                return Err(eyre!("Job {jobid} is not in the catalog"));
+            }
+            report::render(&entries, format)?
+        }
+        "report" => report::render(&report::summarize(&catalog.query(&query)?), format)?,
+        _ => report::render(&catalog.query(&query)?, format)?,
+    };
+    print!("{output}");
+    Ok(())
+}
+
+// Declarations at or below `path`, leaving out the aggregate itself
+fn find_declarations(
+    path: &Path,
+    aggregate: &Path,
+    found: &mut Vec<PathBuf>,
+) -> Result<(), Report> {
+    if !path.is_dir() {
+        if path != aggregate {
+            found.push(path.to_path_buf());
+        }
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)
+        .wrap_err_with(|| format!("Failed to list {}", path.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for entry in entries {
+        let name = entry.file_name().unwrap_or_default().to_string_lossy();
+        if entry.is_dir() && !name.starts_with('.') {
+            find_declarations(&entry, aggregate, found)?;
+        } else if name.starts_with("tro-") && name.ends_with(".jsonld") && entry != aggregate {
+            found.push(entry);
+        }
+    }
+    Ok(())
+}
+
+// The job a declaration named tro-<jobid>.jsonld was generated for, as far as
+// tro-utils is concerned, run by whoever owns the declaration
+fn job_for(path: &Path) -> Job {
+    let (uid, gid) = path
+        .metadata()
+        .map_or((0, 0), |metadata| (metadata.uid(), metadata.gid()));
+    let jobid = path
+        .file_stem()
+        .and_then(|stem| stem.to_string_lossy().strip_prefix("tro-")?.parse().ok())
+        .unwrap_or_default();
+    Job {
+        jobid,
+        uid,
+        gid,
+        declaration: path.to_path_buf(),
+        workdir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        ..Job::default()
+    }
+}
@@ -1,289 +1,369 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
 use eyre::{eyre, Report, WrapErr};
-use serde_json::Value;
 use slurm_spank::{
-    spank_log_user, Context, Plugin, SpankHandle, SpankOption, SLURM_VERSION_NUMBER, SPANK_PLUGIN,
+    spank_log_error, spank_log_info, spank_log_user, Context, Plugin, SpankHandle, SpankOption,
+    SLURM_VERSION_NUMBER, SPANK_PLUGIN,
 };
-use users::get_user_by_uid;
+use users::{get_current_gid, get_current_uid, get_current_username, get_user_by_uid};
 
-use std::env::set_var;
+use std::env::{self, set_var};
 use std::error::Error;
-use std::fs::{read_dir, File};
-use std::io::BufReader;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
 use tracing::info;
 
+use tro_core::config::Config;
+use tro_core::context::{
+    self, JobContext, EXEC_DIR_OPTION, GENERATE_OPTION, INPUT_OPTION, NO_GENERATE_OPTION,
+    QUIET_OPTION, ROOT_OPTION, SNAPSHOT_OPTION, TRACK_GPU_OPTION, XALT_SAMPLING_OPTION,
+    XALT_TRACING_OPTION,
+};
+use tro_core::job::{Job, TaskExit};
+use tro_core::lifecycle;
+use tro_core::logging;
+use tro_core::preflight;
+use tro_core::session::{Session, SESSION_ENV};
+
 // All spank plugins must define this macro for the
 // Slurm plugin loader.
 SPANK_PLUGIN!(b"hello", SLURM_VERSION_NUMBER, SpankHello);
 
+// The SPANK side of TRO generation: reads the job from the SPANK handle, seen
+// through tro_core::context, and drives tro_core::lifecycle from the plugin hooks
 #[derive(Default)]
 struct SpankHello {
     generate_tro: bool,
-    xalt_dir: PathBuf,
-    gpg_home: PathBuf,
-    gpg_fingerprint: String,
-    gpg_passphrase: String,
-    trs_caps: PathBuf,
-    tro_utils: PathBuf,
+    config: Config,
+    job: Job,
+    // steps of an interactive allocation are covered by its own TRO
+    in_session: bool,
+    // whether the job runs where the plugin is enabled
+    enabled: bool,
 }
 
 unsafe impl Plugin for SpankHello {
     fn init(&mut self, spank: &mut SpankHandle) -> Result<(), Box<dyn Error>> {
-        // Register the --generate-tro option
+        // Register the --generate-tro and --no-generate-tro options
         match spank.context()? {
             Context::Local | Context::Remote | Context::Allocator => {
                 spank
                     .register_option(
-                        SpankOption::new("generate-tro").usage("Generate a TRO for a running job"),
+                        SpankOption::new(GENERATE_OPTION).usage("Generate a TRO for a running job"),
                     )
                     .wrap_err("Failed to register generate-tro option")?;
+                spank
+                    .register_option(
+                        SpankOption::new(NO_GENERATE_OPTION)
+                            .usage("Do not generate a TRO even if the site does by default"),
+                    )
+                    .wrap_err("Failed to register no-generate-tro option")?;
+                spank
+                    .register_option(
+                        SpankOption::new(INPUT_OPTION)
+                            .takes_value("path|URI|DOI[,...]")
+                            .usage("Record an input of the job outside its working directory"),
+                    )
+                    .wrap_err("Failed to register tro-input option")?;
+                spank
+                    .register_option(SpankOption::new(EXEC_DIR_OPTION).takes_value("dir").usage(
+                        "Directory the job runs in and its TRO fingerprints, e.g. in $SCRATCH",
+                    ))
+                    .wrap_err("Failed to register tro-exec-dir option")?;
+                spank
+                    .register_option(
+                        SpankOption::new(ROOT_OPTION)
+                            .takes_value("label=dir[,...]")
+                            .usage("Add a labelled directory to the job's arrangements"),
+                    )
+                    .wrap_err("Failed to register tro-root option")?;
+                spank
+                    .register_option(
+                        SpankOption::new(QUIET_OPTION)
+                            .usage("Only tell about the TRO when something goes wrong"),
+                    )
+                    .wrap_err("Failed to register tro-quiet option")?;
+                spank
+                    .register_option(
+                        SpankOption::new(XALT_TRACING_OPTION)
+                            .usage("Make XALT trace what it does to the job's stderr"),
+                    )
+                    .wrap_err("Failed to register tro-xalt-tracing option")?;
+                spank
+                    .register_option(
+                        SpankOption::new(XALT_SAMPLING_OPTION)
+                            .takes_value("on|off")
+                            .usage("Whether XALT samples the job's programs or records all"),
+                    )
+                    .wrap_err("Failed to register tro-xalt-sampling option")?;
+                spank
+                    .register_option(
+                        SpankOption::new(TRACK_GPU_OPTION)
+                            .usage("Make XALT record the GPUs the job's programs use"),
+                    )
+                    .wrap_err("Failed to register tro-track-gpu option")?;
             }
             _ => {}
         }
-        if spank.context()? == Context::Remote {
+        if matches!(spank.context()?, Context::Local | Context::Remote) {
+            spank
+                .register_option(
+                    SpankOption::new(SNAPSHOT_OPTION)
+                        .takes_value("label")
+                        .usage("Record an arrangement of the job's TRO before this step"),
+                )
+                .wrap_err("Failed to register tro-snapshot option")?;
+        }
+        if matches!(
+            spank.context()?,
+            Context::Remote | Context::Allocator | Context::Local
+        ) {
             // Parse plugin configuration file
-            for arg in spank.plugin_argv().wrap_err("Invalid plugin argument")? {
-                if arg.starts_with("xalt_dir=") {
-                    match arg.strip_prefix("xalt_dir=") {
-                        Some(value) => {
-                            self.xalt_dir = parse_xalt_dir(value).wrap_err("Invalid xalt_dir")?
-                        }
-                        None => return Err(eyre!("Invalid plugin argument: {}", arg).into()),
-                    }
-                } else if arg.starts_with("gpg_home=") {
-                    match arg.strip_prefix("gpg_home=") {
-                        Some(value) => {
-                            self.gpg_home = PathBuf::from(value);
-                        }
-                        None => return Err(eyre!("Invalid plugin argument: {}", arg).into()),
-                    }
-                } else if arg.starts_with("gpg_fingerprint=") {
-                    match arg.strip_prefix("gpg_fingerprint=") {
-                        Some(value) => {
-                            self.gpg_fingerprint = value.to_string();
-                        }
-                        None => return Err(eyre!("Invalid plugin argument: {}", arg).into()),
-                    }
-                } else if arg.starts_with("gpg_passphrase=") {
-                    match arg.strip_prefix("gpg_passphrase=") {
-                        Some(value) => {
-                            self.gpg_passphrase = value.to_string();
-                        }
-                        None => return Err(eyre!("Invalid plugin argument: {}", arg).into()),
-                    }
-                } else if arg.starts_with("trs_caps=") {
-                    match arg.strip_prefix("trs_caps=") {
-                        Some(value) => {
-                            self.trs_caps = PathBuf::from(value);
-                        }
-                        None => return Err(eyre!("Invalid plugin argument: {}", arg).into()),
-                    }
-                } else if arg.starts_with("tro_utils=") {
-                    match arg.strip_prefix("tro_utils=") {
-                        Some(value) => {
-                            self.tro_utils = PathBuf::from(value);
-                        }
-                        None => return Err(eyre!("Invalid plugin argument: {}", arg).into()),
-                    }
-                }
+            let args = spank.plugin_argv().wrap_err("Invalid plugin argument")?;
+            self.config = Config::from_args(args)?;
+            self.config.slurm_version = Some(SLURM_VERSION_NUMBER);
+            self.enabled = context::enabled(&self.config, &Spank(spank))?;
+        }
+        if spank.context()? == Context::Slurmd {
+            // check the node once rather than fail its jobs one by one
+            let args = spank.plugin_argv().wrap_err("Invalid plugin argument")?;
+            let problems = match Config::from_args(args) {
+                Ok(config) => preflight::check_install(&config),
+                Err(e) => vec![format!("{:#}", e)],
+            };
+            match problems.is_empty() {
+                true => spank_log_info!("{}", preflight::summary(&problems)),
+                false => spank_log_error!("{}", preflight::summary(&problems)),
             }
-            unsafe {
-                set_var("GPGPGHOME", self.gpg_home.as_os_str().to_str().unwrap());
-                set_var("GPG_HOME", self.gpg_home.as_os_str().to_str().unwrap());
+        }
+        if spank.context()? == Context::Remote && self.enabled {
+            let ctx = Spank(spank);
+            if ctx.getenv(SESSION_ENV)?.is_some() {
+                self.in_session = true;
+                return Ok(());
             }
             // create a TRO for the job in workdir and name it after the jobid
-            let workdir = spank.getenv("SLURM_SUBMIT_DIR")?.unwrap();
-            let tro_file = PathBuf::from(format!("{}/tro-{}.jsonld", workdir, spank.job_id()?));
-            let initial_args = [
-                "--declaration",
-                tro_file.to_str().unwrap(),
-                "--profile",
-                self.trs_caps.to_str().unwrap(),
-                "--gpg-fingerprint",
-                &self.gpg_fingerprint,
-                "--gpg-passphrase",
-                &self.gpg_passphrase,
-                "arrangement",
-                "add",
-                "-m",
-                "'Initial arrangement'",
-                "-i",
-                ".git",
-                &workdir,
-            ];
-            let output = Command::new(self.tro_utils.to_str().unwrap())
-                .args(initial_args.iter())
-                .output()
-                .expect("Failed");
-            //info!("Called {}", initial_args.join(" "));
-            //info!("Output: {}", String::from_utf8_lossy(&output.stdout));
+            self.job = context::job(&self.config, &ctx)?;
+            let settings = &self.config.settings;
+            let job_log = lifecycle::job_log(&self.config, &self.job)?;
+            logging::init(settings.log_level, settings.log_target, job_log);
+            for (name, value) in &self.config.plugin_env {
+                info!("Setting {name}={value} in the plugin's environment");
+                unsafe { set_var(name, value) };
+            }
+            let script_path = ctx.getenv("SLURM_JOB_SCRIPT")?.map(PathBuf::from);
+            let notify = |msg: &str| spank_log_user!("{}", msg);
+            lifecycle::start(&self.config, &mut self.job, script_path.as_deref(), &notify)?;
         }
         Ok(())
     }
     fn init_post_opt(&mut self, spank: &mut SpankHandle) -> Result<(), Box<dyn Error>> {
-        // Check if the option was set
-        self.generate_tro = spank.is_option_set("generate-tro");
+        // once per step, from its first node
+        if spank.context()? == Context::Remote
+            && spank.is_option_set(SNAPSHOT_OPTION)
+            && spank.job_nodeid()? == 0
+        {
+            let label = spank.get_option_value(SNAPSHOT_OPTION)?;
+            self.snapshot(&Spank(spank), label.as_deref().unwrap_or("srun"))?;
+        }
+        // Check if the option was set, or if the site wants a TRO anyway
+        let explicit = spank.is_option_set(GENERATE_OPTION);
+        let notify = |msg: &str| spank_log_user!("{}", msg);
+        self.generate_tro = context::generate(
+            &self.config,
+            &Spank(spank),
+            self.enabled,
+            self.in_session,
+            current_user(),
+            &notify,
+        )?;
         if self.generate_tro {
             info!("I will generate a marvelous TRO!");
+            if matches!(spank.context()?, Context::Allocator | Context::Local) {
+                self.preflight(&Spank(spank), explicit)?;
+            }
+            if spank.context()? == Context::Allocator {
+                self.start_allocation(&mut Spank(spank))?;
+            }
         }
         Ok(())
     }
 
-    fn user_init(&mut self, _spank: &mut SpankHandle) -> Result<(), Box<dyn Error>> {
-        // Greet as requested
-        if self.generate_tro && _spank.context()? == Context::Remote {
-            _spank.setenv("XALT_DIR", self.xalt_dir.as_os_str(), true)?;
-            let preloader: PathBuf = PathBuf::from(
-                self.xalt_dir
-                    .as_path()
-                    .join("lib64")
-                    .join("libxalt_init.so"),
-            );
-            if _spank.getenv("LD_PRELOAD")?.is_none() {
-                _spank.setenv("LD_PRELOAD", preloader.as_os_str(), true)?;
-            } else {
-                let old_preload = _spank.getenv("LD_PRELOAD")?.unwrap();
-                let new_preload = preloader.as_os_str().to_str().unwrap();
-                _spank.setenv("LD_PRELOAD", format!("{new_preload}:{old_preload}"), true)?;
-            }
+    fn user_init(&mut self, spank: &mut SpankHandle) -> Result<(), Box<dyn Error>> {
+        // Make the tracker record the programs of the job
+        if self.generate_tro && spank.context()? == Context::Remote {
+            let uid = spank.job_uid()?;
+            // e.g. while sssd is down
+            let user = get_user_by_uid(uid).ok_or_else(|| eyre!("Unknown user {uid}"))?;
+            context::track(
+                &self.config,
+                &mut Spank(spank),
+                &mut self.job,
+                &user.name().to_string_lossy(),
+            )?;
+        }
+        Ok(())
+    }
 
-            // Sometimes USER is not set and it trips XALT badly...
-            let user = get_user_by_uid(_spank.job_uid()?).unwrap();
-            _spank.setenv("USER", user.name(), true)?;
+    fn task_init(&mut self, spank: &mut SpankHandle) -> Result<(), Box<dyn Error>> {
+        if self.generate_tro && spank.context()? == Context::Remote {
+            context::wrap(&self.config, &mut Spank(spank), &self.job)?;
+        }
+        Ok(())
+    }
 
-            // It would be super-cool if I could inject those to control XALT...
-            //_spank.setenv("XALT_RESULT_DIR", "/tmp", true)?;
-            //_spank.setenv("XALT_RESULT_FILE", "foo.run", true)?;
-            _spank.setenv("XALT_EXECUTABLE_TRACKING", "yes", true)?;
-            _spank.setenv("XALT_TRACING", "no", true)?;
+    fn task_exit(&mut self, spank: &mut SpankHandle) -> Result<(), Box<dyn Error>> {
+        if self.generate_tro && spank.context()? == Context::Remote {
+            let task =
+                TaskExit::from_wait_status(spank.task_global_id()?, spank.task_exit_status()?);
+            info!("Task {} ended: {:?}", task.task, task);
+            self.job.tasks.push(task);
         }
         Ok(())
     }
 
     fn exit(&mut self, spank: &mut SpankHandle) -> Result<(), Box<dyn Error>> {
+        let notify = |msg: &str| spank_log_user!("{}", msg);
+        if self.generate_tro && spank.context()? == Context::Allocator {
+            let ctx = Spank(spank);
+            let jobid = context::allocation_jobid(&ctx)?;
+            let session = ctx.getenv(SESSION_ENV)?.map(PathBuf::from);
+            lifecycle::finish_session(
+                &self.config,
+                &mut self.job,
+                jobid,
+                session.as_deref(),
+                &notify,
+            )?;
+        }
         if self.generate_tro && spank.context()? == Context::Remote {
-            let workdir = spank.getenv("SLURM_SUBMIT_DIR")?.unwrap();
-            let tro_file = PathBuf::from(format!("{}/tro-{}.jsonld", workdir, spank.job_id()?));
-            let final_args = [
-                "--declaration",
-                tro_file.to_str().unwrap(),
-                "--profile",
-                self.trs_caps.to_str().unwrap(),
-                "--gpg-fingerprint",
-                &self.gpg_fingerprint,
-                "--gpg-passphrase",
-                &self.gpg_passphrase,
-                "arrangement",
-                "add",
-                "-m",
-                "'Final arrangement'",
-                "-i",
-                ".git",
-                &workdir,
-            ];
-            let output = Command::new(self.tro_utils.to_str().unwrap())
-                .args(final_args.iter())
-                .output()
-                .expect("Failed");
-            //info!("Called {}", final_args.join(" "));
-            //info!("Output: {}", String::from_utf8_lossy(&output.stdout));
+            lifecycle::finish(&self.config, &mut self.job, &notify)?;
+        }
+        Ok(())
+    }
+}
 
-            // add performance
-            let xalt_trace = get_xalt_trace(spank);
-            match xalt_trace {
-                Ok(trace) => {
-                    let start_time: f64 = trace["userDT"]["start_time"].as_f64().unwrap();
-                    let end_time: f64 = trace["userDT"]["end_time"].as_f64().unwrap();
-                    //let command = trace["cmdlineA"].as_array().unwrap().join(" ");
-                    let perf_args = [
-                        "--declaration",
-                        tro_file.to_str().unwrap(),
-                        "--profile",
-                        self.trs_caps.to_str().unwrap(),
-                        "--gpg-fingerprint",
-                        &self.gpg_fingerprint,
-                        "--gpg-passphrase",
-                        &self.gpg_passphrase,
-                        "performance",
-                        "add",
-                        "-m",
-                        &format!("'Run magic'"),
-                        "-s",
-                        &get_date_from_timestamp(start_time as i64),
-                        "-e",
-                        &get_date_from_timestamp(end_time as i64),
-                        "-a",
-                        "arrangement/0",
-                        "-M",
-                        "arrangement/1",
-                    ];
-                    let output = Command::new(self.tro_utils.to_str().unwrap())
-                        .args(perf_args.iter())
-                        .output()
-                        .expect("Failed");
-                    info!("Called {}", perf_args.join(" "));
-                    info!("Output: {}", String::from_utf8_lossy(&output.stdout));
-                    //    get_date_from_timestamp(start_time as i64)
-                }
-                Err(e) => {
-                    info!("Failed to get XALT trace: {}", e);
-                    return Err(e);
-                }
-            }
+impl SpankHello {
+    // srun --tro-snapshot: record an arrangement of the TRO of the job, or of
+    // the interactive allocation, the step runs in. The step runs either way.
+    fn snapshot(&self, ctx: &impl JobContext, label: &str) -> Result<(), Report> {
+        let settings = &self.config.settings;
+        let notify = |msg: &str| spank_log_user!("{}", msg);
+        let job = match ctx.getenv(SESSION_ENV)? {
+            Some(session) => Session::read(Path::new(&session))?.job,
+            None => context::job(&self.config, ctx)?,
+        };
+        match lifecycle::snapshot(settings, &job, label, &notify) {
+            Ok(id) => spank_log_user!("spank-tro: recorded {} ({})", id, label),
+            Err(e) => spank_log_user!("spank-tro: no snapshot was recorded: {:#}", e),
+        }
+        Ok(())
+    }
 
-            // sign TRO
-            let sing_args = [
-                "--declaration",
-                tro_file.to_str().unwrap(),
-                "--gpg-fingerprint",
-                &self.gpg_fingerprint,
-                "--gpg-passphrase",
-                &self.gpg_passphrase,
-                "sign",
-            ];
-            let output = Command::new(self.tro_utils.to_str().unwrap())
-                .args(sing_args.iter())
-                .output()
-                .expect("Failed");
-            //info!("Called {}", sing_args.join(" "));
-            //info!("Output: {}", String::from_utf8_lossy(&output.stdout));
+    // Tell the user right away whether the TRO can be generated, rather than
+    // once the job is over. Only jobs that asked for a TRO are rejected.
+    fn preflight(&self, ctx: &impl JobContext, explicit: bool) -> Result<(), Report> {
+        // srun within an allocation, the user already heard about it
+        if ctx.getenv("SLURM_JOB_ID")?.is_some() {
+            return Ok(());
+        }
+        let workdir = match ctx.getenv("SLURM_SUBMIT_DIR")? {
+            Some(workdir) => PathBuf::from(workdir),
+            None => env::current_dir().wrap_err("Failed to get the current directory")?,
+        };
+        let problems = preflight::check(&self.config.settings, &workdir);
+        if problems.is_empty() {
+            spank_log_user!(
+                "spank-tro: a TRO will be generated in {}",
+                workdir.display()
+            );
+            return Ok(());
         }
+        for problem in &problems {
+            spank_log_user!("spank-tro: {}", problem);
+        }
+        if !explicit {
+            spank_log_user!("spank-tro: the TRO of this job will likely be incomplete");
+            return Ok(());
+        }
+        Err(eyre!(
+            "Cannot generate a TRO, fix the above or submit without --generate-tro"
+        ))
+    }
+
+    // salloc --generate-tro: record the initial arrangement of the current
+    // directory and let the commands run in the allocation know about the TRO
+    // so that they can snapshot it with spank-tro-snapshot
+    fn start_allocation(&mut self, ctx: &mut impl JobContext) -> Result<(), Report> {
+        let settings = &self.config.settings;
+        logging::init(settings.log_level, settings.log_target, None);
+        let workdir = env::current_dir().wrap_err("Failed to get the current directory")?;
+        let notify = |msg: &str| spank_log_user!("{}", msg);
+        let (job, session) = lifecycle::start_session(
+            &self.config,
+            (get_current_uid(), get_current_gid()),
+            current_user().unwrap_or_default(),
+            workdir,
+            &notify,
+        )?;
+        self.job = job;
+        ctx.setenv(SESSION_ENV, &session.to_string_lossy())
+            .wrap_err_with(|| format!("Failed to set {SESSION_ENV}"))?;
+        spank_log_user!(
+            "spank-tro: recording {}, run spank-tro-snapshot to add arrangements",
+            self.job.declaration.display()
+        );
         Ok(())
     }
 }
 
-fn parse_xalt_dir(value: &str) -> Result<PathBuf, Report> {
-    let xalt_dir: PathBuf = PathBuf::from(value);
-    match xalt_dir.is_dir() {
-        true => Ok(xalt_dir),
-        _ => Err(eyre!("xalt_dir={value} is not a valid directory")),
+// The SPANK handle of a hook, as seen by tro_core
+struct Spank<'a, 'b>(&'a mut SpankHandle<'b>);
+
+impl JobContext for Spank<'_, '_> {
+    fn context(&self) -> Result<context::Context, Report> {
+        Ok(match self.0.context()? {
+            Context::Local => context::Context::Local,
+            Context::Allocator => context::Context::Allocator,
+            Context::Remote => context::Context::Remote,
+            _ => context::Context::Other,
+        })
     }
-}
 
-fn get_xalt_trace(spank: &mut SpankHandle) -> Result<serde_json::Value, Box<dyn Error>> {
-    // assume that the jobid is set and XALT stores the trace in the user's home directory
-    let jobid = spank.job_id()?;
-    let user = spank.getenv("SLURM_JOB_USER")?.unwrap();
-    let xalt_dir = format!("/home/{}/.xalt.d", user);
-    // list xalt_dir in a reverse name order, parse each json file, and find the one that has
-    // ["userT"]["job_id"] == jobid
-    for entry in read_dir(xalt_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        let u: Value = serde_json::from_reader(reader)?;
-        if u["userT"]["job_id"] == jobid.to_string() {
-            return Ok(u);
-        }
+    fn getenv(&self, name: &str) -> Result<Option<String>, Report> {
+        Ok(self.0.getenv(name)?)
+    }
+
+    fn setenv(&mut self, name: &str, value: &str) -> Result<(), Report> {
+        Ok(self.0.setenv(name, value, true)?)
+    }
+
+    fn job_id(&self) -> Result<u32, Report> {
+        Ok(self.0.job_id()?)
+    }
+
+    fn job_uid(&self) -> Result<u32, Report> {
+        Ok(self.0.job_uid()?)
+    }
+
+    fn job_gid(&self) -> Result<u32, Report> {
+        Ok(self.0.job_gid()?)
+    }
+
+    fn job_stepid(&self) -> Result<u32, Report> {
+        Ok(self.0.job_stepid()?)
+    }
+
+    fn is_option_set(&self, name: &str) -> bool {
+        self.0.is_option_set(name)
+    }
+
+    fn option_value(&self, name: &str) -> Result<Option<String>, Report> {
+        Ok(self.0.get_option_value(name)?)
+    }
+
+    fn prepend_task_argv(&mut self, argv: &[&str]) -> Result<(), Report> {
+        Ok(self.0.prepend_task_argv(argv.to_vec())?)
     }
-    Ok(().into())
 }
 
-fn get_date_from_timestamp(timestamp: i64) -> String {
-    let naive = NaiveDateTime::from_timestamp(timestamp, 0);
-    let datetime: DateTime<Utc> = DateTime::from_utc(naive, Utc);
-    datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+fn current_user() -> Option<String> {
+    get_current_username().map(|user| user.to_string_lossy().into_owned())
 }
@@ -0,0 +1,151 @@
+// End-to-end tests against a single-node Slurm running in Docker, with the
+// plugin built and installed in plugstack.conf by tests/slurm/Dockerfile.
+// They need docker and take a while, run them with `cargo test -- --ignored`.
+use std::process::{Command, Output};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const IMAGE: &str = "spank-tro-slurm";
+
+// A running test cluster, removed when dropped
+struct Cluster {
+    container: String,
+}
+
+impl Cluster {
+    fn start(name: &str) -> Self {
+        let root = env!("CARGO_MANIFEST_DIR");
+        run(Command::new("docker").args([
+            "build",
+            "-q",
+            "-f",
+            "tests/slurm/Dockerfile",
+            "-t",
+            IMAGE,
+            root,
+        ]));
+        let container = format!("spank-tro-{name}-{}", std::process::id());
+        run(Command::new("docker").args([
+            "run",
+            "-d",
+            "--privileged",
+            "--hostname",
+            "localhost",
+            "--name",
+            &container,
+            IMAGE,
+        ]));
+        let cluster = Cluster { container };
+        cluster.wait_for_node();
+        cluster
+    }
+
+    fn exec(&self, user: &str, command: &str) -> Output {
+        Command::new("docker")
+            .args(["exec", "-u", user, "-w", "/home/alice", &self.container])
+            .args(["bash", "-c", command])
+            .output()
+            .expect("Failed to run docker exec")
+    }
+
+    // Output of a command that has to succeed
+    fn check(&self, user: &str, command: &str) -> String {
+        let output = self.exec(user, command);
+        assert!(
+            output.status.success(),
+            "{command} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    fn wait_for_node(&self) {
+        let deadline = Instant::now() + Duration::from_secs(60);
+        while Instant::now() < deadline {
+            let output = self.exec("root", "sinfo -h -o %t");
+            if String::from_utf8_lossy(&output.stdout).trim() == "idle" {
+                return;
+            }
+            sleep(Duration::from_secs(1));
+        }
+        panic!("Slurm did not come up in {}", self.container);
+    }
+
+    // Submit a batch script as alice and wait for it, returning the jobid
+    fn sbatch(&self, options: &str, script: &str) -> u32 {
+        let command =
+            format!("sbatch --parsable --wait {options} <<'EOF'\n#!/bin/bash\n{script}\nEOF");
+        self.check("alice", &command)
+            .trim()
+            .parse()
+            .expect("sbatch did not print a jobid")
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.exec("root", &format!("test -e {path}"))
+            .status
+            .success()
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        let _ = Command::new("docker")
+            .args(["rm", "-f", &self.container])
+            .output();
+    }
+}
+
+fn run(command: &mut Command) {
+    let output = command.output().expect("Failed to run docker");
+    assert!(
+        output.status.success(),
+        "{:?} failed: {}",
+        command,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+#[ignore]
+fn batch_job_gets_a_signed_tro() {
+    let cluster = Cluster::start("batch");
+    let jobid = cluster.sbatch("--generate-tro", "echo hello > hello.txt");
+    let declaration = format!("/home/alice/tro-{jobid}.jsonld");
+    assert!(cluster.exists(&declaration));
+    cluster.check(
+        "root",
+        &format!("spank-tro --config /etc/slurm/spank-tro.conf verify {declaration}"),
+    );
+    let summary = cluster.check("root", &format!("spank-tro inspect {declaration}"));
+    assert!(summary.contains("Run magic"), "{summary}");
+}
+
+#[test]
+#[ignore]
+fn no_tro_unless_asked() {
+    let cluster = Cluster::start("optout");
+    let jobid = cluster.sbatch("", "true");
+    assert!(!cluster.exists(&format!("/home/alice/tro-{jobid}.jsonld")));
+}
+
+#[test]
+#[ignore]
+fn tasks_see_the_tracking_environment() {
+    let cluster = Cluster::start("env");
+    let jobid = cluster.sbatch("--generate-tro", "srun bash -c 'echo \"$SPANK_TRO_TRACE\"'");
+    let output = cluster.check("alice", &format!("cat slurm-{jobid}.out"));
+    assert!(output.contains("/home/alice/.tro/"), "{output}");
+}
+
+#[test]
+#[ignore]
+fn allocation_records_snapshots() {
+    let cluster = Cluster::start("salloc");
+    cluster.check(
+        "alice",
+        "salloc --generate-tro bash -c 'echo data > data.txt && spank-tro-snapshot'",
+    );
+    let summary = cluster.check("root", "spank-tro inspect /home/alice/tro-*.jsonld");
+    assert!(summary.contains("arrangement/2"), "{summary}");
+}
@@ -0,0 +1,16 @@
+#!/bin/bash
+# Generate the signing key, start munge and Slurm, then wait forever so that
+# the tests can docker exec into the container.
+set -e
+
+export GNUPGHOME=/etc/spank-tro/gnupg
+mkdir -p -m 700 $GNUPGHOME
+gpg --batch --passphrase spank-tro --quick-gen-key "spank-tro test <spank-tro@example.org>" rsa2048 sign never
+fingerprint=$(gpg --list-keys --with-colons | awk -F: '/^fpr/ { print $10; exit }')
+echo "gpg_fingerprint=$fingerprint" >> /etc/slurm/spank-tro.conf
+
+mkdir -p /var/spool/slurmctld /var/spool/slurmd /var/spool/spank-tro
+service munge start
+slurmctld
+slurmd
+exec sleep infinity
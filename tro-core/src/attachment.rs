@@ -0,0 +1,76 @@
+use eyre::{Report, WrapErr};
+use serde_json::Value;
+use std::process::Command;
+
+use crate::command;
+use crate::declaration::term;
+use crate::encryption;
+use crate::settings::Settings;
+
+// Artifacts whose content can be marked sensitive=
+pub const ARTIFACTS: [&str; 4] = ["stdout", "stderr", "batch_script", "environment"];
+
+// Add the content of an artifact to its description: encrypted to encrypt_to
+// when the artifact is sensitive, zstd-compressed above compress_min_size, or
+// else as is. Sensitive content is left out when there is no one to encrypt
+// it to.
+pub fn attach(
+    settings: &Settings,
+    described: &mut Value,
+    artifact: &str,
+    content: &str,
+) -> Result<(), Report> {
+    if settings.sensitive.iter().any(|name| name == artifact) {
+        // gpg compresses what it encrypts already
+        if !settings.encrypt_to.is_empty() {
+            described[term("encryptedContent")] =
+                Value::from(encryption::encrypt(settings, content.as_bytes())?);
+            described[term("recipients")] = Value::from(settings.encrypt_to.clone());
+        }
+        return Ok(());
+    }
+    let size = content.len() as u64;
+    if settings
+        .compress_min_size
+        .is_some_and(|min_size| size >= min_size)
+    {
+        let compressed = compress(settings, content.as_bytes())?;
+        described[term("compressedContent")] = Value::from(base64(&compressed));
+        described[term("compression")] = Value::from("zstd");
+        described[term("contentSize")] = Value::from(size);
+        return Ok(());
+    }
+    described[term("content")] = Value::from(content);
+    Ok(())
+}
+
+pub fn compress(settings: &Settings, content: &[u8]) -> Result<Vec<u8>, Report> {
+    let output = command::run_with_input(
+        Command::new(&settings.zstd).args(["-q", "-c"]),
+        Some(content),
+        settings.command_timeout,
+    )
+    .wrap_err("Failed to compress")?;
+    Ok(output.stdout)
+}
+
+// Standard base64 with padding, how binary content goes into the JSON-LD
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(group >> (18 - 6 * i)) as usize & 0x3f] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
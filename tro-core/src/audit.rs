@@ -0,0 +1,69 @@
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::digest::sha256_file;
+use crate::timestamp;
+
+// One line of the signing audit trail
+#[derive(Serialize)]
+pub struct SigningEvent<'a> {
+    pub timestamp: String,
+    pub jobid: u32,
+    pub uid: u32,
+    pub user: &'a str,
+    pub fingerprint: &'a str,
+    pub declaration: &'a Path,
+    pub sha256: Option<String>,
+    pub success: bool,
+}
+
+impl<'a> SigningEvent<'a> {
+    // Describe a signing attempt of the declaration that just finished
+    pub fn new(
+        jobid: u32,
+        uid: u32,
+        user: &'a str,
+        fingerprint: &'a str,
+        declaration: &'a Path,
+        success: bool,
+    ) -> Self {
+        SigningEvent {
+            timestamp: timestamp::now(),
+            jobid,
+            uid,
+            user,
+            fingerprint,
+            declaration,
+            sha256: sha256_file(declaration).ok(),
+            success,
+        }
+    }
+}
+
+// Append-only JSON lines log of every use of a signing key
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: &Path) -> Self {
+        AuditLog {
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub fn record(&self, event: &SigningEvent) -> io::Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        // several jobs may finish on the node at the same time
+        file.lock()?;
+        file.write_all(&line)?;
+        file.sync_data()
+    }
+}
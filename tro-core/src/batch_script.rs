@@ -0,0 +1,46 @@
+use eyre::{Report, WrapErr};
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::attachment;
+use crate::command;
+use crate::declaration::{term, Declaration};
+use crate::digest::sha256_bytes;
+use crate::job::Job;
+use crate::redact;
+use crate::settings::Settings;
+
+// The script the job was submitted with, read from `path` when slurmd exposes
+// it, or else asked to slurmctld
+pub fn fetch(settings: &Settings, jobid: u32, path: Option<&Path>) -> Result<Vec<u8>, Report> {
+    if let Some(path) = path {
+        return fs::read(path).wrap_err_with(|| format!("Failed to read {}", path.display()));
+    }
+    let output = command::run(
+        Command::new(&settings.scontrol)
+            .args(["write", "batch_script"])
+            .arg(jobid.to_string())
+            .arg("-"),
+        settings.command_timeout,
+    )
+    .wrap_err_with(|| format!("Failed to get the batch script of job {jobid}"))?;
+    Ok(output.stdout)
+}
+
+// Record the batch script's digest, and its content with embed_batch_script,
+// in the latest arrangement
+pub fn record(settings: &Settings, job: &Job, script: &[u8]) -> Result<(), Report> {
+    let mut described = json!({
+        term("size"): script.len(),
+        term("sha256"): sha256_bytes(script),
+    });
+    if settings.embed_batch_script {
+        let script = redact::text(&settings.redact, &String::from_utf8_lossy(script));
+        attachment::attach(settings, &mut described, "batch_script", &script)?;
+    }
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_arrangement("batchScript", described)?;
+    declaration.save()
+}
@@ -0,0 +1,103 @@
+// Content-addressable store of the artifacts TROs capture, shared by the
+// jobs of a site: <cas_dir>/sha256/<first two digits>/<other digits>, each
+// content stored once however many TROs refer to it, e.g. the inputs of
+// every task of an array. Objects are read-only, who may read them is up to
+// the permissions of cas_dir.
+use eyre::{eyre, Report, WrapErr};
+use sha2::{Digest, Sha256};
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::job::Job;
+use crate::privilege;
+use crate::settings::Settings;
+
+pub struct Cas {
+    dir: PathBuf,
+    // who the stored files are read as
+    owner: (u32, u32),
+}
+
+impl Cas {
+    // The store, if the site has one, for the files of `job`
+    pub fn of(settings: &Settings, job: &Job) -> Option<Self> {
+        Some(Cas {
+            dir: settings.cas_dir.clone()?,
+            owner: (job.uid, job.gid),
+        })
+    }
+
+    pub fn path(&self, sha256: &str) -> PathBuf {
+        let (prefix, rest) = sha256.split_at(2.min(sha256.len()));
+        self.dir.join("sha256").join(prefix).join(rest)
+    }
+
+    // Store the content of `path`, whose SHA-256 is known, unless it is
+    // stored already
+    pub fn store_file(&self, path: &Path, sha256: &str) -> Result<PathBuf, Report> {
+        let object = self.path(sha256);
+        if object.exists() {
+            return Ok(object);
+        }
+        let file = {
+            let _owner = privilege::as_owner(self.owner);
+            File::open(path).wrap_err_with(|| format!("Failed to read {}", path.display()))?
+        };
+        self.put(file, sha256)
+            .wrap_err_with(|| format!("Failed to store {}", path.display()))
+    }
+
+    pub fn store_bytes(&self, content: &[u8], sha256: &str) -> Result<PathBuf, Report> {
+        let object = self.path(sha256);
+        if object.exists() {
+            return Ok(object);
+        }
+        self.put(content, sha256)
+    }
+
+    // Copy next to the object, then move it in place once its digest is
+    // checked: a file changed since it was hashed is not stored
+    fn put(&self, mut content: impl Read, sha256: &str) -> Result<PathBuf, Report> {
+        let object = self.path(sha256);
+        let parent = object.parent().unwrap_or(&self.dir);
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o755)
+            .create(parent)
+            .wrap_err_with(|| format!("Failed to create {}", parent.display()))?;
+        let tmp_path = parent.join(format!(".{sha256}.{}.tmp", std::process::id()));
+        let mut tmp = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .mode(0o444)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(&tmp_path)
+            .wrap_err_with(|| format!("Failed to create {}", tmp_path.display()))?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 1 << 20];
+        let copied = loop {
+            let read = match content.read(&mut buffer) {
+                Ok(0) => break Ok(()),
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => break Err(e),
+            };
+            hasher.update(&buffer[..read]);
+            if let Err(e) = tmp.write_all(&buffer[..read]) {
+                break Err(e);
+            }
+        };
+        let stored = format!("{:x}", hasher.finalize());
+        let result = match copied.and_then(|_| tmp.sync_all()) {
+            Err(e) => Err(Report::new(e)),
+            Ok(()) if stored != sha256 => Err(eyre!("its content changed since it was hashed")),
+            Ok(()) => fs::rename(&tmp_path, &object).map_err(Report::new),
+        };
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result.map(|_| object)
+    }
+}
@@ -0,0 +1,369 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use eyre::{eyre, Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::command;
+use crate::digest::sha256_file;
+use crate::job::Job;
+use crate::settings::{parse_duration, Settings};
+use crate::timestamp;
+
+// Several jobs may finish on the node at the same time, sqlite3 waits this
+// long for the others' writes
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The central catalog is reached over the network at job end, like scontrol
+const PSQL_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Shared by both backends: BIGINT for step ids past 2^31, e.g. the batch
+// step, and "user" quoted as PostgreSQL reserves it
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS tros (
+    jobid BIGINT NOT NULL,
+    step BIGINT,
+    cluster TEXT,
+    node TEXT,
+    \"user\" TEXT NOT NULL,
+    uid BIGINT NOT NULL,
+    partition TEXT,
+    declaration TEXT NOT NULL,
+    sha256 TEXT,
+    fingerprints TEXT NOT NULL,
+    status TEXT NOT NULL,
+    finalized TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS tros_jobid ON tros (jobid);
+CREATE INDEX IF NOT EXISTS tros_user ON tros (\"user\");";
+
+// Where finalizations are indexed: a SQLite file on each node, or one
+// PostgreSQL database the nodes of all clusters report to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CatalogBackend {
+    Sqlite(PathBuf),
+    // a libpq connection string, postgresql://user@host/db or key=value
+    Postgres(String),
+}
+
+impl FromStr for CatalogBackend {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.starts_with("postgresql://") || value.starts_with("postgres://") {
+            return Ok(CatalogBackend::Postgres(value.to_string()));
+        }
+        if let Some(conninfo) = value.strip_prefix("postgresql:") {
+            return Ok(CatalogBackend::Postgres(conninfo.to_string()));
+        }
+        let path = value.strip_prefix("sqlite:").unwrap_or(value);
+        if path.is_empty() {
+            return Err(eyre!("{value} names no catalog"));
+        }
+        Ok(CatalogBackend::Sqlite(PathBuf::from(path)))
+    }
+}
+
+impl fmt::Display for CatalogBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogBackend::Sqlite(path) => write!(f, "sqlite:{}", path.display()),
+            // without the password it may hold
+            CatalogBackend::Postgres(_) => write!(f, "postgresql"),
+        }
+    }
+}
+
+// How a finalization ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Signed,
+    // finalized, but signing failed and on_failure let it through
+    Unsigned,
+    Failed,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Signed => "signed",
+            Status::Unsigned => "unsigned",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+// A finalization, as the catalog has it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    pub jobid: u32,
+    pub step: Option<u32>,
+    // SLURM_CLUSTER_NAME and the node the job ended on, for catalogs shared
+    // by several
+    #[serde(default)]
+    pub cluster: Option<String>,
+    #[serde(default)]
+    pub node: Option<String>,
+    pub user: String,
+    pub uid: u32,
+    pub partition: Option<String>,
+    pub declaration: PathBuf,
+    pub sha256: Option<String>,
+    // of the keys that signed the declaration, comma-separated
+    pub fingerprints: String,
+    pub status: Status,
+    // RFC 3339
+    pub finalized: String,
+}
+
+impl CatalogEntry {
+    // The finalization of the job that just ended
+    pub fn new(
+        job: &Job,
+        partition: Option<&str>,
+        fingerprints: &[String],
+        status: Status,
+    ) -> Self {
+        CatalogEntry {
+            jobid: job.jobid,
+            step: job.step,
+            cluster: job.cluster.clone(),
+            node: hostname(),
+            user: job.user.clone(),
+            uid: job.uid,
+            partition: partition.map(str::to_string),
+            declaration: job.declaration.clone(),
+            sha256: sha256_file(&job.declaration).ok(),
+            fingerprints: fingerprints.join(","),
+            status,
+            finalized: timestamp::now(),
+        }
+    }
+}
+
+// An index of finalizations
+pub trait Catalog {
+    fn record(&self, entry: &CatalogEntry) -> Result<(), Report>;
+
+    // The finalizations matching an SQL condition, oldest first
+    fn select(&self, condition: &str) -> Result<Vec<CatalogEntry>, Report>;
+
+    // The finalizations of a job, oldest first
+    fn find(&self, jobid: u32) -> Result<Vec<CatalogEntry>, Report> {
+        self.select(&format!("WHERE jobid = {jobid}"))
+    }
+
+    fn query(&self, query: &Query) -> Result<Vec<CatalogEntry>, Report> {
+        self.select(&query.condition())
+    }
+}
+
+// Which finalizations to report on, all of them by default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    pub since: Option<DateTime<Utc>>,
+    pub user: Option<String>,
+    pub partition: Option<String>,
+}
+
+impl Query {
+    fn condition(&self) -> String {
+        let mut conditions = Vec::new();
+        // finalized is RFC 3339 in UTC, ordered as text
+        if let Some(since) = self.since {
+            conditions.push(format!(
+                "finalized >= {}",
+                quote(&timestamp::format(&since))
+            ));
+        }
+        if let Some(user) = &self.user {
+            conditions.push(format!("\"user\" = {}", quote(user)));
+        }
+        if let Some(partition) = &self.partition {
+            conditions.push(format!("partition = {}", quote(partition)));
+        }
+        match conditions.is_empty() {
+            true => String::new(),
+            false => format!("WHERE {}", conditions.join(" AND ")),
+        }
+    }
+}
+
+// A --since value: a date, a time in RFC 3339, or how long ago, e.g. 7d
+pub fn parse_since(value: &str) -> Result<DateTime<Utc>, Report> {
+    if let Ok(time) = timestamp::parse(value) {
+        return Ok(time);
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Local
+            .from_local_datetime(&date.and_time(NaiveTime::MIN))
+            .earliest()
+            .map(|time| time.with_timezone(&Utc))
+            .ok_or_else(|| eyre!("{value} has no midnight"));
+    }
+    let ago = parse_duration(value)
+        .map_err(|_| eyre!("{value} is neither a date, a time nor a duration"))?;
+    Ok(Utc::now() - chrono::Duration::from_std(ago)?)
+}
+
+// Node-local SQLite index of the TROs finalized on the node, kept with the
+// sqlite3 shell
+pub struct SqliteCatalog {
+    sqlite3: PathBuf,
+    path: PathBuf,
+}
+
+impl SqliteCatalog {
+    pub fn new(sqlite3: PathBuf, path: PathBuf) -> Self {
+        SqliteCatalog { sqlite3, path }
+    }
+
+    fn run(&self, sql: &str) -> Result<String, Report> {
+        let script = format!(".timeout {}\n{sql}\n", BUSY_TIMEOUT.as_millis());
+        let output = command::run_with_input(
+            Command::new(&self.sqlite3)
+                .args(["-batch", "-bail"])
+                .arg(&self.path),
+            Some(script.as_bytes()),
+            Some(BUSY_TIMEOUT * 2),
+        )
+        .wrap_err_with(|| format!("sqlite3 failed on {}", self.path.display()))?;
+        String::from_utf8(output.stdout).map_err(|_| eyre!("sqlite3 printed invalid UTF-8"))
+    }
+}
+
+impl Catalog for SqliteCatalog {
+    fn record(&self, entry: &CatalogEntry) -> Result<(), Report> {
+        self.run(&format!("{SCHEMA}\n{}", insert(entry)))
+            .map(|_| ())
+            .wrap_err_with(|| format!("Failed to record job {} in the catalog", entry.jobid))
+    }
+
+    fn select(&self, condition: &str) -> Result<Vec<CatalogEntry>, Report> {
+        let output = self.run(&format!(
+            "{SCHEMA}\n.mode json\nSELECT * FROM tros {condition} ORDER BY finalized;"
+        ))?;
+        // no rows, no output
+        if output.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        serde_json::from_str(&output).wrap_err("sqlite3 printed unexpected rows")
+    }
+}
+
+// Cluster-wide index in a PostgreSQL database, kept with psql. The password
+// belongs in ~/.pgpass or the configured passfile rather than the connection
+// string, which psql gets on its command line.
+pub struct PostgresCatalog {
+    psql: PathBuf,
+    conninfo: String,
+    passfile: Option<PathBuf>,
+}
+
+impl PostgresCatalog {
+    pub fn new(psql: PathBuf, conninfo: String, passfile: Option<PathBuf>) -> Self {
+        PostgresCatalog {
+            psql,
+            conninfo,
+            passfile,
+        }
+    }
+
+    fn run(&self, sql: &str) -> Result<String, Report> {
+        let mut psql = Command::new(&self.psql);
+        psql.args(["-X", "-q", "-A", "-t", "-v", "ON_ERROR_STOP=1", "-d"])
+            .arg(&self.conninfo)
+            .env("PGAPPNAME", "spank-tro")
+            .env("PGCONNECT_TIMEOUT", PSQL_TIMEOUT.as_secs().to_string());
+        if let Some(passfile) = &self.passfile {
+            psql.env("PGPASSFILE", passfile);
+        }
+        let output = command::run_with_input(&mut psql, Some(sql.as_bytes()), Some(PSQL_TIMEOUT))
+            .wrap_err("psql failed on the catalog")?;
+        String::from_utf8(output.stdout).map_err(|_| eyre!("psql printed invalid UTF-8"))
+    }
+}
+
+impl Catalog for PostgresCatalog {
+    fn record(&self, entry: &CatalogEntry) -> Result<(), Report> {
+        self.run(&format!("{SCHEMA}\n{}", insert(entry)))
+            .map(|_| ())
+            .wrap_err_with(|| format!("Failed to record job {} in the catalog", entry.jobid))
+    }
+
+    fn select(&self, condition: &str) -> Result<Vec<CatalogEntry>, Report> {
+        let output = self.run(&format!(
+            "{SCHEMA}\nSELECT coalesce(json_agg(t ORDER BY finalized), '[]') \
+             FROM (SELECT * FROM tros {condition}) t;"
+        ))?;
+        serde_json::from_str(output.trim()).wrap_err("psql printed unexpected rows")
+    }
+}
+
+// The catalog the site configured, if any
+pub fn open(settings: &Settings) -> Option<Box<dyn Catalog>> {
+    match settings.catalog.as_ref()? {
+        CatalogBackend::Sqlite(path) => Some(Box::new(SqliteCatalog::new(
+            settings.sqlite3.clone(),
+            path.clone(),
+        ))),
+        CatalogBackend::Postgres(conninfo) => Some(Box::new(PostgresCatalog::new(
+            settings.psql.clone(),
+            conninfo.clone(),
+            settings.catalog_passfile.clone(),
+        ))),
+    }
+}
+
+// Record a finalization in the catalog the site configured, if any. The TRO
+// is there whether or not it gets indexed.
+pub fn record(settings: &Settings, entry: &CatalogEntry) {
+    if settings.dry_run {
+        return;
+    }
+    let Some(catalog) = open(settings) else {
+        return;
+    };
+    if let Err(e) = catalog.record(entry) {
+        warn!("{:#}", e);
+    }
+}
+
+fn insert(entry: &CatalogEntry) -> String {
+    let optional = |value: Option<&str>| value.map_or("NULL".to_string(), quote);
+    format!(
+        "INSERT INTO tros VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {});",
+        entry.jobid,
+        entry
+            .step
+            .map_or("NULL".to_string(), |step| step.to_string()),
+        optional(entry.cluster.as_deref()),
+        optional(entry.node.as_deref()),
+        quote(&entry.user),
+        entry.uid,
+        optional(entry.partition.as_deref()),
+        quote(&entry.declaration.to_string_lossy()),
+        optional(entry.sha256.as_deref()),
+        quote(&entry.fingerprints),
+        quote(entry.status.as_str()),
+        quote(&entry.finalized),
+    )
+}
+
+pub fn hostname() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+// An SQL string literal
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
@@ -0,0 +1,213 @@
+use eyre::{eyre, Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::ffi::CString;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::job::Job;
+use crate::privilege;
+
+// The files of the workdir the job wrote, as recorded by spank-tro-watch for
+// the final arrangement
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Changes {
+    // relative to the workdir
+    pub files: BTreeSet<String>,
+    // false if the kernel dropped events, some files may be missing
+    pub complete: bool,
+}
+
+pub fn changes_path(job: &Job) -> PathBuf {
+    job.workdir
+        .join(".tro")
+        .join(format!("{}.changes.json", job.key()))
+}
+
+impl Changes {
+    // What spank-tro-watch recorded for the job, if it watched it
+    pub fn load(job: &Job) -> Result<Option<Self>, Report> {
+        let path = changes_path(job);
+        match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .map(Some)
+                .wrap_err_with(|| format!("Failed to parse {}", path.display())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).wrap_err_with(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, job: &Job) -> Result<(), Report> {
+        let path = changes_path(job);
+        let partial = path.with_extension("json.part");
+        // spank-tro-watch is root, the workdir is the user's
+        let _user = privilege::as_user(job);
+        privilege::create_nofollow(&partial)
+            .and_then(|mut file| file.write_all(&serde_json::to_vec(self)?))
+            .and_then(|_| fs::rename(&partial, &path))
+            .wrap_err_with(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+// Writes to the files of a workdir, seen through fanotify on the mount that
+// holds it. Needs CAP_SYS_ADMIN, which spank-tro-watch has from slurmstepd.
+pub struct FileWatch {
+    fd: OwnedFd,
+    workdir: PathBuf,
+    // files of the TRO itself, which tro-utils writes as the job runs: .tro
+    // and the declaration with its signatures, tro-1.jsonld, tro-1.sig, ...
+    tro_dir: PathBuf,
+    declaration: PathBuf,
+    uid: u32,
+    pub changes: Changes,
+}
+
+impl FileWatch {
+    pub fn new(job: &Job) -> Result<Self, Report> {
+        let fd = unsafe {
+            libc::fanotify_init(
+                libc::FAN_CLASS_NOTIF | libc::FAN_CLOEXEC | libc::FAN_NONBLOCK,
+                (libc::O_RDONLY | libc::O_LARGEFILE | libc::O_CLOEXEC) as u32,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).wrap_err("fanotify_init failed");
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        // the kernel reports the paths resolved
+        let workdir = fs::canonicalize(&job.workdir)
+            .wrap_err_with(|| format!("Failed to resolve {}", job.workdir.display()))?;
+        let path = CString::new(workdir.as_os_str().as_bytes())?;
+        let marked = unsafe {
+            libc::fanotify_mark(
+                fd.as_raw_fd(),
+                libc::FAN_MARK_ADD | libc::FAN_MARK_MOUNT,
+                // not FAN_MODIFY, an event for every write to the mount;
+                // files still open as the job ends are left to open_files()
+                libc::FAN_CLOSE_WRITE,
+                libc::AT_FDCWD,
+                path.as_ptr(),
+            )
+        };
+        if marked < 0 {
+            return Err(io::Error::last_os_error())
+                .wrap_err_with(|| format!("Failed to watch {}", workdir.display()));
+        }
+        let declaration = fs::canonicalize(job.declaration.parent().unwrap_or(&workdir))
+            .unwrap_or_else(|_| workdir.clone())
+            .join(job.declaration.file_stem().unwrap_or_default());
+        Ok(FileWatch {
+            fd,
+            tro_dir: workdir.join(".tro"),
+            declaration,
+            workdir,
+            uid: job.uid,
+            changes: Changes {
+                files: BTreeSet::new(),
+                complete: true,
+            },
+        })
+    }
+
+    // Take in the events queued since the previous call
+    pub fn read(&mut self) -> Result<(), Report> {
+        const METADATA_LEN: usize = std::mem::size_of::<libc::fanotify_event_metadata>();
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            };
+            if read < 0 {
+                let e = io::Error::last_os_error();
+                return match e.kind() {
+                    ErrorKind::WouldBlock | ErrorKind::Interrupted => Ok(()),
+                    _ => Err(e).wrap_err("Failed to read fanotify events"),
+                };
+            }
+            let read = read as usize;
+            if read == 0 {
+                return Ok(());
+            }
+            let mut offset = 0;
+            while offset + METADATA_LEN <= read {
+                let event: libc::fanotify_event_metadata =
+                    unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr() as *const _) };
+                if (event.event_len as usize) < METADATA_LEN {
+                    return Err(eyre!("Malformed fanotify event"));
+                }
+                offset += event.event_len as usize;
+                if event.mask & libc::FAN_Q_OVERFLOW != 0 {
+                    self.changes.complete = false;
+                }
+                if event.fd < 0 {
+                    continue;
+                }
+                // closed once the path is known
+                let file = unsafe { OwnedFd::from_raw_fd(event.fd) };
+                if let Ok(path) = fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd())) {
+                    self.record(&path);
+                }
+            }
+        }
+    }
+
+    // Take in the files the user's processes still have open for writing,
+    // which were never closed
+    pub fn open_files(&mut self) {
+        let processes = fs::read_dir("/proc").into_iter().flatten().flatten();
+        for process in processes {
+            let owned = process
+                .metadata()
+                .is_ok_and(|metadata| metadata.uid() == self.uid);
+            if !owned
+                || !process
+                    .file_name()
+                    .as_bytes()
+                    .iter()
+                    .all(u8::is_ascii_digit)
+            {
+                continue;
+            }
+            let fds = fs::read_dir(process.path().join("fd"))
+                .into_iter()
+                .flatten()
+                .flatten();
+            for fd in fds {
+                let fdinfo = process.path().join("fdinfo").join(fd.file_name());
+                let writable = fs::read_to_string(fdinfo).is_ok_and(|fdinfo| {
+                    fdinfo
+                        .lines()
+                        .find_map(|line| line.strip_prefix("flags:"))
+                        .and_then(|flags| i32::from_str_radix(flags.trim(), 8).ok())
+                        .is_some_and(|flags| flags & libc::O_ACCMODE != libc::O_RDONLY)
+                });
+                if let (true, Ok(path)) = (writable, fs::read_link(fd.path())) {
+                    self.record(&path);
+                }
+            }
+        }
+    }
+
+    fn record(&mut self, path: &Path) {
+        let Ok(relative) = path.strip_prefix(&self.workdir) else {
+            return;
+        };
+        let name = path.file_name().unwrap_or_default().as_bytes();
+        let stem = self.declaration.file_name().unwrap_or_default().as_bytes();
+        if path.starts_with(&self.tro_dir)
+            || path.parent() == self.declaration.parent() && name.starts_with(stem)
+        {
+            return;
+        }
+        let relative = relative.to_string_lossy().into_owned();
+        self.changes.files.insert(relative);
+    }
+}
@@ -0,0 +1,138 @@
+use eyre::{eyre, Report, WrapErr};
+use std::io::{self, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const STOP_GRACE: Duration = Duration::from_secs(5);
+
+// Start a command in its own session, detached from the caller, without
+// waiting for it. Returns its pid.
+pub fn spawn_detached(command: &mut Command) -> io::Result<u32> {
+    spawn_detached_to(command, Stdio::null())
+}
+
+// Same, with its output going to `stdout`
+pub fn spawn_detached_to(command: &mut Command, stdout: impl Into<Stdio>) -> io::Result<u32> {
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    command
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .stderr(Stdio::null())
+        .spawn()
+        .map(|child| child.id())
+}
+
+// Stop a helper started with spawn_detached, giving it a moment to finish what
+// it is writing before it gets killed
+pub fn stop(pid: u32) {
+    let pid = pid as libc::pid_t;
+    unsafe {
+        libc::kill(pid, libc::SIGINT);
+    }
+    let deadline = Instant::now() + STOP_GRACE;
+    while Instant::now() < deadline {
+        if unsafe { libc::waitpid(pid, std::ptr::null_mut(), libc::WNOHANG) } != 0 {
+            return;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    debug!("{} did not stop in time, killing it", pid);
+    unsafe {
+        libc::kill(pid, libc::SIGKILL);
+        libc::waitpid(pid, std::ptr::null_mut(), 0);
+    }
+}
+
+// Run an external command to completion, killing it (and everything it spawned)
+// if it is still running after `timeout`. A non-zero exit status is an error.
+pub fn run(command: &mut Command, timeout: Option<Duration>) -> Result<Output, Report> {
+    run_with_input(command, None, timeout)
+}
+
+// Like `run`, with `input` written to the command's stdin
+pub fn run_with_input(
+    command: &mut Command,
+    input: Option<&[u8]>,
+    timeout: Option<Duration>,
+) -> Result<Output, Report> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let stdin = match input {
+        Some(_) => Stdio::piped(),
+        None => Stdio::null(),
+    };
+    let mut child = command
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .wrap_err_with(|| format!("Failed to start {program}"))?;
+
+    // feed and drain the pipes while waiting so the child cannot block on them
+    if let (Some(mut pipe), Some(input)) = (child.stdin.take(), input) {
+        let input = input.to_vec();
+        thread::spawn(move || pipe.write_all(&input));
+    }
+    let stdout = child.stdout.take().map(|mut pipe| {
+        thread::spawn(move || {
+            let mut buffer = Vec::new();
+            let _ = pipe.read_to_end(&mut buffer);
+            buffer
+        })
+    });
+    let stderr = child.stderr.take().map(|mut pipe| {
+        thread::spawn(move || {
+            let mut buffer = Vec::new();
+            let _ = pipe.read_to_end(&mut buffer);
+            buffer
+        })
+    });
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let status = loop {
+        if let Some(status) = child
+            .try_wait()
+            .wrap_err_with(|| format!("Failed to wait for {program}"))?
+        {
+            break status;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            unsafe {
+                libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+            }
+            let _ = child.wait();
+            return Err(eyre!(
+                "{program} did not finish within {}s and was killed",
+                timeout.unwrap_or_default().as_secs()
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    let output = Output {
+        status,
+        stdout: stdout
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default(),
+        stderr: stderr
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default(),
+    };
+    match output.status.success() {
+        true => Ok(output),
+        false => Err(eyre!(
+            "{program} failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
@@ -0,0 +1,161 @@
+// Differences between two declarations, e.g. two runs of the same pipeline:
+// the files of their last arrangements, the programs and libraries their
+// performances ran with, and how those performances went
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use crate::declaration::{term, Declaration};
+use crate::diff::Diff;
+use crate::report::Row;
+
+// Run times closer than this fraction of the longer one are the same
+const RUN_TIME_TOLERANCE: f64 = 0.1;
+
+// Performance terms compared as they are
+const ENVIRONMENT: &[&str] = &["preload", "commands"];
+const PERFORMANCE: &[&str] = &["succeeded", "tasks", "attempt"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aspect {
+    Artifact,
+    Environment,
+    Performance,
+}
+
+impl Aspect {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Aspect::Artifact => "artifact",
+            Aspect::Environment => "environment",
+            Aspect::Performance => "performance",
+        }
+    }
+}
+
+// A value that differs, None where one side does not have it
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Difference {
+    pub aspect: Aspect,
+    pub key: String,
+    pub left: Option<Value>,
+    pub right: Option<Value>,
+}
+
+impl Row for Difference {
+    const HEADERS: &'static [&'static str] = &["ASPECT", "KEY", "LEFT", "RIGHT"];
+
+    fn cells(&self) -> Vec<String> {
+        let cell = |value: &Option<Value>| match value {
+            None => "-".to_string(),
+            Some(Value::String(value)) => value.clone(),
+            Some(value) => value.to_string(),
+        };
+        vec![
+            self.aspect.as_str().to_string(),
+            self.key.clone(),
+            cell(&self.left),
+            cell(&self.right),
+        ]
+    }
+}
+
+pub fn compare(left: &Declaration, right: &Declaration) -> Vec<Difference> {
+    let mut differences = artifacts(left, right);
+    differences.extend(executables(left, right));
+    for (aspect, keys) in [
+        (Aspect::Environment, ENVIRONMENT),
+        (Aspect::Performance, PERFORMANCE),
+    ] {
+        for key in keys {
+            let left = left.last_performance_term(key);
+            let right = right.last_performance_term(key);
+            if left != right {
+                differences.push(Difference {
+                    aspect,
+                    key: key.to_string(),
+                    left: left.cloned(),
+                    right: right.cloned(),
+                });
+            }
+        }
+    }
+    differences.extend(run_time(left, right));
+    differences
+}
+
+fn artifacts(left: &Declaration, right: &Declaration) -> Vec<Difference> {
+    let (left, right) = (left.last_locations(), right.last_locations());
+    let diff = Diff::between(&left, &right);
+    let left: BTreeMap<String, String> = left.into_iter().collect();
+    let right: BTreeMap<String, String> = right.into_iter().collect();
+    let mut locations: Vec<String> = diff
+        .added
+        .into_iter()
+        .chain(diff.removed)
+        .chain(diff.modified)
+        .collect();
+    locations.sort();
+    locations
+        .into_iter()
+        .map(|location| Difference {
+            aspect: Aspect::Artifact,
+            left: left.get(&location).map(|sha256| json!(sha256)),
+            right: right.get(&location).map(|sha256| json!(sha256)),
+            key: location,
+        })
+        .collect()
+}
+
+// Programs by path, with their SHA-256 when it could be taken
+fn executables(left: &Declaration, right: &Declaration) -> Vec<Difference> {
+    let by_path = |declaration: &Declaration| -> BTreeMap<String, Value> {
+        declaration
+            .last_performance_term("executables")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|executable| {
+                let path = executable[term("path")].as_str()?;
+                Some((path.to_string(), executable[term("sha256")].clone()))
+            })
+            .collect()
+    };
+    let (left, right) = (by_path(left), by_path(right));
+    let mut paths: Vec<&String> = left.keys().chain(right.keys()).collect();
+    paths.sort();
+    paths.dedup();
+    paths
+        .into_iter()
+        .filter(|path| left.get(*path) != right.get(*path))
+        .map(|path| Difference {
+            aspect: Aspect::Environment,
+            key: format!("executable {path}"),
+            left: left.get(path).cloned(),
+            right: right.get(path).cloned(),
+        })
+        .collect()
+}
+
+fn run_time(left: &Declaration, right: &Declaration) -> Option<Difference> {
+    let seconds = |declaration: &Declaration| {
+        declaration
+            .last_performance_term("timeline")
+            .and_then(|timeline| timeline[term("runSeconds")].as_i64())
+    };
+    let (left, right) = (seconds(left), seconds(right));
+    let similar = match (left, right) {
+        (Some(left), Some(right)) => {
+            let longer = left.max(right).max(1) as f64;
+            ((left - right).abs() as f64) / longer <= RUN_TIME_TOLERANCE
+        }
+        (left, right) => left == right,
+    };
+    (!similar).then(|| Difference {
+        aspect: Aspect::Performance,
+        key: "runSeconds".to_string(),
+        left: left.map(Value::from),
+        right: right.map(Value::from),
+    })
+}
@@ -0,0 +1,350 @@
+use eyre::{eyre, Report, WrapErr};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::finalize::FinalizeMode;
+use crate::policy::Policy;
+use crate::roots::{self, Root};
+use crate::settings::{parse_bool, parse_list, parse_size, Settings};
+use crate::xalt::{self, PreloadPolicy};
+
+// Everything the site configures, from the plugin arguments in plugstack.conf
+// and the file they may point to with config=
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub settings: Settings,
+    pub policy: Policy,
+    pub xalt_dir: PathBuf,
+    // candidates for the XALT preload library, relative to xalt_dir
+    pub xalt_preload: Vec<String>,
+    // where it goes in the job's LD_PRELOAD, and the libraries it conflicts
+    // with
+    pub preload_policy: PreloadPolicy,
+    pub preload_conflicts: Vec<String>,
+    // per-job log in <workdir>/.tro/<jobid>.log
+    pub job_log: bool,
+    pub job_log_max_size: u64,
+    pub job_log_keep: usize,
+    pub finalize: FinalizeMode,
+    pub spool_dir: PathBuf,
+    // where TROs go when neither SLURM_SUBMIT_DIR nor slurmctld tell the
+    // job's working directory
+    pub fallback_workdir: Option<PathBuf>,
+    pub finalizer: PathBuf,
+    // launches the tasks with tracker=wrapper
+    pub wrapper: PathBuf,
+    // records arrangements while the job runs, e.g. with checkpoints=yes
+    pub watcher: PathBuf,
+    // an arrangement/performance pair per step rather than per job
+    pub per_step: bool,
+    // generate TROs without --generate-tro, on default_partitions if any
+    pub default_generate: bool,
+    pub default_partitions: Vec<String>,
+    // where the plugin is active at all, everywhere when empty
+    pub partitions: Vec<String>,
+    pub clusters: Vec<String>,
+    // file name of the declarations, see job::declaration_name
+    pub tro_name_template: String,
+    // where declarations go rather than the submission directory, e.g.
+    // /archive/%u, and where jobs run rather than there, `auto` for the
+    // working directory they are started in. Both expand like
+    // tro_name_template.
+    pub declaration_dir: Option<String>,
+    pub exec_dir: Option<String>,
+    // label=path directories the arrangements of every job cover besides its
+    // workdir, paths expanding like tro_name_template
+    pub roots: Vec<Root>,
+    // variables set in the environment of the jobs generating a TRO, and in
+    // the plugin's own, e.g. for the programs it calls
+    pub job_env: Vec<(String, String)>,
+    pub plugin_env: Vec<(String, String)>,
+    // SLURM_VERSION_NUMBER of the Slurm the plugin was built against, set by
+    // the plugin rather than configured
+    pub slurm_version: Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            settings: Settings::default(),
+            policy: Policy::default(),
+            xalt_dir: PathBuf::new(),
+            xalt_preload: vec![
+                "lib64/libxalt_init.so".to_string(),
+                "lib/libxalt_init.so".to_string(),
+            ],
+            preload_policy: PreloadPolicy::default(),
+            preload_conflicts: xalt::default_conflicts(),
+            job_log: false,
+            job_log_max_size: 10 * 1024 * 1024,
+            job_log_keep: 3,
+            finalize: FinalizeMode::Sync,
+            spool_dir: PathBuf::from("/var/spool/spank-tro"),
+            fallback_workdir: None,
+            finalizer: PathBuf::from("/usr/libexec/spank-tro-finalize"),
+            wrapper: PathBuf::from("/usr/libexec/spank-tro-wrap"),
+            watcher: PathBuf::from("/usr/libexec/spank-tro-watch"),
+            per_step: false,
+            default_generate: false,
+            default_partitions: Vec::new(),
+            partitions: Vec::new(),
+            clusters: Vec::new(),
+            tro_name_template: "tro-%j.jsonld".to_string(),
+            declaration_dir: None,
+            exec_dir: None,
+            roots: Vec::new(),
+            job_env: Vec::new(),
+            plugin_env: Vec::new(),
+            slurm_version: None,
+        }
+    }
+}
+
+impl Config {
+    // Build the configuration from `key=value` plugin arguments. Settings of
+    // the file given with config= take precedence.
+    pub fn from_args<'a>(args: impl IntoIterator<Item = &'a str>) -> Result<Self, Report> {
+        let mut config = Config::default();
+        let mut config_file = None;
+        for arg in args {
+            let Some((key, value)) = arg.split_once('=') else {
+                continue;
+            };
+            match key {
+                "config" => config_file = Some(PathBuf::from(value)),
+                _ => config.set(key, value)?,
+            }
+        }
+        if let Some(path) = config_file {
+            let file = ConfigFile::load(&path)?;
+            for (key, value) in file.entries() {
+                config
+                    .set(key, value)
+                    .wrap_err_with(|| format!("In {}", path.display()))?;
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Report> {
+        if self
+            .settings
+            .set(key, value)
+            .wrap_err_with(|| format!("Invalid {key}"))?
+            || self.policy.set(key, value)?
+        {
+            return Ok(());
+        }
+        match key {
+            "xalt_dir" => {
+                self.xalt_dir = parse_xalt_dir(value).wrap_err("Invalid xalt_dir")?;
+            }
+            "xalt_preload" => {
+                self.xalt_preload = parse_list(value);
+            }
+            "preload_policy" => {
+                self.preload_policy = value.parse().wrap_err("Invalid preload_policy")?;
+            }
+            "preload_conflicts" => {
+                self.preload_conflicts = parse_list(value);
+            }
+            "job_log" => {
+                self.job_log = parse_bool(value).wrap_err("Invalid job_log")?;
+            }
+            "job_log_max_size" => {
+                self.job_log_max_size = parse_size(value).wrap_err("Invalid job_log_max_size")?;
+            }
+            "job_log_keep" => {
+                self.job_log_keep = value.parse().wrap_err("Invalid job_log_keep")?;
+            }
+            "finalize" => {
+                self.finalize = value.parse().wrap_err("Invalid finalize")?;
+            }
+            "spool_dir" => {
+                self.spool_dir = PathBuf::from(value);
+            }
+            "fallback_workdir" => {
+                if !value.starts_with('/') {
+                    return Err(eyre!("Invalid fallback_workdir: {value} is not absolute"));
+                }
+                self.fallback_workdir = Some(PathBuf::from(value));
+            }
+            "finalizer" => {
+                self.finalizer = PathBuf::from(value);
+            }
+            "wrapper" => {
+                self.wrapper = PathBuf::from(value);
+            }
+            "watcher" => {
+                self.watcher = PathBuf::from(value);
+            }
+            "default_generate" => {
+                self.default_generate = parse_bool(value).wrap_err("Invalid default_generate")?;
+            }
+            "default_partitions" => {
+                self.default_partitions = parse_list(value);
+            }
+            "partitions" => {
+                self.partitions = parse_list(value);
+            }
+            "clusters" => {
+                self.clusters = parse_list(value);
+            }
+            "tro_name_template" => {
+                if value.is_empty() {
+                    return Err(eyre!("Invalid tro_name_template: it is empty"));
+                }
+                self.tro_name_template = value.to_string();
+            }
+            "declaration_dir" => {
+                if !value.starts_with('/') {
+                    return Err(eyre!("Invalid declaration_dir: {value} is not absolute"));
+                }
+                self.declaration_dir = Some(value.to_string());
+            }
+            "exec_dir" => {
+                if value != "auto" && !value.starts_with('/') {
+                    return Err(eyre!("Invalid exec_dir: {value} is not absolute"));
+                }
+                self.exec_dir = Some(value.to_string());
+            }
+            "roots" => {
+                self.roots = roots::parse(value).wrap_err("Invalid roots")?;
+            }
+            "job_env" => {
+                self.job_env = parse_env(value).wrap_err("Invalid job_env")?;
+            }
+            "plugin_env" => {
+                self.plugin_env = parse_env(value).wrap_err("Invalid plugin_env")?;
+            }
+            "granularity" => {
+                self.per_step = match value {
+                    "job" => false,
+                    "step" => true,
+                    _ => return Err(eyre!("Invalid granularity: {value}")),
+                };
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Whether a job in these (comma-separated) partitions and cluster is in the
+    // scope of partitions= and clusters=. At submission the partition may not
+    // be known yet, the job is then assumed to be in scope.
+    pub fn in_scope(&self, partitions: Option<&str>, cluster: Option<&str>) -> bool {
+        matches_any(&self.partitions, partitions) && matches_any(&self.clusters, cluster)
+    }
+
+    // Whether default_generate=yes applies to a job in these partitions
+    pub fn generated_by_default(&self, partitions: Option<&str>) -> bool {
+        match (self.default_generate, self.default_partitions.is_empty()) {
+            (false, _) => false,
+            (true, true) => true,
+            (true, false) => {
+                partitions.is_some() && matches_any(&self.default_partitions, partitions)
+            }
+        }
+    }
+}
+
+// NAME=value pairs separated by commas, e.g. job_env=OMP_PROC_BIND=true
+pub fn parse_env(value: &str) -> Result<Vec<(String, String)>, Report> {
+    let mut env: Vec<(String, String)> = Vec::new();
+    for item in parse_list(value) {
+        let (name, value) = item
+            .split_once('=')
+            .ok_or_else(|| eyre!("{item} is not NAME=value"))?;
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(eyre!("{name} is not a valid variable name"));
+        }
+        if env.iter().any(|(set, _)| set == name) {
+            return Err(eyre!("{name} is set twice"));
+        }
+        env.push((name.to_string(), value.to_string()));
+    }
+    Ok(env)
+}
+
+// Whether any of the comma-separated `values` is listed, an empty list or
+// unknown values matching everything
+fn matches_any(list: &[String], values: Option<&str>) -> bool {
+    match values {
+        _ if list.is_empty() => true,
+        Some(values) => values
+            .split(',')
+            .any(|value| list.iter().any(|item| item == value)),
+        None => true,
+    }
+}
+
+fn parse_xalt_dir(value: &str) -> Result<PathBuf, Report> {
+    let xalt_dir: PathBuf = PathBuf::from(value);
+    match xalt_dir.is_dir() {
+        true => Ok(xalt_dir),
+        _ => Err(eyre!("xalt_dir={value} is not a valid directory")),
+    }
+}
+
+// Settings kept in a file rather than in plugstack.conf, one `key=value` per
+// line like the plugin arguments, with # comments. slurmstepd loads the plugin
+// for every step, so edits apply to the next job without restarting slurmd;
+// long-running processes use `refresh` to pick them up.
+#[derive(Debug)]
+pub struct ConfigFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    entries: Vec<(String, String)>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self, Report> {
+        let mut config = ConfigFile {
+            path: path.to_path_buf(),
+            modified: None,
+            entries: Vec::new(),
+        };
+        config.refresh()?;
+        Ok(config)
+    }
+
+    // Re-read the file if it changed since it was last read, returns whether it did
+    pub fn refresh(&mut self) -> Result<bool, Report> {
+        let modified = fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .wrap_err_with(|| format!("Failed to stat {}", self.path.display()))?;
+        if self.modified == Some(modified) {
+            return Ok(false);
+        }
+        let content = fs::read_to_string(&self.path)
+            .wrap_err_with(|| format!("Failed to read {}", self.path.display()))?;
+        self.entries = parse(&content)
+            .wrap_err_with(|| format!("Invalid configuration {}", self.path.display()))?;
+        self.modified = Some(modified);
+        Ok(true)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+fn parse(content: &str) -> Result<Vec<(String, String)>, Report> {
+    let mut entries = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| eyre!("line {}: expected key=value", number + 1))?;
+        entries.push((key.trim().to_string(), value.trim().to_string()));
+    }
+    Ok(entries)
+}
@@ -0,0 +1,497 @@
+use eyre::{eyre, Report, WrapErr};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+use crate::dependency;
+use crate::envspec::Environment;
+use crate::het::HetComponent;
+use crate::inputs;
+use crate::job::{declaration_path, expand_dir, Job, NameFields, WorkdirSource};
+use crate::policy::{Decision, Subject};
+use crate::privilege;
+use crate::roots::{self, Root};
+use crate::scontrol;
+use crate::settings::parse_list;
+use crate::tracker::{self, Tracker, TRACE_ENV};
+use crate::watch::{self, CHECKPOINT_ENV};
+use crate::workflow::Workflow;
+use crate::xalt;
+
+pub const GENERATE_OPTION: &str = "generate-tro";
+pub const NO_GENERATE_OPTION: &str = "no-generate-tro";
+// srun --tro-snapshot=<label> within a job records an arrangement
+pub const SNAPSHOT_OPTION: &str = "tro-snapshot";
+// --tro-input=<path|URI|DOI>[,...] declares inputs outside the workdir
+pub const INPUT_OPTION: &str = "tro-input";
+// how XALT records the job's programs, see xalt::Behavior
+pub const XALT_TRACING_OPTION: &str = "tro-xalt-tracing";
+pub const XALT_SAMPLING_OPTION: &str = "tro-xalt-sampling";
+pub const TRACK_GPU_OPTION: &str = "tro-track-gpu";
+// --tro-exec-dir=<dir> is where the job runs, e.g. in $SCRATCH, rather than
+// where it was submitted
+pub const EXEC_DIR_OPTION: &str = "tro-exec-dir";
+// --tro-root=<label>=<dir>[,...] adds directories to the arrangements
+pub const ROOT_OPTION: &str = "tro-root";
+// --tro-quiet leaves out the messages telling how the TRO is coming along
+pub const QUIET_OPTION: &str = "tro-quiet";
+
+// Where the plugin runs: srun, sbatch/salloc or slurmstepd
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+    Local,
+    Allocator,
+    Remote,
+    Other,
+}
+
+// What the plugin needs from the SPANK handle, implemented by the plugin on
+// top of slurm-spank and by MockContext to exercise the hooks without Slurm
+pub trait JobContext {
+    fn context(&self) -> Result<Context, Report>;
+    fn getenv(&self, name: &str) -> Result<Option<String>, Report>;
+    // Set a variable of the job's tasks
+    fn setenv(&mut self, name: &str, value: &str) -> Result<(), Report>;
+    fn job_id(&self) -> Result<u32, Report>;
+    fn job_uid(&self) -> Result<u32, Report>;
+    fn job_gid(&self) -> Result<u32, Report>;
+    fn job_stepid(&self) -> Result<u32, Report>;
+    fn is_option_set(&self, name: &str) -> bool;
+    fn option_value(&self, name: &str) -> Result<Option<String>, Report>;
+    // Run the task's command through `argv`, in the task_init hook
+    fn prepend_task_argv(&mut self, argv: &[&str]) -> Result<(), Report>;
+}
+
+// A job context made up by hand
+#[derive(Debug, Clone)]
+pub struct MockContext {
+    pub context: Context,
+    pub env: HashMap<String, String>,
+    pub options: HashSet<String>,
+    pub option_values: HashMap<String, String>,
+    pub job_id: Option<u32>,
+    pub job_uid: u32,
+    pub job_gid: u32,
+    pub job_stepid: u32,
+    pub task_argv: Vec<String>,
+}
+
+impl MockContext {
+    pub fn new(context: Context) -> Self {
+        MockContext {
+            context,
+            env: HashMap::new(),
+            options: HashSet::new(),
+            option_values: HashMap::new(),
+            job_id: None,
+            job_uid: 1000,
+            job_gid: 1000,
+            job_stepid: 0,
+            task_argv: Vec::new(),
+        }
+    }
+
+    pub fn with_env(mut self, name: &str, value: &str) -> Self {
+        self.env.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_option(mut self, name: &str) -> Self {
+        self.options.insert(name.to_string());
+        self
+    }
+
+    pub fn with_option_value(mut self, name: &str, value: &str) -> Self {
+        self.options.insert(name.to_string());
+        self.option_values
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_job(mut self, jobid: u32) -> Self {
+        self.job_id = Some(jobid);
+        self
+    }
+}
+
+impl JobContext for MockContext {
+    fn context(&self) -> Result<Context, Report> {
+        Ok(self.context)
+    }
+
+    fn getenv(&self, name: &str) -> Result<Option<String>, Report> {
+        Ok(self.env.get(name).cloned())
+    }
+
+    fn setenv(&mut self, name: &str, value: &str) -> Result<(), Report> {
+        self.env.insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn job_id(&self) -> Result<u32, Report> {
+        self.job_id.ok_or_else(|| eyre!("No job in this context"))
+    }
+
+    fn job_uid(&self) -> Result<u32, Report> {
+        Ok(self.job_uid)
+    }
+
+    fn job_gid(&self) -> Result<u32, Report> {
+        Ok(self.job_gid)
+    }
+
+    fn job_stepid(&self) -> Result<u32, Report> {
+        Ok(self.job_stepid)
+    }
+
+    fn is_option_set(&self, name: &str) -> bool {
+        self.options.contains(name)
+    }
+
+    fn option_value(&self, name: &str) -> Result<Option<String>, Report> {
+        Ok(self.option_values.get(name).cloned())
+    }
+
+    fn prepend_task_argv(&mut self, argv: &[&str]) -> Result<(), Report> {
+        let argv = argv.iter().map(|arg| arg.to_string());
+        self.task_argv.splice(0..0, argv);
+        Ok(())
+    }
+}
+
+// Whether the job runs where the plugin is enabled
+pub fn enabled(config: &Config, ctx: &impl JobContext) -> Result<bool, Report> {
+    Ok(config.in_scope(partitions(ctx)?.as_deref(), cluster(ctx)?.as_deref()))
+}
+
+// Whether to generate a TRO for the job, given the options, the site defaults
+// and the policy. The user hears about requests that are turned down.
+pub fn generate(
+    config: &Config,
+    ctx: &impl JobContext,
+    enabled: bool,
+    in_session: bool,
+    current_user: Option<String>,
+    notify: &dyn Fn(&str),
+) -> Result<bool, Report> {
+    let explicit = ctx.is_option_set(GENERATE_OPTION);
+    let requested = explicit || config.generated_by_default(partitions(ctx)?.as_deref());
+    let opted_out = ctx.is_option_set(NO_GENERATE_OPTION);
+    if !enabled {
+        if explicit {
+            notify("spank-tro: TROs are not generated on this partition or cluster");
+        }
+        return Ok(false);
+    }
+    Ok(match config.policy.evaluate(&subject(ctx, current_user)?) {
+        Decision::Denied(reason) => {
+            if explicit {
+                notify(&format!("spank-tro: no TRO will be generated, {reason}"));
+            }
+            false
+        }
+        Decision::Forced => {
+            if opted_out {
+                notify("spank-tro: the site requires a TRO for this job");
+            }
+            !in_session
+        }
+        Decision::Allowed => requested && !opted_out && !in_session,
+    })
+}
+
+// The job as seen from slurmstepd, with its TRO in the submission directory
+pub fn job(config: &Config, ctx: &impl JobContext) -> Result<Job, Report> {
+    let jobid = ctx.job_id()?;
+    let (submit_dir, workdir_source) = workdir(config, ctx, jobid)?;
+    let het = HetComponent::from_env(
+        jobid,
+        ctx.getenv("SLURM_HET_SIZE")?.as_deref(),
+        ctx.getenv("SLURM_JOB_ID_HET_GROUP_0")?.as_deref(),
+    )?;
+    let step = match config.per_step {
+        true => Some(ctx.job_stepid()?),
+        false => None,
+    };
+    let mut job = Job::new(
+        jobid,
+        (ctx.job_uid()?, ctx.job_gid()?),
+        ctx.getenv("SLURM_JOB_USER")?.unwrap_or_default(),
+        submit_dir.clone(),
+        het,
+        step,
+    );
+    job.workdir_source = workdir_source;
+    let array = match (
+        ctx.getenv("SLURM_ARRAY_JOB_ID")?,
+        ctx.getenv("SLURM_ARRAY_TASK_ID")?,
+    ) {
+        (Some(array_job), Some(task)) => Some((
+            array_job
+                .parse()
+                .wrap_err_with(|| format!("Invalid SLURM_ARRAY_JOB_ID {array_job}"))?,
+            task.parse()
+                .wrap_err_with(|| format!("Invalid SLURM_ARRAY_TASK_ID {task}"))?,
+        )),
+        _ => None,
+    };
+    if let Some(restart) = ctx.getenv("SLURM_RESTART_COUNT")? {
+        job.restart = restart
+            .parse()
+            .wrap_err_with(|| format!("Invalid SLURM_RESTART_COUNT {restart}"))?;
+    }
+    if let Some(inputs) = ctx.option_value(INPUT_OPTION)? {
+        inputs::parse(&inputs).wrap_err_with(|| format!("Invalid --{INPUT_OPTION}"))?;
+        job.inputs = parse_list(&inputs);
+    }
+    if let Some(start) = ctx.getenv("SLURM_JOB_START_TIME")? {
+        job.scheduled_start = Some(
+            start
+                .parse()
+                .wrap_err_with(|| format!("Invalid SLURM_JOB_START_TIME {start}"))?,
+        );
+    }
+    if let Some(dependency) = ctx.getenv("SLURM_JOB_DEPENDENCY")? {
+        dependency::parse(&dependency)
+            .wrap_err_with(|| format!("Invalid SLURM_JOB_DEPENDENCY {dependency}"))?;
+        job.dependency = Some(dependency);
+    }
+    job.cluster = cluster(ctx)?;
+    let job_name = ctx.getenv("SLURM_JOB_NAME")?.unwrap_or_default();
+    let fields = NameFields {
+        // het components share the TRO of their leader
+        jobid: het.map_or(jobid, |het| het.leader),
+        user: &job.user,
+        job_name: &job_name,
+        array,
+        start: job.scheduled_start,
+    };
+    job.declaration = declaration_path(config, &fields, &submit_dir);
+    if let Some(exec_dir) = exec_dir(config, ctx, &fields)? {
+        job.workdir = exec_dir;
+        job.workdir_source = WorkdirSource::Exec;
+    }
+    job.roots = config
+        .roots
+        .iter()
+        .map(|root| Root {
+            label: root.label.clone(),
+            path: expand_dir(&root.path.to_string_lossy(), &fields),
+        })
+        .collect();
+    if let Some(value) = ctx.option_value(ROOT_OPTION)? {
+        for root in roots::parse(&value).wrap_err_with(|| format!("Invalid --{ROOT_OPTION}"))? {
+            if job.roots.iter().any(|other| other.label == root.label) {
+                return Err(eyre!(
+                    "Invalid --{ROOT_OPTION}: {} is already a root",
+                    root.label
+                ));
+            }
+            job.roots.push(root);
+        }
+    }
+    job.quiet = ctx.is_option_set(QUIET_OPTION);
+    if let Some(node_id) = ctx.getenv("SLURM_NODEID")? {
+        job.node_id = node_id
+            .parse()
+            .wrap_err_with(|| format!("Invalid SLURM_NODEID {node_id}"))?;
+    }
+    if let Some(nodes) = first_env(ctx, &["SLURM_JOB_NUM_NODES", "SLURM_NNODES"])? {
+        job.node_count = nodes
+            .parse()
+            .wrap_err_with(|| format!("Invalid SLURM_JOB_NUM_NODES {nodes}"))?;
+    }
+    if config.settings.capture_environment {
+        job.environments = Environment::detect(ctx)?;
+    }
+    if config.settings.aggregate_workflows {
+        job.workflow = Workflow::detect(ctx, &job.workdir)?;
+    }
+    Ok(job)
+}
+
+// SLURM_SUBMIT_DIR, missing from the environment of some steps, then the
+// job's WorkDir as slurmctld knows it, then the site's fallback_workdir
+fn workdir(
+    config: &Config,
+    ctx: &impl JobContext,
+    jobid: u32,
+) -> Result<(PathBuf, WorkdirSource), Report> {
+    if let Some(workdir) = ctx
+        .getenv("SLURM_SUBMIT_DIR")?
+        .filter(|dir| !dir.is_empty())
+    {
+        return Ok((PathBuf::from(workdir), WorkdirSource::Submit));
+    }
+    match scontrol::show_job(&config.settings, jobid) {
+        Ok(info) => {
+            if let Some(workdir) = info.get("WorkDir").filter(|dir| dir.starts_with('/')) {
+                return Ok((PathBuf::from(workdir), WorkdirSource::Slurmctld));
+            }
+        }
+        Err(e) => debug!("{:#}", e),
+    }
+    let fallback = config.fallback_workdir.clone().ok_or_else(|| {
+        eyre!("SLURM_SUBMIT_DIR is not set and slurmctld did not tell the job's WorkDir")
+    })?;
+    warn!(
+        "No working directory known for job {jobid}, its TRO goes to {}",
+        fallback.display()
+    );
+    Ok((fallback, WorkdirSource::Fallback))
+}
+
+// Where the job runs and its files are fingerprinted when it is not where its
+// TRO goes: --tro-exec-dir, or the site's exec_dir=, `auto` for the working
+// directory slurmctld started it in
+fn exec_dir(
+    config: &Config,
+    ctx: &impl JobContext,
+    fields: &NameFields,
+) -> Result<Option<PathBuf>, Report> {
+    let exec_dir = match (ctx.option_value(EXEC_DIR_OPTION)?, &config.exec_dir) {
+        (Some(dir), _) => PathBuf::from(dir),
+        (None, Some(auto)) if auto == "auto" => {
+            let info = scontrol::show_job(&config.settings, fields.jobid)?;
+            match info.get("WorkDir") {
+                Some(dir) => PathBuf::from(dir),
+                None => return Ok(None),
+            }
+        }
+        (None, Some(template)) => expand_dir(template, fields),
+        (None, None) => return Ok(None),
+    };
+    if !exec_dir.is_absolute() {
+        return Err(eyre!(
+            "Invalid --{EXEC_DIR_OPTION}: {} is not absolute",
+            exec_dir.display()
+        ));
+    }
+    Ok(Some(exec_dir))
+}
+
+// Set up the environment of the job's tasks: the site's job_env, and what the
+// tracker needs to record their programs
+pub fn track(
+    config: &Config,
+    ctx: &mut impl JobContext,
+    job: &mut Job,
+    user: &str,
+) -> Result<(), Report> {
+    let environment = match config.settings.tracker {
+        Tracker::Xalt => {
+            // rather than preloading a missing file into every task
+            let preloader = xalt::preload_library(&config.xalt_dir, &config.xalt_preload)?;
+            let record_dir = xalt::record_dir(job);
+            // XALT falls back on the home directory when it can't write there
+            let user_fs = privilege::as_user(job);
+            let created = fs::create_dir_all(&record_dir);
+            drop(user_fs);
+            if let Err(e) = created {
+                warn!("Failed to create {}: {}", record_dir.display(), e);
+            }
+            let ld_preload = xalt::preload_chain(
+                &preloader,
+                ctx.getenv("LD_PRELOAD")?.as_deref(),
+                config.preload_policy,
+                &config.preload_conflicts,
+            )?;
+            job.preload = Some(ld_preload.clone());
+            xalt::environment(
+                &config.xalt_dir,
+                ld_preload,
+                user,
+                &record_dir,
+                xalt_behavior(ctx)?,
+            )
+        }
+        Tracker::None | Tracker::Ebpf | Tracker::Strace => Vec::new(),
+        Tracker::Wrapper => vec![(
+            TRACE_ENV,
+            tracker::trace_path(job).to_string_lossy().into_owned(),
+        )],
+    };
+    // the site's first, the tracker depends on its own
+    for (name, value) in &config.job_env {
+        info!("Setting {name}={value} in the job's environment");
+        ctx.setenv(name, value)?;
+    }
+    for (name, value) in environment {
+        ctx.setenv(name, &value)?;
+    }
+    if config.settings.checkpoints {
+        let checkpoints = watch::checkpoint_path(job);
+        ctx.setenv(CHECKPOINT_ENV, &checkpoints.to_string_lossy())?;
+    }
+    Ok(())
+}
+
+// What the user asked of XALT
+pub fn xalt_behavior(ctx: &impl JobContext) -> Result<xalt::Behavior, Report> {
+    let sampling = ctx
+        .option_value(XALT_SAMPLING_OPTION)?
+        .map(|value| xalt::parse_switch(&value))
+        .transpose()
+        .wrap_err_with(|| format!("Invalid --{XALT_SAMPLING_OPTION}"))?;
+    Ok(xalt::Behavior {
+        tracing: ctx.is_option_set(XALT_TRACING_OPTION),
+        sampling,
+        gpu_tracking: ctx.is_option_set(TRACK_GPU_OPTION),
+    })
+}
+
+// Launch a task through spank-tro-wrap or strace for the trackers that need it
+pub fn wrap(config: &Config, ctx: &mut impl JobContext, job: &Job) -> Result<(), Report> {
+    let argv = match config.settings.tracker {
+        Tracker::Wrapper => vec![config.wrapper.to_string_lossy().into_owned()],
+        Tracker::Strace => tracker::strace_argv(&config.settings, job)?,
+        Tracker::Xalt | Tracker::None | Tracker::Ebpf => return Ok(()),
+    };
+    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+    ctx.prepend_task_argv(&argv)
+}
+
+// The job of an allocation, once salloc got one
+pub fn allocation_jobid(ctx: &impl JobContext) -> Result<Option<u32>, Report> {
+    match ctx.getenv("SLURM_JOB_ID")? {
+        Some(jobid) => Ok(Some(jobid.parse().wrap_err("Invalid SLURM_JOB_ID")?)),
+        None => Ok(None),
+    }
+}
+
+// The job's partitions once it runs, the requested ones at submission
+pub fn partitions(ctx: &impl JobContext) -> Result<Option<String>, Report> {
+    first_env(
+        ctx,
+        &["SLURM_JOB_PARTITION", "SBATCH_PARTITION", "SLURM_PARTITION"],
+    )
+}
+
+pub fn cluster(ctx: &impl JobContext) -> Result<Option<String>, Report> {
+    first_env(ctx, &["SLURM_CLUSTER_NAME", "SLURM_CLUSTERS"])
+}
+
+// Who the job belongs to, for the policy
+pub fn subject(ctx: &impl JobContext, current_user: Option<String>) -> Result<Subject, Report> {
+    Ok(Subject {
+        user: ctx.getenv("SLURM_JOB_USER")?.or(current_user),
+        account: first_env(
+            ctx,
+            &["SLURM_JOB_ACCOUNT", "SBATCH_ACCOUNT", "SLURM_ACCOUNT"],
+        )?,
+        qos: first_env(ctx, &["SLURM_JOB_QOS", "SBATCH_QOS", "SLURM_QOS"])?,
+    })
+}
+
+// The value of the job's variable while it runs, falling back on the
+// variables of the submission commands
+pub fn first_env(ctx: &impl JobContext, vars: &[&str]) -> Result<Option<String>, Report> {
+    for var in vars {
+        if let Some(value) = ctx.getenv(var)? {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,252 @@
+// Export of a TRO as a CWLProv research object: a BagIt bag holding the
+// declaration and its signatures as payload, and the arrangements and
+// performances as W3C PROV in metadata/provenance, for CWLProv tooling
+use chrono::Utc;
+use eyre::{eyre, Report, WrapErr};
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::declaration::NAMESPACE;
+use crate::digest;
+use crate::signing;
+use crate::sigstore;
+use crate::timestamp;
+
+pub const CWLPROV_VERSION: &str = "https://w3id.org/cwl/prov/0.6.0";
+const PROVENANCE: &str = "metadata/provenance/primary.cwlprov.json";
+
+// Where the research object of a declaration goes by default, tro-1.cwlprov
+// next to tro-1.jsonld
+pub fn default_dir(declaration: &Path) -> PathBuf {
+    declaration.with_extension("cwlprov")
+}
+
+// Write the research object of `declaration` to `dir`, which must not exist
+pub fn export(declaration: &Path, dir: &Path) -> Result<(), Report> {
+    if dir.exists() {
+        return Err(eyre!("{} already exists", dir.display()));
+    }
+    let content = fs::read(declaration)
+        .wrap_err_with(|| format!("Failed to read {}", declaration.display()))?;
+    let document: Value = serde_json::from_slice(&content)
+        .wrap_err_with(|| format!("Invalid declaration {}", declaration.display()))?;
+
+    // payload: the declaration and whatever signatures it has
+    let mut payload = vec![declaration.to_path_buf()];
+    payload.extend(signing::signature(declaration));
+    payload.extend(
+        [
+            signing::detached_signature(declaration, None),
+            signing::detached_signature(declaration, Some("user")),
+            sigstore::bundle_path(declaration, None),
+        ]
+        .into_iter()
+        .filter(|path| path.exists()),
+    );
+    fs::create_dir_all(dir.join("data"))?;
+    fs::create_dir_all(dir.join("metadata/provenance"))?;
+    let mut manifest = Vec::new();
+    let mut oxum = (0, 0);
+    for path in &payload {
+        let name = format!(
+            "data/{}",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        );
+        fs::copy(path, dir.join(&name))
+            .wrap_err_with(|| format!("Failed to copy {}", path.display()))?;
+        manifest.push((digest::sha256_file(path)?, name));
+        oxum = (oxum.0 + fs::metadata(path)?.len(), oxum.1 + 1);
+    }
+    let data_name = manifest[0].1.clone();
+    let tro_id = format!("urn:hash::sha256:{}", digest::sha256_bytes(&content));
+
+    let mut tags = Vec::new();
+    let mut write_tag = |name: &str, content: &[u8]| -> Result<(), Report> {
+        fs::write(dir.join(name), content)
+            .wrap_err_with(|| format!("Failed to write {}", dir.join(name).display()))?;
+        tags.push((digest::sha256_bytes(content), name.to_string()));
+        Ok(())
+    };
+    let provenance = provenance(&document, &tro_id)?;
+    write_tag(PROVENANCE, &serde_json::to_vec_pretty(&provenance)?)?;
+    let ro_manifest = json!({
+        "@context": ["https://w3id.org/bundle/context"],
+        "id": "/",
+        "manifest": "manifest.json",
+        "createdOn": timestamp::now(),
+        "createdBy": { "name": "spank-tro" },
+        "conformsTo": CWLPROV_VERSION,
+        "aggregates": manifest
+            .iter()
+            .map(|(_, name)| json!({
+                "uri": format!("../{name}"),
+                "mediatype": match name.ends_with(".jsonld") {
+                    true => "application/ld+json",
+                    false => "application/pgp-signature",
+                },
+            }))
+            .collect::<Vec<_>>(),
+        "annotations": [{
+            "about": format!("../{data_name}"),
+            "content": "provenance/primary.cwlprov.json",
+            "oa:motivatedBy": { "@id": "http://www.w3.org/ns/prov#has_provenance" },
+        }],
+    });
+    write_tag(
+        "metadata/manifest.json",
+        &serde_json::to_vec_pretty(&ro_manifest)?,
+    )?;
+    write_tag(
+        "bagit.txt",
+        b"BagIt-Version: 1.0\nTag-File-Character-Encoding: UTF-8\n",
+    )?;
+    let bag_info = format!(
+        "Bagging-Date: {}\nExternal-Identifier: {tro_id}\nPayload-Oxum: {}.{}\n",
+        Utc::now().format("%Y-%m-%d"),
+        oxum.0,
+        oxum.1
+    );
+    write_tag("bag-info.txt", bag_info.as_bytes())?;
+    write_tag("manifest-sha256.txt", manifest_lines(&manifest).as_bytes())?;
+    fs::write(dir.join("tagmanifest-sha256.txt"), manifest_lines(&tags))?;
+    Ok(())
+}
+
+// PROV-JSON of the TRO: each arrangement a collection of the artifacts it
+// located, each performance an activity using the arrangement it accessed
+// and generating the one it contributed to
+fn provenance(document: &Value, tro_id: &str) -> Result<Value, Report> {
+    let tro = &document["@graph"][0];
+    if !tro.is_object() {
+        return Err(eyre!("The declaration has no TRO"));
+    }
+    let mut entities = Map::new();
+    let mut members = Vec::new();
+    for arrangement in records(&tro["trov:hasArrangement"]) {
+        let id = format!("tro:{}", arrangement["@id"].as_str().unwrap_or_default());
+        entities.insert(
+            id.clone(),
+            json!({
+                "prov:type": { "$": "prov:Collection", "type": "prov:QUALIFIED_NAME" },
+                "prov:label": arrangement["rdfs:comment"],
+            }),
+        );
+        for locus in records(&arrangement["trov:hasLocus"])
+            .into_iter()
+            .chain(records(&arrangement["trov:hasArtifactLocation"]))
+        {
+            let artifact = artifact_id(tro, &locus["trov:hasArtifact"]);
+            let Some(artifact) = artifact else { continue };
+            entities.entry(artifact.clone()).or_insert_with(|| {
+                json!({ "prov:type": { "$": "wfprov:Artifact", "type": "prov:QUALIFIED_NAME" } })
+            });
+            members.push(json!({
+                "prov:collection": id,
+                "prov:entity": artifact,
+                "spank:location": locus["trov:hasLocation"],
+            }));
+        }
+    }
+
+    let mut activities = Map::new();
+    let (mut used, mut generated) = (Map::new(), Map::new());
+    for (i, performance) in records(&tro["trov:hasPerformance"]).into_iter().enumerate() {
+        let id = format!(
+            "tro:{}",
+            performance["@id"].as_str().unwrap_or("performance")
+        );
+        activities.insert(
+            id.clone(),
+            json!({
+                "prov:type": { "$": "wfprov:ProcessRun", "type": "prov:QUALIFIED_NAME" },
+                "prov:label": performance["rdfs:comment"],
+                "prov:startTime": performance["trov:startedAtTime"],
+                "prov:endTime": performance["trov:endedAtTime"],
+            }),
+        );
+        for arrangement in arrangement_ids(&performance["trov:accessedArrangement"]) {
+            used.insert(
+                format!("_:u{i}-{}", used.len()),
+                json!({ "prov:activity": id, "prov:entity": format!("tro:{arrangement}") }),
+            );
+        }
+        for arrangement in arrangement_ids(&performance["trov:contributedToArrangement"]) {
+            generated.insert(
+                format!("_:g{i}-{}", generated.len()),
+                json!({ "prov:activity": id, "prov:entity": format!("tro:{arrangement}") }),
+            );
+        }
+    }
+
+    let hadmember: Map<String, Value> = members
+        .into_iter()
+        .enumerate()
+        .map(|(i, member)| (format!("_:m{i}"), member))
+        .collect();
+    Ok(json!({
+        "prefix": {
+            "prov": "http://www.w3.org/ns/prov#",
+            "wfprov": "http://purl.org/wf4ever/wfprov#",
+            "cwlprov": "https://w3id.org/cwl/prov#",
+            "data": "urn:hash::sha256:",
+            "tro": format!("{tro_id}#"),
+            "spank": NAMESPACE,
+        },
+        "agent": {
+            "spank:spank-tro": {
+                "prov:type": { "$": "wfprov:WorkflowEngine", "type": "prov:QUALIFIED_NAME" },
+                "prov:label": format!("spank-tro {}", env!("CARGO_PKG_VERSION")),
+            },
+        },
+        "entity": entities,
+        "activity": activities,
+        "used": used,
+        "wasGeneratedBy": generated,
+        "hadMember": hadmember,
+    }))
+}
+
+// An artifact as data:<sha256> when the composition gives its digest
+fn artifact_id(tro: &Value, reference: &Value) -> Option<String> {
+    let id = reference["@id"].as_str()?;
+    let artifacts = records(&tro["trov:hasComposition"]["trov:hasArtifact"]);
+    let artifact = artifacts.iter().find(|artifact| artifact["@id"] == id);
+    match artifact.and_then(|artifact| artifact["trov:sha256"].as_str()) {
+        Some(sha256) => Some(format!("data:{sha256}")),
+        None => Some(format!("tro:{id}")),
+    }
+}
+
+// The arrangements a performance refers to, however deeply
+fn arrangement_ids(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(values) => values.iter().flat_map(arrangement_ids).collect(),
+        Value::Object(object) => {
+            let own = object
+                .get("@id")
+                .and_then(Value::as_str)
+                .filter(|id| id.starts_with("arrangement/"))
+                .map(str::to_string);
+            own.into_iter()
+                .chain(object.values().flat_map(arrangement_ids))
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn records(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Array(records) => records.iter().collect(),
+        Value::Null => Vec::new(),
+        record => vec![record],
+    }
+}
+
+fn manifest_lines(entries: &[(String, String)]) -> String {
+    entries
+        .iter()
+        .map(|(sha256, name)| format!("{sha256}  {name}\n"))
+        .collect()
+}
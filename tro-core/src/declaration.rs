@@ -0,0 +1,320 @@
+use eyre::{eyre, Report, WrapErr};
+use serde_json::{Map, Value};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::job::Job;
+use crate::lockfile::LockFile;
+use crate::privilege::{self, UserFs};
+use crate::schema;
+
+// Terms spank-tro adds to the declarations written by tro-utils
+const PREFIX: &str = "spank";
+pub const NAMESPACE: &str = "https://github.com/transparency-certified/spank-tro#";
+
+// Compact IRI of a spank-tro term
+pub fn term(name: &str) -> String {
+    format!("{PREFIX}:{name}")
+}
+
+// Exclusive access to a declaration, shared by the steps and het components of
+// a job that may update it at the same time, possibly from several nodes
+// sharing its directory. Released when dropped.
+pub fn lock(path: &Path) -> io::Result<LockFile> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    LockFile::acquire(Path::new(&lock_path))
+}
+
+// A TRO declaration (JSON-LD) loaded for annotation before it gets signed.
+// That of a job stays locked until dropped.
+pub struct Declaration {
+    path: PathBuf,
+    document: Value,
+    // the schema version it was written with
+    schema: u32,
+    // @id of the performance annotate_performance() sets terms on, the most
+    // recent one if empty
+    performance: String,
+    // none when only read, save() then takes it
+    lock: Option<LockFile>,
+    // released after the lock
+    _user: Option<UserFs>,
+}
+
+impl Declaration {
+    // Read without the lock, e.g. a TRO in a directory the reader may not
+    // write to
+    pub fn load(path: &Path) -> Result<Self, Report> {
+        Self::open(path, None, None)
+    }
+
+    // The declaration of a job, read and written as the job's user
+    pub fn of(job: &Job) -> Result<Self, Report> {
+        let user = privilege::as_user(job);
+        let lock = lock(&job.declaration)
+            .wrap_err_with(|| format!("Failed to lock {}", job.declaration.display()))?;
+        Self::locked(job, lock, user)
+    }
+
+    // Same, with its lock already held, e.g. since tro-utils last wrote it
+    pub fn of_locked(job: &Job, lock: LockFile) -> Result<Self, Report> {
+        Self::locked(job, lock, privilege::as_user(job))
+    }
+
+    fn locked(job: &Job, lock: LockFile, user: UserFs) -> Result<Self, Report> {
+        let mut declaration = Self::open(&job.declaration, Some(lock), Some(user))?;
+        declaration.performance = job.performance.clone();
+        Ok(declaration)
+    }
+
+    fn open(path: &Path, lock: Option<LockFile>, user: Option<UserFs>) -> Result<Self, Report> {
+        let content =
+            fs::read(path).wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        let mut document = serde_json::from_slice(&content)
+            .wrap_err_with(|| format!("Invalid declaration {}", path.display()))?;
+        let schema = schema::upgrade(&mut document)
+            .wrap_err_with(|| format!("Failed to upgrade {}", path.display()))?;
+        let mut declaration = Declaration {
+            path: path.to_path_buf(),
+            document,
+            schema,
+            performance: String::new(),
+            lock,
+            _user: user,
+        };
+        if schema <= schema::VERSION && declaration.document["@graph"][0].is_object() {
+            declaration.add_namespace();
+            declaration.document["@graph"][0][term("schemaVersion")] = Value::from(schema::VERSION);
+        }
+        Ok(declaration)
+    }
+
+    // The schema version the declaration was written with, before it was
+    // upgraded
+    pub fn schema_version(&self) -> u32 {
+        self.schema
+    }
+
+    // @id and comment of each arrangement
+    pub fn arrangements(&self) -> Vec<(String, String)> {
+        self.records("trov:hasArrangement")
+    }
+
+    // @id and comment of each performance
+    pub fn performances(&self) -> Vec<(String, String)> {
+        self.records("trov:hasPerformance")
+    }
+
+    fn records(&self, key: &str) -> Vec<(String, String)> {
+        all(&self.document["@graph"][0][key])
+            .into_iter()
+            .map(|record| {
+                (
+                    record["@id"].as_str().unwrap_or("?").to_string(),
+                    record["rdfs:comment"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                )
+            })
+            .collect()
+    }
+
+    // @id of the most recently added arrangement, e.g. arrangement/1
+    pub fn last_arrangement(&mut self) -> Option<String> {
+        let arrangement = match self.tro().ok()?.get("trov:hasArrangement")? {
+            Value::Array(arrangements) => arrangements.last()?,
+            arrangement => arrangement,
+        };
+        arrangement["@id"].as_str().map(str::to_string)
+    }
+
+    // Location and SHA-256 of each file of the most recent arrangement
+    pub fn last_locations(&self) -> Vec<(String, String)> {
+        match last(&self.document["@graph"][0]["trov:hasArrangement"]) {
+            Some(arrangement) => self.locations_of(arrangement),
+            None => Vec::new(),
+        }
+    }
+
+    // Location and SHA-256 of each file of the arrangement with the given @id
+    pub fn locations(&self, id: &str) -> Vec<(String, String)> {
+        all(&self.document["@graph"][0]["trov:hasArrangement"])
+            .into_iter()
+            .find(|arrangement| arrangement["@id"] == id)
+            .map(|arrangement| self.locations_of(arrangement))
+            .unwrap_or_default()
+    }
+
+    fn locations_of(&self, arrangement: &Value) -> Vec<(String, String)> {
+        let tro = &self.document["@graph"][0];
+        let artifacts = &tro["trov:hasComposition"]["trov:hasArtifact"];
+        let sha256 = |id: &Value| {
+            all(artifacts)
+                .into_iter()
+                .find(|artifact| artifact["@id"] == *id)
+                .and_then(|artifact| artifact["trov:sha256"].as_str())
+        };
+        all(&arrangement["trov:hasLocus"])
+            .into_iter()
+            .chain(all(&arrangement["trov:hasArtifactLocation"]))
+            .filter_map(|locus| {
+                let location = locus["trov:hasLocation"].as_str()?;
+                let sha256 = sha256(&locus["trov:hasArtifact"]["@id"])?;
+                Some((location.to_string(), sha256.to_string()))
+            })
+            .collect()
+    }
+
+    // spank:merkle of the most recent arrangement
+    pub fn last_merkle(&self) -> Option<&Value> {
+        last(&self.document["@graph"][0]["trov:hasArrangement"])?.get(term("merkle"))
+    }
+
+    // spank:<key> of the first arrangement, the job's initial one
+    pub fn first_arrangement_term(&self, key: &str) -> Option<&Value> {
+        all(&self.document["@graph"][0]["trov:hasArrangement"])
+            .first()?
+            .get(term(key))
+    }
+
+    // spank:<key> of the TRO
+    pub fn tro_term(&self, key: &str) -> Option<&Value> {
+        self.document["@graph"][0].get(term(key))
+    }
+
+    // spank:<key> of the most recent performance
+    pub fn last_performance_term(&self, key: &str) -> Option<&Value> {
+        last(&self.document["@graph"][0]["trov:hasPerformance"])?.get(term(key))
+    }
+
+    pub fn arrangement_count(&mut self) -> usize {
+        match self
+            .tro()
+            .ok()
+            .and_then(|tro| tro.get("trov:hasArrangement"))
+        {
+            Some(Value::Array(arrangements)) => arrangements.len(),
+            Some(_) => 1,
+            None => 0,
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Report> {
+        if self.schema > schema::VERSION {
+            return Err(eyre!(
+                "{} was written by a newer spank-tro (schema version {}), it is left as is",
+                self.path.display(),
+                self.schema
+            ));
+        }
+        let _lock = match self.lock {
+            Some(_) => None,
+            None => Some(
+                lock(&self.path)
+                    .wrap_err_with(|| format!("Failed to lock {}", self.path.display()))?,
+            ),
+        };
+        // renamed over it once complete, a crash never leaves half of it
+        let mut tmp_path = self.path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        privilege::create_nofollow(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(&serde_json::to_vec_pretty(&self.document)?)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, &self.path))
+            .wrap_err_with(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    // Set spank:<key> on the TRO itself
+    pub fn annotate_tro(&mut self, key: &str, value: Value) -> Result<(), Report> {
+        self.add_namespace();
+        self.tro()?.insert(term(key), value);
+        Ok(())
+    }
+
+    // Set spank:<key> on the job's performance, or the most recently added
+    // one
+    pub fn annotate_performance(&mut self, key: &str, value: Value) -> Result<(), Report> {
+        self.add_namespace();
+        let (path, id) = (self.path.clone(), self.performance.clone());
+        let performance = match self.tro()?.get_mut("trov:hasPerformance") {
+            Some(Value::Array(performances)) if !id.is_empty() => performances
+                .iter_mut()
+                .find(|performance| performance["@id"] == id.as_str()),
+            Some(Value::Array(performances)) => performances.last_mut(),
+            Some(performance) => Some(performance),
+            None => None,
+        };
+        performance
+            .and_then(Value::as_object_mut)
+            .ok_or_else(|| eyre!("{} has no performance", path.display()))?
+            .insert(term(key), value);
+        Ok(())
+    }
+
+    // Set spank:<key> on the most recently added arrangement
+    pub fn annotate_arrangement(&mut self, key: &str, value: Value) -> Result<(), Report> {
+        self.add_namespace();
+        let path = self.path.clone();
+        let arrangement = match self.tro()?.get_mut("trov:hasArrangement") {
+            Some(Value::Array(arrangements)) => arrangements.last_mut(),
+            Some(arrangement) => Some(arrangement),
+            None => None,
+        };
+        arrangement
+            .and_then(Value::as_object_mut)
+            .ok_or_else(|| eyre!("{} has no arrangement", path.display()))?
+            .insert(term(key), value);
+        Ok(())
+    }
+
+    // The TRO node, for what the helpers above do not cover
+    pub fn tro(&mut self) -> Result<&mut Map<String, Value>, Report> {
+        self.document["@graph"][0]
+            .as_object_mut()
+            .ok_or_else(|| eyre!("{} has no TRO", self.path.display()))
+    }
+
+    fn add_namespace(&mut self) {
+        let namespace = Value::from(NAMESPACE);
+        match &mut self.document["@context"] {
+            Value::Array(contexts) => {
+                let defined = contexts.iter().any(|context| context.get(PREFIX).is_some());
+                if !defined {
+                    let mut context = Map::new();
+                    context.insert(PREFIX.to_string(), namespace);
+                    contexts.push(Value::Object(context));
+                }
+            }
+            Value::Object(context) => {
+                context.entry(PREFIX).or_insert(namespace);
+            }
+            context => {
+                let mut extended = Map::new();
+                extended.insert(PREFIX.to_string(), namespace);
+                *context = match context.take() {
+                    Value::Null => Value::Object(extended),
+                    other => Value::Array(vec![other, Value::Object(extended)]),
+                };
+            }
+        }
+    }
+}
+
+// The records of a property holding one or several
+fn all(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Array(records) => records.iter().collect(),
+        Value::Null => Vec::new(),
+        record => vec![record],
+    }
+}
+
+fn last(value: &Value) -> Option<&Value> {
+    all(value).pop()
+}
@@ -0,0 +1,86 @@
+use eyre::{eyre, Report};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use crate::config::Config;
+use crate::declaration::term;
+use crate::digest;
+use crate::job::{declaration_path, NameFields};
+use crate::scontrol::{self, JobInfo};
+use crate::timeline::parse_slurm_time;
+
+// A job this one waits for, from SLURM_JOB_DEPENDENCY, e.g. afterok:123 or
+// afterany:124_2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub kind: String,
+    // a jobid, or an array task as <array job>_<task>
+    pub jobid: String,
+}
+
+// The jobs of a --dependency expression, all of them whether they are
+// separated by , or ?. singleton names no job.
+pub fn parse(value: &str) -> Result<Vec<Dependency>, Report> {
+    let mut dependencies = Vec::new();
+    for item in value.split([',', '?']).filter(|item| !item.is_empty()) {
+        let mut parts = item.split(':');
+        let kind = parts.next().unwrap_or_default();
+        for jobid in parts {
+            // after:123+10 waits 10 minutes after job 123 started
+            let jobid = jobid.split_once('+').map_or(jobid, |(jobid, _)| jobid);
+            let (array_job, task) = jobid.split_once('_').unwrap_or((jobid, "0"));
+            if array_job.parse::<u32>().is_err() || task.parse::<u32>().is_err() {
+                return Err(eyre!("{jobid} in {value} is not a jobid"));
+            }
+            dependencies.push(Dependency {
+                kind: kind.to_string(),
+                jobid: jobid.to_string(),
+            });
+        }
+    }
+    Ok(dependencies)
+}
+
+impl Dependency {
+    // Where the TRO of the job is, from what slurmctld still knows of it,
+    // declaration_dir and the name template. A job it forgot is assumed to
+    // have been submitted from `workdir`.
+    pub fn declaration(&self, config: &Config, workdir: &Path) -> PathBuf {
+        let info = scontrol::show_job(&config.settings, &self.jobid).unwrap_or_else(|e| {
+            debug!("{:#}", e);
+            JobInfo::default()
+        });
+        let number = |key: &str| info.get(key).and_then(|value| value.parse::<u32>().ok());
+        let jobid = number("JobId").unwrap_or_else(|| {
+            let jobid = self.jobid.split('_').next().unwrap_or_default();
+            jobid.parse().unwrap_or_default()
+        });
+        // UserId=alice(1000)
+        let user = info
+            .get("UserId")
+            .and_then(|user| user.split('(').next())
+            .unwrap_or_default();
+        let fields = NameFields {
+            jobid,
+            user,
+            job_name: info.get("JobName").unwrap_or_default(),
+            array: number("ArrayJobId").zip(number("ArrayTaskId")),
+            start: info.get("StartTime").and_then(parse_slurm_time),
+        };
+        let submit_dir = info.get("WorkDir").map_or(workdir, Path::new);
+        declaration_path(config, &fields, submit_dir)
+    }
+
+    // How the declaration records the job, with the digest of its TRO when
+    // there is one
+    pub fn describe(&self, declaration: &Path) -> Value {
+        let sha256 = digest::sha256_file(declaration).ok();
+        json!({
+            term("jobid"): self.jobid,
+            term("dependency"): self.kind,
+            term("declaration"): sha256.as_ref().map(|_| declaration),
+            term("sha256"): sha256,
+        })
+    }
+}
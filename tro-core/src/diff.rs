@@ -0,0 +1,51 @@
+// Files the job added, removed and modified between the initial and the
+// final arrangement, so that what a job produced can be read off the TRO
+// instead of comparing two manifests
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use crate::declaration::term;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Diff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+impl Diff {
+    // Compare two lists of (location, SHA-256), locations come out sorted
+    pub fn between(initial: &[(String, String)], last: &[(String, String)]) -> Self {
+        let initial: BTreeMap<&str, &str> = initial
+            .iter()
+            .map(|(location, sha256)| (location.as_str(), sha256.as_str()))
+            .collect();
+        let last: BTreeMap<&str, &str> = last
+            .iter()
+            .map(|(location, sha256)| (location.as_str(), sha256.as_str()))
+            .collect();
+        let mut diff = Diff::default();
+        for (location, sha256) in &last {
+            match initial.get(location) {
+                None => diff.added.push(location.to_string()),
+                Some(before) if before != sha256 => diff.modified.push(location.to_string()),
+                Some(_) => {}
+            }
+        }
+        diff.removed = initial
+            .keys()
+            .filter(|location| !last.contains_key(*location))
+            .map(|location| location.to_string())
+            .collect();
+        diff
+    }
+
+    pub fn to_json(&self, from: &str) -> Value {
+        json!({
+            term("from"): from,
+            term("added"): self.added,
+            term("removed"): self.removed,
+            term("modified"): self.modified,
+        })
+    }
+}
@@ -0,0 +1,123 @@
+use chrono::{DateTime, Utc};
+use eyre::{eyre, Report};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::declaration::term;
+use crate::settings::Settings;
+use crate::timestamp;
+
+// Bytes hashed of a file above max_hash_size with large_files=head
+pub const HEAD_SIZE: u64 = 64 * 1024 * 1024;
+
+// Hex encoded SHA-256 of a file's content
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn sha256_bytes(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+// How files above max_hash_size are told apart without reading them whole:
+// by their size and modification time, or the digest of their first
+// HEAD_SIZE bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LargeFiles {
+    #[default]
+    Stat,
+    Head,
+}
+
+impl FromStr for LargeFiles {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "stat" => Ok(LargeFiles::Stat),
+            "head" => Ok(LargeFiles::Head),
+            _ => Err(eyre!("{value} is not one of stat/head")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fingerprint {
+    Sha256(String),
+    // unix time of the last modification
+    Stat(i64),
+    Head(String),
+}
+
+// The SHA-256 of a file, or what large_files says above max_hash_size
+pub fn fingerprint(settings: &Settings, path: &Path) -> io::Result<Fingerprint> {
+    fingerprint_of(settings, File::open(path)?)
+}
+
+// The same of a file already open, read from where it is
+pub fn fingerprint_of(settings: &Settings, mut file: File) -> io::Result<Fingerprint> {
+    let metadata = file.metadata()?;
+    match settings.max_hash_size {
+        Some(max_size) if metadata.len() > max_size => match settings.large_files {
+            LargeFiles::Stat => {
+                let modified: DateTime<Utc> = metadata.modified()?.into();
+                Ok(Fingerprint::Stat(modified.timestamp()))
+            }
+            LargeFiles::Head => {
+                let mut hasher = Sha256::new();
+                io::copy(&mut file.take(HEAD_SIZE), &mut hasher)?;
+                Ok(Fingerprint::Head(format!("{:x}", hasher.finalize())))
+            }
+        },
+        _ => {
+            let mut hasher = Sha256::new();
+            io::copy(&mut file, &mut hasher)?;
+            Ok(Fingerprint::Sha256(format!("{:x}", hasher.finalize())))
+        }
+    }
+}
+
+impl Fingerprint {
+    pub fn is_partial(&self) -> bool {
+        !matches!(self, Fingerprint::Sha256(_))
+    }
+
+    // What a declaration says of the file besides its size
+    pub fn terms(&self) -> Map<String, Value> {
+        let mut terms = Map::new();
+        match self {
+            Fingerprint::Sha256(sha256) => {
+                terms.insert(term("sha256"), json!(sha256));
+            }
+            Fingerprint::Stat(modified) => {
+                terms.insert(
+                    term("modified"),
+                    json!(timestamp::from_unix(*modified).ok()),
+                );
+            }
+            Fingerprint::Head(sha256) => {
+                terms.insert(term("headSha256"), json!(sha256));
+                terms.insert(term("headSize"), json!(HEAD_SIZE));
+            }
+        }
+        terms
+    }
+
+    // Its part of a directory's digest, in place of the SHA-256 of its content
+    pub fn token(&self) -> String {
+        match self {
+            Fingerprint::Sha256(sha256) => sha256.clone(),
+            Fingerprint::Stat(modified) => format!("modified:{modified}"),
+            Fingerprint::Head(sha256) => format!("head:{sha256}"),
+        }
+    }
+}
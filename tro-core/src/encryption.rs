@@ -0,0 +1,22 @@
+use eyre::{eyre, Report, WrapErr};
+use std::process::Command;
+
+use crate::command;
+use crate::settings::Settings;
+
+// ASCII-armored `content` only the encrypt_to keys, found in gpg_home, can
+// decrypt
+pub fn encrypt(settings: &Settings, content: &[u8]) -> Result<String, Report> {
+    let mut command = Command::new(&settings.gpg);
+    if !settings.gpg_home.as_os_str().is_empty() {
+        command.env("GNUPGHOME", &settings.gpg_home);
+    }
+    command.args(["--batch", "--armor", "--trust-model", "always"]);
+    for recipient in &settings.encrypt_to {
+        command.args(["--recipient", recipient]);
+    }
+    command.args(["--output", "-", "--encrypt"]);
+    let output = command::run_with_input(&mut command, Some(content), settings.command_timeout)
+        .wrap_err("Failed to encrypt")?;
+    String::from_utf8(output.stdout).map_err(|_| eyre!("gpg did not armor its output"))
+}
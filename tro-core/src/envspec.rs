@@ -0,0 +1,162 @@
+// The conda environment, virtualenv or Spack environment a job runs in, from
+// CONDA_PREFIX, VIRTUAL_ENV and SPACK_ENV, with its specification in the
+// initial arrangement: what `conda list --explicit` or `pip freeze` tell of
+// it when the job starts, or its spack.lock
+use eyre::{Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+use tracing::info;
+
+use crate::attachment;
+use crate::command;
+use crate::context::JobContext;
+use crate::declaration::{term, Declaration};
+use crate::digest::sha256_bytes;
+use crate::job::Job;
+use crate::privilege;
+use crate::settings::Settings;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Conda,
+    Virtualenv,
+    Spack,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Environment {
+    pub kind: Kind,
+    pub prefix: PathBuf,
+    // CONDA_EXE, the conda that activated the environment
+    #[serde(default)]
+    pub conda: Option<PathBuf>,
+    // SPACK_LOADED_HASHES, the packages loaded with `spack load`
+    #[serde(default)]
+    pub loaded: Vec<String>,
+}
+
+impl Environment {
+    // The environments active in the job, a virtualenv possibly on top of a
+    // conda environment
+    pub fn detect(ctx: &impl JobContext) -> Result<Vec<Self>, Report> {
+        let mut environments = Vec::new();
+        if let Some(prefix) = ctx
+            .getenv("CONDA_PREFIX")?
+            .filter(|prefix| !prefix.is_empty())
+        {
+            environments.push(Environment {
+                kind: Kind::Conda,
+                prefix: PathBuf::from(prefix),
+                conda: ctx.getenv("CONDA_EXE")?.map(PathBuf::from),
+                loaded: Vec::new(),
+            });
+        }
+        if let Some(prefix) = ctx
+            .getenv("VIRTUAL_ENV")?
+            .filter(|prefix| !prefix.is_empty())
+        {
+            environments.push(Environment {
+                kind: Kind::Virtualenv,
+                prefix: PathBuf::from(prefix),
+                conda: None,
+                loaded: Vec::new(),
+            });
+        }
+        // packages may be loaded outside of an environment, the Spack
+        // instance they come from stands for it then
+        let loaded: Vec<String> = ctx
+            .getenv("SPACK_LOADED_HASHES")?
+            .unwrap_or_default()
+            .split(':')
+            .filter(|hash| !hash.is_empty())
+            .map(str::to_string)
+            .collect();
+        let spack = match ctx.getenv("SPACK_ENV")?.filter(|env| !env.is_empty()) {
+            Some(env) => Some(env),
+            None if !loaded.is_empty() => ctx.getenv("SPACK_ROOT")?,
+            None => None,
+        };
+        if let Some(prefix) = spack {
+            environments.push(Environment {
+                kind: Kind::Spack,
+                prefix: PathBuf::from(prefix),
+                conda: None,
+                loaded,
+            });
+        }
+        Ok(environments)
+    }
+
+    // Run as the job's user: the environment is theirs, and so are its
+    // programs
+    pub fn specification(&self, settings: &Settings, job: &Job) -> Result<String, Report> {
+        let mut command = match self.kind {
+            Kind::Spack => {
+                let path = self.prefix.join("spack.lock");
+                let _user = privilege::as_user(job);
+                return fs::read_to_string(&path)
+                    .wrap_err_with(|| format!("Failed to read {}", path.display()));
+            }
+            Kind::Conda => {
+                let mut command = Command::new(self.conda.as_ref().unwrap_or(&settings.conda));
+                command
+                    .args(["list", "--explicit", "--prefix"])
+                    .arg(&self.prefix);
+                command
+            }
+            Kind::Virtualenv => {
+                let mut command = Command::new(self.prefix.join("bin").join("python"));
+                command.args(["-m", "pip", "freeze", "--all"]);
+                command
+            }
+        };
+        // only root can switch, anyone else is the user already
+        if unsafe { libc::geteuid() } == 0 {
+            command.uid(job.uid).gid(job.gid);
+        }
+        let output = command::run(&mut command, settings.command_timeout).wrap_err_with(|| {
+            format!("Failed to export the environment {}", self.prefix.display())
+        })?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn describe(&self, settings: &Settings, job: &Job) -> Result<Value, Report> {
+        let mut described = json!({
+            term("kind"): self.kind,
+            term("prefix"): self.prefix,
+        });
+        if !self.loaded.is_empty() {
+            described[term("loadedHashes")] = Value::from(self.loaded.clone());
+        }
+        // an environment whose specification could not be had is still
+        // recorded
+        match self.specification(settings, job) {
+            Ok(specification) => {
+                described[term("sha256")] = Value::from(sha256_bytes(specification.as_bytes()));
+                attachment::attach(settings, &mut described, "environment", &specification)?;
+            }
+            Err(e) => info!("{:#}", e),
+        }
+        Ok(described)
+    }
+}
+
+// Record the environments of the job in the latest arrangement
+pub fn record(settings: &Settings, job: &Job) -> Result<(), Report> {
+    if job.environments.is_empty() || settings.dry_run {
+        return Ok(());
+    }
+    let described = job
+        .environments
+        .iter()
+        .map(|environment| environment.describe(settings, job))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_arrangement("environments", Value::Array(described))?;
+    declaration.save()
+}
@@ -0,0 +1,72 @@
+use eyre::{eyre, Report};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use tracing::warn;
+
+use crate::phase::Phase;
+use crate::settings::parse_list;
+
+// What a failed phase means for the job
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    // log it and keep producing the TRO without that phase
+    #[default]
+    Warn,
+    // return the error to Slurm
+    Fail,
+}
+
+impl FromStr for FailurePolicy {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "warn" => Ok(FailurePolicy::Warn),
+            "fail" => Ok(FailurePolicy::Fail),
+            _ => Err(eyre!("{value} is not one of warn/fail")),
+        }
+    }
+}
+
+// The policy of each phase: on_failure, unless fail_on or warn_on list the
+// phase, e.g. with fail_on=sign and warn_on=trace a missing trace only
+// degrades the TRO but an unsigned one fails the job
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OnFailure {
+    pub policy: FailurePolicy,
+    per_phase: Vec<(Phase, FailurePolicy)>,
+}
+
+impl OnFailure {
+    // Apply `policy` to the phases of `<phase>[,...]`, instead of what
+    // fail_on or warn_on said about them before
+    pub fn set_phases(&mut self, value: &str, policy: FailurePolicy) -> Result<(), Report> {
+        self.per_phase.retain(|(_, p)| *p != policy);
+        for phase in parse_list(value) {
+            let phase: Phase = phase.parse()?;
+            self.per_phase.retain(|(p, _)| *p != phase);
+            self.per_phase.push((phase, policy));
+        }
+        Ok(())
+    }
+
+    pub fn policy(&self, phase: Phase) -> FailurePolicy {
+        self.per_phase
+            .iter()
+            .find(|(p, _)| *p == phase)
+            .map_or(self.policy, |(_, policy)| *policy)
+    }
+
+    // Ok(Some) on success, Ok(None) for a failure that is only warned about
+    pub fn check<T>(&self, phase: Phase, result: Result<T, Report>) -> Result<Option<T>, Report> {
+        match (result, self.policy(phase)) {
+            (Ok(value), _) => Ok(Some(value)),
+            (Err(e), FailurePolicy::Warn) => {
+                warn!("TRO {} phase failed: {:#}", phase, e);
+                Ok(None)
+            }
+            (Err(e), FailurePolicy::Fail) => Err(e.wrap_err(format!("TRO {phase} phase failed"))),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// The filesystems under the workdir and the other roots of a job, in the
+// initial arrangement: their type and mount options, and the striping of
+// Lustre and GPFS directories, as I/O behavior and even the order readdir()
+// lists files in differ across them
+use eyre::Report;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::debug;
+
+use crate::command;
+use crate::declaration::{term, Declaration};
+use crate::job::Job;
+use crate::privilege;
+use crate::roots::WORKDIR_LABEL;
+use crate::settings::Settings;
+
+// A line of /proc/self/mountinfo
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    pub mount_point: PathBuf,
+    pub options: String,
+    pub fstype: String,
+    pub source: String,
+    pub super_options: String,
+}
+
+// Spaces and the like are written as octal escapes, e.g. \040
+fn unescape(field: &str) -> String {
+    let mut unescaped = Vec::new();
+    let bytes = field.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok());
+        match (bytes[i], octal) {
+            (b'\\', Some(byte)) => {
+                unescaped.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                unescaped.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+pub fn parse_mountinfo(mountinfo: &str) -> Vec<Mount> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let (mount, filesystem) = line.split_once(" - ")?;
+            let mount: Vec<&str> = mount.split(' ').collect();
+            let mut filesystem = filesystem.split(' ');
+            Some(Mount {
+                mount_point: PathBuf::from(unescape(mount.get(4)?)),
+                options: mount.get(5)?.to_string(),
+                fstype: filesystem.next()?.to_string(),
+                source: unescape(filesystem.next()?),
+                super_options: filesystem.next().unwrap_or_default().to_string(),
+            })
+        })
+        .collect()
+}
+
+// The mount `path` is on: the last one mounted on the longest mount point
+// above it, as later mounts hide earlier ones
+pub fn mount_of<'a>(mounts: &'a [Mount], path: &Path) -> Option<&'a Mount> {
+    // max_by_key() gives the last of equal ones
+    mounts
+        .iter()
+        .filter(|mount| path.starts_with(&mount.mount_point))
+        .max_by_key(|mount| mount.mount_point.as_os_str().len())
+}
+
+// lfs getstripe -d or mmlsattr -L of the directory, for the filesystems that
+// stripe files across servers
+fn striping(settings: &Settings, fstype: &str, dir: &Path) -> Option<String> {
+    let mut command = match fstype {
+        "lustre" => {
+            let mut command = Command::new(&settings.lfs);
+            command.args(["getstripe", "-d"]);
+            command
+        }
+        "gpfs" => {
+            let mut command = Command::new(&settings.mmlsattr);
+            command.arg("-L");
+            command
+        }
+        _ => return None,
+    };
+    match command::run(command.arg(dir), settings.command_timeout) {
+        Ok(output) => Some(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        Err(e) => {
+            debug!("{:#}", e);
+            None
+        }
+    }
+}
+
+fn describe(settings: &Settings, job: &Job, mounts: &[Mount], label: &str, dir: &Path) -> Value {
+    let resolved = {
+        let _user = privilege::as_user(job);
+        fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf())
+    };
+    let mut described = json!({
+        term("root"): label,
+        term("path"): dir,
+    });
+    if let Some(mount) = mount_of(mounts, &resolved) {
+        described[term("mountPoint")] = json!(mount.mount_point);
+        described[term("type")] = json!(mount.fstype);
+        described[term("source")] = json!(mount.source);
+        described[term("mountOptions")] = json!(mount.options);
+        described[term("superOptions")] = json!(mount.super_options);
+        if let Some(striping) = striping(settings, &mount.fstype, &resolved) {
+            described[term("striping")] = json!(striping);
+        }
+    }
+    described
+}
+
+// Record the filesystems of the workdir and the other roots in the latest
+// arrangement
+pub fn record(settings: &Settings, job: &Job) -> Result<(), Report> {
+    if settings.dry_run {
+        return Ok(());
+    }
+    let mounts = parse_mountinfo(&fs::read_to_string("/proc/self/mountinfo")?);
+    let mut described = vec![describe(
+        settings,
+        job,
+        &mounts,
+        WORKDIR_LABEL,
+        &job.workdir,
+    )];
+    for root in &job.roots {
+        described.push(describe(settings, job, &mounts, &root.label, &root.path));
+    }
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_arrangement("filesystems", Value::Array(described))?;
+    declaration.save()
+}
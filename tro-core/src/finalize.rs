@@ -0,0 +1,666 @@
+use eyre::{eyre, Report, WrapErr};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
+use tracing::{debug, info};
+
+use crate::audit::{AuditLog, SigningEvent};
+use crate::catalog::{self, CatalogEntry, Status};
+use crate::changes::{changes_path, Changes};
+use crate::declaration::{term, Declaration};
+use crate::diff::Diff;
+use crate::digest;
+use crate::incident;
+use crate::intoto;
+use crate::job::{Job, TaskExit, BATCH_STEP};
+use crate::journal::Journal;
+use crate::libraries;
+use crate::mpi;
+use crate::nodes;
+use crate::output;
+use crate::phase::Phase;
+use crate::platform;
+use crate::privilege;
+use crate::redact;
+use crate::sbom;
+use crate::scontrol::{self, JobInfo};
+use crate::settings::Settings;
+use crate::signing::{self, Signer, SigningKey};
+use crate::sigstore;
+use crate::slsa::{self, Builder};
+use crate::slurmrestd;
+use crate::termination::Termination;
+use crate::timeline::Timeline;
+use crate::timestamp;
+use crate::tracker::{self, Trace, Tracker};
+use crate::tro_utils::TroUtils;
+use crate::workflow;
+
+// Where the final arrangement, performance and signing happen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinalizeMode {
+    // in the exit hook
+    Sync,
+    // in a helper started detached by the exit hook
+    Deferred,
+    // in spank-tro-finalizerd, the exit hook only queues the job
+    Daemon,
+    // in the spank-tro-epilog Slurm Epilog, the exit hook only queues the job
+    Epilog,
+}
+
+impl FromStr for FinalizeMode {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "sync" => Ok(FinalizeMode::Sync),
+            "deferred" => Ok(FinalizeMode::Deferred),
+            "daemon" => Ok(FinalizeMode::Daemon),
+            "epilog" => Ok(FinalizeMode::Epilog),
+            _ => Err(eyre!("{value} is not one of sync/deferred/daemon/epilog")),
+        }
+    }
+}
+
+// Record the final arrangement and the performance found by the tracker, then
+// sign the job's TRO, picking up from where the journal says an earlier
+// finalization stopped
+pub fn finalize(
+    settings: &Settings,
+    job: &Job,
+    journal: &mut Journal,
+    notify: &dyn Fn(&str),
+) -> Result<(), Report> {
+    let result = finalize_tro(settings, job, journal, notify);
+    if result.is_err() {
+        catalog::record(settings, &CatalogEntry::new(job, None, &[], Status::Failed));
+    }
+    result
+}
+
+fn finalize_tro(
+    settings: &Settings,
+    job: &Job,
+    journal: &mut Journal,
+    notify: &dyn Fn(&str),
+) -> Result<(), Report> {
+    let tro = TroUtils::new(settings, job, notify);
+    if journal.resumed() {
+        info!(
+            "Resuming the finalization of job {}{}",
+            job.jobid,
+            job.label()
+        );
+    }
+
+    // what slurmctld knows about the job, if it can still be reached
+    let info = scontrol::show_job(settings, job.jobid).unwrap_or_else(|e| {
+        debug!("{:#}", e);
+        JobInfo::default()
+    });
+
+    let result = match journal.final_arrangement() {
+        Some(id) => Ok(id.to_string()),
+        None => {
+            tro.report_finalizing();
+            journal
+                .add_arrangement(job, || {
+                    tro.add_final_arrangement(&format!("'Final arrangement{}'", job.label()))
+                })
+                .and_then(|id| record_outputs(settings, job, &info).map(|_| id))
+                .and_then(|id| record_written(settings, job).map(|_| id))
+                .and_then(|id| record_diff(settings, job, &id).map(|_| id))
+                .and_then(|id| journal.set_final_arrangement(&id).map(|_| id))
+        }
+    };
+    // without a final arrangement, the performance can only point at the
+    // initial one
+    let final_arrangement = incident::check(settings, job, Phase::Arrangement, result)?
+        .unwrap_or_else(|| job.initial_arrangement.clone());
+
+    // with a TRO per step, the performance is that of the step's programs, and
+    // the TRO is signed when the batch step ends
+    let step_start = job.step.map(|_| job.start_time);
+    let last = match job.step {
+        Some(step) => step == BATCH_STEP || info.get("BatchFlag") != Some("1"),
+        None => true,
+    };
+    if journal.performance_done() {
+        return match last {
+            true => sign(&tro, settings, job, &info, journal, notify),
+            false => Ok(()),
+        };
+    }
+
+    // killed jobs may be short on time and are unlikely to have a complete
+    // trace, record what is known and get the TRO signed
+    if let Some(termination) = Termination::of(&info) {
+        notify(&format!(
+            "spank-tro: job {} ended with {}, finalizing its TRO right away",
+            job.jobid, termination
+        ));
+        let start_time = tracker::find_trace(settings.tracker, job, step_start)
+            .map_or(job.start_time as f64, |trace| trace.start_time);
+        let result = timestamp::from_unix_f64(start_time).and_then(|start| {
+            journal.add_performance(job, || {
+                tro.add_performance(
+                    &format!("'Terminated: {}{}'", termination, job.label()),
+                    &start,
+                    &timestamp::now(),
+                    &job.initial_arrangement,
+                    &final_arrangement,
+                )
+            })
+        });
+        // annotated rather than the latest, which may be another step's
+        let job = &Job {
+            performance: result.as_deref().unwrap_or_default().to_string(),
+            ..job.clone()
+        };
+        let result = result
+            .and_then(|_| record_tasks(settings, job))
+            .and_then(|_| record_attempt(settings, job))
+            .and_then(|_| record_preload(settings, job))
+            .and_then(|_| record_timeline(settings, job, &info))
+            .and_then(|_| record_job_record(settings, job))
+            .and_then(|_| nodes::merge(settings, job));
+        incident::check(settings, job, Phase::Performance, result)?;
+        journal.set_performance_done()?;
+        return match last {
+            true => sign(&tro, settings, job, &info, journal, notify),
+            false => Ok(()),
+        };
+    }
+
+    // add performance
+    let started = Instant::now();
+    let trace = settings.retry.run(Phase::Trace, || {
+        tracker::wait_for_trace(settings, job, step_start)
+    });
+    tro.finish_phase(Phase::Trace, started, trace.is_ok());
+    let trace = match trace {
+        Err(e) if settings.dry_run => {
+            notify(&format!(
+                "spank-tro (dry-run): failed to get the trace: {}",
+                e
+            ));
+            None
+        }
+        result => incident::check(settings, job, Phase::Trace, result)?,
+    };
+    // without a trace, the performance spans the job as the plugin saw it
+    let arrived = trace.is_some();
+    let mut trace = match trace {
+        Some(trace) => trace,
+        None => tracker::find_trace(Tracker::None, job, step_start)?,
+    };
+    for argv in &mut trace.commands {
+        *argv = redact::argv(&settings.redact, argv);
+    }
+    // a performance ending before it starts is recorded as instantaneous, and
+    // flagged by record_trace
+    let end_time = trace.end_time.max(trace.start_time);
+    let result = timestamp::from_unix_f64(trace.start_time)
+        .and_then(|start| Ok((start, timestamp::from_unix_f64(end_time)?)))
+        .and_then(|(start, end)| {
+            journal.add_performance(job, || {
+                tro.add_performance(
+                    &format!("'Run magic{}{}'", job.label(), describe_command(&trace)),
+                    &start,
+                    &end,
+                    &job.initial_arrangement,
+                    &final_arrangement,
+                )
+            })
+        });
+    let job = &Job {
+        performance: result.as_deref().unwrap_or_default().to_string(),
+        ..job.clone()
+    };
+    let result = result
+        .and_then(|_| record_trace(settings, job, &trace, arrived))
+        .and_then(|_| sbom::record(settings, job, &trace))
+        .and_then(|_| libraries::record(settings, job, &trace))
+        .and_then(|_| mpi::record(settings, job, step_start))
+        .and_then(|_| platform::record(settings, job))
+        .and_then(|_| record_tasks(settings, job))
+        .and_then(|_| record_attempt(settings, job))
+        .and_then(|_| record_preload(settings, job))
+        .and_then(|_| record_timeline(settings, job, &info))
+        .and_then(|_| record_job_record(settings, job))
+        .and_then(|_| nodes::merge(settings, job));
+    incident::check(settings, job, Phase::Performance, result)?;
+    journal.set_performance_done()?;
+
+    match last {
+        true => sign(&tro, settings, job, &info, journal, notify),
+        false => Ok(()),
+    }
+}
+
+fn sign(
+    tro: &TroUtils,
+    settings: &Settings,
+    job: &Job,
+    info: &JobInfo,
+    journal: &mut Journal,
+    notify: &dyn Fn(&str),
+) -> Result<(), Report> {
+    // het jobs get signed by the last component to finish, which this one
+    // may have found out before being interrupted
+    if let (Some(het), false) = (job.het, settings.dry_run || journal.het_finished()) {
+        let finished = {
+            // the components' record is beside the declaration, the user's
+            let _user = privilege::as_user(job);
+            het.finish(&job.declaration)?
+        };
+        if !finished {
+            notify(&format!(
+                "spank-tro: the TRO of het job {} will be signed once all its components are done",
+                het.leader
+            ));
+            return Ok(());
+        }
+        journal.set_het_finished()?;
+    }
+    // what went missing is covered by the signatures
+    if let Err(e) = incident::annotate(settings, job) {
+        info!("Failed to record incidents: {:#}", e);
+    }
+    // sign TRO, with each of the keys in turn
+    let keys = signing::keys(settings, (job.uid, job.gid));
+    let fingerprints: Vec<String> = keys
+        .as_ref()
+        .map(|keys| keys.iter().map(|key| key.fingerprint.clone()).collect())
+        .unwrap_or_default();
+    let result = match keys {
+        Ok(_) if settings.signer == Signer::Sigstore => {
+            sign_keyless(tro, settings, job, journal, notify)
+        }
+        Ok(keys) => sign_with(tro, settings, job, &keys, journal, notify),
+        Err(e) => {
+            audit_signing(settings, job, "", false)?;
+            Err(e)
+        }
+    };
+    let signed = incident::check(settings, job, Phase::Sign, result)?.is_some();
+    // an unsigned TRO still tells why
+    if !signed {
+        if let Err(e) = incident::annotate(settings, job) {
+            info!("Failed to record incidents: {:#}", e);
+        }
+    }
+    let status = match signed {
+        true => Status::Signed,
+        false => Status::Unsigned,
+    };
+    let entry = CatalogEntry::new(job, info.get("Partition"), &fingerprints, status);
+    catalog::record(settings, &entry);
+    if let (Some(metrics), true) = (tro.metrics(), signed) {
+        if let Err(e) = metrics.record_generated() {
+            info!("Failed to update metrics: {}", e);
+        }
+    }
+    if settings.export_intoto && signed && !settings.dry_run {
+        let attestation = intoto::default_path(&job.declaration);
+        let _user = privilege::as_user(job);
+        match intoto::export(&job.declaration, &attestation) {
+            Ok(()) => info!("Wrote {}", attestation.display()),
+            Err(e) => info!("Failed to export the in-toto attestation: {:#}", e),
+        }
+    }
+    if settings.export_slsa && signed && !settings.dry_run {
+        let provenance = slsa::default_path(&job.declaration);
+        let builder = Builder {
+            cluster: job.cluster.clone(),
+            partition: info.get("Partition").map(str::to_string),
+            nodes: info
+                .get("NodeList")
+                .map(str::to_string)
+                .into_iter()
+                .collect(),
+        };
+        let _user = privilege::as_user(job);
+        match slsa::export(&job.declaration, &provenance, Some(&builder)) {
+            Ok(()) => info!("Wrote {}", provenance.display()),
+            Err(e) => info!("Failed to export the SLSA provenance: {:#}", e),
+        }
+    }
+    // the task's TRO stands on its own, the workflow's is a convenience
+    if let (Some(workflow), true) = (&job.workflow, signed) {
+        let aggregate = workflow.aggregate_path();
+        // the workflow's root is the user's, as the tasks' declarations
+        let _user = privilege::as_user(job);
+        match workflow::aggregate(&aggregate, Some(workflow), &job.declaration) {
+            Ok(()) => info!(
+                "Added {} to {}",
+                job.declaration.display(),
+                aggregate.display()
+            ),
+            Err(e) => info!("Failed to add to the workflow's TRO: {:#}", e),
+        }
+    }
+    Ok(())
+}
+
+fn sign_with(
+    tro: &TroUtils,
+    settings: &Settings,
+    job: &Job,
+    keys: &[SigningKey],
+    journal: &mut Journal,
+    notify: &dyn Fn(&str),
+) -> Result<(), Report> {
+    // every signature covers who else signs the declaration, and until when
+    let described = keys.len() > 1 || keys.iter().any(|key| key.expires.is_some());
+    if described && !settings.dry_run {
+        let signers: Vec<Value> = keys
+            .iter()
+            .map(|key| {
+                json!({
+                    term("fingerprint"): key.fingerprint,
+                    term("role"): key.role(),
+                    term("expires"): key.expires,
+                })
+            })
+            .collect();
+        let mut declaration = Declaration::of(job)?;
+        declaration.annotate_tro("signers", Value::Array(signers))?;
+        declaration.save()?;
+    }
+    // tro-utils and gpg sign as root with the site key
+    if !settings.dry_run {
+        privilege::refuse_symlink(&job.declaration)?;
+        privilege::refuse_symlink(&job.declaration.with_extension("sig"))?;
+        for role in [None, Some("user"), Some("site")] {
+            privilege::refuse_symlink(&signing::detached_signature(&job.declaration, role))?;
+        }
+    }
+    // signed before the finalization was interrupted, and unchanged since
+    if journal.signed(&job.declaration) {
+        info!("{} is already signed", job.declaration.display());
+    } else {
+        for (i, key) in keys.iter().enumerate() {
+            let last = i + 1 == keys.len();
+            let mut result = tro.sign(key);
+            // the last signature stays where verifiers look for it
+            if result.is_ok() && !last && !settings.dry_run {
+                result = signing::set_aside(&job.declaration, key.role()).map(|_| ());
+            }
+            if result.is_ok() && settings.detached_signature {
+                let signature =
+                    signing::detached_signature(&job.declaration, (!last).then(|| key.role()));
+                result = match settings.dry_run {
+                    true => {
+                        notify(&format!(
+                            "spank-tro (dry-run): would write {} with gpg",
+                            signature.display()
+                        ));
+                        Ok(())
+                    }
+                    false => signing::detach_sign(settings, key, &job.declaration, &signature),
+                };
+            }
+            audit_signing(settings, job, &key.fingerprint, result.is_ok())?;
+            result?;
+            tro.progress(&format!("TRO signed with key {}", key.fingerprint));
+        }
+        if !settings.dry_run {
+            journal.set_signed(&job.declaration)?;
+        }
+    }
+    if !settings.dry_run {
+        let mut files = vec![job.declaration.clone()];
+        files.extend(signing::signature_files(&job.declaration));
+        privilege::hand_over(job, &files)?;
+    }
+    Ok(())
+}
+
+// signer=sigstore: cosign signs with a certificate issued for the cluster's
+// identity rather than with a key
+fn sign_keyless(
+    tro: &TroUtils,
+    settings: &Settings,
+    job: &Job,
+    journal: &mut Journal,
+    notify: &dyn Fn(&str),
+) -> Result<(), Report> {
+    let bundle = sigstore::bundle_path(&job.declaration, None);
+    if settings.dry_run {
+        notify(&format!(
+            "spank-tro (dry-run): would sign {} with cosign into {}",
+            job.declaration.display(),
+            bundle.display()
+        ));
+        return Ok(());
+    }
+    privilege::refuse_symlink(&job.declaration)?;
+    privilege::refuse_symlink(&bundle)?;
+    if journal.signed(&job.declaration) {
+        info!("{} is already signed", job.declaration.display());
+    } else {
+        let result = sigstore::sign(settings, &job.declaration);
+        audit_signing(settings, job, "sigstore", result.is_ok())?;
+        result?;
+        tro.progress("TRO signed keylessly with Sigstore");
+        journal.set_signed(&job.declaration)?;
+    }
+    privilege::hand_over(job, &[job.declaration.clone(), bundle])
+}
+
+fn audit_signing(
+    settings: &Settings,
+    job: &Job,
+    fingerprint: &str,
+    success: bool,
+) -> Result<(), Report> {
+    let (Some(audit_log), false) = (&settings.audit_log, settings.dry_run) else {
+        return Ok(());
+    };
+    let event = SigningEvent::new(
+        job.jobid,
+        job.uid,
+        &job.user,
+        fingerprint,
+        &job.declaration,
+        success,
+    );
+    AuditLog::new(Path::new(audit_log))
+        .record(&event)
+        .wrap_err("Failed to record signing in the audit log")
+}
+
+// Record the digests of the job's stdout and stderr in the final arrangement,
+// they usually live in the workdir but don't have to
+fn record_outputs(settings: &Settings, job: &Job, info: &JobInfo) -> Result<(), Report> {
+    let outputs = output::resolve(job, info);
+    if settings.dry_run || outputs.is_empty() {
+        return Ok(());
+    }
+    let mut described: Vec<Value> = Vec::new();
+    {
+        // as the user, who chose where they go
+        let _user = privilege::as_user(job);
+        for (stream, path) in outputs {
+            match output::describe(settings, stream, &path) {
+                Ok(output) => described.push(output),
+                // e.g. --output=/dev/null
+                Err(e) => info!("Skipping {}: {:#}", path.display(), e),
+            }
+        }
+    }
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_arrangement("outputs", Value::Array(described))?;
+    declaration.save()
+}
+
+// Record the files of the workdir the job wrote, when spank-tro-watch tracked
+// them, telling its outputs apart from the inputs also in the arrangement
+fn record_written(settings: &Settings, job: &Job) -> Result<(), Report> {
+    let Some(changes) = Changes::load(job)? else {
+        return Ok(());
+    };
+    if !settings.dry_run {
+        let mut declaration = Declaration::of(job)?;
+        declaration.annotate_arrangement(
+            "written",
+            json!({
+                term("files"): changes.files,
+                term("complete"): changes.complete,
+            }),
+        )?;
+        declaration.save()?;
+    }
+    let path = changes_path(job);
+    fs::remove_file(&path).wrap_err_with(|| format!("Failed to remove {}", path.display()))
+}
+
+// Record the files added, removed and modified since the initial arrangement
+// in the final one
+fn record_diff(settings: &Settings, job: &Job, final_arrangement: &str) -> Result<(), Report> {
+    if settings.dry_run || job.initial_arrangement.is_empty() {
+        return Ok(());
+    }
+    let mut declaration = Declaration::of(job)?;
+    let diff = Diff::between(
+        &declaration.locations(&job.initial_arrangement),
+        &declaration.locations(final_arrangement),
+    );
+    declaration.annotate_arrangement("diff", diff.to_json(&job.initial_arrangement))?;
+    declaration.save()
+}
+
+// Record whether the trace arrived, how long it ran, and the command lines
+// and files the tracker saw, in the performance that was just added
+fn record_trace(
+    settings: &Settings,
+    job: &Job,
+    trace: &Trace,
+    arrived: bool,
+) -> Result<(), Report> {
+    if settings.dry_run {
+        return Ok(());
+    }
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_performance("traceArrived", Value::Bool(arrived))?;
+    declaration.annotate_performance("durationSeconds", json!(trace.duration()))?;
+    declaration.annotate_performance("clockAnomaly", Value::Bool(trace.clock_anomaly()))?;
+    if !trace.commands.is_empty() {
+        let commands: Vec<Value> = trace.commands.iter().map(|argv| json!(argv)).collect();
+        declaration.annotate_performance("commands", Value::Array(commands))?;
+    }
+    if !trace.files.is_empty() {
+        declaration.annotate_performance("files", json!(trace.files))?;
+    }
+    if !trace.executables.is_empty() {
+        let executables: Vec<Value> = trace
+            .executables
+            .iter()
+            .map(|executable| {
+                json!({
+                    term("path"): executable.path,
+                    // the program may be gone by now
+                    term("sha256"): digest::sha256_file(&executable.path).ok(),
+                    term("xaltHash"): executable.xalt_hash,
+                })
+            })
+            .collect();
+        declaration.annotate_performance("executables", Value::Array(executables))?;
+    }
+    declaration.save()
+}
+
+// ": <command line>" of the first program the tracker saw, kept short as it
+// ends up in the performance's comment
+fn describe_command(trace: &Trace) -> String {
+    const MAX_LENGTH: usize = 200;
+    let Some(argv) = trace.commands.first() else {
+        return String::new();
+    };
+    let mut command = argv.join(" ").replace('\'', "");
+    if command.chars().count() > MAX_LENGTH {
+        command = command.chars().take(MAX_LENGTH).collect::<String>() + "...";
+    }
+    format!(": {command}")
+}
+
+// Number the performance of a requeued job with its attempt
+fn record_attempt(settings: &Settings, job: &Job) -> Result<(), Report> {
+    if settings.dry_run || job.restart == 0 {
+        return Ok(());
+    }
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_performance("attempt", Value::from(job.restart + 1))?;
+    declaration.save()
+}
+
+// Record the libraries preloaded into the tasks, in the order ld.so loads
+// them, as the tracker left them
+fn record_preload(settings: &Settings, job: &Job) -> Result<(), Report> {
+    let (Some(preload), false) = (&job.preload, settings.dry_run) else {
+        return Ok(());
+    };
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_performance("preload", json!(preload.split(':').collect::<Vec<_>>()))?;
+    declaration.save()
+}
+
+// Record when the job was submitted, became eligible, started and ended, to
+// tell its time in the queue from its run time
+fn record_timeline(settings: &Settings, job: &Job, info: &JobInfo) -> Result<(), Report> {
+    if settings.dry_run {
+        return Ok(());
+    }
+    let timeline = Timeline::of(settings, job, info);
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_performance("timeline", timeline.describe())?;
+    declaration.save()
+}
+
+// Record the configured fields of slurmrestd's record of the job, if it can
+// be reached. The TRO is complete without them.
+fn record_job_record(settings: &Settings, job: &Job) -> Result<(), Report> {
+    if settings.dry_run {
+        return Ok(());
+    }
+    let fields = match slurmrestd::job_fields(settings, job.jobid) {
+        Ok(Some(fields)) => fields,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            info!("{:#}", e);
+            return Ok(());
+        }
+    };
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_tro("slurmJob", fields)?;
+    declaration.save()
+}
+
+// Record how each task ended in the performance that was just added
+fn record_tasks(settings: &Settings, job: &Job) -> Result<(), Report> {
+    if settings.dry_run || job.tasks.is_empty() {
+        return Ok(());
+    }
+    let tasks: Vec<Value> = job
+        .tasks
+        .iter()
+        .map(|task| {
+            json!({
+                term("task"): task.task,
+                term("exitCode"): task.exit_code,
+                term("signal"): task.signal,
+            })
+        })
+        .collect();
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_performance("tasks", Value::Array(tasks))?;
+    declaration.annotate_performance(
+        "succeeded",
+        Value::Bool(job.tasks.iter().all(TaskExit::succeeded)),
+    )?;
+    declaration.save()
+}
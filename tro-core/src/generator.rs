@@ -0,0 +1,71 @@
+// What generated a TRO: spank-tro and the commit it was built from, the Slurm
+// it was built against, tro-utils and XALT. Stamped on every TRO so that the
+// ones a bug of any of them affects can be told apart.
+use eyre::Report;
+use serde_json::{json, Value};
+use std::process::Command;
+use tracing::debug;
+
+use crate::command;
+use crate::config::Config;
+use crate::declaration::{term, Declaration};
+use crate::job::Job;
+use crate::tracker::Tracker;
+use crate::tro_utils;
+
+// SLURM_VERSION_NUMBER as Slurm writes its versions, e.g. 23.02.7
+pub fn slurm_version(number: u32) -> String {
+    format!(
+        "{}.{:02}.{}",
+        number >> 16,
+        (number >> 8) & 0xff,
+        number & 0xff
+    )
+}
+
+// XALT_VERSION of xalt_configuration_report
+pub fn parse_xalt_version(report: &str) -> Option<String> {
+    report.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        let key = key.trim().to_uppercase().replace(' ', "_");
+        (key == "XALT_VERSION").then(|| value.trim().to_string())
+    })
+}
+
+fn xalt_version(config: &Config) -> Option<String> {
+    let report = config
+        .xalt_dir
+        .join("bin")
+        .join("xalt_configuration_report");
+    match command::run(&mut Command::new(report), config.settings.command_timeout) {
+        Ok(output) => parse_xalt_version(&String::from_utf8_lossy(&output.stdout)),
+        Err(e) => {
+            debug!("{:#}", e);
+            None
+        }
+    }
+}
+
+pub fn describe(config: &Config) -> Value {
+    let settings = &config.settings;
+    let xalt = match settings.tracker {
+        Tracker::Xalt => xalt_version(config),
+        _ => None,
+    };
+    json!({
+        term("version"): env!("CARGO_PKG_VERSION"),
+        term("commit"): option_env!("SPANK_TRO_GIT_COMMIT"),
+        term("slurmVersion"): config.slurm_version.map(slurm_version),
+        term("troUtilsVersion"): tro_utils::version(settings).ok().map(tro_utils::display_version),
+        term("xaltVersion"): xalt,
+    })
+}
+
+pub fn record(config: &Config, job: &Job) -> Result<(), Report> {
+    if config.settings.dry_run {
+        return Ok(());
+    }
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_tro("generator", describe(config))?;
+    declaration.save()
+}
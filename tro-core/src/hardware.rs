@@ -0,0 +1,182 @@
+// The hardware of a node, as part of its evidence with hardware_inventory:
+// performance, and sometimes numerical results, depend on its generation
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tracing::debug;
+
+use crate::command;
+use crate::declaration::term;
+use crate::settings::Settings;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Inventory {
+    pub cpu_model: Option<String>,
+    pub sockets: Option<usize>,
+    // physical, the node's cpus count hardware threads
+    pub cores: Option<usize>,
+    pub gpus: Vec<Gpu>,
+    pub interconnects: Vec<Interconnect>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Gpu {
+    pub model: String,
+    pub driver: Option<String>,
+    pub memory_mib: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Interconnect {
+    pub device: String,
+    // InfiniBand, Omni-Path, Slingshot or RoCE
+    pub kind: String,
+    pub rate: Option<String>,
+}
+
+impl Inventory {
+    pub fn collect(settings: &Settings) -> Self {
+        let cpuinfo = fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+        let (cpu_model, sockets, cores) = cpu(&cpuinfo);
+        Inventory {
+            cpu_model,
+            sockets,
+            cores,
+            gpus: gpus(settings),
+            interconnects: interconnects(Path::new("/sys/class")),
+        }
+    }
+
+    pub fn describe(&self) -> Value {
+        let gpus: Vec<Value> = self
+            .gpus
+            .iter()
+            .map(|gpu| {
+                json!({
+                    term("model"): gpu.model,
+                    term("driver"): gpu.driver,
+                    term("memoryMiB"): gpu.memory_mib,
+                })
+            })
+            .collect();
+        let interconnects: Vec<Value> = self
+            .interconnects
+            .iter()
+            .map(|interconnect| {
+                json!({
+                    term("device"): interconnect.device,
+                    term("kind"): interconnect.kind,
+                    term("rate"): interconnect.rate,
+                })
+            })
+            .collect();
+        json!({
+            term("cpuModel"): self.cpu_model,
+            term("sockets"): self.sockets,
+            term("cores"): self.cores,
+            term("gpus"): gpus,
+            term("interconnects"): interconnects,
+        })
+    }
+}
+
+// Model, sockets and physical cores from /proc/cpuinfo, whose x86 flavor
+// names the model and tells the physical id and core id of each thread
+pub fn cpu(cpuinfo: &str) -> (Option<String>, Option<usize>, Option<usize>) {
+    let mut model = None;
+    let mut sockets = HashSet::new();
+    let mut cores = HashSet::new();
+    let mut socket = None;
+    for line in cpuinfo.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "model name" if model.is_none() => model = Some(value.to_string()),
+            "physical id" => {
+                socket = Some(value.to_string());
+                sockets.insert(value.to_string());
+            }
+            "core id" => {
+                cores.insert((socket.clone(), value.to_string()));
+            }
+            _ => {}
+        }
+    }
+    let count = |set: usize| (set > 0).then_some(set);
+    (model, count(sockets.len()), count(cores.len()))
+}
+
+// From nvidia-smi, none on nodes without it
+fn gpus(settings: &Settings) -> Vec<Gpu> {
+    let output = command::run(
+        Command::new(&settings.nvidia_smi).args([
+            "--query-gpu=name,driver_version,memory.total",
+            "--format=csv,noheader,nounits",
+        ]),
+        settings.command_timeout,
+    );
+    let output = match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+        Err(e) => {
+            debug!("{:#}", e);
+            return Vec::new();
+        }
+    };
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(',').map(str::trim);
+            Some(Gpu {
+                model: fields.next().filter(|model| !model.is_empty())?.to_string(),
+                driver: fields.next().map(str::to_string),
+                memory_mib: fields.next().and_then(|memory| memory.parse().ok()),
+            })
+        })
+        .collect()
+}
+
+// The RDMA devices below /sys/class/infiniband and the Cassini NICs of
+// Slingshot below /sys/class/cxi
+pub fn interconnects(sys_class: &Path) -> Vec<Interconnect> {
+    let names = |dir: &Path| -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| Some(entry.ok()?.file_name().to_string_lossy().into_owned()))
+            .collect();
+        names.sort();
+        names
+    };
+    let mut interconnects = Vec::new();
+    for device in names(&sys_class.join("infiniband")) {
+        let port = sys_class.join("infiniband").join(&device).join("ports/1");
+        let read = |name: &str| {
+            fs::read_to_string(port.join(name))
+                .ok()
+                .map(|value| value.trim().to_string())
+        };
+        let kind = match (device.starts_with("hfi1"), read("link_layer").as_deref()) {
+            (true, _) => "Omni-Path",
+            (false, Some("Ethernet")) => "RoCE",
+            (false, _) => "InfiniBand",
+        };
+        interconnects.push(Interconnect {
+            device,
+            kind: kind.to_string(),
+            rate: read("rate"),
+        });
+    }
+    for device in names(&sys_class.join("cxi")) {
+        interconnects.push(Interconnect {
+            device,
+            kind: "Slingshot".to_string(),
+            rate: None,
+        });
+    }
+    interconnects
+}
@@ -0,0 +1,80 @@
+use eyre::{Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::declaration;
+use crate::privilege;
+
+// A component of a heterogeneous job. All components share the declaration of
+// the het leader, each adding its own arrangements and performance.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct HetComponent {
+    pub leader: u32,
+    pub offset: u32,
+    pub size: u32,
+}
+
+impl HetComponent {
+    // Component jobs are numbered after their leader
+    pub fn new(jobid: u32, leader: u32, size: u32) -> Self {
+        HetComponent {
+            leader,
+            offset: jobid.saturating_sub(leader),
+            size,
+        }
+    }
+
+    // The component described by SLURM_HET_SIZE and SLURM_JOB_ID_HET_GROUP_0,
+    // if the job is heterogeneous
+    pub fn from_env(
+        jobid: u32,
+        het_size: Option<&str>,
+        het_leader: Option<&str>,
+    ) -> Result<Option<Self>, Report> {
+        let Some(size) = het_size else {
+            return Ok(None);
+        };
+        let size = size.parse().wrap_err("Invalid SLURM_HET_SIZE")?;
+        let leader = match het_leader {
+            Some(leader) => leader
+                .parse()
+                .wrap_err("Invalid SLURM_JOB_ID_HET_GROUP_0")?,
+            None => jobid,
+        };
+        Ok(Some(HetComponent::new(jobid, leader, size)))
+    }
+
+    // Record that this component has been finalized, returns true once every
+    // component of the job is, i.e. when the TRO can be signed
+    pub fn finish(&self, declaration: &Path) -> Result<bool, Report> {
+        let _lock = declaration::lock(declaration)
+            .wrap_err_with(|| format!("Failed to lock {}", declaration.display()))?;
+        let mut path = declaration.as_os_str().to_owned();
+        path.push(".het");
+        let mut finished: BTreeSet<u32> = match fs::read_to_string(&path) {
+            Ok(content) => content.lines().filter_map(|l| l.parse().ok()).collect(),
+            Err(_) => BTreeSet::new(),
+        };
+        finished.insert(self.offset);
+        if finished.len() >= self.size as usize {
+            let _ = fs::remove_file(&path);
+            return Ok(true);
+        }
+        let content: Vec<String> = finished.iter().map(u32::to_string).collect();
+        let path = PathBuf::from(path);
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        privilege::create_nofollow(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(content.join("\n").as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, &path))
+            .wrap_err_with(|| format!("Failed to update {}", path.display()))?;
+        Ok(false)
+    }
+}
@@ -0,0 +1,120 @@
+// Phases that failed without failing the job, recorded in the TRO it still
+// gets as spank:incidents, so that what is missing from it is told apart
+// from what was never attempted
+use eyre::{Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::declaration::{term, Declaration};
+use crate::failure::FailurePolicy;
+use crate::job::Job;
+use crate::phase::Phase;
+use crate::privilege;
+use crate::settings::Settings;
+use crate::timestamp;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Incident {
+    pub phase: Phase,
+    pub error: String,
+    pub time: String,
+}
+
+impl Incident {
+    fn describe(&self) -> Value {
+        json!({
+            term("phase"): self.phase.to_string(),
+            term("error"): self.error,
+            term("time"): self.time,
+        })
+    }
+}
+
+// Incidents of the job until its TRO is signed, the declaration may not be
+// there yet when the first ones happen
+pub fn incidents_path(job: &Job) -> PathBuf {
+    job.workdir
+        .join(".tro")
+        .join(format!("{}.incidents.jsonl", job.key()))
+}
+
+// on_failure.check(), keeping the failures it only warns about
+pub fn check<T>(
+    settings: &Settings,
+    job: &Job,
+    phase: Phase,
+    result: Result<T, Report>,
+) -> Result<Option<T>, Report> {
+    if let Err(e) = &result {
+        if settings.on_failure.policy(phase) == FailurePolicy::Warn && !settings.dry_run {
+            if let Err(e) = record(job, phase, e) {
+                info!("Failed to record the incident: {:#}", e);
+            }
+        }
+    }
+    settings.on_failure.check(phase, result)
+}
+
+pub fn record(job: &Job, phase: Phase, error: &Report) -> Result<(), Report> {
+    let incident = Incident {
+        phase,
+        error: format!("{error:#}"),
+        time: timestamp::now(),
+    };
+    let mut line = serde_json::to_string(&incident)?;
+    line.push('\n');
+    let path = incidents_path(job);
+    let _user = privilege::as_user(job);
+    fs::create_dir_all(job.workdir.join(".tro"))?;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))
+}
+
+pub fn load(job: &Job) -> Result<Vec<Incident>, Report> {
+    let path = incidents_path(job);
+    let _user = privilege::as_user(job);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    content
+        .lines()
+        .map(|line| {
+            serde_json::from_str(line)
+                .wrap_err_with(|| format!("Invalid incident in {}", path.display()))
+        })
+        .collect()
+}
+
+// Add the job's incidents to its declaration before it gets signed
+pub fn annotate(settings: &Settings, job: &Job) -> Result<(), Report> {
+    if settings.dry_run {
+        return Ok(());
+    }
+    let incidents = load(job)?;
+    if incidents.is_empty() {
+        return Ok(());
+    }
+    let mut declaration = Declaration::of(job)?;
+    // those of the previous attempts of a requeued job come first
+    let mut described = declaration
+        .tro_term("incidents")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    described.extend(incidents.iter().map(Incident::describe));
+    declaration.annotate_tro("incidents", Value::Array(described))?;
+    declaration.save()?;
+    let path = incidents_path(job);
+    let _user = privilege::as_user(job);
+    fs::remove_file(&path).wrap_err_with(|| format!("Failed to remove {}", path.display()))
+}
@@ -0,0 +1,257 @@
+// The final arrangement of a job from its initial one: files whose size and
+// modification time did not change since the plugin looked at them before
+// the initial arrangement keep their artifact, only the others are hashed
+// again. Written by the plugin rather than tro-utils, in the shape of the
+// initial arrangement, and only when it can keep the composition's
+// fingerprint as tro-utils computes it.
+use eyre::{Report, WrapErr};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::declaration::{term, Declaration};
+use crate::digest;
+use crate::inputs;
+use crate::job::Job;
+use crate::privilege;
+
+// Size and modification time in nanoseconds of each file, by path relative
+// to the workdir
+pub type Stats = BTreeMap<String, (u64, i64)>;
+
+pub fn stats_path(job: &Job) -> PathBuf {
+    job.workdir
+        .join(".tro")
+        .join(format!("{}.stats.json", job.key()))
+}
+
+fn stat_workdir(job: &Job) -> Result<Stats, Report> {
+    let mut files = Vec::new();
+    inputs::list_files(&job.workdir, &job.workdir, &mut files)?;
+    let mut stats = Stats::new();
+    for relative in files {
+        // tro-utils leaves .git out
+        if relative.starts_with(".git") {
+            continue;
+        }
+        let metadata = match fs::symlink_metadata(job.workdir.join(&relative)) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let mtime = metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec();
+        stats.insert(
+            relative.to_string_lossy().into_owned(),
+            (metadata.len(), mtime),
+        );
+    }
+    Ok(stats)
+}
+
+// Look at the workdir before tro-utils hashes it for the initial arrangement:
+// a file changing in between then differs at the end and is hashed again
+pub fn snapshot(job: &Job) -> Result<(), Report> {
+    let _user = privilege::as_user(job);
+    let stats = stat_workdir(job)?;
+    let path = stats_path(job);
+    fs::create_dir_all(job.workdir.join(".tro"))?;
+    fs::write(&path, serde_json::to_vec(&stats)?)
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))
+}
+
+// Add the final arrangement, returns its @id, or None if it has to be left to
+// tro-utils: no snapshot, no initial arrangement, or a composition fingerprint
+// computed otherwise
+pub fn final_arrangement(
+    declaration: &mut Declaration,
+    job: &Job,
+    comment: &str,
+) -> Result<Option<String>, Report> {
+    let stats_path = stats_path(job);
+    let before: Stats = match fs::read(&stats_path) {
+        Ok(content) => serde_json::from_slice(&content)
+            .wrap_err_with(|| format!("Failed to parse {}", stats_path.display()))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let tro = declaration.tro()?;
+    let Some(composition) = tro
+        .get("trov:hasComposition")
+        .and_then(Value::as_object)
+        .cloned()
+    else {
+        return Ok(None);
+    };
+    let mut artifacts = records(&composition["trov:hasArtifact"]);
+    if composition.contains_key("trov:hasFingerprint")
+        && composition["trov:hasFingerprint"]["trov:sha256"] != json!(fingerprint(&artifacts))
+    {
+        info!(
+            "The composition fingerprint is not the expected one, tro-utils rehashes the workdir"
+        );
+        return Ok(None);
+    }
+    let arrangements = records(&tro["trov:hasArrangement"]);
+    let Some(initial) = arrangements
+        .iter()
+        .find(|arrangement| arrangement["@id"] == json!(job.initial_arrangement))
+    else {
+        return Ok(None);
+    };
+    let Some((locus_key, template)) = ["trov:hasLocus", "trov:hasArtifactLocation"]
+        .into_iter()
+        .find_map(|key| Some((key, records(initial.get(key)?).first()?.clone())))
+    else {
+        return Ok(None);
+    };
+    // other shapes than the one the plugin knows are left to tro-utils
+    let Some(artifact_template) = artifacts.first().cloned() else {
+        return Ok(None);
+    };
+    if template["trov:hasLocation"].is_null() || template["trov:hasArtifact"].is_null() {
+        return Ok(None);
+    }
+
+    // where each file of the initial arrangement was, and its artifact
+    let mut initial_loci: HashMap<String, Value> = HashMap::new();
+    for locus in records(&initial[locus_key]) {
+        if let Some(location) = locus["trov:hasLocation"].as_str() {
+            initial_loci.insert(location.to_string(), locus.clone());
+        }
+    }
+    let mut by_sha256: HashMap<String, String> = artifacts
+        .iter()
+        .filter_map(|artifact| {
+            Some((
+                artifact["trov:sha256"].as_str()?.to_string(),
+                artifact["@id"].as_str()?.to_string(),
+            ))
+        })
+        .collect();
+
+    let after = {
+        let _user = privilege::as_user(job);
+        stat_workdir(job)?
+    };
+    let id = format!("arrangement/{}", arrangements.len());
+    let initial_id = job.initial_arrangement.as_str();
+    let mut loci = Vec::new();
+    let (mut carried, mut recomputed) = (0, Vec::new());
+    for (location, stat) in &after {
+        let artifact_id = match (before.get(location), initial_loci.get(location)) {
+            (Some(previous), Some(locus)) if previous == stat => {
+                carried += 1;
+                locus["trov:hasArtifact"]["@id"]
+                    .as_str()
+                    .map(str::to_string)
+            }
+            _ => None,
+        };
+        let artifact_id = match artifact_id {
+            Some(artifact_id) => artifact_id,
+            None => {
+                let path = job.workdir.join(location);
+                let sha256 = {
+                    let _user = privilege::as_user(job);
+                    digest::sha256_file(&path)
+                        .wrap_err_with(|| format!("Failed to hash {}", path.display()))?
+                };
+                recomputed.push(location.clone());
+                by_sha256
+                    .entry(sha256.clone())
+                    .or_insert_with(|| {
+                        let mut artifact = artifact_template.clone();
+                        let artifact_id = renumber(
+                            artifact_template["@id"].as_str().unwrap_or_default(),
+                            artifacts.len(),
+                        );
+                        artifact["@id"] = json!(artifact_id);
+                        artifact["trov:sha256"] = json!(sha256);
+                        // nothing is known of its type
+                        if let Value::Object(artifact) = &mut artifact {
+                            artifact.remove("trov:mimeType");
+                        }
+                        artifacts.push(artifact);
+                        artifact_id
+                    })
+                    .clone()
+            }
+        };
+        let mut locus = template.clone();
+        if let Some(template_id) = template["@id"].as_str() {
+            let suffix = template_id
+                .strip_prefix(initial_id)
+                .unwrap_or("/location/0");
+            locus["@id"] = json!(format!("{id}{}", renumber(suffix, loci.len())));
+        }
+        locus["trov:hasArtifact"] = json!({ "@id": artifact_id });
+        locus["trov:hasLocation"] = json!(location);
+        loci.push(locus);
+    }
+
+    let mut arrangement = Map::new();
+    if let Some(kind) = initial.get("@type") {
+        arrangement.insert("@type".to_string(), kind.clone());
+    }
+    arrangement.insert("@id".to_string(), json!(id));
+    arrangement.insert("rdfs:comment".to_string(), json!(comment));
+    arrangement.insert(locus_key.to_string(), Value::Array(loci));
+    arrangement.insert(
+        term("incremental"),
+        json!({
+            term("carriedOver"): carried,
+            term("recomputed"): recomputed,
+        }),
+    );
+
+    let mut composition = composition;
+    if composition.contains_key("trov:hasFingerprint") {
+        composition["trov:hasFingerprint"]["trov:sha256"] = json!(fingerprint(&artifacts));
+    }
+    composition.insert("trov:hasArtifact".to_string(), Value::Array(artifacts));
+    let tro = declaration.tro()?;
+    tro.insert(
+        "trov:hasComposition".to_string(),
+        Value::Object(composition),
+    );
+    let mut all: Vec<Value> = arrangements;
+    all.push(Value::Object(arrangement));
+    tro.insert("trov:hasArrangement".to_string(), Value::Array(all));
+    declaration.save()?;
+    info!(
+        "Final arrangement {id}: {carried} files carried over, {} hashed again",
+        recomputed.len()
+    );
+    Ok(Some(id))
+}
+
+// tro-utils' composition fingerprint: the SHA-256 of its artifacts' digests,
+// sorted and concatenated
+fn fingerprint(artifacts: &[Value]) -> String {
+    let mut digests: Vec<&str> = artifacts
+        .iter()
+        .filter_map(|artifact| artifact["trov:sha256"].as_str())
+        .collect();
+    digests.sort();
+    digest::sha256_bytes(digests.concat().as_bytes())
+}
+
+// e.g. composition/1/artifact/3 to composition/1/artifact/<n>
+fn renumber(id: &str, n: usize) -> String {
+    match id.rsplit_once('/') {
+        Some((prefix, _)) => format!("{prefix}/{n}"),
+        None => n.to_string(),
+    }
+}
+
+fn records(value: &Value) -> Vec<Value> {
+    match value {
+        Value::Array(records) => records.clone(),
+        Value::Null => Vec::new(),
+        record => vec![record.clone()],
+    }
+}
@@ -0,0 +1,168 @@
+use eyre::{eyre, Report, WrapErr};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::cas::Cas;
+use crate::declaration::term;
+use crate::digest::{self, Fingerprint};
+use crate::job::Job;
+use crate::privilege;
+use crate::settings::Settings;
+
+// An input of the job that lives outside its workdir, declared with
+// --tro-input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    // hashed when the job starts, relative paths are in the workdir
+    Path(PathBuf),
+    // e.g. https://zenodo.org/records/123, recorded as given
+    Uri(String),
+    // 10.5281/zenodo.123, given as is, as doi:... or https://doi.org/...
+    Doi(String),
+}
+
+impl FromStr for Input {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let doi = value
+            .strip_prefix("doi:")
+            .or_else(|| value.strip_prefix("https://doi.org/"))
+            .or_else(|| value.starts_with("10.").then_some(value));
+        if let Some(doi) = doi {
+            return match doi.split_once('/') {
+                Some((prefix, suffix)) if prefix.starts_with("10.") && !suffix.is_empty() => {
+                    Ok(Input::Doi(doi.to_string()))
+                }
+                _ => Err(eyre!("{value} is not a DOI")),
+            };
+        }
+        if let Some(path) = value.strip_prefix("file://") {
+            return Ok(Input::Path(PathBuf::from(path)));
+        }
+        match value.split_once("://") {
+            Some((scheme, _)) if !scheme.is_empty() => Ok(Input::Uri(value.to_string())),
+            _ if value.is_empty() => Err(eyre!("An input cannot be empty")),
+            _ => Ok(Input::Path(PathBuf::from(value))),
+        }
+    }
+}
+
+// The inputs of a --tro-input value, separated by commas
+pub fn parse(value: &str) -> Result<Vec<Input>, Report> {
+    value
+        .split(',')
+        .filter(|input| !input.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+impl Input {
+    // What the initial arrangement says about the input, with where its
+    // content is in the site's store, if any. It is read as the job's user,
+    // the store is written as root.
+    pub fn describe(
+        &self,
+        settings: &Settings,
+        job: &Job,
+        cas: Option<&Cas>,
+    ) -> Result<Value, Report> {
+        match self {
+            Input::Doi(doi) => Ok(json!({ term("doi"): doi })),
+            Input::Uri(uri) => Ok(json!({ term("uri"): uri })),
+            Input::Path(path) => {
+                let (path, metadata) = {
+                    let _user = privilege::as_user(job);
+                    let path = fs::canonicalize(job.workdir.join(path))
+                        .wrap_err_with(|| format!("Input {} does not exist", path.display()))?;
+                    let metadata = fs::metadata(&path)?;
+                    (path, metadata)
+                };
+                let mut described = json!({
+                    term("path"): path,
+                    term("contentSize"): metadata.len(),
+                });
+                if metadata.is_dir() {
+                    let (sha256, size, partial) = sha256_dir(settings, job, &path, cas)?;
+                    described[term("sha256")] = json!(sha256);
+                    described[term("contentSize")] = json!(size);
+                    if partial > 0 {
+                        described[term("partiallyHashedFiles")] = json!(partial);
+                    }
+                    return Ok(described);
+                }
+                let fingerprint = {
+                    let _user = privilege::as_user(job);
+                    digest::fingerprint(settings, &path)?
+                };
+                if let (Some(cas), Fingerprint::Sha256(sha256)) = (cas, &fingerprint) {
+                    described[term("casPath")] = json!(cas.store_file(&path, sha256)?);
+                }
+                if let Value::Object(described) = &mut described {
+                    described.extend(fingerprint.terms());
+                }
+                Ok(described)
+            }
+        }
+    }
+}
+
+// SHA-256 of a directory: of the sorted lines "<sha256>  <relative path>" of
+// its files, as sha256sum would print them, their total size and how many
+// were above max_hash_size, whose lines have their fingerprint instead. In a
+// store, the lines are stored under the directory's digest and the files
+// under theirs.
+fn sha256_dir(
+    settings: &Settings,
+    job: &Job,
+    dir: &Path,
+    cas: Option<&Cas>,
+) -> Result<(String, u64, usize), Report> {
+    let mut files = Vec::new();
+    let mut lines = String::new();
+    let (mut size, mut partial) = (0, 0);
+    {
+        let _user = privilege::as_user(job);
+        list_files(dir, dir, &mut files)?;
+    }
+    files.sort();
+    for relative in files {
+        let path = dir.join(&relative);
+        let fingerprint = {
+            let _user = privilege::as_user(job);
+            size += fs::metadata(&path)?.len();
+            digest::fingerprint(settings, &path)
+                .wrap_err_with(|| format!("Failed to hash {}", path.display()))?
+        };
+        partial += usize::from(fingerprint.is_partial());
+        if let (Some(cas), Fingerprint::Sha256(sha256)) = (cas, &fingerprint) {
+            cas.store_file(&path, sha256)?;
+        }
+        lines.push_str(&format!(
+            "{}  {}\n",
+            fingerprint.token(),
+            relative.display()
+        ));
+    }
+    let sha256 = digest::sha256_bytes(lines.as_bytes());
+    if let Some(cas) = cas {
+        cas.store_bytes(lines.as_bytes(), &sha256)?;
+    }
+    Ok((sha256, size, partial))
+}
+
+pub fn list_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Report> {
+    for entry in fs::read_dir(dir).wrap_err_with(|| format!("Failed to list {}", dir.display()))? {
+        let path = entry?.path();
+        // like tro-utils, links are not followed
+        let metadata = path.symlink_metadata()?;
+        if metadata.is_dir() {
+            list_files(root, &path, files)?;
+        } else if metadata.is_file() {
+            files.push(path.strip_prefix(root)?.to_path_buf());
+        }
+    }
+    Ok(())
+}
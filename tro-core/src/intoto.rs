@@ -0,0 +1,90 @@
+// Export of a TRO as an in-toto attestation, for supply-chain tooling: a
+// Statement whose subject is what the run produced, the files of its final
+// arrangement, with a link predicate whose materials are what it started
+// from, the files of its initial arrangement
+use eyre::{eyre, Report, WrapErr};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::declaration::{term, Declaration};
+use crate::digest;
+
+pub const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+pub const LINK_PREDICATE: &str = "https://in-toto.io/attestation/link/v0.3";
+
+// Where the attestation of a declaration goes by default, tro-1.intoto.json
+// next to tro-1.jsonld
+pub fn default_path(declaration: &Path) -> PathBuf {
+    declaration.with_extension("intoto.json")
+}
+
+fn descriptors(locations: Vec<(String, String)>) -> Vec<Value> {
+    locations
+        .into_iter()
+        .map(|(location, sha256)| json!({ "name": location, "digest": { "sha256": sha256 } }))
+        .collect()
+}
+
+// What the run produced, the files of the final arrangement. A statement
+// needs a subject, the TRO itself for runs leaving no file.
+pub fn subject(declaration: &Declaration, name: &str, tro_sha256: &str) -> Vec<Value> {
+    let mut products = descriptors(declaration.last_locations());
+    if products.is_empty() {
+        products.push(json!({ "name": name, "digest": { "sha256": tro_sha256 } }));
+    }
+    products
+}
+
+// argv of the first program of the job, its batch script usually
+pub fn command(declaration: &Declaration) -> Value {
+    declaration
+        .last_performance_term("commands")
+        .and_then(Value::as_array)
+        .and_then(|commands| commands.first())
+        .cloned()
+        .unwrap_or(Value::Array(Vec::new()))
+}
+
+// The statement for a declaration named `name` whose content hashes to
+// `tro_sha256`
+pub fn statement(declaration: &Declaration, name: &str, tro_sha256: &str) -> Result<Value, Report> {
+    let arrangements = declaration.arrangements();
+    let (initial, _) = arrangements
+        .first()
+        .ok_or_else(|| eyre!("The declaration has no arrangement"))?;
+    let materials = descriptors(declaration.locations(initial));
+    let mut environment = serde_json::Map::new();
+    for key in ["preload", "timeline"] {
+        if let Some(value) = declaration.last_performance_term(key) {
+            environment.insert(term(key), value.clone());
+        }
+    }
+    Ok(json!({
+        "_type": STATEMENT_TYPE,
+        "subject": subject(declaration, name, tro_sha256),
+        "predicateType": LINK_PREDICATE,
+        "predicate": {
+            "name": name,
+            "command": command(declaration),
+            "materials": materials,
+            "byproducts": {
+                "tro": { "name": name, "digest": { "sha256": tro_sha256 } },
+            },
+            "environment": environment,
+        },
+    }))
+}
+
+pub fn export(declaration: &Path, path: &Path) -> Result<(), Report> {
+    let name = declaration
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let tro_sha256 = digest::sha256_file(declaration)
+        .wrap_err_with(|| format!("Failed to read {}", declaration.display()))?;
+    let statement = statement(&Declaration::load(declaration)?, &name, &tro_sha256)?;
+    fs::write(path, serde_json::to_vec_pretty(&statement)?)
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))
+}
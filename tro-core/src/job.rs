@@ -0,0 +1,289 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::envspec::Environment;
+use crate::het::HetComponent;
+use crate::roots::Root;
+use crate::workflow::Workflow;
+
+// Step ids with a special meaning
+pub const BATCH_STEP: u32 = 0xfffffffb;
+pub const EXTERN_STEP: u32 = 0xfffffffc;
+pub const INTERACTIVE_STEP: u32 = 0xfffffffa;
+
+// The job a TRO is generated for
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Job {
+    pub jobid: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub user: String,
+    // where the job runs and its files are fingerprinted, and the
+    // declaration, there or in the site's declaration_dir
+    pub workdir: PathBuf,
+    pub declaration: PathBuf,
+    // unix time at which the plugin set up the job
+    pub start_time: i64,
+    // unix time at which the job's tasks were done, once they are
+    pub end_time: Option<i64>,
+    pub tasks: Vec<TaskExit>,
+    pub het: Option<HetComponent>,
+    // @id of the arrangement recorded when the job started
+    pub initial_arrangement: String,
+    // @id of the performance recorded for the job, once it is
+    #[serde(default)]
+    pub performance: String,
+    // with a TRO per step rather than per job, the step the records are for
+    pub step: Option<u32>,
+    // pid of the tracer recording the job's programs while they run
+    pub tracer: Option<u32>,
+    // pid of spank-tro-watch, recording arrangements while the job runs
+    pub watcher: Option<u32>,
+    // how many times the job was requeued or restarted before this attempt
+    #[serde(default)]
+    pub restart: u32,
+    // inputs outside the workdir declared with --tro-input
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    // SLURM_JOB_DEPENDENCY, the jobs whose TROs this one's links to
+    #[serde(default)]
+    pub dependency: Option<String>,
+    // the Nextflow or Snakemake run the job is a task of
+    #[serde(default)]
+    pub workflow: Option<Workflow>,
+    // unix time at which slurmctld started the job, SLURM_JOB_START_TIME
+    #[serde(default)]
+    pub scheduled_start: Option<i64>,
+    // SLURM_CLUSTER_NAME, for catalogs shared by clusters
+    #[serde(default)]
+    pub cluster: Option<String>,
+    // LD_PRELOAD of the tasks once the tracker set it up
+    #[serde(default)]
+    pub preload: Option<String>,
+    // where workdir came from
+    #[serde(default)]
+    pub workdir_source: WorkdirSource,
+    // directories the arrangements cover besides workdir
+    #[serde(default)]
+    pub roots: Vec<Root>,
+    // no progress messages, only warnings and errors
+    #[serde(default)]
+    pub quiet: bool,
+    // SLURM_NODEID, the head node is 0
+    #[serde(default)]
+    pub node_id: u32,
+    // SLURM_JOB_NUM_NODES, the nodes that leave their evidence for the TRO
+    #[serde(default)]
+    pub node_count: u32,
+    // conda, virtualenv and Spack environments the job runs in
+    #[serde(default)]
+    pub environments: Vec<Environment>,
+}
+
+// The directory a TRO fingerprints: the submission directory, the job's
+// working directory as slurmctld knows it, or the site's fallback_workdir
+// when neither is known, unless the job runs elsewhere with --tro-exec-dir or
+// exec_dir=
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkdirSource {
+    #[default]
+    Submit,
+    Slurmctld,
+    Fallback,
+    Exec,
+}
+
+impl WorkdirSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkdirSource::Submit => "submit",
+            WorkdirSource::Slurmctld => "slurmctld",
+            WorkdirSource::Fallback => "fallback",
+            WorkdirSource::Exec => "exec",
+        }
+    }
+}
+
+impl Job {
+    // A job starting now in `workdir`. Its TRO is named after the job, or the
+    // het leader.
+    pub fn new(
+        jobid: u32,
+        (uid, gid): (u32, u32),
+        user: String,
+        workdir: PathBuf,
+        het: Option<HetComponent>,
+        step: Option<u32>,
+    ) -> Self {
+        let tro_jobid = het.map_or(jobid, |het| het.leader);
+        Job {
+            jobid,
+            uid,
+            gid,
+            user,
+            declaration: workdir.join(format!("tro-{tro_jobid}.jsonld")),
+            workdir,
+            start_time: now(),
+            end_time: None,
+            tasks: Vec::new(),
+            het,
+            initial_arrangement: String::new(),
+            performance: String::new(),
+            step,
+            tracer: None,
+            watcher: None,
+            restart: 0,
+            inputs: Vec::new(),
+            dependency: None,
+            workflow: None,
+            scheduled_start: None,
+            cluster: None,
+            preload: None,
+            workdir_source: WorkdirSource::Submit,
+            roots: Vec::new(),
+            quiet: false,
+            node_id: 0,
+            node_count: 1,
+            environments: Vec::new(),
+        }
+    }
+
+    // Identifies the job, or the step with a TRO per step, e.g. in the spool
+    pub fn key(&self) -> String {
+        match self.step {
+            Some(step) => format!("{}.{}", self.jobid, step),
+            None => self.jobid.to_string(),
+        }
+    }
+
+    // Suffix of the comments of the records added by this job, telling het
+    // components and steps apart
+    pub fn label(&self) -> String {
+        let mut parts: Vec<String> = Vec::new();
+        if self.restart > 0 {
+            parts.push(format!("attempt {}", self.restart + 1));
+        }
+        if let Some(het) = self.het {
+            parts.push(format!("het component {}", het.offset));
+        }
+        match self.step {
+            Some(BATCH_STEP) => parts.push("batch step".to_string()),
+            Some(EXTERN_STEP) => parts.push("extern step".to_string()),
+            Some(INTERACTIVE_STEP) => parts.push("interactive step".to_string()),
+            Some(step) => parts.push(format!("step {step}")),
+            None => {}
+        }
+        match parts.is_empty() {
+            true => String::new(),
+            false => format!(" ({})", parts.join(", ")),
+        }
+    }
+}
+
+// What the placeholders of tro_name_template stand for
+#[derive(Debug, Clone, Default)]
+pub struct NameFields<'a> {
+    pub jobid: u32,
+    pub user: &'a str,
+    pub job_name: &'a str,
+    // array job id and task id
+    pub array: Option<(u32, u32)>,
+    // unix time at which the job started, that of the name of every step
+    pub start: Option<i64>,
+}
+
+// Expand a declaration file name template: %j jobid, %u user, %x job name,
+// %A array job id (the jobid outside arrays), %a array task id (empty
+// outside arrays), %d date the job started as YYYYMMDD and %% for %
+pub fn declaration_name(template: &str, fields: &NameFields) -> String {
+    let mut name = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            name.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => name.push('%'),
+            Some('j') => name.push_str(&fields.jobid.to_string()),
+            Some('u') => name.push_str(fields.user),
+            Some('x') => name.push_str(fields.job_name),
+            Some('A') => {
+                let array_job = fields.array.map_or(fields.jobid, |(job, _)| job);
+                name.push_str(&array_job.to_string())
+            }
+            Some('a') => {
+                if let Some((_, task)) = fields.array {
+                    name.push_str(&task.to_string())
+                }
+            }
+            Some('d') => {
+                let start = fields
+                    .start
+                    .and_then(|start| chrono::DateTime::from_timestamp(start, 0))
+                    .unwrap_or_else(chrono::Utc::now);
+                name.push_str(&start.format("%Y%m%d").to_string())
+            }
+            Some(other) => {
+                name.push('%');
+                name.push(other);
+            }
+            None => name.push('%'),
+        }
+    }
+    // a job name must not take the TRO out of the workdir
+    name.replace('/', "_")
+}
+
+// Expand a directory template like a file name, component by component,
+// e.g. /archive/%u
+pub fn expand_dir(template: &str, fields: &NameFields) -> PathBuf {
+    let components: Vec<String> = template
+        .split('/')
+        .map(|component| declaration_name(component, fields))
+        .collect();
+    PathBuf::from(components.join("/"))
+}
+
+// Where the declaration of a job goes: the site's declaration_dir, or else
+// `submit_dir`, under the name of tro_name_template
+pub fn declaration_path(config: &Config, fields: &NameFields, submit_dir: &Path) -> PathBuf {
+    let dir = match &config.declaration_dir {
+        Some(template) => expand_dir(template, fields),
+        None => submit_dir.to_path_buf(),
+    };
+    dir.join(declaration_name(&config.tro_name_template, fields))
+}
+
+// How one of the job's tasks ended
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskExit {
+    pub task: u32,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+}
+
+impl TaskExit {
+    // Decode a wait(2) status
+    pub fn from_wait_status(task: u32, status: i32) -> Self {
+        TaskExit {
+            task,
+            exit_code: libc::WIFEXITED(status).then(|| libc::WEXITSTATUS(status)),
+            signal: libc::WIFSIGNALED(status).then(|| libc::WTERMSIG(status)),
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+// Current unix time
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
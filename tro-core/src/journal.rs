@@ -0,0 +1,175 @@
+// Progress of a finalization queued in the spool, kept as <key>.journal next
+// to its spec. A finalizer dying halfway, with slurmstepd or the node, leaves
+// the spec claimed; the next one to pick it up starts from the journal
+// rather than adding a second final arrangement or performance, and only
+// signs again if the declaration changed since it was signed.
+use eyre::{Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::declaration::Declaration;
+use crate::digest;
+use crate::job::Job;
+use crate::privilege;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Journal {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    // records in the declaration before tro-utils was asked to add one, to
+    // tell whether it got to do it
+    arrangements_before: Option<usize>,
+    final_arrangement: Option<String>,
+    performances_before: Option<usize>,
+    performance_done: bool,
+    // the last component of a het job to finish, which signs its TRO
+    het_finished: bool,
+    // SHA-256 of the declaration when it was signed
+    signed_sha256: Option<String>,
+}
+
+pub fn journal_path(spool_dir: &Path, key: &str) -> PathBuf {
+    spool_dir.join(format!("{key}.journal"))
+}
+
+impl Journal {
+    // Not kept anywhere, for finalizations that cannot be resumed
+    pub fn none() -> Self {
+        Journal::default()
+    }
+
+    pub fn open(path: &Path) -> Result<Self, Report> {
+        let mut journal: Journal = match fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .wrap_err_with(|| format!("Invalid journal {}", path.display()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Journal::default(),
+            Err(e) => return Err(e).wrap_err_with(|| format!("Failed to read {}", path.display())),
+        };
+        journal.path = Some(path.to_path_buf());
+        Ok(journal)
+    }
+
+    // Whether an earlier finalization got anywhere
+    pub fn resumed(&self) -> bool {
+        self.arrangements_before.is_some()
+    }
+
+    fn save(&self) -> Result<(), Report> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        privilege::create_nofollow(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(&serde_json::to_vec(self)?)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .wrap_err_with(|| format!("Failed to write {}", path.display()))
+    }
+
+    // The finalization is over, there is nothing to resume
+    pub fn remove(&self) -> Result<(), Report> {
+        match &self.path {
+            Some(path) => match fs::remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    Err(e).wrap_err_with(|| format!("Failed to remove {}", path.display()))
+                }
+                _ => Ok(()),
+            },
+            None => Ok(()),
+        }
+    }
+
+    // @id of the final arrangement, once it is recorded with what goes in it
+    pub fn final_arrangement(&self) -> Option<&str> {
+        self.final_arrangement.as_deref()
+    }
+
+    // Add the final arrangement with `add`, or return the one an earlier
+    // finalization added
+    pub fn add_arrangement(
+        &mut self,
+        job: &Job,
+        add: impl FnOnce() -> Result<String, Report>,
+    ) -> Result<String, Report> {
+        if self.path.is_some() {
+            let mut declaration = Declaration::of(job)?;
+            let count = declaration.arrangement_count();
+            match (self.arrangements_before, declaration.last_arrangement()) {
+                (Some(before), Some(id)) if count > before => return Ok(id),
+                _ => {
+                    self.arrangements_before = Some(count);
+                    drop(declaration);
+                    self.save()?;
+                }
+            }
+        }
+        add()
+    }
+
+    pub fn set_final_arrangement(&mut self, id: &str) -> Result<(), Report> {
+        self.final_arrangement = Some(id.to_string());
+        self.save()
+    }
+
+    // Add the performance with `add` unless an earlier finalization did,
+    // returns its @id
+    pub fn add_performance(
+        &mut self,
+        job: &Job,
+        add: impl FnOnce() -> Result<String, Report>,
+    ) -> Result<String, Report> {
+        if self.path.is_some() {
+            let performances = Declaration::of(job)?.performances();
+            match self.performances_before {
+                // the first one after those there were before
+                Some(before) if performances.len() > before => {
+                    return Ok(performances[before].0.clone())
+                }
+                _ => {
+                    self.performances_before = Some(performances.len());
+                    self.save()?;
+                }
+            }
+        }
+        add()
+    }
+
+    pub fn performance_done(&self) -> bool {
+        self.performance_done
+    }
+
+    pub fn set_performance_done(&mut self) -> Result<(), Report> {
+        self.performance_done = true;
+        self.save()
+    }
+
+    pub fn het_finished(&self) -> bool {
+        self.het_finished
+    }
+
+    pub fn set_het_finished(&mut self) -> Result<(), Report> {
+        self.het_finished = true;
+        self.save()
+    }
+
+    // Whether the declaration is still what was signed
+    pub fn signed(&self, declaration: &Path) -> bool {
+        self.signed_sha256
+            .as_ref()
+            .is_some_and(|signed| digest::sha256_file(declaration).ok().as_ref() == Some(signed))
+    }
+
+    pub fn set_signed(&mut self, declaration: &Path) -> Result<(), Report> {
+        if self.path.is_none() {
+            return Ok(());
+        }
+        self.signed_sha256 = Some(digest::sha256_file(declaration)?);
+        self.save()
+    }
+}
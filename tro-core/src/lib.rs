@@ -0,0 +1,76 @@
+// TRO generation independent of Slurm, shared by the SPANK plugin and the
+// helpers that finalize TROs outside of slurmstepd. `lifecycle` drives a job's
+// TRO from a `config::Config` and a `job::Job`, `context` decides what to do
+// from what the plugin sees of the job; the other modules are the building
+// blocks: tro-utils calls, traces of the job's programs, declaration
+// annotations and the spool of deferred finalizations.
+pub mod attachment;
+pub mod audit;
+pub mod batch_script;
+pub mod cas;
+pub mod catalog;
+pub mod changes;
+pub mod command;
+pub mod compare;
+pub mod config;
+pub mod context;
+pub mod cwlprov;
+pub mod declaration;
+pub mod dependency;
+pub mod diff;
+pub mod digest;
+pub mod encryption;
+pub mod envspec;
+pub mod failure;
+pub mod filesystems;
+pub mod finalize;
+pub mod generator;
+pub mod hardware;
+pub mod het;
+pub mod incident;
+pub mod incremental;
+pub mod inputs;
+pub mod intoto;
+pub mod job;
+pub mod journal;
+pub mod libraries;
+pub mod lifecycle;
+pub mod lockfile;
+pub mod logging;
+pub mod merkle;
+pub mod metrics;
+pub mod mpi;
+pub mod munge;
+pub mod nodes;
+pub mod output;
+pub mod phase;
+pub mod platform;
+pub mod policy;
+pub mod preflight;
+pub mod privilege;
+pub mod redact;
+pub mod report;
+pub mod retry;
+pub mod roots;
+pub mod sbom;
+pub mod schema;
+pub mod scontrol;
+pub mod secrets;
+pub mod session;
+pub mod settings;
+pub mod signing;
+pub mod sigstore;
+pub mod slsa;
+pub mod slurmrestd;
+pub mod spool;
+pub mod termination;
+pub mod timeline;
+pub mod timestamp;
+pub mod topology;
+pub mod tpm;
+pub mod tracker;
+pub mod tro_utils;
+pub mod watch;
+pub mod workdir;
+pub mod workflow;
+pub mod xalt;
@@ -0,0 +1,101 @@
+// Where the shared libraries of a job came from: for each library XALT saw
+// its programs load, the Lmod module providing it, the package that installed
+// it and its build hash, so that the TRO tells which BLAS was actually used
+use eyre::{Report, WrapErr};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::declaration::{term, Declaration};
+use crate::digest;
+use crate::job::Job;
+use crate::privilege;
+use crate::sbom::Owners;
+use crate::settings::Settings;
+use crate::tracker::Trace;
+
+// Lmod's reverse map of the directories of modules to their names, as
+// written by `spider -o jsonReverseMapT` or kept by XALT in xalt_rmapT.json
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReverseMap {
+    // longest directories first
+    directories: Vec<(PathBuf, String)>,
+}
+
+impl ReverseMap {
+    pub fn load(path: &Path) -> Result<Self, Report> {
+        let map: Value = serde_json::from_slice(
+            &fs::read(path).wrap_err_with(|| format!("Failed to read {}", path.display()))?,
+        )
+        .wrap_err_with(|| format!("Invalid reverse map {}", path.display()))?;
+        Ok(ReverseMap::from_json(&map))
+    }
+
+    pub fn from_json(map: &Value) -> Self {
+        let map = map.get("reverseMapT").unwrap_or(map);
+        let mut directories: Vec<(PathBuf, String)> = map
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(directory, module)| {
+                // the name, or with newer Lmod, an object naming it
+                let module = module.as_str().or_else(|| module["pkg"].as_str())?;
+                Some((PathBuf::from(directory), module.to_string()))
+            })
+            .collect();
+        directories.sort_by_key(|(directory, _)| std::cmp::Reverse(directory.as_os_str().len()));
+        ReverseMap { directories }
+    }
+
+    pub fn module(&self, path: &Path) -> Option<&str> {
+        self.directories
+            .iter()
+            .find(|(directory, _)| path.starts_with(directory))
+            .map(|(_, module)| module.as_str())
+    }
+}
+
+// The libraries of a trace, as in the performance
+pub fn resolve(settings: &Settings, trace: &Trace, modules: &ReverseMap) -> Vec<Value> {
+    let mut owners = Owners::new(settings);
+    let mut resolved: Vec<Value> = Vec::new();
+    for library in &trace.libraries {
+        if resolved
+            .iter()
+            .any(|known| known[term("path")] == json!(library.path))
+        {
+            continue;
+        }
+        let package = owners.of(&library.path);
+        resolved.push(json!({
+            term("path"): library.path,
+            // the library may be gone by now
+            term("sha256"): digest::sha256_file(&library.path).ok(),
+            term("xaltHash"): library.xalt_hash,
+            term("module"): modules.module(&library.path),
+            term("package"): package.as_ref().map(|package| package.purl()),
+            term("buildHash"): package.and_then(|package| package.hash),
+        }));
+    }
+    resolved
+}
+
+// Add where the libraries of the trace came from to the performance that was
+// just added
+pub fn record(settings: &Settings, job: &Job, trace: &Trace) -> Result<(), Report> {
+    if !settings.library_provenance || settings.dry_run || trace.libraries.is_empty() {
+        return Ok(());
+    }
+    let modules = match &settings.lmod_reverse_map {
+        Some(path) => ReverseMap::load(path)?,
+        None => ReverseMap::default(),
+    };
+    let libraries = {
+        // the libraries are hashed as the user, who loaded them
+        let _user = privilege::as_user(job);
+        resolve(settings, trace, &modules)
+    };
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_performance("libraries", Value::Array(libraries))?;
+    declaration.save()
+}
@@ -0,0 +1,378 @@
+use eyre::{eyre, Report, WrapErr};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use tracing::info;
+
+use crate::batch_script;
+use crate::cas::Cas;
+use crate::command;
+use crate::config::Config;
+use crate::declaration::{term, Declaration};
+use crate::dependency;
+use crate::envspec;
+use crate::filesystems;
+use crate::finalize::{finalize, FinalizeMode};
+use crate::generator;
+use crate::incident;
+use crate::incremental;
+use crate::inputs::Input;
+use crate::job::{declaration_path, now, Job, NameFields};
+use crate::journal::Journal;
+use crate::logging::JobLog;
+use crate::nodes;
+use crate::phase::Phase;
+use crate::privilege;
+use crate::session::Session;
+use crate::settings::Settings;
+use crate::signing;
+use crate::sigstore;
+use crate::spool::FinalizationSpec;
+use crate::tracker;
+use crate::tro_utils::{self, TroUtils};
+use crate::watch;
+
+// The life of a job's TRO, from its initial arrangement to its signature, for
+// the SPANK plugin or any other tool running jobs
+
+// The per-job log, if the site enabled it
+pub fn job_log(config: &Config, job: &Job) -> Result<Option<JobLog>, Report> {
+    if !config.job_log {
+        return Ok(None);
+    }
+    let _user = privilege::as_user(job);
+    JobLog::create(
+        job.workdir.join(".tro"),
+        job.jobid,
+        (job.uid, job.gid),
+        config.job_log_max_size,
+        config.job_log_keep,
+    )
+    .map(Some)
+    .wrap_err("Failed to create the job log")
+}
+
+// Record the initial arrangement, with the batch script read from
+// `script_path` or asked to slurmctld
+pub fn start(
+    config: &Config,
+    job: &mut Job,
+    script_path: Option<&Path>,
+    notify: &dyn Fn(&str),
+) -> Result<(), Report> {
+    let settings = &config.settings;
+    // the other nodes of the job only leave their evidence once it is over
+    if job.node_id > 0 {
+        return Ok(());
+    }
+    if job.restart > 0 && !settings.dry_run {
+        resume(job, notify)?;
+    }
+    if settings.incremental_arrangement && !settings.dry_run {
+        // the final arrangement then hashes everything again
+        if let Err(e) = incremental::snapshot(job) {
+            info!("{:#}", e);
+        }
+    }
+    let tro = TroUtils::new(settings, job, notify);
+    let result = match settings.dry_run {
+        true => Ok(()),
+        false => tro_utils::negotiate(settings)
+            .and_then(|_| declaration_dir(job))
+            .map(|_| ()),
+    };
+    let result =
+        result.and_then(|_| tro.add_arrangement(&format!("'Initial arrangement{}'", job.label())));
+    let result = match result {
+        Ok(id) if settings.dry_run => Ok(id),
+        Ok(id) => batch_script::fetch(settings, job.jobid, script_path)
+            .and_then(|script| batch_script::record(settings, job, &script))
+            .map(|_| id),
+        Err(e) => Err(e),
+    };
+    let result = result
+        .and_then(|id| record_attempt(settings, job).map(|_| id))
+        .and_then(|id| record_workdir(settings, job).map(|_| id))
+        .and_then(|id| generator::record(config, job).map(|_| id))
+        .and_then(|id| filesystems::record(settings, job).map(|_| id))
+        .and_then(|id| record_inputs(settings, job).map(|_| id))
+        .and_then(|id| envspec::record(settings, job).map(|_| id))
+        .and_then(|id| record_dependencies(config, job).map(|_| id));
+    if let Some(id) = incident::check(settings, job, Phase::Arrangement, result)? {
+        tro.progress(&format!("TRO initialized at {}", job.declaration.display()));
+        job.initial_arrangement = id;
+    }
+    if !settings.dry_run {
+        let result = tracker::start(settings, job);
+        job.tracer = incident::check(settings, job, Phase::Trace, result)?.flatten();
+    }
+    if watch::needed(settings) && !settings.dry_run {
+        let result = watch::start(config, job);
+        job.watcher = incident::check(settings, job, Phase::Arrangement, result)?;
+    }
+    Ok(())
+}
+
+// With declaration_dir=, the TRO goes to a directory the user may not have
+// used yet
+fn declaration_dir(job: &Job) -> Result<(), Report> {
+    let Some(dir) = job.declaration.parent() else {
+        return Ok(());
+    };
+    let _user = privilege::as_user(job);
+    fs::create_dir_all(dir).wrap_err_with(|| format!("Failed to create {}", dir.display()))
+}
+
+// A requeued or restarted job adds an epoch, its own arrangements and
+// performance, to the TRO of its previous attempts. Whatever signed them no
+// longer covers the TRO, the signatures are kept aside as tro-1.attempt-1.sig
+// and the like.
+fn resume(job: &Job, notify: &dyn Fn(&str)) -> Result<(), Report> {
+    if !job.declaration.exists() {
+        return Ok(());
+    }
+    notify(&format!(
+        "spank-tro: job {} was requeued, attempt {} is added to {}",
+        job.jobid,
+        job.restart + 1,
+        job.declaration.display()
+    ));
+    let tag = format!("attempt-{}", job.restart);
+    if signing::signature(&job.declaration).is_some() {
+        signing::set_aside(&job.declaration, &tag)?;
+    }
+    let detached = signing::detached_signature(&job.declaration, None);
+    if detached.exists() {
+        let aside = signing::detached_signature(&job.declaration, Some(&tag));
+        fs::rename(&detached, &aside)
+            .wrap_err_with(|| format!("Failed to rename {}", detached.display()))?;
+    }
+    let bundle = sigstore::bundle_path(&job.declaration, None);
+    if bundle.exists() {
+        let aside = sigstore::bundle_path(&job.declaration, Some(&tag));
+        fs::rename(&bundle, &aside)
+            .wrap_err_with(|| format!("Failed to rename {}", bundle.display()))?;
+    }
+    Ok(())
+}
+
+// Number the initial arrangement of a requeued job with its attempt
+fn record_attempt(settings: &Settings, job: &Job) -> Result<(), Report> {
+    if settings.dry_run || job.restart == 0 {
+        return Ok(());
+    }
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_arrangement("attempt", Value::from(job.restart + 1))?;
+    declaration.save()
+}
+
+// Record the directory the TRO fingerprints and where it came from, e.g. the
+// fallback_workdir or --tro-exec-dir rather than where the job was submitted
+fn record_workdir(settings: &Settings, job: &Job) -> Result<(), Report> {
+    if settings.dry_run {
+        return Ok(());
+    }
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_tro(
+        "workdir",
+        json!({
+            term("path"): job.workdir,
+            term("source"): job.workdir_source.as_str(),
+            term("declarationDir"): job.declaration.parent(),
+        }),
+    )?;
+    declaration.save()
+}
+
+// Record the inputs declared with --tro-input in the initial arrangement,
+// local ones with their digests
+fn record_inputs(settings: &Settings, job: &Job) -> Result<(), Report> {
+    if settings.dry_run || job.inputs.is_empty() {
+        return Ok(());
+    }
+    let cas = Cas::of(settings, job);
+    let mut described = Vec::new();
+    for input in &job.inputs {
+        let input: Input = input.parse()?;
+        described.push(input.describe(settings, job, cas.as_ref())?);
+    }
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_arrangement("inputs", Value::Array(described))?;
+    declaration.save()
+}
+
+// Link the TRO to those of the jobs it depends on, and to the workflow run it
+// is a task of, so that the TROs of a pipeline of jobs make up one graph
+fn record_dependencies(config: &Config, job: &Job) -> Result<(), Report> {
+    let settings = &config.settings;
+    if settings.dry_run || job.dependency.is_none() && job.workflow.is_none() {
+        return Ok(());
+    }
+    let mut described = Vec::new();
+    if let Some(dependency) = &job.dependency {
+        for dependency in dependency::parse(dependency)? {
+            let dir = job.declaration.parent().unwrap_or(&job.workdir);
+            let path = dependency.declaration(config, dir);
+            // another user's TRO is hashed only if they let this one read it
+            let _user = privilege::as_user(job);
+            described.push(dependency.describe(&path));
+        }
+    }
+    let mut declaration = Declaration::of(job)?;
+    if !described.is_empty() {
+        declaration.annotate_tro("dependsOn", Value::Array(described))?;
+    }
+    if let Some(workflow) = &job.workflow {
+        declaration.annotate_tro("workflow", workflow.describe())?;
+    }
+    declaration.save()
+}
+
+// Record an arrangement of the workdir of a running job, labelled by its user
+pub fn snapshot(
+    settings: &Settings,
+    job: &Job,
+    label: &str,
+    notify: &dyn Fn(&str),
+) -> Result<String, Report> {
+    if !job.declaration.exists() && !settings.dry_run {
+        return Err(eyre!(
+            "{} does not exist, the job has no TRO to snapshot",
+            job.declaration.display()
+        ));
+    }
+    let comment = format!("'Snapshot: {}{}'", label.replace('\'', ""), job.label());
+    TroUtils::new(settings, job, notify).add_arrangement(&comment)
+}
+
+// Finalize the TRO once the job is over, in place or as configured with
+// finalize=
+pub fn finish(config: &Config, job: &mut Job, notify: &dyn Fn(&str)) -> Result<(), Report> {
+    job.end_time = Some(now());
+    if let Some(tracer) = job.tracer.take() {
+        command::stop(tracer);
+    }
+    if let Some(watcher) = job.watcher.take() {
+        watch::stop(watcher, job);
+    }
+    if nodes::needed(&config.settings, job) {
+        let result = nodes::deposit(&config.settings, job, nodes::collect(job));
+        incident::check(&config.settings, job, Phase::Performance, result)?;
+        if job.node_id > 0 {
+            return Ok(());
+        }
+    }
+    // dry runs report to the user, so they always happen right here
+    if config.finalize != FinalizeMode::Sync && !config.settings.dry_run {
+        match defer(config, job) {
+            Ok(()) => return Ok(()),
+            // finalize in place rather than lose the TRO
+            Err(e) => info!("Failed to defer finalization: {:#}", e),
+        }
+    }
+    finalize(&config.settings, job, &mut Journal::none(), notify)
+}
+
+// Queue the finalization in the spool, and unless the finalizer daemon or the
+// epilog picks it up, hand it to a detached helper so that slurmstepd can
+// return immediately
+fn defer(config: &Config, job: &Job) -> Result<(), Report> {
+    let spec = FinalizationSpec {
+        settings: config.settings.clone(),
+        job: job.clone(),
+    };
+    let path = spec.write(&config.spool_dir)?;
+    if matches!(config.finalize, FinalizeMode::Daemon | FinalizeMode::Epilog) {
+        info!(
+            "Finalization of job {} queued in {}",
+            job.jobid,
+            path.display()
+        );
+        return Ok(());
+    }
+    if let Err(e) = command::spawn_detached(Command::new(&config.finalizer).arg(&path)) {
+        let _ = fs::remove_file(&path);
+        return Err(e).wrap_err_with(|| format!("Failed to start {}", config.finalizer.display()));
+    }
+    info!(
+        "Finalization of job {} deferred to {}",
+        job.jobid,
+        path.display()
+    );
+    Ok(())
+}
+
+// Start the TRO of an interactive allocation of `user` in `workdir`, whose job
+// id is not known yet. Returns the session to hand to the commands run in the
+// allocation so that they can snapshot it with spank-tro-snapshot.
+pub fn start_session(
+    config: &Config,
+    (uid, gid): (u32, u32),
+    user: String,
+    workdir: PathBuf,
+    notify: &dyn Fn(&str),
+) -> Result<(Job, PathBuf), Report> {
+    let session_id = process::id();
+    let mut job = Job {
+        uid,
+        gid,
+        user,
+        declaration: workdir.join(format!("tro-salloc-{session_id}.jsonld")),
+        start_time: now(),
+        workdir,
+        ..Job::default()
+    };
+    let tro = TroUtils::new(&config.settings, &job, notify);
+    let result = tro
+        .add_arrangement("'Initial arrangement'")
+        .and_then(|id| generator::record(config, &job).map(|_| id));
+    if let Some(id) = incident::check(&config.settings, &job, Phase::Arrangement, result)? {
+        job.initial_arrangement = id;
+    }
+
+    let session = job
+        .workdir
+        .join(".tro")
+        .join(format!("salloc-{session_id}.json"));
+    Session::new(&config.settings, &job).write(&session)?;
+    Ok((job, session))
+}
+
+// Finalize the TRO of an interactive allocation once it is released
+pub fn finish_session(
+    config: &Config,
+    job: &mut Job,
+    jobid: Option<u32>,
+    session: Option<&Path>,
+    notify: &dyn Fn(&str),
+) -> Result<(), Report> {
+    job.end_time = Some(now());
+    if let Some(session) = session {
+        // snapshots may have been taken in the meantime, nothing else changes
+        let _ = fs::remove_file(session);
+    }
+    if let Some(jobid) = jobid {
+        job.jobid = jobid;
+        // name the TRO after the job now that it is known, as a batch job's,
+        // but for the job name salloc does not tell its plugin
+        let fields = NameFields {
+            jobid,
+            user: &job.user,
+            start: Some(job.start_time),
+            ..NameFields::default()
+        };
+        let declaration = declaration_path(config, &fields, &job.workdir);
+        if !config.settings.dry_run {
+            let _user = privilege::as_user(job);
+            if let Some(dir) = declaration.parent() {
+                fs::create_dir_all(dir)
+                    .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+            }
+            fs::rename(&job.declaration, &declaration)
+                .wrap_err_with(|| format!("Failed to rename {}", job.declaration.display()))?;
+        }
+        job.declaration = declaration;
+    }
+    finalize(&config.settings, job, &mut Journal::none(), notify)
+}
@@ -0,0 +1,157 @@
+// Lock files that hold across the nodes sharing a directory, where flock()
+// may not: <path>.lock is created with O_EXCL and names its holder. Its
+// holder touches it while it runs, so that a lock left by a process or a
+// node that died is taken over once it is stale.
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+use crate::catalog::hostname;
+
+const HEARTBEAT: Duration = Duration::from_secs(10);
+// untouched for this long, the holder is gone
+const STALE_AFTER: Duration = Duration::from_secs(60);
+const RETRY: Duration = Duration::from_millis(100);
+
+// Held until dropped
+pub struct LockFile {
+    path: PathBuf,
+    holder: String,
+    stop: Arc<AtomicBool>,
+    heartbeat: Option<JoinHandle<()>>,
+}
+
+impl LockFile {
+    // Wait for the lock of `path`, taking it over from holders that are gone
+    pub fn acquire(path: &Path) -> io::Result<Self> {
+        let holder = format!(
+            "{} {} {}",
+            hostname().unwrap_or_default(),
+            process::id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+        );
+        loop {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o644)
+                .custom_flags(libc::O_NOFOLLOW)
+                .open(path)
+            {
+                Ok(mut file) => {
+                    file.write_all(holder.as_bytes())?;
+                    return Ok(LockFile::held(path, holder, file));
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    if let Err(e) = break_if_stale(path) {
+                        debug!("{}: {}", path.display(), e);
+                    }
+                    thread::sleep(RETRY);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn held(path: &Path, holder: String, file: File) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let heartbeat = thread::spawn(move || loop {
+            thread::park_timeout(HEARTBEAT);
+            if stopped.load(Ordering::Relaxed) {
+                return;
+            }
+            if let Err(e) = file.set_modified(SystemTime::now()) {
+                warn!("Failed to touch a lock file: {}", e);
+            }
+        });
+        LockFile {
+            path: path.to_path_buf(),
+            holder,
+            stop,
+            heartbeat: Some(heartbeat),
+        }
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.thread().unpark();
+            let _ = heartbeat.join();
+        }
+        // unless someone took it over
+        if read_holder(&self.path).is_ok_and(|holder| holder == self.holder) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn read_holder(path: &Path) -> io::Result<String> {
+    let mut holder = String::new();
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?
+        .read_to_string(&mut holder)?;
+    Ok(holder)
+}
+
+// Whether the holder of a lock is gone: a process of this node that is no
+// more, or a lock nobody touched for STALE_AFTER
+fn is_stale(path: &Path, holder: &str) -> io::Result<bool> {
+    let mut fields = holder.split(' ');
+    let (host, pid) = (
+        fields.next(),
+        fields.next().and_then(|pid| pid.parse::<u32>().ok()),
+    );
+    if let (Some(host), Some(pid)) = (host, pid) {
+        if Some(host) == hostname().as_deref() && pid != process::id() {
+            // kill(0) fails with ESRCH for a process that is gone
+            let alive = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0
+                || io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH);
+            if !alive {
+                return Ok(true);
+            }
+        }
+    }
+    let age = fs::symlink_metadata(path)?
+        .modified()?
+        .elapsed()
+        .unwrap_or_default();
+    Ok(age > STALE_AFTER)
+}
+
+// Remove a stale lock. It is moved aside first, so that of several
+// processes finding it stale, only one removes it, and put back if it turns
+// out to have been taken over in between.
+fn break_if_stale(path: &Path) -> io::Result<()> {
+    let holder = read_holder(path)?;
+    if !is_stale(path, &holder)? {
+        return Ok(());
+    }
+    let mut aside = path.as_os_str().to_owned();
+    aside.push(format!(".stale.{}", process::id()));
+    let aside = PathBuf::from(aside);
+    fs::rename(path, &aside)?;
+    if read_holder(&aside)? == holder {
+        warn!("Took over {}, left by {}", path.display(), holder);
+        fs::remove_file(&aside)
+    } else {
+        // without replacing one taken since
+        let restored = fs::hard_link(&aside, path);
+        fs::remove_file(&aside)?;
+        restored
+    }
+}
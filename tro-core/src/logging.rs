@@ -0,0 +1,302 @@
+use eyre::{eyre, Report};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Write as _};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{fchown, OpenOptionsExt};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Registry;
+
+const IDENTIFIER: &str = "spank-tro";
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+// LOG_DAEMON
+const SYSLOG_FACILITY: u8 = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTarget {
+    #[default]
+    Stderr,
+    Syslog,
+    Journald,
+}
+
+impl FromStr for LogTarget {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "stderr" => Ok(LogTarget::Stderr),
+            "syslog" => Ok(LogTarget::Syslog),
+            "journald" => Ok(LogTarget::Journald),
+            _ => Err(eyre!("{value} is not one of stderr/syslog/journald")),
+        }
+    }
+}
+
+// Serialize tracing levels by name
+pub mod level {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use tracing::Level;
+
+    pub fn serialize<S: Serializer>(level: &Level, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(level)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Level, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(D::Error::custom)
+    }
+}
+
+// Install the tracing subscriber. With the stderr target records are
+// written to the stderr of the hosting Slurm daemon, otherwise they are sent to
+// the local syslog or journald socket with their fields (jobid, uid, phase,
+// duration_ms, ...) kept as structured data. When a job log is given, it
+// additionally receives every record down to debug level.
+pub fn init(level: Level, target: LogTarget, job_log: Option<JobLog>) {
+    let filter = LevelFilter::from_level(level);
+    let target_layer: Box<dyn Layer<Registry> + Send + Sync> = match target {
+        LogTarget::Stderr => tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .with_ansi(false)
+            .with_target(false)
+            .with_filter(filter)
+            .boxed(),
+        LogTarget::Syslog => SocketLayer::new(target, SYSLOG_SOCKET)
+            .with_filter(filter)
+            .boxed(),
+        LogTarget::Journald => SocketLayer::new(target, JOURNALD_SOCKET)
+            .with_filter(filter)
+            .boxed(),
+    };
+    let job_layer = job_log.map(|job_log| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(Mutex::new(job_log))
+            .with_ansi(false)
+            .with_target(false)
+            .with_filter(LevelFilter::DEBUG)
+    });
+    // A subscriber may already be installed if the plugin was initialized before
+    // in this process, keep the existing one in that case.
+    let _ = tracing_subscriber::registry()
+        .with(target_layer)
+        .with(job_layer)
+        .try_init();
+}
+
+// Log file of a single job with size-based rotation: once the file grows over
+// max_size it is renamed to <jobid>.log.1 (shifting older ones up) and only
+// the newest `keep` rotated files are retained.
+pub struct JobLog {
+    path: PathBuf,
+    owner: (u32, u32),
+    max_size: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl JobLog {
+    pub fn create(
+        dir: PathBuf,
+        jobid: u32,
+        owner: (u32, u32),
+        max_size: u64,
+        keep: usize,
+    ) -> io::Result<Self> {
+        if !dir.is_dir() {
+            fs::create_dir_all(&dir)?;
+            let opened = OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_DIRECTORY | libc::O_NOFOLLOW)
+                .open(&dir)?;
+            fchown(&opened, Some(owner.0), Some(owner.1))?;
+        }
+        let path = dir.join(format!("{jobid}.log"));
+        let (file, size) = open_owned(&path, owner)?;
+        Ok(JobLog {
+            path,
+            owner,
+            max_size,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated(self.keep));
+            for index in (1..self.keep).rev() {
+                let _ = fs::rename(self.rotated(index), self.rotated(index + 1));
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        (self.file, self.size) = open_owned(&self.path, self.owner)?;
+        Ok(())
+    }
+}
+
+fn open_owned(path: &Path, owner: (u32, u32)) -> io::Result<(File, u64)> {
+    // in directories the job's user may write to, not through their links
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?;
+    fchown(&file, Some(owner.0), Some(owner.1))?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+impl Write for JobLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// Sends every event as a single datagram to a syslog or journald socket.
+// Delivery is best effort: records are dropped if the socket is unavailable.
+struct SocketLayer {
+    target: LogTarget,
+    socket: Option<UnixDatagram>,
+}
+
+impl SocketLayer {
+    fn new(target: LogTarget, path: &str) -> Self {
+        let socket = UnixDatagram::unbound()
+            .and_then(|socket| socket.connect(path).map(|_| socket))
+            .ok();
+        SocketLayer { target, socket }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for SocketLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let Some(socket) = &self.socket else {
+            return;
+        };
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let level = *event.metadata().level();
+        let datagram = match self.target {
+            LogTarget::Journald => journald_datagram(level, &fields),
+            _ => syslog_datagram(level, &fields).into_bytes(),
+        };
+        let _ = socket.send(&datagram);
+    }
+}
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    values: Vec<(String, String)>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.values
+                .push((field.name().to_string(), value.to_string()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        } else {
+            self.values
+                .push((field.name().to_string(), format!("{value:?}")));
+        }
+    }
+}
+
+fn syslog_severity(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        _ => 7,
+    }
+}
+
+// RFC 3164 style message, the local syslog daemon adds timestamp and hostname
+fn syslog_datagram(level: Level, fields: &Fields) -> String {
+    let priority = SYSLOG_FACILITY * 8 + syslog_severity(level);
+    let mut line = format!(
+        "<{priority}>{IDENTIFIER}[{}]: {}",
+        process::id(),
+        fields.message
+    );
+    for (key, value) in &fields.values {
+        let _ = write!(line, " {key}={value}");
+    }
+    line
+}
+
+// systemd journal native protocol, see systemd.journal-fields(7)
+fn journald_datagram(level: Level, fields: &Fields) -> Vec<u8> {
+    let mut datagram = Vec::new();
+    journald_field(&mut datagram, "MESSAGE", &fields.message);
+    journald_field(
+        &mut datagram,
+        "PRIORITY",
+        &syslog_severity(level).to_string(),
+    );
+    journald_field(&mut datagram, "SYSLOG_IDENTIFIER", IDENTIFIER);
+    for (key, value) in &fields.values {
+        let name: String = key
+            .trim_start_matches('_')
+            .chars()
+            .map(|c| match c {
+                'a'..='z' => c.to_ascii_uppercase(),
+                'A'..='Z' | '0'..='9' => c,
+                _ => '_',
+            })
+            .collect();
+        journald_field(&mut datagram, &name, value);
+    }
+    datagram
+}
+
+fn journald_field(datagram: &mut Vec<u8>, name: &str, value: &str) {
+    if value.contains('\n') {
+        // Multi-line values use the binary framing: name, length, raw value
+        let _ = writeln!(datagram, "{name}");
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        datagram.extend_from_slice(value.as_bytes());
+        datagram.push(b'\n');
+    } else {
+        let _ = writeln!(datagram, "{name}={value}");
+    }
+}
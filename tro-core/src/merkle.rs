@@ -0,0 +1,117 @@
+// Merkle tree of an arrangement: each directory's digest is the SHA-256 of
+// the sorted lines "<digest>  <name>" of its entries, subdirectories named
+// with a trailing slash, up to the root digest of the workdir. A
+// subdirectory can then be checked against a declaration on its own.
+use eyre::{eyre, Report, WrapErr};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::declaration::{term, Declaration};
+use crate::digest;
+use crate::inputs;
+
+const ROOT: &str = ".";
+
+// Digests of every directory holding files, by path relative to the workdir,
+// "." for the workdir itself
+pub fn directories(files: &[(String, String)]) -> BTreeMap<String, String> {
+    // entries of each directory, name to digest
+    let mut entries: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    for (location, sha256) in files {
+        let (dir, name) = split(location);
+        entries
+            .entry(dir.to_string())
+            .or_default()
+            .insert(name.to_string(), sha256.clone());
+        // every ancestor is a directory, even without files of its own
+        let mut dir = dir;
+        while !dir.is_empty() {
+            let (parent, _) = split(dir);
+            entries.entry(parent.to_string()).or_default();
+            dir = parent;
+        }
+    }
+    let mut digests = BTreeMap::new();
+    // deepest first, each adding itself to its parent
+    let mut dirs: Vec<String> = entries.keys().cloned().collect();
+    dirs.sort_by_key(|dir| std::cmp::Reverse(depth(dir)));
+    for dir in dirs {
+        let lines: String = entries[&dir]
+            .iter()
+            .map(|(name, sha256)| format!("{sha256}  {name}\n"))
+            .collect();
+        let sha256 = digest::sha256_bytes(lines.as_bytes());
+        if !dir.is_empty() {
+            let (parent, name) = split(&dir);
+            if let Some(parent) = entries.get_mut(parent) {
+                parent.insert(format!("{name}/"), sha256.clone());
+            }
+        }
+        digests.insert(dir, sha256);
+    }
+    if let Some(root) = digests.remove("") {
+        digests.insert(ROOT.to_string(), root);
+    }
+    digests
+}
+
+fn split(location: &str) -> (&str, &str) {
+    location.rsplit_once('/').unwrap_or(("", location))
+}
+
+fn depth(dir: &str) -> usize {
+    match dir.is_empty() {
+        true => 0,
+        false => dir.matches('/').count() + 1,
+    }
+}
+
+// What the most recent arrangement of a declaration says about its tree
+pub fn describe(declaration: &Declaration) -> Value {
+    let digests = directories(&declaration.last_locations());
+    json!({
+        term("root"): digests.get(ROOT).cloned().unwrap_or_default(),
+        term("directories"): digests,
+    })
+}
+
+// Rehash the files of `dir`, relative to `workdir`, and compare its digest to
+// the one the most recent arrangement of `declaration` recorded
+pub fn verify_dir(declaration: &Declaration, workdir: &Path, dir: &str) -> Result<(), Report> {
+    let dir = match dir.trim_matches('/') {
+        "" | ROOT => ROOT,
+        dir => dir,
+    };
+    let recorded = declaration
+        .last_merkle()
+        .ok_or_else(|| eyre!("The last arrangement has no Merkle tree"))?;
+    let expected = recorded[term("directories")][dir]
+        .as_str()
+        .ok_or_else(|| eyre!("The last arrangement has no directory {dir}"))?;
+    let root = match dir {
+        ROOT => workdir.to_path_buf(),
+        dir => workdir.join(dir),
+    };
+    let mut files = Vec::new();
+    inputs::list_files(&root, &root, &mut files)?;
+    // tro-utils leaves .git out of the arrangements
+    if dir == ROOT {
+        files.retain(|file| !file.starts_with(".git"));
+    }
+    let mut hashed = Vec::new();
+    for relative in files {
+        let path = root.join(&relative);
+        let sha256 = digest::sha256_file(&path)
+            .wrap_err_with(|| format!("Failed to hash {}", path.display()))?;
+        hashed.push((relative.to_string_lossy().into_owned(), sha256));
+    }
+    let actual = directories(&hashed).remove(ROOT).unwrap_or_default();
+    match actual == expected {
+        true => Ok(()),
+        false => Err(eyre!(
+            "{} changed since the arrangement ({actual}, recorded {expected})",
+            root.display()
+        )),
+    }
+}
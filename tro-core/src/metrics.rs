@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use crate::phase::Phase;
+
+const FILE_NAME: &str = "spank_tro.prom";
+
+// Metric families written to the textfile, with their help text and type
+const FAMILIES: [(&str, &str, &str); 4] = [
+    (
+        "spank_tro_generated_total",
+        "TROs signed on this node.",
+        "counter",
+    ),
+    (
+        "spank_tro_failures_total",
+        "Failed TRO generation steps by phase.",
+        "counter",
+    ),
+    (
+        "spank_tro_phase_duration_seconds",
+        "Time spent in each TRO generation phase.",
+        "summary",
+    ),
+    (
+        "spank_tro_hashed_bytes_total",
+        "Bytes of workdir content fingerprinted into arrangements.",
+        "counter",
+    ),
+];
+
+// Node-local metrics in the node_exporter textfile collector format. All jobs
+// on the node share one file, so every update re-reads the current values
+// under a lock and atomically replaces the file.
+pub struct Metrics {
+    path: PathBuf,
+}
+
+impl Metrics {
+    pub fn new(dir: &Path) -> Self {
+        Metrics {
+            path: dir.join(FILE_NAME),
+        }
+    }
+
+    pub fn record_phase(&self, phase: Phase, duration: Duration, success: bool) -> io::Result<()> {
+        self.update(|samples| {
+            let label = format!("{{phase=\"{phase}\"}}");
+            add(
+                samples,
+                &format!("spank_tro_phase_duration_seconds_sum{label}"),
+                duration.as_secs_f64(),
+            );
+            add(
+                samples,
+                &format!("spank_tro_phase_duration_seconds_count{label}"),
+                1.0,
+            );
+            if !success {
+                add(samples, &format!("spank_tro_failures_total{label}"), 1.0);
+            }
+        })
+    }
+
+    pub fn record_generated(&self) -> io::Result<()> {
+        self.update(|samples| add(samples, "spank_tro_generated_total", 1.0))
+    }
+
+    pub fn record_hashed(&self, bytes: u64) -> io::Result<()> {
+        self.update(|samples| add(samples, "spank_tro_hashed_bytes_total", bytes as f64))
+    }
+
+    fn update(&self, f: impl FnOnce(&mut BTreeMap<String, f64>)) -> io::Result<()> {
+        let mut lock_path = self.path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_path)?;
+        lock.lock()?;
+
+        let mut samples = match fs::read_to_string(&self.path) {
+            Ok(content) => parse(&content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        f(&mut samples);
+
+        // the collector only picks up *.prom files, so the temporary one is ignored
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(format!(".{}.tmp", process::id()));
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(render(&samples).as_bytes())?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+fn add(samples: &mut BTreeMap<String, f64>, series: &str, value: f64) {
+    *samples.entry(series.to_string()).or_default() += value;
+}
+
+fn parse(content: &str) -> BTreeMap<String, f64> {
+    content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.rsplit_once(' '))
+        .filter_map(|(series, value)| Some((series.to_string(), value.parse().ok()?)))
+        .collect()
+}
+
+fn render(samples: &BTreeMap<String, f64>) -> String {
+    let mut out = String::new();
+    for (family, help, kind) in FAMILIES {
+        out.push_str(&format!("# HELP {family} {help}\n# TYPE {family} {kind}\n"));
+        for (series, value) in samples {
+            let name = series.split('{').next().unwrap_or_default();
+            let base = name
+                .strip_suffix("_sum")
+                .or_else(|| name.strip_suffix("_count"))
+                .unwrap_or(name);
+            if name == family || (kind == "summary" && base == family) {
+                out.push_str(&format!("{series} {value}\n"));
+            }
+        }
+    }
+    out
+}
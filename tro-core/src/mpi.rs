@@ -0,0 +1,203 @@
+// What the XALT records of an MPI job tell of it as a whole: XALT leaves a
+// record for each rank of each program, summarized here into the ranks and
+// runtimes of each program, with the MPI library the programs loaded and the
+// launcher that started them
+use eyre::Report;
+use serde_json::{json, Value};
+use std::path::Path;
+
+use crate::declaration::{term, Declaration};
+use crate::job::Job;
+use crate::settings::Settings;
+use crate::tracker::Tracker;
+use crate::xalt;
+
+// Libraries of MPI implementations, by the start of their file name
+const LIBRARIES: [&str; 4] = ["libmpi.", "libmpich", "libmpi_cray", "libmpi_intel"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramSummary {
+    pub path: String,
+    pub records: usize,
+    pub ranks: u64,
+    // seconds
+    pub min_runtime: f64,
+    pub max_runtime: f64,
+    pub mean_runtime: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub ranks: u64,
+    pub programs: Vec<ProgramSummary>,
+    pub library: Option<String>,
+    pub implementation: Option<&'static str>,
+    pub launcher: Option<&'static str>,
+}
+
+impl Summary {
+    pub fn describe(&self) -> Value {
+        let programs: Vec<Value> = self
+            .programs
+            .iter()
+            .map(|program| {
+                json!({
+                    term("path"): program.path,
+                    term("records"): program.records,
+                    term("ranks"): program.ranks,
+                    term("minRuntimeSeconds"): program.min_runtime,
+                    term("maxRuntimeSeconds"): program.max_runtime,
+                    term("meanRuntimeSeconds"): program.mean_runtime,
+                })
+            })
+            .collect();
+        json!({
+            term("rankCount"): self.ranks,
+            term("programs"): programs,
+            term("library"): self.library,
+            term("implementation"): self.implementation,
+            term("launcher"): self.launcher,
+        })
+    }
+}
+
+// XALT keeps counts as numbers in userDT, older versions as strings in userT
+fn num_tasks(record: &Value) -> Option<u64> {
+    record["userDT"]["num_tasks"]
+        .as_f64()
+        .map(|n| n as u64)
+        .or_else(|| {
+            record["userT"]["num_tasks"]
+                .as_str()
+                .and_then(|n| n.parse().ok())
+        })
+}
+
+fn runtime(record: &Value) -> Option<f64> {
+    let times = &record["userDT"];
+    times["run_time"]
+        .as_f64()
+        .or_else(|| Some(times["end_time"].as_f64()? - times["start_time"].as_f64()?))
+}
+
+fn mpi_library(record: &Value) -> Option<String> {
+    record["libA"]
+        .as_array()?
+        .iter()
+        .filter_map(|library| library[0].as_str())
+        .find(|path| {
+            let name = Path::new(path).file_name().unwrap_or_default();
+            LIBRARIES
+                .iter()
+                .any(|library| name.to_string_lossy().starts_with(library))
+        })
+        .map(str::to_string)
+}
+
+// Told by where the library is installed, when it is
+fn implementation(library: &str) -> Option<&'static str> {
+    let library = library.to_lowercase();
+    [
+        ("cray", "Cray MPICH"),
+        ("mvapich", "MVAPICH"),
+        ("openmpi", "Open MPI"),
+        ("impi", "Intel MPI"),
+        ("intel", "Intel MPI"),
+        ("mpich", "MPICH"),
+    ]
+    .into_iter()
+    .find(|(marker, _)| library.contains(marker))
+    .map(|(_, name)| name)
+}
+
+// Told by the variables each launcher gives the ranks, as far as XALT kept
+// them in envT
+fn launcher(record: &Value) -> Option<&'static str> {
+    let env = record["envT"].as_object()?;
+    let has = |prefix: &str| env.keys().any(|name| name.starts_with(prefix));
+    if has("OMPI_COMM_WORLD_") {
+        Some("mpirun")
+    } else if has("HYDRA_") || has("I_MPI_HYDRA_") {
+        Some("mpiexec.hydra")
+    } else if has("ALPS_APP_") {
+        Some("aprun")
+    } else if has("SLURM_PROCID") {
+        Some("srun")
+    } else {
+        None
+    }
+}
+
+// The summary of the records of a job, None unless it ran MPI programs
+pub fn summarize(records: &[Value]) -> Option<Summary> {
+    let library = records.iter().find_map(mpi_library);
+    let parallel = records
+        .iter()
+        .any(|record| num_tasks(record).is_some_and(|n| n > 1));
+    if library.is_none() && !parallel {
+        return None;
+    }
+    let mut programs: Vec<(String, Vec<&Value>)> = Vec::new();
+    for record in records {
+        let path = record["userT"]["exec_path"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        match programs.iter_mut().find(|(known, _)| *known == path) {
+            Some((_, records)) => records.push(record),
+            None => programs.push((path, vec![record])),
+        }
+    }
+    let programs: Vec<ProgramSummary> = programs
+        .into_iter()
+        .map(|(path, records)| {
+            let runtimes: Vec<f64> = records
+                .iter()
+                .filter_map(|record| runtime(record))
+                .collect();
+            let ranks = records
+                .iter()
+                .filter_map(|record| num_tasks(record))
+                .max()
+                .unwrap_or(0)
+                .max(records.len() as u64);
+            ProgramSummary {
+                path,
+                records: records.len(),
+                ranks,
+                min_runtime: runtimes.iter().copied().reduce(f64::min).unwrap_or(0.0),
+                max_runtime: runtimes.iter().copied().reduce(f64::max).unwrap_or(0.0),
+                mean_runtime: runtimes.iter().sum::<f64>() / runtimes.len().max(1) as f64,
+            }
+        })
+        .collect();
+    Some(Summary {
+        ranks: programs
+            .iter()
+            .map(|program| program.ranks)
+            .max()
+            .unwrap_or(0),
+        programs,
+        implementation: library.as_deref().and_then(implementation),
+        library,
+        launcher: records.iter().find_map(launcher),
+    })
+}
+
+// Add the summary of the MPI programs of the job, or of those started after
+// `started_after`, to the performance that was just added
+pub fn record(settings: &Settings, job: &Job, started_after: Option<i64>) -> Result<(), Report> {
+    if settings.tracker != Tracker::Xalt || settings.dry_run {
+        return Ok(());
+    }
+    // a missing trace is flagged already
+    let Ok(records) = xalt::find_records(job, started_after) else {
+        return Ok(());
+    };
+    let Some(summary) = summarize(&records) else {
+        return Ok(());
+    };
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_performance("mpi", summary.describe())?;
+    declaration.save()
+}
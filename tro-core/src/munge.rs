@@ -0,0 +1,55 @@
+// MUNGE credentials attesting the evidence a node left for the TRO. Only
+// root on a node sharing the cluster's MUNGE key can make one saying it comes
+// from root, so that each node's evidence is anchored to a slurmd of the
+// cluster rather than to the site GPG key alone. unmunge only accepts a
+// credential within its TTL, the digests are what stays checkable.
+use eyre::{eyre, Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::command;
+use crate::declaration::term;
+use crate::digest;
+use crate::settings::Settings;
+
+const MUNGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attestation {
+    // SHA-256 of what the credential carries
+    pub payload_sha256: String,
+    pub credential: String,
+    pub credential_sha256: String,
+}
+
+impl Attestation {
+    pub fn describe(&self) -> Value {
+        json!({
+            term("payloadSha256"): self.payload_sha256,
+            term("credential"): self.credential,
+            term("credentialSha256"): self.credential_sha256,
+        })
+    }
+}
+
+// A credential carrying the SHA-256 of `payload`
+pub fn attest(settings: &Settings, payload: &[u8]) -> Result<Attestation, Report> {
+    let payload_sha256 = digest::sha256_bytes(payload);
+    let output = command::run_with_input(
+        &mut Command::new(&settings.munge),
+        Some(payload_sha256.as_bytes()),
+        Some(MUNGE_TIMEOUT),
+    )
+    .wrap_err("Failed to get a MUNGE credential")?;
+    let credential = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !credential.starts_with("MUNGE:") {
+        return Err(eyre!("munge did not return a credential"));
+    }
+    Ok(Attestation {
+        credential_sha256: digest::sha256_bytes(credential.as_bytes()),
+        payload_sha256,
+        credential,
+    })
+}
@@ -0,0 +1,285 @@
+// Evidence of each node of a multi-node job. Every node drops what it knows
+// of the job, its inventory, the usage of the job's cgroup and the XALT
+// records only it can see, in <declaration>.nodes/ next to the TRO, and the
+// head node merges all of it into the performance before signing.
+use eyre::{Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use crate::catalog::hostname;
+use crate::declaration::{term, Declaration};
+use crate::digest;
+use crate::hardware::Inventory;
+use crate::job::Job;
+use crate::munge::{self, Attestation};
+use crate::platform::Platform;
+use crate::privilege;
+use crate::settings::Settings;
+use crate::timestamp;
+use crate::topology::Topology;
+use crate::tpm::{self, Quote};
+use crate::xalt;
+
+// How long the head node waits for the other nodes' exit hooks
+const NODE_WAIT: Duration = Duration::from_secs(10);
+const POLL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeEvidence {
+    pub node: String,
+    pub node_id: u32,
+    pub collected: String,
+    pub cpus: Option<usize>,
+    pub memory_kib: Option<u64>,
+    pub kernel: Option<String>,
+    #[serde(default)]
+    pub platform: Option<Platform>,
+    pub cgroup: Option<CgroupUsage>,
+    // copied to <host>.xalt/ next to the evidence
+    #[serde(default)]
+    pub xalt_records: Vec<String>,
+    // with hardware_inventory
+    #[serde(default)]
+    pub hardware: Option<Inventory>,
+    // with capture_topology
+    #[serde(default)]
+    pub topology: Option<Topology>,
+    // TPM quote of the node, its nonce the SHA-256 of the evidence before
+    #[serde(default)]
+    pub tpm_quote: Option<Quote>,
+    // MUNGE credential over the rest of the evidence, with munge_attestation
+    #[serde(default)]
+    pub attestation: Option<Attestation>,
+}
+
+// Usage of the job's cgroup on the node, from cgroup v2
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CgroupUsage {
+    pub path: String,
+    pub cpu_usec: Option<u64>,
+    pub memory_peak: Option<u64>,
+}
+
+impl NodeEvidence {
+    fn describe(&self, dir: &Path) -> Value {
+        let executables: Vec<Value> = self
+            .xalt_records
+            .iter()
+            .filter_map(|name| {
+                let record: Value = serde_json::from_slice(
+                    &fs::read(dir.join(xalt_dir(&self.node)).join(name)).ok()?,
+                )
+                .ok()?;
+                Some(record["userT"]["exec_path"].clone())
+            })
+            .collect();
+        json!({
+            term("hostname"): self.node,
+            term("nodeId"): self.node_id,
+            term("collected"): self.collected,
+            term("cpus"): self.cpus,
+            term("memoryKiB"): self.memory_kib,
+            term("kernel"): self.kernel,
+            term("platform"): self.platform.as_ref().map(Platform::describe),
+            term("cgroup"): self.cgroup.as_ref().map(|cgroup| json!({
+                term("path"): cgroup.path,
+                term("cpuSeconds"): cgroup.cpu_usec.map(|usec| usec as f64 / 1e6),
+                term("memoryPeakBytes"): cgroup.memory_peak,
+            })),
+            term("executables"): executables,
+            term("hardware"): self.hardware.as_ref().map(Inventory::describe),
+            term("topology"): self.topology.as_ref().map(Topology::describe),
+            term("tpmQuote"): self.tpm_quote.as_ref().map(Quote::describe),
+            term("munge"): self.attestation.as_ref().map(Attestation::describe),
+        })
+    }
+}
+
+pub fn evidence_dir(job: &Job) -> PathBuf {
+    let mut dir = job.declaration.as_os_str().to_owned();
+    dir.push(".nodes");
+    PathBuf::from(dir)
+}
+
+fn xalt_dir(node: &str) -> String {
+    format!("{node}.xalt")
+}
+
+// What this node knows of the job
+pub fn collect(job: &Job) -> NodeEvidence {
+    let meminfo = fs::read_to_string("/proc/meminfo").unwrap_or_default();
+    NodeEvidence {
+        node: hostname().unwrap_or_else(|| format!("node{}", job.node_id)),
+        node_id: job.node_id,
+        collected: timestamp::now(),
+        cpus: thread::available_parallelism().ok().map(usize::from),
+        memory_kib: meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix("MemTotal:"))
+            .and_then(|total| total.trim().trim_end_matches("kB").trim().parse().ok()),
+        kernel: fs::read_to_string("/proc/sys/kernel/osrelease")
+            .ok()
+            .map(|release| release.trim().to_string()),
+        platform: Some(Platform::collect()),
+        cgroup: cgroup_usage(job),
+        xalt_records: Vec::new(),
+        hardware: None,
+        topology: None,
+        tpm_quote: None,
+        attestation: None,
+    }
+}
+
+// The job's cgroup v2, job_<id> above slurmstepd's own in /proc/self/cgroup,
+// and its directory
+pub fn job_cgroup(job: &Job) -> Option<(String, PathBuf)> {
+    let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let own = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+    let component = format!("job_{}", job.jobid);
+    let end = own.find(&format!("/{component}"))? + component.len() + 1;
+    let path = &own[..end];
+    let dir = Path::new("/sys/fs/cgroup").join(path.trim_start_matches('/'));
+    Some((path.to_string(), dir))
+}
+
+fn cgroup_usage(job: &Job) -> Option<CgroupUsage> {
+    let (path, dir) = job_cgroup(job)?;
+    let cpu_usec = fs::read_to_string(dir.join("cpu.stat"))
+        .ok()
+        .and_then(|stat| {
+            stat.lines()
+                .find_map(|line| line.strip_prefix("usage_usec "))
+                .and_then(|usec| usec.trim().parse().ok())
+        });
+    let memory_peak = fs::read_to_string(dir.join("memory.peak"))
+        .ok()
+        .and_then(|peak| peak.trim().parse().ok());
+    Some(CgroupUsage {
+        path,
+        cpu_usec,
+        memory_peak,
+    })
+}
+
+// Leave the evidence of this node for the head node. XALT records are copied
+// when they are written where the head node may not see them, e.g. below a
+// node-local --tro-exec-dir.
+pub fn deposit(settings: &Settings, job: &Job, mut evidence: NodeEvidence) -> Result<(), Report> {
+    if settings.dry_run {
+        return Ok(());
+    }
+    let dir = evidence_dir(job);
+    {
+        let _user = privilege::as_user(job);
+        fs::create_dir_all(&dir).wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+        let record_dir = xalt::record_dir(job);
+        let shared = job
+            .declaration
+            .parent()
+            .is_some_and(|parent| record_dir.starts_with(parent));
+        if !shared && record_dir.is_dir() {
+            let copies = dir.join(xalt_dir(&evidence.node));
+            fs::create_dir_all(&copies)?;
+            for entry in fs::read_dir(&record_dir)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "json") {
+                    let name = path.file_name().unwrap_or_default();
+                    fs::copy(&path, copies.join(name))
+                        .wrap_err_with(|| format!("Failed to copy {}", path.display()))?;
+                    evidence
+                        .xalt_records
+                        .push(name.to_string_lossy().into_owned());
+                }
+            }
+        }
+    }
+    if settings.hardware_inventory {
+        evidence.hardware = Some(Inventory::collect(settings));
+    }
+    if settings.capture_topology {
+        evidence.topology = Some(Topology::collect(settings, job));
+    }
+    if let Some(ak) = &settings.tpm_ak {
+        // as root, for tpm2_quote to write in a directory only root may
+        let nonce = digest::sha256_bytes(&serde_json::to_vec(&evidence)?);
+        evidence.tpm_quote = Some(tpm::quote(settings, ak, &nonce)?);
+    }
+    if settings.munge_attestation {
+        evidence.attestation = Some(munge::attest(settings, &serde_json::to_vec(&evidence)?)?);
+    }
+    let _user = privilege::as_user(job);
+    let path = dir.join(format!("{}.json", evidence.node));
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, serde_json::to_vec(&evidence)?)
+        .and_then(|_| fs::rename(&tmp_path, &path))
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))
+}
+
+// The evidence left so far, by node id
+pub fn load(job: &Job) -> Result<Vec<NodeEvidence>, Report> {
+    let dir = evidence_dir(job);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).wrap_err_with(|| format!("Failed to read {}", dir.display())),
+    };
+    let mut nodes = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let evidence: NodeEvidence = serde_json::from_slice(&fs::read(&path)?)
+                .wrap_err_with(|| format!("Invalid node evidence {}", path.display()))?;
+            nodes.push(evidence);
+        }
+    }
+    nodes.sort_by_key(|evidence| evidence.node_id);
+    Ok(nodes)
+}
+
+// Whether the nodes of the job leave their evidence: those of multi-node
+// jobs, or the only one to attest it or describe its hardware
+pub fn needed(settings: &Settings, job: &Job) -> bool {
+    job.node_count > 1
+        || settings.munge_attestation
+        || settings.tpm_ak.is_some()
+        || settings.hardware_inventory
+        || settings.capture_topology
+}
+
+// Add the evidence of every node to the performance, waiting a little for
+// the nodes whose exit hooks are still running
+pub fn merge(settings: &Settings, job: &Job) -> Result<(), Report> {
+    if settings.dry_run || !needed(settings, job) {
+        return Ok(());
+    }
+    let _user = privilege::as_user(job);
+    let started = Instant::now();
+    let mut nodes = load(job)?;
+    while nodes.len() < job.node_count as usize && started.elapsed() < NODE_WAIT {
+        thread::sleep(POLL);
+        nodes = load(job)?;
+    }
+    if nodes.len() < job.node_count as usize {
+        info!(
+            "Only {} of the {} nodes of job {} left their evidence",
+            nodes.len(),
+            job.node_count,
+            job.jobid
+        );
+    }
+    let dir = evidence_dir(job);
+    let described: Vec<Value> = nodes.iter().map(|node| node.describe(&dir)).collect();
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_performance("nodes", Value::Array(described))?;
+    declaration.annotate_performance("nodeCount", Value::from(job.node_count))?;
+    declaration.save()?;
+    drop(declaration);
+    fs::remove_dir_all(&dir).wrap_err_with(|| format!("Failed to remove {}", dir.display()))
+}
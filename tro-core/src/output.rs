@@ -0,0 +1,118 @@
+use eyre::{Report, WrapErr};
+use serde_json::{json, Value};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::attachment;
+use crate::declaration::term;
+use crate::digest;
+use crate::job::Job;
+use crate::privilege;
+use crate::redact;
+use crate::scontrol::JobInfo;
+use crate::settings::Settings;
+
+// The files a batch job's stdout and stderr were written to
+pub fn resolve(job: &Job, info: &JobInfo) -> Vec<(&'static str, PathBuf)> {
+    let workdir = info
+        .get("WorkDir")
+        .map_or_else(|| job.workdir.clone(), PathBuf::from);
+    let mut outputs: Vec<(&'static str, PathBuf)> = Vec::new();
+    for (stream, key) in [("stdout", "StdOut"), ("stderr", "StdErr")] {
+        let Some(pattern) = info.get(key) else {
+            continue;
+        };
+        let path = workdir.join(expand_pattern(pattern, job, info));
+        // stderr defaults to the stdout file
+        if !outputs.iter().any(|(_, known)| *known == path) {
+            outputs.push((stream, path));
+        }
+    }
+    outputs
+}
+
+// Expand the replacement symbols of sbatch's --output/--error, including
+// zero padding such as %4j
+pub fn expand_pattern(pattern: &str, job: &Job, info: &JobInfo) -> String {
+    let jobid = job.jobid.to_string();
+    let array_job = info.get("ArrayJobId").unwrap_or(&jobid);
+    let array_task = info.get("ArrayTaskId").unwrap_or("4294967294");
+    let node = info
+        .get("BatchHost")
+        .map(|host| host.split('.').next().unwrap_or(host))
+        .unwrap_or_default();
+    let user = info
+        .get("UserId")
+        .and_then(|user| user.split('(').next())
+        .unwrap_or(&job.user);
+    let name = info.get("JobName").unwrap_or_default();
+
+    let mut expanded = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // a backslash anywhere disables the expansion of the whole pattern
+            '\\' => return pattern.replace('\\', ""),
+            '%' => {
+                let mut width = String::new();
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    width.push(digit);
+                }
+                let width: usize = width.parse().unwrap_or(0);
+                let number = |value: &str| format!("{:0>width$}", value);
+                match chars.next() {
+                    Some('%') => expanded.push('%'),
+                    Some('A') => expanded.push_str(&number(array_job)),
+                    Some('a') => expanded.push_str(&number(array_task)),
+                    Some('J') => expanded.push_str(&format!("{}.batch", number(&jobid))),
+                    Some('j') => expanded.push_str(&number(&jobid)),
+                    Some('N') => expanded.push_str(node),
+                    Some('n') | Some('t') => expanded.push_str(&number("0")),
+                    Some('s') => expanded.push_str("batch"),
+                    Some('u') => expanded.push_str(user),
+                    Some('x') => expanded.push_str(name),
+                    Some(other) => {
+                        expanded.push('%');
+                        expanded.push(other);
+                    }
+                    None => expanded.push('%'),
+                }
+            }
+            _ => expanded.push(c),
+        }
+    }
+    expanded
+}
+
+// Digest of an output file, with its content inlined when it is valid UTF-8
+// and at most attach_output_max_size bytes. It is opened once, without
+// following a symbolic link, and described from that one descriptor.
+pub fn describe(settings: &Settings, stream: &str, path: &Path) -> Result<Value, Report> {
+    let file = privilege::open_nofollow(path)
+        .wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+    let size = file
+        .metadata()
+        .wrap_err_with(|| format!("Failed to stat {}", path.display()))?
+        .len();
+    let fingerprint = digest::fingerprint_of(settings, file.try_clone()?)
+        .wrap_err_with(|| format!("Failed to hash {}", path.display()))?;
+    let mut output = json!({
+        term("stream"): stream,
+        term("path"): path.to_string_lossy(),
+        term("size"): size,
+    });
+    if let Value::Object(output) = &mut output {
+        output.extend(fingerprint.terms());
+    }
+    let attach_max_size = settings.attach_output_max_size;
+    if attach_max_size.is_some_and(|max_size| size <= max_size) {
+        let mut content = Vec::new();
+        (&file).seek(SeekFrom::Start(0))?;
+        (&file).read_to_end(&mut content)?;
+        if let Ok(content) = String::from_utf8(content) {
+            let content = redact::text(&settings.redact, &content);
+            attachment::attach(settings, &mut output, stream, &content)?;
+        }
+    }
+    Ok(output)
+}
@@ -0,0 +1,45 @@
+use eyre::{eyre, Report};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// Steps of TRO generation, used to label logs and failures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Arrangement,
+    // finding what the tracker recorded of the job
+    Trace,
+    Performance,
+    Sign,
+    Verify,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Phase::Arrangement => "arrangement",
+            Phase::Trace => "trace",
+            Phase::Performance => "performance",
+            Phase::Sign => "sign",
+            Phase::Verify => "verify",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Phase {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "arrangement" => Ok(Phase::Arrangement),
+            // phases were named after XALT before other trackers
+            "trace" | "xalt" => Ok(Phase::Trace),
+            "performance" => Ok(Phase::Performance),
+            "sign" => Ok(Phase::Sign),
+            "verify" => Ok(Phase::Verify),
+            _ => Err(eyre!("{value} is not a known phase")),
+        }
+    }
+}
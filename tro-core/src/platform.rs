@@ -0,0 +1,99 @@
+// What a node ran the job on: kernel, distribution, glibc and CPU microcode.
+// All of them may change numerical behavior, are read in an instant while
+// the job runs and cannot be told afterwards.
+use eyre::Report;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+
+use crate::declaration::{term, Declaration};
+use crate::job::Job;
+use crate::settings::Settings;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Platform {
+    pub kernel: Option<String>,
+    // uname -v, the build of the kernel
+    pub kernel_version: Option<String>,
+    // PRETTY_NAME, ID and VERSION_ID of /etc/os-release
+    pub distribution: Option<String>,
+    pub distribution_id: Option<String>,
+    pub distribution_version: Option<String>,
+    pub glibc: Option<String>,
+    pub microcode: Option<String>,
+}
+
+impl Platform {
+    pub fn collect() -> Self {
+        let read = |path: &str| {
+            fs::read_to_string(path)
+                .ok()
+                .map(|value| value.trim().to_string())
+        };
+        let os_release = read("/etc/os-release")
+            .or_else(|| read("/usr/lib/os-release"))
+            .unwrap_or_default();
+        let field = |name: &str| os_release_field(&os_release, name);
+        Platform {
+            kernel: read("/proc/sys/kernel/osrelease"),
+            kernel_version: read("/proc/sys/kernel/version"),
+            distribution: field("PRETTY_NAME"),
+            distribution_id: field("ID"),
+            distribution_version: field("VERSION_ID"),
+            glibc: glibc_version(),
+            microcode: microcode(&read("/proc/cpuinfo").unwrap_or_default()),
+        }
+    }
+
+    pub fn describe(&self) -> Value {
+        json!({
+            term("kernel"): self.kernel,
+            term("kernelVersion"): self.kernel_version,
+            term("distribution"): self.distribution,
+            term("distributionId"): self.distribution_id,
+            term("distributionVersion"): self.distribution_version,
+            term("glibc"): self.glibc,
+            term("microcode"): self.microcode,
+        })
+    }
+}
+
+// A variable of os-release(5), unquoted
+pub fn os_release_field(os_release: &str, name: &str) -> Option<String> {
+    os_release.lines().find_map(|line| {
+        let value = line.strip_prefix(name)?.strip_prefix('=')?;
+        let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
+
+// The microcode revision of the first CPU, the same on all of them once the
+// kernel loaded it
+pub fn microcode(cpuinfo: &str) -> Option<String> {
+    cpuinfo.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "microcode").then(|| value.trim().to_string())
+    })
+}
+
+#[cfg(target_env = "gnu")]
+fn glibc_version() -> Option<String> {
+    let version = unsafe { std::ffi::CStr::from_ptr(libc::gnu_get_libc_version()) };
+    Some(version.to_string_lossy().into_owned())
+}
+
+#[cfg(not(target_env = "gnu"))]
+fn glibc_version() -> Option<String> {
+    None
+}
+
+// Add the platform of this node, the head node of multi-node jobs, to the
+// performance that was just added
+pub fn record(settings: &Settings, job: &Job) -> Result<(), Report> {
+    if settings.dry_run {
+        return Ok(());
+    }
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_performance("platform", Platform::collect().describe())?;
+    declaration.save()
+}
@@ -0,0 +1,95 @@
+use eyre::Report;
+use serde::{Deserialize, Serialize};
+
+use crate::settings::parse_list;
+
+// Who may request a TRO, and who gets one whether they asked or not
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Policy {
+    allow_users: Vec<String>,
+    deny_users: Vec<String>,
+    force_users: Vec<String>,
+    allow_accounts: Vec<String>,
+    deny_accounts: Vec<String>,
+    force_accounts: Vec<String>,
+    allow_qos: Vec<String>,
+    deny_qos: Vec<String>,
+    force_qos: Vec<String>,
+}
+
+// What the policy is evaluated against
+#[derive(Debug, Default)]
+pub struct Subject {
+    pub user: Option<String>,
+    pub account: Option<String>,
+    pub qos: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decision {
+    Allowed,
+    Denied(String),
+    Forced,
+}
+
+impl Policy {
+    // Apply a `key=value` plugin argument such as deny_users=alice,bob, returns
+    // false for keys that are not policy settings
+    pub fn set(&mut self, key: &str, value: &str) -> Result<bool, Report> {
+        let list = match key {
+            "allow_users" => &mut self.allow_users,
+            "deny_users" => &mut self.deny_users,
+            "force_users" => &mut self.force_users,
+            "allow_accounts" => &mut self.allow_accounts,
+            "deny_accounts" => &mut self.deny_accounts,
+            "force_accounts" => &mut self.force_accounts,
+            "allow_qos" => &mut self.allow_qos,
+            "deny_qos" => &mut self.deny_qos,
+            "force_qos" => &mut self.force_qos,
+            _ => return Ok(false),
+        };
+        *list = parse_list(value);
+        Ok(true)
+    }
+
+    // Deny lists win over everything, then a non-empty allow list must contain
+    // the subject, and only then force lists apply
+    pub fn evaluate(&self, subject: &Subject) -> Decision {
+        let checks = [
+            ("user", &subject.user, &self.allow_users, &self.deny_users),
+            (
+                "account",
+                &subject.account,
+                &self.allow_accounts,
+                &self.deny_accounts,
+            ),
+            ("QoS", &subject.qos, &self.allow_qos, &self.deny_qos),
+        ];
+        for (what, value, allow, deny) in checks {
+            let value = value.as_deref().unwrap_or_default();
+            if contains(deny, value) {
+                return Decision::Denied(format!("{what} {value} is not allowed to generate TROs"));
+            }
+            if !allow.is_empty() && !contains(allow, value) {
+                return Decision::Denied(format!(
+                    "{what} {value} is not among those allowed to generate TROs"
+                ));
+            }
+        }
+        let forced = [
+            (&subject.user, &self.force_users),
+            (&subject.account, &self.force_accounts),
+            (&subject.qos, &self.force_qos),
+        ];
+        for (value, force) in forced {
+            if contains(force, value.as_deref().unwrap_or_default()) {
+                return Decision::Forced;
+            }
+        }
+        Decision::Allowed
+    }
+}
+
+fn contains(list: &[String], value: &str) -> bool {
+    list.iter().any(|item| item == value)
+}
@@ -0,0 +1,147 @@
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::settings::Settings;
+use crate::signing::{self, Signer};
+use crate::sigstore;
+use crate::tracker::Tracker;
+use crate::tro_utils;
+use crate::xalt;
+
+// Problems that would prevent the TRO of a job submitted from `workdir` from
+// being generated, as far as the submit host can tell
+pub fn check(settings: &Settings, workdir: &Path) -> Vec<String> {
+    let mut problems = Vec::new();
+    if !accessible(workdir, libc::W_OK | libc::X_OK) {
+        problems.push(format!(
+            "{} is not writable, the TRO is written next to the job's files",
+            workdir.display()
+        ));
+    }
+    if settings.trs_caps.as_os_str().is_empty() {
+        problems.push("no TRS profile is configured (trs_caps=)".to_string());
+    } else if !accessible(&settings.trs_caps, libc::R_OK) {
+        problems.push(format!(
+            "the TRS profile {} is not readable",
+            settings.trs_caps.display()
+        ));
+    }
+    if matches!(settings.signer, Signer::Site | Signer::Both) {
+        if let Err(e) = signing::SigningKey::site(settings) {
+            problems.push(format!("{:#}", e));
+        }
+    }
+    if matches!(settings.signer, Signer::User | Signer::Both) {
+        let owner = unsafe { (libc::getuid(), libc::getgid()) };
+        if let Err(e) = signing::SigningKey::user(owner) {
+            problems.push(format!("TROs are signed with your own key: {:#}", e));
+        }
+    }
+    problems
+}
+
+// Problems with what the node needs to generate any TRO, checked once when
+// slurmd loads the plugin rather than by every job
+pub fn check_install(config: &Config) -> Vec<String> {
+    let settings = &config.settings;
+    let mut problems = Vec::new();
+    for (program, key) in [(&settings.gpg, "gpg"), (&settings.scontrol, "scontrol")] {
+        if !executable(program) {
+            problems.push(format!("{} is not executable ({key}=)", program.display()));
+        }
+    }
+    if matches!(settings.signer, Signer::Site | Signer::Both) {
+        if let Err(e) = signing::check_site_key(settings) {
+            problems.push(format!("{:#}", e));
+        }
+    }
+    if settings.signer == Signer::Sigstore {
+        let cosign = &settings.sigstore.cosign;
+        if !executable(cosign) {
+            problems.push(format!("{} is not executable (cosign=)", cosign.display()));
+        }
+        if let Err(e) = sigstore::identity_token(settings) {
+            problems.push(format!("{:#}", e));
+        }
+    }
+    if config.spool_dir.exists() {
+        if !accessible(&config.spool_dir, libc::W_OK | libc::X_OK) {
+            problems.push(format!(
+                "the spool {} is not writable (spool_dir=)",
+                config.spool_dir.display()
+            ));
+        }
+    } else if !config
+        .spool_dir
+        .parent()
+        .is_some_and(|parent| accessible(parent, libc::W_OK | libc::X_OK))
+    {
+        problems.push(format!(
+            "the spool {} cannot be created (spool_dir=)",
+            config.spool_dir.display()
+        ));
+    }
+    if settings.tracker == Tracker::Xalt {
+        if let Err(e) = xalt::preload_library(&config.xalt_dir, &config.xalt_preload) {
+            problems.push(e.to_string());
+        }
+    }
+    if !accessible(&settings.tro_utils, libc::X_OK) {
+        problems.push(format!(
+            "tro-utils {} is not executable (tro_utils=)",
+            settings.tro_utils.display()
+        ));
+    } else if let Err(e) = tro_utils::negotiate(settings) {
+        problems.push(format!("{:#}", e));
+    }
+    match fs::read_to_string(&settings.trs_caps) {
+        Ok(profile) => {
+            if let Err(e) = serde_json::from_str::<serde_json::Value>(&profile) {
+                problems.push(format!(
+                    "the TRS profile {} is not valid JSON: {}",
+                    settings.trs_caps.display(),
+                    e
+                ));
+            }
+        }
+        Err(e) => problems.push(format!(
+            "cannot read the TRS profile {}: {}",
+            settings.trs_caps.display(),
+            e
+        )),
+    }
+    problems
+}
+
+// The line slurmd logs for the node once check_install is done
+pub fn summary(problems: &[String]) -> String {
+    match problems {
+        [] => "spank-tro: node check PASS".to_string(),
+        _ => format!(
+            "spank-tro: node check FAIL, TROs cannot be generated on this node: {}",
+            problems.join("; ")
+        ),
+    }
+}
+
+// A program named by path, or found in PATH like Command does
+fn executable(program: &Path) -> bool {
+    if program.components().count() > 1 {
+        return accessible(program, libc::X_OK);
+    }
+    env::var_os("PATH").is_some_and(|path| {
+        env::split_paths(&path).any(|dir| accessible(&dir.join(program), libc::X_OK))
+    })
+}
+
+// access(2), i.e. with the real uid of the submitting user
+fn accessible(path: &Path, mode: libc::c_int) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
@@ -0,0 +1,103 @@
+// Access to the job's files as its user rather than as slurmstepd: root is
+// nobody on root-squashed NFS, and should not read or write where the user
+// could not. setfsuid(2) only changes who the calling thread is to the
+// filesystem, the programs it starts are root again.
+use eyre::{eyre, Report, WrapErr};
+use std::fs::{self, File, OpenOptions};
+use std::marker::PhantomData;
+use std::os::unix::fs::{fchown, OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+use crate::job::Job;
+
+// Permissions of the files of a TRO: the user's, readable by their group
+pub const TRO_MODE: u32 = 0o640;
+
+// The job's user as far as the filesystem is concerned, until dropped
+pub struct UserFs {
+    previous: Option<(u32, u32)>,
+    // the identity belongs to the thread
+    _thread: PhantomData<*const ()>,
+}
+
+impl Drop for UserFs {
+    fn drop(&mut self) {
+        if let Some((uid, gid)) = self.previous {
+            unsafe {
+                libc::setfsuid(uid);
+                libc::setfsgid(gid);
+            }
+        }
+    }
+}
+
+// Access the filesystem as the owner of `job`, when running as root
+pub fn as_user(job: &Job) -> UserFs {
+    as_owner((job.uid, job.gid))
+}
+
+pub fn as_owner((uid, gid): (u32, u32)) -> UserFs {
+    if unsafe { libc::geteuid() } != 0 || uid == 0 {
+        return UserFs {
+            previous: None,
+            _thread: PhantomData,
+        };
+    }
+    // the group first, root may no longer change it once it is the user
+    let previous_gid = unsafe { libc::setfsgid(gid) } as u32;
+    let previous_uid = unsafe { libc::setfsuid(uid) } as u32;
+    UserFs {
+        previous: Some((previous_uid, previous_gid)),
+        _thread: PhantomData,
+    }
+}
+
+// Fail if root would write through `path` to wherever a symbolic link points
+pub fn refuse_symlink(path: &Path) -> Result<(), Report> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => Err(eyre!(
+            "{} is a symbolic link, TRO files are not written through one",
+            path.display()
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Open `path` for writing, truncated, without following a symbolic link
+pub fn create_nofollow(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .mode(TRO_MODE)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+}
+
+// Open `path` for reading, unless it is a symbolic link
+pub fn open_nofollow(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+}
+
+// Give the files of a job's TRO to its user with TRO_MODE, those that exist
+pub fn hand_over(job: &Job, paths: &[impl AsRef<Path>]) -> Result<(), Report> {
+    for path in paths {
+        let path = path.as_ref();
+        let file = match open_nofollow(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).wrap_err_with(|| format!("Failed to open {}", path.display())),
+        };
+        if unsafe { libc::geteuid() } == 0 {
+            fchown(&file, Some(job.uid), Some(job.gid)).wrap_err_with(|| {
+                format!("Failed to give {} to uid {}", path.display(), job.uid)
+            })?;
+        }
+        file.set_permissions(fs::Permissions::from_mode(TRO_MODE))
+            .wrap_err_with(|| format!("Failed to set the mode of {}", path.display()))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+// Hiding secrets from command lines and scripts before they end up in a TRO
+// or a log, e.g. `--password=...` or `export GITHUB_TOKEN=...`
+
+pub const REDACTED: &str = "[REDACTED]";
+
+// Names that hold secrets unless the site configures its own with redact=
+pub fn default_patterns() -> Vec<String> {
+    [
+        "*TOKEN*",
+        "*PASSWORD*",
+        "*PASSWD*",
+        "*PASSPHRASE*",
+        "*SECRET*",
+        "*API_KEY*",
+        "*APIKEY*",
+        "*CREDENTIAL*",
+        "AWS_SECRET*",
+    ]
+    .map(str::to_string)
+    .to_vec()
+}
+
+// Whether `name` matches one of the glob `patterns`, ignoring case and
+// treating - as _
+pub fn is_secret(patterns: &[String], name: &str) -> bool {
+    let name = name.to_uppercase().replace('-', "_");
+    patterns
+        .iter()
+        .any(|pattern| glob(&pattern.to_uppercase().replace('-', "_"), &name))
+}
+
+// A command line with the values of secret options and variables replaced,
+// i.e. NAME=value, --name=value and --name value
+pub fn argv(patterns: &[String], argv: &[String]) -> Vec<String> {
+    let mut redacted = Vec::with_capacity(argv.len());
+    let mut hide_next = false;
+    for arg in argv {
+        if hide_next {
+            redacted.push(REDACTED.to_string());
+            hide_next = false;
+        } else if let Some((name, _)) = arg.split_once('=') {
+            match is_secret(patterns, name.trim_start_matches('-')) {
+                true => redacted.push(format!("{name}={REDACTED}")),
+                false => redacted.push(arg.clone()),
+            }
+        } else {
+            hide_next = arg.starts_with('-') && is_secret(patterns, arg.trim_start_matches('-'));
+            redacted.push(arg.clone());
+        }
+    }
+    redacted
+}
+
+// Text such as a batch script with the values of secret variable
+// assignments replaced, quoted or not
+pub fn text(patterns: &[String], text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut redacted = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let starts_name = is_name(chars[i]) && (i == 0 || !is_name(chars[i - 1]));
+        if !starts_name {
+            redacted.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len() && is_name(chars[i]) {
+            i += 1;
+        }
+        let name: String = chars[start..i].iter().collect();
+        redacted.push_str(&name);
+        if i >= chars.len() || chars[i] != '=' || !is_secret(patterns, &name) {
+            continue;
+        }
+        redacted.push('=');
+        redacted.push_str(REDACTED);
+        i += 1;
+        // skip the value, up to its closing quote or the end of the word
+        match chars.get(i) {
+            Some(&quote) if quote == '"' || quote == '\'' => {
+                i += 1;
+                while i < chars.len() && chars[i] != quote {
+                    i += 1;
+                }
+                i += 1;
+            }
+            _ => {
+                while i < chars.len() && !chars[i].is_whitespace() && chars[i] != ';' {
+                    i += 1;
+                }
+            }
+        }
+    }
+    redacted
+}
+
+// Glob matching with * only
+fn glob(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=name.len())
+                .filter(|i| name.is_char_boundary(*i))
+                .any(|i| glob(rest, &name[i..]))
+        }
+    }
+}
@@ -0,0 +1,141 @@
+// Facility reporting over the catalog: finalizations listed one per line, or
+// summed up per user and partition, as a table or JSON
+use eyre::{eyre, Report};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use crate::catalog::{CatalogEntry, Status};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Table,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            _ => Err(eyre!("{value} is not one of table/json")),
+        }
+    }
+}
+
+// What a table shows of a record
+pub trait Row: Serialize {
+    const HEADERS: &'static [&'static str];
+
+    fn cells(&self) -> Vec<String>;
+}
+
+impl Row for CatalogEntry {
+    const HEADERS: &'static [&'static str] = &[
+        "JOBID",
+        "CLUSTER",
+        "USER",
+        "PARTITION",
+        "STATUS",
+        "FINALIZED",
+        "DECLARATION",
+    ];
+
+    fn cells(&self) -> Vec<String> {
+        let jobid = match self.step {
+            Some(step) => format!("{}.{step}", self.jobid),
+            None => self.jobid.to_string(),
+        };
+        vec![
+            jobid,
+            self.cluster.clone().unwrap_or_default(),
+            self.user.clone(),
+            self.partition.clone().unwrap_or_default(),
+            self.status.as_str().to_string(),
+            self.finalized.clone(),
+            self.declaration.display().to_string(),
+        ]
+    }
+}
+
+// Finalizations of a user's jobs on a partition
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    pub user: String,
+    pub partition: Option<String>,
+    pub jobs: usize,
+    pub signed: usize,
+    pub unsigned: usize,
+    pub failed: usize,
+}
+
+impl Row for Summary {
+    const HEADERS: &'static [&'static str] =
+        &["USER", "PARTITION", "JOBS", "SIGNED", "UNSIGNED", "FAILED"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.user.clone(),
+            self.partition.clone().unwrap_or_default(),
+            self.jobs.to_string(),
+            self.signed.to_string(),
+            self.unsigned.to_string(),
+            self.failed.to_string(),
+        ]
+    }
+}
+
+// Per user and partition, counting a job finalized several times (e.g.
+// failed, then finalized again from the spool) by how it last ended
+pub fn summarize(entries: &[CatalogEntry]) -> Vec<Summary> {
+    let mut last = BTreeMap::new();
+    for entry in entries {
+        let key = (&entry.cluster, entry.jobid, entry.step);
+        last.insert(key, entry);
+    }
+    let mut summaries: BTreeMap<_, Summary> = BTreeMap::new();
+    for entry in last.into_values() {
+        let summary = summaries
+            .entry((entry.user.clone(), entry.partition.clone()))
+            .or_insert_with(|| Summary {
+                user: entry.user.clone(),
+                partition: entry.partition.clone(),
+                ..Summary::default()
+            });
+        summary.jobs += 1;
+        match entry.status {
+            Status::Signed => summary.signed += 1,
+            Status::Unsigned => summary.unsigned += 1,
+            Status::Failed => summary.failed += 1,
+        }
+    }
+    summaries.into_values().collect()
+}
+
+pub fn render<T: Row>(rows: &[T], format: Format) -> Result<String, Report> {
+    if format == Format::Json {
+        return Ok(serde_json::to_string_pretty(rows)? + "\n");
+    }
+    let cells: Vec<Vec<String>> = rows.iter().map(Row::cells).collect();
+    let mut widths: Vec<usize> = T::HEADERS.iter().map(|header| header.len()).collect();
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let headers = T::HEADERS.iter().map(|header| header.to_string()).collect();
+    let mut table = String::new();
+    for row in std::iter::once(&headers).chain(&cells) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        table.push_str(line.join("  ").trim_end());
+        table.push('\n');
+    }
+    Ok(table)
+}
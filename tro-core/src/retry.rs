@@ -0,0 +1,88 @@
+use eyre::{eyre, Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::phase::Phase;
+
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+// How often a failing phase is retried and how long to wait in between
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    retries: u32,
+    per_phase: Vec<(Phase, u32)>,
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 0,
+            per_phase: Vec::new(),
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Set retry counts from `<n>[,<phase>:<n>...]`, e.g. `2,trace:5` retries
+    // every phase twice except trace reads
+    pub fn set_retries(&mut self, value: &str) -> Result<(), Report> {
+        self.per_phase.clear();
+        for item in value.split(',') {
+            match item.split_once(':') {
+                Some((phase, retries)) => self.per_phase.push((
+                    phase.parse()?,
+                    retries
+                        .parse()
+                        .wrap_err_with(|| format!("Invalid retry count for {phase}"))?,
+                )),
+                None => {
+                    self.retries = item
+                        .parse()
+                        .map_err(|_| eyre!("{item} is not a valid retry count"))?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn retries(&self, phase: Phase) -> u32 {
+        self.per_phase
+            .iter()
+            .rev()
+            .find(|(p, _)| *p == phase)
+            .map_or(self.retries, |(_, retries)| *retries)
+    }
+
+    // Call f until it succeeds or the retries for the phase are used up,
+    // doubling the delay between attempts
+    pub fn run<T>(
+        &self,
+        phase: Phase,
+        mut f: impl FnMut() -> Result<T, Report>,
+    ) -> Result<T, Report> {
+        let mut delay = self.backoff;
+        let mut attempt = 0;
+        loop {
+            match f() {
+                Err(e) if attempt < self.retries(phase) => {
+                    attempt += 1;
+                    warn!(
+                        "TRO {} phase failed ({:#}), retrying in {}s ({}/{})",
+                        phase,
+                        e,
+                        delay.as_secs_f32(),
+                        attempt,
+                        self.retries(phase)
+                    );
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(MAX_BACKOFF);
+                }
+                result => return result,
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+// Directories an arrangement covers besides the job's workdir, e.g. a data
+// and a results directory, each with a label. tro-utils hashes one directory
+// per arrangement: the others are hashed here and listed on the same
+// arrangement, root by root.
+use eyre::{eyre, Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use crate::declaration::term;
+use crate::digest;
+use crate::inputs;
+use crate::settings::Settings;
+
+// The label of the workdir among the roots of an arrangement
+pub const WORKDIR_LABEL: &str = "workdir";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Root {
+    pub label: String,
+    pub path: PathBuf,
+}
+
+// label=path
+impl FromStr for Root {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (label, path) = value
+            .split_once('=')
+            .ok_or_else(|| eyre!("{value} is not label=path"))?;
+        let valid = !label.is_empty()
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(eyre!("{label} is not a valid root label"));
+        }
+        if label == WORKDIR_LABEL {
+            return Err(eyre!("{WORKDIR_LABEL} is the label of the job's workdir"));
+        }
+        if !path.starts_with('/') {
+            return Err(eyre!("{path} is not absolute"));
+        }
+        Ok(Root {
+            label: label.to_string(),
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+// Roots separated by commas, each label once
+pub fn parse(value: &str) -> Result<Vec<Root>, Report> {
+    let mut roots: Vec<Root> = Vec::new();
+    for root in value.split(',').filter(|root| !root.is_empty()) {
+        let root: Root = root.parse()?;
+        if roots.iter().any(|other| other.label == root.label) {
+            return Err(eyre!("{} is labelled twice", root.label));
+        }
+        roots.push(root);
+    }
+    Ok(roots)
+}
+
+impl Root {
+    // Its files with their digests and sizes, skipping .git like tro-utils is
+    // told to
+    pub fn describe(&self, settings: &Settings) -> Result<Value, Report> {
+        let mut files = Vec::new();
+        inputs::list_files(&self.path, &self.path, &mut files)
+            .wrap_err_with(|| format!("Failed to list root {}", self.label))?;
+        files.retain(|file| file.components().next() != Some(Component::Normal(".git".as_ref())));
+        files.sort();
+        let mut artifacts = Vec::new();
+        for relative in files {
+            let path = self.path.join(&relative);
+            let fingerprint = digest::fingerprint(settings, &path)
+                .wrap_err_with(|| format!("Failed to hash {}", path.display()))?;
+            let mut artifact = json!({
+                term("location"): relative,
+                term("contentSize"): fs::metadata(&path)?.len(),
+            });
+            if let Value::Object(artifact) = &mut artifact {
+                artifact.extend(fingerprint.terms());
+            }
+            artifacts.push(artifact);
+        }
+        Ok(json!({
+            term("label"): self.label,
+            term("path"): self.path,
+            term("artifacts"): artifacts,
+        }))
+    }
+}
+
+// What an arrangement says about its roots: the workdir, whose files
+// tro-utils lists, then the others
+pub fn describe(settings: &Settings, workdir: &Path, roots: &[Root]) -> Result<Value, Report> {
+    let mut described = vec![json!({
+        term("label"): WORKDIR_LABEL,
+        term("path"): workdir,
+    })];
+    for root in roots {
+        described.push(root.describe(settings)?);
+    }
+    Ok(Value::Array(described))
+}
@@ -0,0 +1,252 @@
+// Software bill of materials of a job: the programs and shared libraries the
+// tracker saw, mapped to the rpm, dpkg or Spack packages that installed them,
+// as a CycloneDX document in the performance
+use eyre::Report;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::debug;
+
+use crate::command;
+use crate::declaration::Declaration;
+use crate::digest;
+use crate::job::Job;
+use crate::privilege;
+use crate::settings::Settings;
+use crate::tracker::Trace;
+
+pub const SPEC_VERSION: &str = "1.5";
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Package {
+    // rpm, deb or spack, as in package URLs
+    pub kind: &'static str,
+    pub name: String,
+    pub version: String,
+    // of the build, for Spack
+    pub hash: Option<String>,
+}
+
+impl Package {
+    pub fn purl(&self) -> String {
+        format!("pkg:{}/{}@{}", self.kind, self.name, self.version)
+    }
+}
+
+// A file the job's programs ran or loaded
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub sha256: Option<String>,
+    pub package: Option<Package>,
+    pub executable: bool,
+}
+
+// What installed files, asked of the package managers of the node
+pub struct Owners<'a> {
+    settings: &'a Settings,
+    // the Spack packages and their prefixes, listed once
+    spack: Option<Vec<(Package, PathBuf)>>,
+}
+
+impl<'a> Owners<'a> {
+    pub fn new(settings: &'a Settings) -> Self {
+        Owners {
+            settings,
+            spack: None,
+        }
+    }
+
+    pub fn of(&mut self, path: &Path) -> Option<Package> {
+        self.spack(path)
+            .or_else(|| self.rpm(path))
+            .or_else(|| self.dpkg(path))
+    }
+
+    // None for files no package owns, and without the package manager
+    fn query(&self, command: &mut Command) -> Option<String> {
+        match command::run(command, self.settings.command_timeout) {
+            Ok(output) => Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+            Err(e) => {
+                debug!("{:#}", e);
+                None
+            }
+        }
+    }
+
+    fn rpm(&self, path: &Path) -> Option<Package> {
+        let output = self.query(
+            Command::new(&self.settings.rpm)
+                .args(["-qf", "--queryformat", "%{NAME} %{VERSION}-%{RELEASE}\n"])
+                .arg(path),
+        )?;
+        let (name, version) = output.lines().next()?.split_once(' ')?;
+        Some(Package {
+            kind: "rpm",
+            name: name.to_string(),
+            version: version.to_string(),
+            hash: None,
+        })
+    }
+
+    // dpkg-query -S answers "libc6:amd64: /usr/lib/x86_64-linux-gnu/libc.so.6"
+    fn dpkg(&self, path: &Path) -> Option<Package> {
+        let output = self.query(Command::new(&self.settings.dpkg_query).arg("-S").arg(path))?;
+        let package = output
+            .lines()
+            .filter(|line| !line.starts_with("diversion "))
+            .find_map(|line| line.split_once(": "))
+            .and_then(|(packages, _)| packages.split(", ").next())?
+            .to_string();
+        let version = self.query(
+            Command::new(&self.settings.dpkg_query)
+                .args(["-W", "-f", "${Version}"])
+                .arg(&package),
+        )?;
+        let name = package.split(':').next().unwrap_or(&package);
+        Some(Package {
+            kind: "deb",
+            name: name.to_string(),
+            version: version.trim().to_string(),
+            hash: None,
+        })
+    }
+
+    fn spack(&mut self, path: &Path) -> Option<Package> {
+        if self.spack.is_none() {
+            let listed = self
+                .query(Command::new(&self.settings.spack).args([
+                    "find",
+                    "--format",
+                    "{name} {version} {hash} {prefix}",
+                ]))
+                .unwrap_or_default();
+            let packages = listed
+                .lines()
+                .filter_map(|line| {
+                    let mut fields = line.split_whitespace();
+                    let (name, version, hash, prefix) = (
+                        fields.next()?,
+                        fields.next()?,
+                        fields.next()?,
+                        fields.next()?,
+                    );
+                    prefix.starts_with('/').then(|| {
+                        let package = Package {
+                            kind: "spack",
+                            name: name.to_string(),
+                            version: version.to_string(),
+                            hash: Some(hash.to_string()),
+                        };
+                        (package, PathBuf::from(prefix))
+                    })
+                })
+                .collect();
+            self.spack = Some(packages);
+        }
+        self.spack
+            .as_ref()?
+            .iter()
+            .find(|(_, prefix)| path.starts_with(prefix))
+            .map(|(package, _)| package.clone())
+    }
+}
+
+// The files of a trace, each once, with what installed them
+pub fn entries(settings: &Settings, trace: &Trace) -> Vec<Entry> {
+    let mut owners = Owners::new(settings);
+    let executables = trace
+        .executables
+        .iter()
+        .map(|executable| (&executable.path, true));
+    let libraries = trace.libraries.iter().map(|library| (&library.path, false));
+    let mut entries: Vec<Entry> = Vec::new();
+    for (path, executable) in executables.chain(libraries) {
+        if entries.iter().any(|entry| &entry.path == path) {
+            continue;
+        }
+        entries.push(Entry {
+            path: path.clone(),
+            // the file may be gone by now
+            sha256: digest::sha256_file(path).ok(),
+            package: owners.of(path),
+            executable,
+        });
+    }
+    entries
+}
+
+fn file_component(entry: &Entry) -> Value {
+    let mut component = json!({ "type": "file", "name": entry.path });
+    if let Some(sha256) = &entry.sha256 {
+        component["hashes"] = json!([{ "alg": "SHA-256", "content": sha256 }]);
+    }
+    component
+}
+
+// The CycloneDX document: a component for each package, with the files of it
+// the job used, and one for each file no package owns
+pub fn document(entries: &[Entry]) -> Value {
+    let mut packages: BTreeMap<&Package, Vec<&Entry>> = BTreeMap::new();
+    let mut components = Vec::new();
+    for entry in entries {
+        match &entry.package {
+            Some(package) => packages.entry(package).or_default().push(entry),
+            None => components.push(file_component(entry)),
+        }
+    }
+    for (package, files) in packages {
+        let kind = match files.iter().any(|entry| entry.executable) {
+            true => "application",
+            false => "library",
+        };
+        let mut component = json!({
+            "type": kind,
+            "bom-ref": package.purl(),
+            "name": package.name,
+            "version": package.version,
+            "purl": package.purl(),
+            "components": files.into_iter().map(file_component).collect::<Vec<_>>(),
+        });
+        if let Some(hash) = &package.hash {
+            component["properties"] = json!([{ "name": "spack:hash", "value": hash }]);
+        }
+        components.push(component);
+    }
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": SPEC_VERSION,
+        "version": 1,
+        "metadata": {
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": "spank-tro",
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+        },
+        "components": components,
+    })
+}
+
+// Add the bill of materials of the trace to the performance that was just
+// added
+pub fn record(settings: &Settings, job: &Job, trace: &Trace) -> Result<(), Report> {
+    if !settings.sbom
+        || settings.dry_run
+        || (trace.executables.is_empty() && trace.libraries.is_empty())
+    {
+        return Ok(());
+    }
+    let entries = {
+        // the files are hashed as the user, who ran them
+        let _user = privilege::as_user(job);
+        entries(settings, trace)
+    };
+    let document = document(&entries);
+    let mut declaration = Declaration::of(job)?;
+    declaration.annotate_performance("sbom", document)?;
+    declaration.save()
+}
@@ -0,0 +1,38 @@
+// Version of the spank-tro terms of a declaration, spank:schemaVersion on the
+// TRO. Declarations written by an older spank-tro are upgraded as they are
+// loaded, one migration after the other, e.g. when a requeued job adds to the
+// TRO of its previous attempt. Those written by a newer one are read but not
+// written: their terms may mean what this version does not know.
+use eyre::{eyre, Report};
+use serde_json::Value;
+
+use crate::declaration::term;
+
+pub const VERSION: u32 = 1;
+
+type Migration = fn(&mut Value) -> Result<(), Report>;
+
+// MIGRATIONS[i] upgrades declarations of version i + 1 to version i + 2
+const MIGRATIONS: &[Migration] = &[];
+
+// The version of a declaration, 1 for those written before versions were
+pub fn version(document: &Value) -> Result<u32, Report> {
+    match &document["@graph"][0][term("schemaVersion")] {
+        Value::Null => Ok(1),
+        version => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .filter(|version| *version > 0)
+            .ok_or_else(|| eyre!("Invalid schema version {version}")),
+    }
+}
+
+// Bring a declaration of an older version up to VERSION, returns the version
+// it had
+pub fn upgrade(document: &mut Value) -> Result<u32, Report> {
+    let found = version(document)?;
+    for migration in MIGRATIONS.iter().skip(found as usize - 1) {
+        migration(document)?;
+    }
+    Ok(found)
+}
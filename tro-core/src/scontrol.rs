@@ -0,0 +1,60 @@
+use eyre::{Report, WrapErr};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::command;
+use crate::settings::Settings;
+
+// scontrol is asked while the job is being torn down, don't wait on a busy
+// slurmctld for long
+const SCONTROL_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Fields of `scontrol show job`, e.g. JobState, StdOut or WorkDir
+#[derive(Debug, Default)]
+pub struct JobInfo {
+    fields: HashMap<String, String>,
+}
+
+impl JobInfo {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .get(key)
+            .map(String::as_str)
+            .filter(|value| !value.is_empty() && *value != "(null)")
+    }
+
+    fn parse(output: &str) -> Self {
+        let mut fields: HashMap<String, String> = HashMap::new();
+        let mut last_key: Option<String> = None;
+        for token in output.split_whitespace() {
+            match token.split_once('=') {
+                Some((key, value)) => {
+                    fields.insert(key.to_string(), value.to_string());
+                    last_key = Some(key.to_string());
+                }
+                // values with spaces, e.g. job names
+                None => {
+                    if let Some(value) = last_key.as_ref().and_then(|key| fields.get_mut(key)) {
+                        value.push(' ');
+                        value.push_str(token);
+                    }
+                }
+            }
+        }
+        JobInfo { fields }
+    }
+}
+
+// `jobid` may also be an array task, e.g. 123_4
+pub fn show_job(settings: &Settings, jobid: impl Display) -> Result<JobInfo, Report> {
+    let output = command::run(
+        Command::new(&settings.scontrol)
+            .args(["show", "job", "--oneliner"])
+            .arg(jobid.to_string()),
+        Some(SCONTROL_TIMEOUT),
+    )
+    .wrap_err_with(|| format!("Failed to get job {jobid} from scontrol"))?;
+    Ok(JobInfo::parse(&String::from_utf8_lossy(&output.stdout)))
+}
@@ -0,0 +1,130 @@
+use eyre::{eyre, Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process::Command;
+use std::str::FromStr;
+
+use crate::command;
+use crate::settings::Settings;
+
+// Where the passphrase of the site's signing key comes from
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PassphraseSource {
+    // gpg_passphrase
+    #[default]
+    Config,
+    // a field of a Vault KV secret, `vault:<path>[#<field>]`, the field
+    // defaulting to passphrase
+    Vault {
+        path: String,
+        field: String,
+    },
+    // an entry of the system keyring, `keyring:<entry>`, stored for the
+    // KEYRING_SERVICE service
+    Keyring(String),
+}
+
+// Service the keyring entries of passphrase_source=keyring: are stored under
+pub const KEYRING_SERVICE: &str = "spank-tro";
+
+impl FromStr for PassphraseSource {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            None if value == "config" => Ok(PassphraseSource::Config),
+            Some(("vault", secret)) if !secret.is_empty() => {
+                let (path, field) = secret.split_once('#').unwrap_or((secret, "passphrase"));
+                Ok(PassphraseSource::Vault {
+                    path: path.to_string(),
+                    field: field.to_string(),
+                })
+            }
+            Some(("keyring", entry)) if !entry.is_empty() => {
+                Ok(PassphraseSource::Keyring(entry.to_string()))
+            }
+            _ => Err(eyre!(
+                "{value} is not one of config/vault:<path>/keyring:<entry>"
+            )),
+        }
+    }
+}
+
+// How to reach Vault, logging in with the node's own identity, e.g. its TLS
+// client certificate, rather than a token kept around
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultSettings {
+    pub program: PathBuf,
+    pub addr: Option<String>,
+    pub auth_method: String,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+}
+
+impl Default for VaultSettings {
+    fn default() -> Self {
+        VaultSettings {
+            program: PathBuf::from("vault"),
+            addr: None,
+            auth_method: "cert".to_string(),
+            client_cert: None,
+            client_key: None,
+        }
+    }
+}
+
+impl VaultSettings {
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        if let Some(addr) = &self.addr {
+            command.env("VAULT_ADDR", addr);
+        }
+        if let Some(client_cert) = &self.client_cert {
+            command.env("VAULT_CLIENT_CERT", client_cert);
+        }
+        if let Some(client_key) = &self.client_key {
+            command.env("VAULT_CLIENT_KEY", client_key);
+        }
+        command
+    }
+}
+
+// The passphrase of the site's signing key, fetched when it is needed
+pub fn passphrase(settings: &Settings) -> Result<String, Report> {
+    match &settings.passphrase_source {
+        PassphraseSource::Config => Ok(settings.gpg_passphrase.clone()),
+        PassphraseSource::Vault { path, field } => vault(settings, path, field)
+            .wrap_err_with(|| format!("Failed to get the passphrase from Vault {path}")),
+        PassphraseSource::Keyring(entry) => keyring::Entry::new(KEYRING_SERVICE, entry)
+            .and_then(|entry| entry.get_password())
+            .wrap_err_with(|| format!("Failed to get the passphrase from keyring entry {entry}")),
+    }
+}
+
+fn vault(settings: &Settings, path: &str, field: &str) -> Result<String, Report> {
+    let vault = &settings.vault;
+    // the token lives as long as this call, it is never written to disk
+    let login = command::run(
+        vault.command().args([
+            "login",
+            &format!("-method={}", vault.auth_method),
+            "-token-only",
+            "-no-store",
+        ]),
+        settings.command_timeout,
+    )?;
+    let token = String::from_utf8_lossy(&login.stdout).trim().to_string();
+    let secret = command::run(
+        vault.command().env("VAULT_TOKEN", &token).args([
+            "kv",
+            "get",
+            &format!("-field={field}"),
+            path,
+        ]),
+        settings.command_timeout,
+    )?;
+    let passphrase =
+        String::from_utf8(secret.stdout).map_err(|_| eyre!("{field} of {path} is not text"))?;
+    Ok(passphrase.trim_end_matches('\n').to_string())
+}
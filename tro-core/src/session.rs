@@ -0,0 +1,59 @@
+use eyre::{Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
+
+use crate::job::Job;
+use crate::settings::Settings;
+
+// Where salloc tells the commands of an interactive allocation about its TRO
+pub const SESSION_ENV: &str = "SPANK_TRO_SESSION";
+
+// The TRO of an interactive allocation, shared by salloc and the snapshots
+// taken from within the allocation. Written as the user, so it never holds the
+// signing passphrase.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub settings: Settings,
+    pub job: Job,
+}
+
+impl Session {
+    pub fn new(settings: &Settings, job: &Job) -> Self {
+        let mut settings = settings.clone();
+        settings.gpg_passphrase.clear();
+        Session {
+            settings,
+            job: job.clone(),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Report> {
+        if let Some(dir) = path.parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .mode(0o600)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(path)
+            .wrap_err_with(|| format!("Failed to create {}", path.display()))?;
+        file.write_all(&serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Report> {
+        let content =
+            fs::read(path).wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&content)
+            .wrap_err_with(|| format!("Invalid session {}", path.display()))
+    }
+}
@@ -0,0 +1,410 @@
+use eyre::{eyre, Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::Level;
+
+use crate::attachment::ARTIFACTS;
+use crate::catalog::CatalogBackend;
+use crate::config::ConfigFile;
+use crate::digest::LargeFiles;
+use crate::failure::{FailurePolicy, OnFailure};
+use crate::logging::{self, LogTarget};
+use crate::redact;
+use crate::retry::RetryPolicy;
+use crate::secrets::{PassphraseSource, VaultSettings};
+use crate::signing::{Signer, SiteKey};
+use crate::sigstore::SigstoreSettings;
+use crate::slurmrestd::SlurmrestdSettings;
+use crate::tracker::Tracker;
+
+// Site configuration needed to build, finalize and sign TROs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    pub gpg_home: PathBuf,
+    pub gpg_fingerprint: String,
+    pub gpg_passphrase: String,
+    pub passphrase_source: PassphraseSource,
+    pub vault: VaultSettings,
+    // keys taking over from each other, instead of gpg_fingerprint
+    pub gpg_keys: Vec<SiteKey>,
+    pub signer: Signer,
+    pub sigstore: SigstoreSettings,
+    // also write a detached signature gpg can check on its own
+    pub detached_signature: bool,
+    pub gpg: PathBuf,
+    pub trs_caps: PathBuf,
+    pub tro_utils: PathBuf,
+    pub scontrol: PathBuf,
+    pub sacct: PathBuf,
+    pub slurmrestd: SlurmrestdSettings,
+    pub dry_run: bool,
+    #[serde(with = "logging::level")]
+    pub log_level: Level,
+    pub log_target: LogTarget,
+    pub metrics_dir: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    // index of the finalized TROs, node-local or shared by all nodes
+    pub catalog: Option<CatalogBackend>,
+    pub sqlite3: PathBuf,
+    pub psql: PathBuf,
+    // libpq password file for the PostgreSQL catalog, ~/.pgpass without it
+    pub catalog_passfile: Option<PathBuf>,
+    pub command_timeout: Option<Duration>,
+    pub on_failure: OnFailure,
+    pub retry: RetryPolicy,
+    // files above this size are fingerprinted as large_files says rather
+    // than hashed, where the plugin hashes them
+    pub max_hash_size: Option<u64>,
+    pub large_files: LargeFiles,
+    // content-addressable store the inputs of jobs are copied to, see cas
+    pub cas_dir: Option<PathBuf>,
+    // inline job output files up to this size in the declaration
+    pub attach_output_max_size: Option<u64>,
+    // inline the batch script in the declaration, not only its digest
+    pub embed_batch_script: bool,
+    // names of the variables and options whose values are kept out of TROs
+    pub redact: Vec<String>,
+    // artifacts whose content is only attached encrypted to encrypt_to
+    pub sensitive: Vec<String>,
+    pub encrypt_to: Vec<String>,
+    // zstd-compress attached content from this size on
+    pub compress_min_size: Option<u64>,
+    pub zstd: PathBuf,
+    pub tracker: Tracker,
+    // how long to wait for the trace of the job's programs to be written
+    pub trace_timeout: Duration,
+    pub trace_poll_interval: Duration,
+    pub bpftrace: PathBuf,
+    pub strace: PathBuf,
+    // record an arrangement at each checkpoint of the job
+    pub checkpoints: bool,
+    pub checkpoint_poll_interval: Duration,
+    // record an arrangement every snapshot_interval while the job runs, at
+    // most snapshot_max of them
+    pub snapshot_interval: Option<Duration>,
+    pub snapshot_max: u32,
+    // hash again only the files whose size or modification time changed for
+    // the final arrangement, see incremental
+    pub incremental_arrangement: bool,
+    // record which files of the workdir the job wrote, with fanotify
+    pub track_outputs: bool,
+    // add the TROs of workflow tasks to a TRO of the whole workflow run
+    pub aggregate_workflows: bool,
+    // write an in-toto attestation of the run next to each signed TRO
+    pub export_intoto: bool,
+    // and SLSA provenance
+    pub export_slsa: bool,
+    // attest the evidence of each node of the job with a MUNGE credential
+    pub munge_attestation: bool,
+    pub munge: PathBuf,
+    // quote the PCRs of each node with this TPM attestation key
+    pub tpm_ak: Option<String>,
+    pub tpm_pcrs: String,
+    pub tpm2_quote: PathBuf,
+    // add a CycloneDX bill of the packages of the programs and libraries
+    // the tracker saw to the performance
+    pub sbom: bool,
+    pub rpm: PathBuf,
+    pub dpkg_query: PathBuf,
+    pub spack: PathBuf,
+    // record the module, package and build hash of each library XALT saw
+    // loaded, the modules from Lmod's reverse map
+    pub library_provenance: bool,
+    pub lmod_reverse_map: Option<PathBuf>,
+    // add the specification of the job's conda environment, virtualenv or
+    // Spack environment to the initial arrangement
+    pub capture_environment: bool,
+    pub conda: PathBuf,
+    // add the CPUs, GPUs and interconnect of each node to its evidence
+    pub hardware_inventory: bool,
+    pub nvidia_smi: PathBuf,
+    // add the CPU and NUMA binding of the job on each node to its evidence
+    pub capture_topology: bool,
+    pub hwloc_ls: PathBuf,
+    // what tells the striping of Lustre and GPFS directories
+    pub lfs: PathBuf,
+    pub mmlsattr: PathBuf,
+}
+
+// Interval snapshots more frequent than this would mostly hash the same files
+pub const MIN_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            gpg_home: PathBuf::new(),
+            gpg_fingerprint: String::new(),
+            gpg_passphrase: String::new(),
+            passphrase_source: PassphraseSource::default(),
+            vault: VaultSettings::default(),
+            gpg_keys: Vec::new(),
+            signer: Signer::default(),
+            sigstore: SigstoreSettings::default(),
+            detached_signature: false,
+            gpg: PathBuf::from("gpg"),
+            trs_caps: PathBuf::new(),
+            tro_utils: PathBuf::new(),
+            scontrol: PathBuf::from("scontrol"),
+            sacct: PathBuf::from("sacct"),
+            slurmrestd: SlurmrestdSettings::default(),
+            dry_run: false,
+            log_level: Level::INFO,
+            log_target: LogTarget::default(),
+            metrics_dir: None,
+            audit_log: None,
+            catalog: None,
+            sqlite3: PathBuf::from("sqlite3"),
+            psql: PathBuf::from("psql"),
+            catalog_passfile: None,
+            command_timeout: Some(Duration::from_secs(3600)),
+            on_failure: OnFailure::default(),
+            retry: RetryPolicy::default(),
+            max_hash_size: None,
+            large_files: LargeFiles::default(),
+            cas_dir: None,
+            attach_output_max_size: None,
+            embed_batch_script: false,
+            redact: redact::default_patterns(),
+            sensitive: Vec::new(),
+            encrypt_to: Vec::new(),
+            compress_min_size: None,
+            zstd: PathBuf::from("zstd"),
+            tracker: Tracker::default(),
+            trace_timeout: Duration::from_secs(10),
+            trace_poll_interval: Duration::from_secs(1),
+            bpftrace: PathBuf::from("bpftrace"),
+            strace: PathBuf::from("strace"),
+            checkpoints: false,
+            checkpoint_poll_interval: Duration::from_secs(10),
+            snapshot_interval: None,
+            snapshot_max: 24,
+            incremental_arrangement: false,
+            track_outputs: false,
+            aggregate_workflows: true,
+            export_intoto: false,
+            export_slsa: false,
+            munge_attestation: false,
+            munge: PathBuf::from("munge"),
+            tpm_ak: None,
+            tpm_pcrs: "sha256:0,1,2,3,4,5,6,7".to_string(),
+            tpm2_quote: PathBuf::from("tpm2_quote"),
+            sbom: false,
+            rpm: PathBuf::from("rpm"),
+            dpkg_query: PathBuf::from("dpkg-query"),
+            spack: PathBuf::from("spack"),
+            library_provenance: false,
+            lmod_reverse_map: None,
+            capture_environment: false,
+            conda: PathBuf::from("conda"),
+            hardware_inventory: false,
+            nvidia_smi: PathBuf::from("nvidia-smi"),
+            capture_topology: false,
+            hwloc_ls: PathBuf::from("hwloc-ls"),
+            lfs: PathBuf::from("lfs"),
+            mmlsattr: PathBuf::from("/usr/lpp/mmfs/bin/mmlsattr"),
+        }
+    }
+}
+
+impl Settings {
+    // Apply a `key=value` plugin argument, returns false for keys that are not
+    // settings
+    pub fn set(&mut self, key: &str, value: &str) -> Result<bool, Report> {
+        match key {
+            "gpg_home" => self.gpg_home = PathBuf::from(value),
+            "gpg_fingerprint" => self.gpg_fingerprint = value.to_string(),
+            "gpg_passphrase" => self.gpg_passphrase = value.to_string(),
+            "passphrase_source" => self.passphrase_source = value.parse()?,
+            "vault" => self.vault.program = PathBuf::from(value),
+            "vault_addr" => self.vault.addr = Some(value.to_string()),
+            "vault_auth_method" => self.vault.auth_method = value.to_string(),
+            "vault_client_cert" => self.vault.client_cert = Some(PathBuf::from(value)),
+            "vault_client_key" => self.vault.client_key = Some(PathBuf::from(value)),
+            "gpg_keys" => {
+                self.gpg_keys = parse_list(value)
+                    .iter()
+                    .map(|key| key.parse())
+                    .collect::<Result<_, _>>()?
+            }
+            "signer" => self.signer = value.parse()?,
+            "cosign" => self.sigstore.cosign = PathBuf::from(value),
+            "sigstore_token_file" => self.sigstore.token_file = Some(PathBuf::from(value)),
+            "fulcio_url" => self.sigstore.fulcio_url = Some(value.to_string()),
+            "rekor_url" => self.sigstore.rekor_url = Some(value.to_string()),
+            "sigstore_identity" => self.sigstore.identity = Some(value.to_string()),
+            "sigstore_issuer" => self.sigstore.issuer = Some(value.to_string()),
+            "detached_signature" => self.detached_signature = parse_bool(value)?,
+            "gpg" => self.gpg = PathBuf::from(value),
+            "trs_caps" => self.trs_caps = PathBuf::from(value),
+            "tro_utils" => self.tro_utils = PathBuf::from(value),
+            "scontrol" => self.scontrol = PathBuf::from(value),
+            "sacct" => self.sacct = PathBuf::from(value),
+            "catalog" => self.catalog = Some(value.parse()?),
+            "sqlite3" => self.sqlite3 = PathBuf::from(value),
+            "psql" => self.psql = PathBuf::from(value),
+            "catalog_passfile" => self.catalog_passfile = Some(PathBuf::from(value)),
+            "slurmrestd_url" => {
+                self.slurmrestd.url = Some(value.to_string()).filter(|url| !url.is_empty())
+            }
+            "slurmrestd_api_version" => self.slurmrestd.api_version = value.to_string(),
+            "slurmrestd_user" => self.slurmrestd.user = value.to_string(),
+            "slurmrestd_token_file" => self.slurmrestd.token_file = Some(PathBuf::from(value)),
+            "slurmrestd_fields" => self.slurmrestd.fields = parse_list(value),
+            "curl" => self.slurmrestd.curl = PathBuf::from(value),
+            "tro_dry_run" => self.dry_run = parse_bool(value)?,
+            "log_level" => {
+                self.log_level = value
+                    .parse()
+                    .map_err(|_| eyre!("{value} is not a valid log level"))?
+            }
+            "log_target" => self.log_target = value.parse()?,
+            "metrics_dir" => self.metrics_dir = Some(PathBuf::from(value)),
+            "audit_log" => self.audit_log = Some(PathBuf::from(value)),
+            "command_timeout" => {
+                let timeout = parse_duration(value)?;
+                self.command_timeout = (!timeout.is_zero()).then_some(timeout);
+            }
+            "on_failure" => self.on_failure.policy = value.parse()?,
+            "fail_on" => self.on_failure.set_phases(value, FailurePolicy::Fail)?,
+            "warn_on" => self.on_failure.set_phases(value, FailurePolicy::Warn)?,
+            "retries" => self.retry.set_retries(value)?,
+            "retry_backoff" => self.retry.backoff = parse_duration(value)?,
+            "max_hash_size" => {
+                let max_size = parse_size(value)?;
+                self.max_hash_size = (max_size > 0).then_some(max_size);
+            }
+            "large_files" => self.large_files = value.parse()?,
+            "cas_dir" => self.cas_dir = Some(PathBuf::from(value)),
+            "attach_output_max_size" => {
+                let max_size = parse_size(value)?;
+                self.attach_output_max_size = (max_size > 0).then_some(max_size);
+            }
+            "embed_batch_script" => self.embed_batch_script = parse_bool(value)?,
+            "redact" => self.redact = parse_list(value),
+            "sensitive" => {
+                let sensitive = parse_list(value);
+                if let Some(unknown) = sensitive
+                    .iter()
+                    .find(|name| !ARTIFACTS.contains(&name.as_str()))
+                {
+                    return Err(eyre!("{unknown} is not one of {}", ARTIFACTS.join("/")));
+                }
+                self.sensitive = sensitive;
+            }
+            "encrypt_to" => self.encrypt_to = parse_list(value),
+            "compress_min_size" => {
+                let min_size = parse_size(value)?;
+                self.compress_min_size = (min_size > 0).then_some(min_size);
+            }
+            "zstd" => self.zstd = PathBuf::from(value),
+            "tracker" => self.tracker = value.parse()?,
+            "trace_timeout" => self.trace_timeout = parse_duration(value)?,
+            "trace_poll_interval" => self.trace_poll_interval = parse_duration(value)?,
+            "bpftrace" => self.bpftrace = PathBuf::from(value),
+            "strace" => self.strace = PathBuf::from(value),
+            "checkpoints" => self.checkpoints = parse_bool(value)?,
+            "incremental_arrangement" => self.incremental_arrangement = parse_bool(value)?,
+            "checkpoint_poll_interval" => self.checkpoint_poll_interval = parse_duration(value)?,
+            "snapshot_interval" => {
+                let interval = parse_duration(value)?;
+                if !interval.is_zero() && interval < MIN_SNAPSHOT_INTERVAL {
+                    return Err(eyre!(
+                        "{value} is shorter than {}s",
+                        MIN_SNAPSHOT_INTERVAL.as_secs()
+                    ));
+                }
+                self.snapshot_interval = (!interval.is_zero()).then_some(interval);
+            }
+            "aggregate_workflows" => self.aggregate_workflows = parse_bool(value)?,
+            "export_intoto" => self.export_intoto = parse_bool(value)?,
+            "export_slsa" => self.export_slsa = parse_bool(value)?,
+            "track_outputs" => self.track_outputs = parse_bool(value)?,
+            "munge_attestation" => self.munge_attestation = parse_bool(value)?,
+            "munge" => self.munge = PathBuf::from(value),
+            "tpm_ak" => self.tpm_ak = Some(value.to_string()),
+            "tpm_pcrs" => self.tpm_pcrs = value.to_string(),
+            "tpm2_quote" => self.tpm2_quote = PathBuf::from(value),
+            "sbom" => self.sbom = parse_bool(value)?,
+            "rpm" => self.rpm = PathBuf::from(value),
+            "dpkg_query" => self.dpkg_query = PathBuf::from(value),
+            "spack" => self.spack = PathBuf::from(value),
+            "library_provenance" => self.library_provenance = parse_bool(value)?,
+            "lmod_reverse_map" => self.lmod_reverse_map = Some(PathBuf::from(value)),
+            "capture_environment" => self.capture_environment = parse_bool(value)?,
+            "conda" => self.conda = PathBuf::from(value),
+            "hardware_inventory" => self.hardware_inventory = parse_bool(value)?,
+            "nvidia_smi" => self.nvidia_smi = PathBuf::from(value),
+            "capture_topology" => self.capture_topology = parse_bool(value)?,
+            "hwloc_ls" => self.hwloc_ls = PathBuf::from(value),
+            "lfs" => self.lfs = PathBuf::from(value),
+            "mmlsattr" => self.mmlsattr = PathBuf::from(value),
+            "snapshot_max" => {
+                self.snapshot_max = value.parse().map_err(|_| eyre!("{value} is not a count"))?
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // Apply the settings of a configuration file, ignoring the plugin-only keys
+    pub fn apply(&mut self, config: &ConfigFile) -> Result<(), Report> {
+        for (key, value) in config.entries() {
+            self.set(key, value)
+                .wrap_err_with(|| format!("Invalid {key}"))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_bool(value: &str) -> Result<bool, Report> {
+    match value {
+        "yes" | "true" | "1" => Ok(true),
+        "no" | "false" | "0" => Ok(false),
+        _ => Err(eyre!("{value} is not one of yes/no")),
+    }
+}
+
+// Parse a comma-separated list, e.g. of partitions
+pub fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Parse a byte count with an optional K/M/G/T (binary) suffix
+pub fn parse_size(value: &str) -> Result<u64, Report> {
+    let (number, multiplier) = match value.chars().last() {
+        Some('K' | 'k') => (&value[..value.len() - 1], 1 << 10),
+        Some('M' | 'm') => (&value[..value.len() - 1], 1 << 20),
+        Some('G' | 'g') => (&value[..value.len() - 1], 1 << 30),
+        Some('T' | 't') => (&value[..value.len() - 1], 1 << 40),
+        _ => (value, 1),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| eyre!("{value} is not a valid size"))?;
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| eyre!("{value} is too large"))
+}
+
+// Parse a duration given in seconds or with an s/m/h/d suffix
+pub fn parse_duration(value: &str) -> Result<Duration, Report> {
+    let (number, unit) = match value.chars().last() {
+        Some('s') => (&value[..value.len() - 1], 1),
+        Some('m') => (&value[..value.len() - 1], 60),
+        Some('h') => (&value[..value.len() - 1], 3600),
+        Some('d') => (&value[..value.len() - 1], 86400),
+        _ => (value, 1),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| eyre!("{value} is not a valid duration"))?;
+    number
+        .checked_mul(unit)
+        .map(Duration::from_secs)
+        .ok_or_else(|| eyre!("{value} is too long"))
+}
@@ -0,0 +1,398 @@
+use chrono::{NaiveDate, Utc};
+use eyre::{eyre, Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::ffi::CStr;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use crate::command;
+use crate::config::ConfigFile;
+use crate::secrets;
+use crate::settings::Settings;
+
+// Signing settings of a user, relative to their home directory, with the
+// gpg_home, gpg_fingerprint and gpg_passphrase keys of the site configuration
+pub const USER_CONFIG: &str = ".config/spank-tro/signing.conf";
+
+// Whose key signs the TROs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Signer {
+    // the site-wide gpg_fingerprint, or the gpg_keys valid today
+    #[default]
+    Site,
+    // the key the job's user configured in USER_CONFIG
+    User,
+    // the user's key, then the site's attesting that it observed the run
+    Both,
+    // no key, a short-lived certificate for the cluster's identity, see
+    // sigstore
+    Sigstore,
+}
+
+impl FromStr for Signer {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "site" => Ok(Signer::Site),
+            "user" => Ok(Signer::User),
+            "both" => Ok(Signer::Both),
+            "sigstore" => Ok(Signer::Sigstore),
+            _ => Err(eyre!("{value} is not one of site/user/both/sigstore")),
+        }
+    }
+}
+
+// One of the site's keys and the days it is used, both ends included, e.g.
+// 0123ABCD:2025-01-01:2025-12-31. Either end may be left out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SiteKey {
+    pub fingerprint: String,
+    // YYYY-MM-DD, which compare like the dates they are
+    pub from: Option<String>,
+    pub until: Option<String>,
+}
+
+impl SiteKey {
+    pub fn is_valid_on(&self, day: &str) -> bool {
+        self.from.as_deref().is_none_or(|from| from <= day)
+            && self.until.as_deref().is_none_or(|until| day <= until)
+    }
+}
+
+impl FromStr for SiteKey {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.split(':');
+        let fingerprint = parts.next().unwrap_or_default().to_string();
+        if fingerprint.is_empty() {
+            return Err(eyre!("{value} has no fingerprint"));
+        }
+        let mut day = || -> Result<Option<String>, Report> {
+            match parts.next().filter(|day| !day.is_empty()) {
+                Some(day) => {
+                    NaiveDate::parse_from_str(day, "%Y-%m-%d")
+                        .map_err(|_| eyre!("{day} is not a YYYY-MM-DD date"))?;
+                    Ok(Some(day.to_string()))
+                }
+                None => Ok(None),
+            }
+        };
+        let (from, until) = (day()?, day()?);
+        if parts.next().is_some() {
+            return Err(eyre!("{value} is not fingerprint[:from[:until]]"));
+        }
+        Ok(SiteKey {
+            fingerprint,
+            from,
+            until,
+        })
+    }
+}
+
+// A key tro-utils signs with
+#[derive(Debug, Clone, Default)]
+pub struct SigningKey {
+    pub gpg_home: PathBuf,
+    pub fingerprint: String,
+    pub passphrase: String,
+    // uid and gid tro-utils runs as to use a user's keyring
+    pub owner: Option<(u32, u32)>,
+    // last day the key is used, YYYY-MM-DD
+    pub expires: Option<String>,
+}
+
+impl SigningKey {
+    // The site's key in use today: of the gpg_keys valid today the one that
+    // came in last, gpg_fingerprint without any
+    pub fn site(settings: &Settings) -> Result<Self, Report> {
+        let mut key = SigningKey {
+            gpg_home: settings.gpg_home.clone(),
+            fingerprint: settings.gpg_fingerprint.clone(),
+            passphrase: settings.gpg_passphrase.clone(),
+            ..SigningKey::default()
+        };
+        if !settings.gpg_keys.is_empty() {
+            let today = Utc::now().format("%Y-%m-%d").to_string();
+            let active = settings
+                .gpg_keys
+                .iter()
+                .filter(|key| key.is_valid_on(&today))
+                .max_by(|a, b| a.from.cmp(&b.from))
+                .ok_or_else(|| eyre!("None of the gpg_keys is valid on {today}"))?;
+            key.fingerprint = active.fingerprint.clone();
+            key.expires = active.until.clone();
+        }
+        if key.fingerprint.is_empty() {
+            return Err(eyre!("No signing key is configured (gpg_fingerprint=)"));
+        }
+        Ok(key)
+    }
+
+    // The key configured by the user in ~/USER_CONFIG, from their ~/.gnupg
+    // unless it says otherwise
+    pub fn user((uid, gid): (u32, u32)) -> Result<Self, Report> {
+        let home = home_dir(uid)?;
+        let path = home.join(USER_CONFIG);
+        // the plugin reads it as root, only trust what the user could write
+        let metadata = fs::symlink_metadata(&path)
+            .wrap_err_with(|| format!("No signing key configured in {}", path.display()))?;
+        if !metadata.is_file() || metadata.uid() != uid {
+            return Err(eyre!(
+                "{} must be a regular file owned by the user",
+                path.display()
+            ));
+        }
+        let mut key = SigningKey {
+            gpg_home: home.join(".gnupg"),
+            owner: Some((uid, gid)),
+            ..SigningKey::default()
+        };
+        for (name, value) in ConfigFile::load(&path)?.entries() {
+            match name {
+                "gpg_home" => key.gpg_home = home.join(value),
+                "gpg_fingerprint" => key.fingerprint = value.to_string(),
+                "gpg_passphrase" => key.passphrase = value.to_string(),
+                _ => return Err(eyre!("Unknown key {name} in {}", path.display())),
+            }
+        }
+        if key.fingerprint.is_empty() {
+            return Err(eyre!("No gpg_fingerprint in {}", path.display()));
+        }
+        Ok(key)
+    }
+
+    pub fn role(&self) -> &'static str {
+        match self.owner {
+            Some(_) => "user",
+            None => "site",
+        }
+    }
+
+    // Make `command` use the key's keyring, as its owner
+    pub fn apply<'a>(&self, command: &'a mut Command) -> &'a mut Command {
+        if !self.gpg_home.as_os_str().is_empty() {
+            command
+                .env("GNUPGHOME", &self.gpg_home)
+                .env("GPG_HOME", &self.gpg_home);
+        }
+        // only root can switch, anyone else is the user already
+        if let (Some((uid, gid)), 0) = (self.owner, unsafe { libc::geteuid() }) {
+            command.uid(uid).gid(gid);
+        }
+        command
+    }
+
+    // Who the key belongs to, for messages
+    pub fn describe(&self) -> String {
+        let owner = match self.owner {
+            Some((uid, _)) => format!("uid {uid}"),
+            None => "the site".to_string(),
+        };
+        format!(
+            "key {} of {} from {}",
+            self.fingerprint,
+            owner,
+            self.gpg_home.display()
+        )
+    }
+}
+
+impl SigningKey {
+    // Fail unless the key can sign: its secret half is in the keyring, it
+    // is neither expired nor revoked, and a test signature goes through with
+    // its passphrase
+    pub fn check(&self, settings: &Settings) -> Result<(), Report> {
+        let mut list = Command::new(&settings.gpg);
+        list.args([
+            "--batch",
+            "--with-colons",
+            "--list-secret-keys",
+            &self.fingerprint,
+        ]);
+        let output = command::run(self.apply(&mut list), settings.command_timeout)
+            .wrap_err_with(|| format!("The secret {} is missing", self.describe()))?;
+        if let Some(problem) = unusable(&String::from_utf8_lossy(&output.stdout)) {
+            return Err(eyre!("The {} {problem}", self.describe()));
+        }
+        let mut sign = Command::new(&settings.gpg);
+        sign.args([
+            "--batch",
+            "--local-user",
+            &self.fingerprint,
+            // gpg removes the output of a failed signature, /dev/null too
+            "--output",
+            "-",
+        ]);
+        // stdin holds the passphrase rather than what is signed
+        let passphrase = (!self.passphrase.is_empty()).then(|| {
+            sign.args(["--pinentry-mode", "loopback", "--passphrase-fd", "0"]);
+            self.passphrase.as_bytes()
+        });
+        sign.args(["--detach-sign", "/dev/null"]);
+        command::run_with_input(self.apply(&mut sign), passphrase, settings.command_timeout)
+            .wrap_err_with(|| format!("The {} cannot sign", self.describe()))?;
+        Ok(())
+    }
+}
+
+// Why a secret key listed by `gpg --with-colons` cannot sign, if it cannot
+pub fn unusable(colons: &str) -> Option<&'static str> {
+    let sec = colons
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields[0] == "sec");
+    let Some(sec) = sec else {
+        return Some("has no secret key");
+    };
+    match sec.get(1) {
+        Some(&"e") => return Some("has expired"),
+        Some(&"r") => return Some("is revoked"),
+        Some(&"d") => return Some("is disabled"),
+        Some(&"i") => return Some("is invalid"),
+        _ => {}
+    }
+    // capabilities of the whole key are upper case
+    match sec
+        .get(11)
+        .is_some_and(|capabilities| capabilities.contains('S'))
+    {
+        true => None,
+        false => Some("has no signing capability"),
+    }
+}
+
+// Check the site key as configured, fetching its passphrase
+pub fn check_site_key(settings: &Settings) -> Result<(), Report> {
+    site_key(settings)?.check(settings)
+}
+
+// The keys a TRO of a job run by `owner` is signed with, in order
+pub fn keys(settings: &Settings, owner: (u32, u32)) -> Result<Vec<SigningKey>, Report> {
+    match settings.signer {
+        Signer::Site => Ok(vec![site_key(settings)?]),
+        Signer::User => Ok(vec![SigningKey::user(owner)?]),
+        Signer::Both => Ok(vec![SigningKey::user(owner)?, site_key(settings)?]),
+        // signed without a key
+        Signer::Sigstore => Ok(Vec::new()),
+    }
+}
+
+// The site's key with its passphrase, which may have to be fetched
+fn site_key(settings: &Settings) -> Result<SigningKey, Report> {
+    let mut key = SigningKey::site(settings)?;
+    if !settings.dry_run {
+        key.passphrase = secrets::passphrase(settings)?;
+    }
+    Ok(key)
+}
+
+// The signature tro-utils wrote for a declaration, tro-1.sig for tro-1.jsonld
+pub fn signature(declaration: &Path) -> Option<PathBuf> {
+    Some(declaration.with_extension("sig")).filter(|path| path.exists())
+}
+
+// Every signature of a declaration: tro-utils', set aside or not, and the
+// detached ones, e.g. tro-1.sig, tro-1.user.sig and tro-1.jsonld.sig
+pub fn signature_files(declaration: &Path) -> Vec<PathBuf> {
+    let (Some(dir), Some(stem)) = (declaration.parent(), declaration.file_stem()) else {
+        return Vec::new();
+    };
+    let prefix = format!("{}.", stem.to_string_lossy());
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut signatures: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            name.starts_with(&prefix) && name.ends_with(".sig")
+        })
+        .collect();
+    signatures.sort();
+    signatures
+}
+
+// Move the signature tro-utils made out of the way of the next signing, e.g.
+// to tro-1.user.sig with the tag user, returns where it went
+pub fn set_aside(declaration: &Path, tag: &str) -> Result<PathBuf, Report> {
+    let signature = signature(declaration)
+        .ok_or_else(|| eyre!("tro-utils left no signature of {}", declaration.display()))?;
+    let aside = signature.with_extension(format!("{tag}.sig"));
+    fs::rename(&signature, &aside)
+        .wrap_err_with(|| format!("Failed to rename {}", signature.display()))?;
+    Ok(aside)
+}
+
+// Where the ASCII-armored detached signature of a declaration made with a
+// key goes: tro-1.jsonld.sig for the last key, which `gpg --verify` finds on
+// its own, tro-1.jsonld.user.sig for the user's when both sign
+pub fn detached_signature(declaration: &Path, role: Option<&str>) -> PathBuf {
+    let mut name = declaration.as_os_str().to_owned();
+    if let Some(role) = role {
+        name.push(format!(".{role}"));
+    }
+    name.push(".sig");
+    PathBuf::from(name)
+}
+
+// Sign the declaration with plain gpg, for reviewers without TRO tooling
+pub fn detach_sign(
+    settings: &Settings,
+    key: &SigningKey,
+    declaration: &Path,
+    signature: &Path,
+) -> Result<(), Report> {
+    let mut command = Command::new(&settings.gpg);
+    command.args([
+        "--batch",
+        "--yes",
+        "--armor",
+        "--local-user",
+        &key.fingerprint,
+    ]);
+    // on stdin, anyone may read the command line
+    let passphrase = (!key.passphrase.is_empty()).then(|| {
+        command.args(["--pinentry-mode", "loopback", "--passphrase-fd", "0"]);
+        key.passphrase.as_bytes()
+    });
+    command
+        .arg("--output")
+        .arg(signature)
+        .arg("--detach-sign")
+        .arg(declaration);
+    command::run_with_input(
+        key.apply(&mut command),
+        passphrase,
+        settings.command_timeout,
+    )
+    .wrap_err_with(|| format!("Failed to write {}", signature.display()))?;
+    Ok(())
+}
+
+fn home_dir(uid: u32) -> Result<PathBuf, Report> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() {
+        return Err(eyre!("Failed to find the home directory of uid {uid}"));
+    }
+    let home = unsafe { CStr::from_ptr(passwd.pw_dir) };
+    Ok(PathBuf::from(home.to_string_lossy().into_owned()))
+}
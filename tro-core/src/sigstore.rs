@@ -0,0 +1,113 @@
+// Keyless signing with Sigstore, for signer=sigstore: cosign exchanges the
+// cluster's workload OIDC token for a short-lived Fulcio certificate, signs
+// the declaration with it and logs the signature in Rekor. What a verifier
+// needs ends up in tro-1.jsonld.sigstore.json, and no long-lived key has to
+// be kept on the compute nodes.
+use eyre::{eyre, Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::command;
+use crate::settings::Settings;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigstoreSettings {
+    pub cosign: PathBuf,
+    // file the cluster's workload identity provider keeps an OIDC token in
+    pub token_file: Option<PathBuf>,
+    // the public instances without them
+    pub fulcio_url: Option<String>,
+    pub rekor_url: Option<String>,
+    // who verified signatures must come from: the certificate's identity
+    // and the issuer of the token it was issued for
+    pub identity: Option<String>,
+    pub issuer: Option<String>,
+}
+
+impl Default for SigstoreSettings {
+    fn default() -> Self {
+        SigstoreSettings {
+            cosign: PathBuf::from("cosign"),
+            token_file: None,
+            fulcio_url: None,
+            rekor_url: None,
+            identity: None,
+            issuer: None,
+        }
+    }
+}
+
+// The bundle of the signature of a declaration, its certificate and Rekor
+// entry: tro-1.jsonld.sigstore.json, tro-1.jsonld.attempt-1.sigstore.json
+// once set aside
+pub fn bundle_path(declaration: &Path, tag: Option<&str>) -> PathBuf {
+    let mut name = declaration.as_os_str().to_owned();
+    if let Some(tag) = tag {
+        name.push(format!(".{tag}"));
+    }
+    name.push(".sigstore.json");
+    PathBuf::from(name)
+}
+
+// The OIDC token cosign is issued a certificate for
+pub fn identity_token(settings: &Settings) -> Result<String, Report> {
+    let path = settings.sigstore.token_file.as_ref().ok_or_else(|| {
+        eyre!("No OIDC token is configured for keyless signing (sigstore_token_file=)")
+    })?;
+    let token = fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read the OIDC token {}", path.display()))?;
+    match token.trim() {
+        "" => Err(eyre!("The OIDC token {} is empty", path.display())),
+        token => Ok(token.to_string()),
+    }
+}
+
+pub fn sign(settings: &Settings, declaration: &Path) -> Result<(), Report> {
+    let sigstore = &settings.sigstore;
+    let bundle = bundle_path(declaration, None);
+    let mut command = Command::new(&sigstore.cosign);
+    command
+        .args(["sign-blob", "--yes"])
+        // rather than on the command line, where anyone on the node sees it
+        .env("SIGSTORE_ID_TOKEN", identity_token(settings)?);
+    if let Some(url) = &sigstore.fulcio_url {
+        command.args(["--fulcio-url", url]);
+    }
+    if let Some(url) = &sigstore.rekor_url {
+        command.args(["--rekor-url", url]);
+    }
+    command.arg("--bundle").arg(&bundle).arg(declaration);
+    command::run(&mut command, settings.command_timeout)
+        .wrap_err_with(|| format!("Failed to sign {} with cosign", declaration.display()))?;
+    Ok(())
+}
+
+pub fn verify(settings: &Settings, declaration: &Path) -> Result<(), Report> {
+    let sigstore = &settings.sigstore;
+    let (Some(identity), Some(issuer)) = (&sigstore.identity, &sigstore.issuer) else {
+        return Err(eyre!(
+            "Keyless signatures are only verified against sigstore_identity= and sigstore_issuer="
+        ));
+    };
+    let mut command = Command::new(&sigstore.cosign);
+    command
+        .arg("verify-blob")
+        .args(["--certificate-identity", identity])
+        .args(["--certificate-oidc-issuer", issuer]);
+    if let Some(url) = &sigstore.rekor_url {
+        command.args(["--rekor-url", url]);
+    }
+    command
+        .arg("--bundle")
+        .arg(bundle_path(declaration, None))
+        .arg(declaration);
+    command::run(&mut command, settings.command_timeout).wrap_err_with(|| {
+        format!(
+            "The keyless signature of {} is not valid",
+            declaration.display()
+        )
+    })?;
+    Ok(())
+}
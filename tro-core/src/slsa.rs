@@ -0,0 +1,177 @@
+// SLSA v1 provenance of a job, for SLSA verification tooling: the cluster,
+// partition and nodes as the builder, the batch script and the program it
+// ran as the invocation, and the inputs declared with --tro-input as the
+// resolved dependencies, about the files of the final arrangement
+use eyre::{Report, WrapErr};
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::declaration::{term, Declaration};
+use crate::digest;
+use crate::intoto::{self, STATEMENT_TYPE};
+
+pub const PROVENANCE_PREDICATE: &str = "https://slsa.dev/provenance/v1";
+pub const BUILD_TYPE: &str = "https://github.com/transparency-certified/spank-tro/slurm-job/v1";
+
+// Where the provenance of a declaration goes by default, tro-1.slsa.json
+// next to tro-1.jsonld
+pub fn default_path(declaration: &Path) -> PathBuf {
+    declaration.with_extension("slsa.json")
+}
+
+// Where the job ran
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Builder {
+    pub cluster: Option<String>,
+    pub partition: Option<String>,
+    pub nodes: Vec<String>,
+}
+
+impl Builder {
+    // As far as the declaration tells: slurmrestd's record of the job and
+    // the nodes that left their evidence
+    pub fn of(declaration: &Declaration) -> Self {
+        let job = declaration.tro_term("slurmJob");
+        let field = |name: &str| {
+            job.and_then(|job| job[name].as_str())
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        let mut nodes: Vec<String> = declaration
+            .last_performance_term("nodes")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|node| node[term("hostname")].as_str().map(str::to_string))
+            .collect();
+        if nodes.is_empty() {
+            nodes.extend(field("nodes"));
+        }
+        Builder {
+            cluster: field("cluster"),
+            partition: field("partition"),
+            nodes,
+        }
+    }
+
+    fn id(&self) -> String {
+        let part = |value: &Option<String>| value.clone().unwrap_or_else(|| "unknown".to_string());
+        format!(
+            "urn:spank-tro:slurm:{}:{}",
+            part(&self.cluster),
+            part(&self.partition)
+        )
+    }
+
+    fn describe(&self) -> Value {
+        json!({
+            "id": self.id(),
+            "version": { "spank-tro": env!("CARGO_PKG_VERSION") },
+            "builderDependencies": self
+                .nodes
+                .iter()
+                .map(|node| json!({ "name": node, "annotations": { "kind": "node" } }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+// An input as described by inputs::Input::describe
+fn dependency(input: &Value) -> Option<Value> {
+    if let Some(doi) = input[term("doi")].as_str() {
+        return Some(json!({ "uri": format!("https://doi.org/{doi}") }));
+    }
+    if let Some(uri) = input[term("uri")].as_str() {
+        return Some(json!({ "uri": uri }));
+    }
+    let path = input[term("path")].as_str()?;
+    let mut dependency = json!({ "uri": format!("file://{path}") });
+    if let Some(sha256) = input[term("sha256")].as_str() {
+        dependency["digest"] = json!({ "sha256": sha256 });
+    }
+    Some(dependency)
+}
+
+pub fn statement(
+    declaration: &Declaration,
+    builder: &Builder,
+    name: &str,
+    tro_sha256: &str,
+) -> Value {
+    let mut external = Map::new();
+    if let Some(script) = declaration.first_arrangement_term("batchScript") {
+        external.insert(
+            "batchScript".to_string(),
+            json!({ "digest": { "sha256": script[term("sha256")] } }),
+        );
+    }
+    external.insert("command".to_string(), intoto::command(declaration));
+    let dependencies: Vec<Value> = declaration
+        .first_arrangement_term("inputs")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(dependency)
+        .collect();
+    let mut internal = Map::new();
+    if let Some(preload) = declaration.last_performance_term("preload") {
+        internal.insert("preload".to_string(), preload.clone());
+    }
+    let timeline = declaration.last_performance_term("timeline");
+    let time = |key: &str| {
+        timeline
+            .and_then(|timeline| timeline.get(term(key)))
+            .cloned()
+    };
+    let performance = declaration
+        .performances()
+        .pop()
+        .map(|(id, _)| format!("{name}#{id}"));
+    json!({
+        "_type": STATEMENT_TYPE,
+        "subject": intoto::subject(declaration, name, tro_sha256),
+        "predicateType": PROVENANCE_PREDICATE,
+        "predicate": {
+            "buildDefinition": {
+                "buildType": BUILD_TYPE,
+                "externalParameters": external,
+                "internalParameters": internal,
+                "resolvedDependencies": dependencies,
+            },
+            "runDetails": {
+                "builder": builder.describe(),
+                "metadata": {
+                    "invocationId": performance,
+                    "startedOn": time("started"),
+                    "finishedOn": time("ended"),
+                },
+                "byproducts": [{ "name": name, "digest": { "sha256": tro_sha256 } }],
+            },
+        },
+    })
+}
+
+// Write the provenance of a declaration, with what is known of where the job
+// ran on top of what the declaration tells
+pub fn export(declaration: &Path, path: &Path, known: Option<&Builder>) -> Result<(), Report> {
+    let name = declaration
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let tro_sha256 = digest::sha256_file(declaration)
+        .wrap_err_with(|| format!("Failed to read {}", declaration.display()))?;
+    let loaded = Declaration::load(declaration)?;
+    let mut builder = Builder::of(&loaded);
+    if let Some(known) = known {
+        builder.cluster = known.cluster.clone().or(builder.cluster);
+        builder.partition = known.partition.clone().or(builder.partition);
+        if builder.nodes.is_empty() {
+            builder.nodes = known.nodes.clone();
+        }
+    }
+    let statement = statement(&loaded, &builder, &name, &tro_sha256);
+    fs::write(path, serde_json::to_vec_pretty(&statement)?)
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))
+}
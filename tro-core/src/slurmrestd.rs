@@ -0,0 +1,121 @@
+use eyre::{eyre, Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::command;
+use crate::settings::Settings;
+
+// slurmrestd is asked while the job is being torn down, like scontrol
+const SLURMRESTD_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How to reach slurmrestd, with curl, as a user it accepts JWTs for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlurmrestdSettings {
+    // e.g. http://slurmrestd.example.org:6820, none to use scontrol only
+    pub url: Option<String>,
+    pub api_version: String,
+    pub user: String,
+    // a file holding the JWT, `scontrol token` makes a short-lived one
+    // without it
+    pub token_file: Option<PathBuf>,
+    // fields of the job record embedded in the declaration
+    pub fields: Vec<String>,
+    pub curl: PathBuf,
+}
+
+impl Default for SlurmrestdSettings {
+    fn default() -> Self {
+        SlurmrestdSettings {
+            url: None,
+            api_version: "v0.0.40".to_string(),
+            user: "slurm".to_string(),
+            token_file: None,
+            fields: [
+                "account",
+                "cluster",
+                "partition",
+                "qos",
+                "nodes",
+                "tres_alloc_str",
+                "time_limit",
+                "name",
+            ]
+            .map(str::to_string)
+            .to_vec(),
+            curl: PathBuf::from("curl"),
+        }
+    }
+}
+
+// The configured fields of slurmrestd's record of a job, None without
+// slurmrestd_url
+pub fn job_fields(settings: &Settings, jobid: u32) -> Result<Option<Value>, Report> {
+    let slurmrestd = &settings.slurmrestd;
+    let Some(url) = &slurmrestd.url else {
+        return Ok(None);
+    };
+    let token = token(settings)?;
+    let url = format!(
+        "{}/slurm/{}/job/{jobid}",
+        url.trim_end_matches('/'),
+        slurmrestd.api_version
+    );
+    // the token goes through stdin rather than the command line
+    let headers = format!(
+        "X-SLURM-USER-NAME: {}\nX-SLURM-USER-TOKEN: {token}\n",
+        slurmrestd.user
+    );
+    let output = command::run_with_input(
+        Command::new(&slurmrestd.curl).args([
+            "--silent",
+            "--show-error",
+            "--fail",
+            "-H",
+            "@-",
+            &url,
+        ]),
+        Some(headers.as_bytes()),
+        Some(SLURMRESTD_TIMEOUT),
+    )
+    .wrap_err_with(|| format!("Failed to get job {jobid} from slurmrestd"))?;
+    let response: Value =
+        serde_json::from_slice(&output.stdout).wrap_err("slurmrestd did not answer with JSON")?;
+    let record = response["jobs"]
+        .as_array()
+        .and_then(|jobs| jobs.first())
+        .ok_or_else(|| eyre!("slurmrestd does not know job {jobid}"))?;
+    let fields: Map<String, Value> = slurmrestd
+        .fields
+        .iter()
+        .filter_map(|field| Some((field.clone(), record.get(field)?.clone())))
+        .collect();
+    Ok(Some(Value::Object(fields)))
+}
+
+fn token(settings: &Settings) -> Result<String, Report> {
+    let slurmrestd = &settings.slurmrestd;
+    if let Some(token_file) = &slurmrestd.token_file {
+        let token = fs::read_to_string(token_file)
+            .wrap_err_with(|| format!("Failed to read {}", token_file.display()))?;
+        return Ok(token.trim().to_string());
+    }
+    let output = command::run(
+        Command::new(&settings.scontrol).args([
+            "token",
+            &format!("username={}", slurmrestd.user),
+            "lifespan=60",
+        ]),
+        Some(SLURMRESTD_TIMEOUT),
+    )
+    .wrap_err("Failed to get a token from scontrol")?;
+    // SLURM_JWT=<token>
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .strip_prefix("SLURM_JWT=")
+        .map(str::to_string)
+        .ok_or_else(|| eyre!("scontrol token did not print SLURM_JWT="))
+}
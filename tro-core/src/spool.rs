@@ -0,0 +1,251 @@
+use eyre::{eyre, Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, DirBuilder, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{error, info, warn};
+
+use crate::config::ConfigFile;
+use crate::finalize::finalize;
+use crate::job::Job;
+use crate::journal::{journal_path, Journal};
+use crate::privilege;
+use crate::settings::Settings;
+use crate::timestamp;
+
+// Layout of the spool directory:
+//   <key>.json          queued finalization spec
+//   <key>.json.running  spec claimed by a finalizer
+//   <key>.json.failed   spec whose finalization failed
+//   <key>.journal       how far its finalization got, see journal
+//   status/<key>.json   last known state of the job's finalization
+// where <key> is the jobid, or <jobid>.<stepid> with a TRO per step
+const STATUS_DIR: &str = "status";
+
+// Everything needed to finalize a job's TRO after the job is gone. Specs carry
+// the signing passphrase, so the spool is only accessible to root.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FinalizationSpec {
+    pub settings: Settings,
+    pub job: Job,
+}
+
+impl FinalizationSpec {
+    // Queue the spec as <spool_dir>/<key>.json
+    pub fn write(&self, spool_dir: &Path) -> Result<PathBuf, Report> {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(spool_dir)
+            .wrap_err_with(|| format!("Failed to create spool {}", spool_dir.display()))?;
+        let path = spec_path(spool_dir, &self.job.key());
+        let tmp_path = spool_dir.join(format!(".{}.json.tmp", self.job.key()));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .mode(0o600)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(&tmp_path)
+            .wrap_err_with(|| format!("Failed to create {}", tmp_path.display()))?;
+        file.write_all(&serde_json::to_vec_pretty(self)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        write_status(spool_dir, &self.job, State::Queued, "")?;
+        Ok(path)
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Report> {
+        let content =
+            fs::read(path).wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&content)
+            .wrap_err_with(|| format!("Invalid spec {}", path.display()))
+    }
+}
+
+// Where the spec of a queued job lives
+pub fn spec_path(spool_dir: &Path, key: &str) -> PathBuf {
+    spool_dir.join(format!("{key}.json"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Queued,
+    Running,
+    Finalized,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    pub jobid: u32,
+    pub step: Option<u32>,
+    pub state: State,
+    pub message: String,
+    pub updated: String,
+}
+
+pub fn write_status(spool_dir: &Path, job: &Job, state: State, message: &str) -> io::Result<()> {
+    let status_dir = spool_dir.join(STATUS_DIR);
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&status_dir)?;
+    let status = Status {
+        jobid: job.jobid,
+        step: job.step,
+        state,
+        message: message.to_string(),
+        updated: timestamp::now(),
+    };
+    let key = job.key();
+    let tmp_path = status_dir.join(format!(".{key}.json.tmp"));
+    privilege::create_nofollow(&tmp_path)?.write_all(&serde_json::to_vec_pretty(&status)?)?;
+    fs::rename(&tmp_path, status_dir.join(format!("{key}.json")))
+}
+
+// Queued specs, oldest first
+pub fn queued(spool_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut specs = Vec::new();
+    for entry in fs::read_dir(spool_dir)? {
+        let path = entry?.path();
+        let is_spec = path.extension().is_some_and(|ext| ext == "json")
+            && !path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if is_spec && path.is_file() {
+            let modified = path
+                .metadata()?
+                .modified()
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            specs.push((modified, path));
+        }
+    }
+    specs.sort();
+    Ok(specs.into_iter().map(|(_, path)| path).collect())
+}
+
+// Queued specs of a job, including those of its steps
+pub fn queued_for(spool_dir: &Path, jobid: u32) -> io::Result<Vec<PathBuf>> {
+    let job_spec = format!("{jobid}.json");
+    let step_prefix = format!("{jobid}.");
+    let mut specs = queued(spool_dir)?;
+    specs.retain(|path| {
+        path.file_name()
+            .map(|name| name.to_string_lossy())
+            .is_some_and(|name| name == job_spec || name.starts_with(&step_prefix))
+    });
+    Ok(specs)
+}
+
+// Put a spec whose finalization failed, or whose finalizer died, back in the
+// queue
+pub fn requeue(path: &Path) -> Result<PathBuf, Report> {
+    let name = path.to_string_lossy();
+    let queued = match (name.strip_suffix(".failed"), name.strip_suffix(".running")) {
+        (Some(queued), _) => PathBuf::from(queued),
+        (_, Some(queued)) => {
+            let _claim =
+                claim(path)?.ok_or_else(|| eyre!("{} is being finalized", path.display()))?;
+            PathBuf::from(queued)
+        }
+        _ => return Ok(path.to_path_buf()),
+    };
+    fs::rename(path, &queued).wrap_err_with(|| format!("Failed to requeue {}", path.display()))?;
+    Ok(queued)
+}
+
+// Specs claimed by finalizers that are gone, e.g. with their node, put back
+// in the queue to be resumed from their journal
+pub fn requeue_abandoned(spool_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut requeued = Vec::new();
+    for entry in fs::read_dir(spool_dir)? {
+        let path = entry?.path();
+        if !path.to_string_lossy().ends_with(".json.running") {
+            continue;
+        }
+        match claim(&path) {
+            Ok(Some(_claim)) => {
+                let queued = path.with_extension("");
+                fs::rename(&path, &queued)?;
+                requeued.push(queued);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("{}: {:#}", path.display(), e),
+        }
+    }
+    Ok(requeued)
+}
+
+// A finalizer holds a lock on the spec it works on until it is done, None if
+// another one does
+fn claim(path: &Path) -> Result<Option<File>, Report> {
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+        .wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(file)),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => {
+            Err(e).wrap_err_with(|| format!("Failed to lock {}", path.display()))
+        }
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// Finalize a queued spec. The spec is claimed by renaming it first, so that
+// concurrent finalizers never process the same job twice. Settings from
+// `config` override those the spec was queued with, e.g. a rotated key.
+pub fn process(
+    path: &Path,
+    config: Option<&ConfigFile>,
+    notify: &dyn Fn(&str),
+) -> Result<(), Report> {
+    let spool_dir = path
+        .parent()
+        .ok_or_else(|| eyre!("{} is not in a spool directory", path.display()))?;
+    let running = with_suffix(path, ".running");
+    // the lock follows the spec, and goes with the finalizer
+    let _claim =
+        claim(path)?.ok_or_else(|| eyre!("{} is claimed by another finalizer", path.display()))?;
+    fs::rename(path, &running).wrap_err_with(|| format!("Failed to claim {}", path.display()))?;
+    let mut spec = FinalizationSpec::read(&running)?;
+    if let Some(config) = config {
+        spec.settings.apply(config)?;
+    }
+    let job = &spec.job;
+    write_status(spool_dir, job, State::Running, "")?;
+
+    let mut journal = Journal::open(&journal_path(spool_dir, &job.key()))?;
+    match finalize(&spec.settings, &spec.job, &mut journal, notify) {
+        Ok(()) => {
+            journal.remove()?;
+            fs::remove_file(&running)?;
+            write_status(spool_dir, job, State::Finalized, "")?;
+            info!("Finalized TRO of job {}{}", job.jobid, job.label());
+            Ok(())
+        }
+        Err(e) => {
+            error!(
+                "Failed to finalize TRO of job {}{}: {:#}",
+                job.jobid,
+                job.label(),
+                e
+            );
+            // keep the spec around for inspection or a later retry
+            fs::rename(&running, with_suffix(path, ".failed"))?;
+            write_status(spool_dir, job, State::Failed, &format!("{e:#}"))?;
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use std::fmt;
+
+use crate::scontrol::JobInfo;
+
+// Why Slurm ended a job before it completed on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    Timeout,
+    Preempted,
+    Cancelled,
+    NodeFail,
+    OutOfMemory,
+    Deadline,
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            Termination::Timeout => "TIMEOUT",
+            Termination::Preempted => "PREEMPTED",
+            Termination::Cancelled => "CANCELLED",
+            Termination::NodeFail => "NODE_FAIL",
+            Termination::OutOfMemory => "OUT_OF_MEMORY",
+            Termination::Deadline => "DEADLINE",
+        };
+        write!(f, "{}", state)
+    }
+}
+
+impl Termination {
+    // Whether slurmctld reports the job as terminated
+    pub fn of(job: &JobInfo) -> Option<Self> {
+        match job.get("JobState")? {
+            "TIMEOUT" => Some(Termination::Timeout),
+            "PREEMPTED" => Some(Termination::Preempted),
+            "CANCELLED" => Some(Termination::Cancelled),
+            "NODE_FAIL" => Some(Termination::NodeFail),
+            "OUT_OF_MEMORY" => Some(Termination::OutOfMemory),
+            "DEADLINE" => Some(Termination::Deadline),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use chrono::{Local, NaiveDateTime, TimeZone};
+use eyre::{eyre, Report, WrapErr};
+use serde_json::{json, Value};
+use std::process::Command;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::command;
+use crate::declaration::term;
+use crate::job::Job;
+use crate::scontrol::JobInfo;
+use crate::settings::Settings;
+use crate::timestamp;
+
+// sacct is asked while the job is being torn down, like scontrol
+const SACCT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// When the job went through the scheduler, as unix times: queued from
+// `submitted` (or `eligible`, once its dependencies and begin time allowed)
+// until `started`, running until `ended`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timeline {
+    pub submitted: Option<i64>,
+    pub eligible: Option<i64>,
+    pub started: Option<i64>,
+    pub ended: Option<i64>,
+}
+
+impl Timeline {
+    // From slurmctld's view of the job, completed with sacct if it already
+    // forgot it. The end is when the plugin saw the tasks end: slurmctld
+    // still has the job running then.
+    pub fn of(settings: &Settings, job: &Job, info: &JobInfo) -> Self {
+        let time = |key: &str| info.get(key).and_then(parse_slurm_time);
+        let mut timeline = Timeline {
+            submitted: time("SubmitTime"),
+            eligible: time("EligibleTime"),
+            started: time("StartTime").or(job.scheduled_start),
+            ended: job.end_time,
+        };
+        if timeline.submitted.is_none() {
+            match sacct(settings, job.jobid) {
+                Ok(accounted) => {
+                    timeline.submitted = accounted.submitted;
+                    timeline.eligible = accounted.eligible;
+                    timeline.started = timeline.started.or(accounted.started);
+                }
+                Err(e) => debug!("{:#}", e),
+            }
+        }
+        timeline
+    }
+
+    // Seconds spent waiting for resources, once eligible
+    pub fn queued(&self) -> Option<i64> {
+        Some(self.started? - self.eligible.or(self.submitted)?)
+    }
+
+    pub fn describe(&self) -> Value {
+        let date = |time: Option<i64>| time.and_then(|time| timestamp::from_unix(time).ok());
+        json!({
+            term("submitted"): date(self.submitted),
+            term("eligible"): date(self.eligible),
+            term("started"): date(self.started),
+            term("ended"): date(self.ended),
+            term("queuedSeconds"): self.queued(),
+            term("runSeconds"): self.started.zip(self.ended).map(|(start, end)| end - start),
+        })
+    }
+}
+
+fn sacct(settings: &Settings, jobid: u32) -> Result<Timeline, Report> {
+    let output = command::run(
+        Command::new(&settings.sacct)
+            .args(["-X", "-n", "-P", "-o", "Submit,Eligible,Start", "-j"])
+            .arg(jobid.to_string()),
+        Some(SACCT_TIMEOUT),
+    )
+    .wrap_err_with(|| format!("Failed to get job {jobid} from sacct"))?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let line = output
+        .lines()
+        .next()
+        .ok_or_else(|| eyre!("sacct does not know job {jobid}"))?;
+    let mut fields = line.split('|').map(parse_slurm_time);
+    Ok(Timeline {
+        submitted: fields.next().flatten(),
+        eligible: fields.next().flatten(),
+        started: fields.next().flatten(),
+        ended: None,
+    })
+}
+
+// Slurm prints times in the local time zone, e.g. 2024-05-01T12:00:00, and
+// Unknown or None for those that did not happen yet
+pub fn parse_slurm_time(value: &str) -> Option<i64> {
+    let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S").ok()?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.timestamp())
+}
@@ -0,0 +1,39 @@
+// Timestamps as spank-tro records them: RFC 3339 in UTC with a Z, e.g.
+// 2024-05-01T12:00:00Z, so that a signed record never depends on the time
+// zone of the node that wrote it
+use chrono::{DateTime, SecondsFormat, Utc};
+use eyre::{eyre, Report};
+
+pub fn format(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+pub fn now() -> String {
+    format(&Utc::now())
+}
+
+// Seconds since the epoch, e.g. from a trace or sacct
+pub fn from_unix(seconds: i64) -> Result<String, Report> {
+    DateTime::<Utc>::from_timestamp(seconds, 0)
+        .map(|time| format(&time))
+        .ok_or_else(|| eyre!("{seconds} is out of range for a timestamp"))
+}
+
+// Fractional seconds since the epoch, e.g. from XALT, kept to the
+// microsecond and only printed when there are any
+pub fn from_unix_f64(seconds: f64) -> Result<String, Report> {
+    let micros = (seconds * 1e6).round();
+    // casting would saturate
+    let in_range = micros.is_finite() && micros.abs() < i64::MAX as f64;
+    in_range
+        .then(|| DateTime::<Utc>::from_timestamp_micros(micros as i64))
+        .flatten()
+        .map(|time| time.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+        .ok_or_else(|| eyre!("{seconds} is out of range for a timestamp"))
+}
+
+pub fn parse(value: &str) -> Result<DateTime<Utc>, Report> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| eyre!("{value} is not an RFC 3339 timestamp: {e}"))
+}
@@ -0,0 +1,117 @@
+// Where on a node the job ran, as part of its evidence with capture_topology:
+// the CPUs and memory nodes of the job's cpuset, the NUMA nodes they make up
+// and whether SMT was on, with hwloc's view of the node when it is installed
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tracing::debug;
+
+use crate::command;
+use crate::declaration::term;
+use crate::job::Job;
+use crate::nodes;
+use crate::settings::Settings;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Topology {
+    // cpu lists as the kernel writes them, e.g. 0-15,32-47
+    pub cpus: Option<String>,
+    pub mems: Option<String>,
+    // the NUMA nodes holding the job's CPUs
+    pub numa_nodes: Vec<u32>,
+    // on, off, forceoff or notsupported
+    pub smt: Option<String>,
+    pub smt_active: Option<bool>,
+    // hwloc-ls --no-io
+    pub hwloc: Option<String>,
+}
+
+impl Topology {
+    pub fn collect(settings: &Settings, job: &Job) -> Self {
+        let cgroup = nodes::job_cgroup(job).map(|(_, dir)| dir);
+        Topology {
+            hwloc: hwloc(settings),
+            ..Topology::from_sysfs(Path::new("/sys"), cgroup.as_deref())
+        }
+    }
+
+    // From the `cgroup` of the job below `sys`, and the node's own
+    // description there
+    pub fn from_sysfs(sys: &Path, cgroup: Option<&Path>) -> Self {
+        let read = |path: &Path| {
+            fs::read_to_string(path)
+                .ok()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        let cpus = cgroup.and_then(|dir| read(&dir.join("cpuset.cpus.effective")));
+        let mems = cgroup.and_then(|dir| read(&dir.join("cpuset.mems.effective")));
+        let job_cpus = cpus.as_deref().map(parse_cpulist).unwrap_or_default();
+        let mut numa_nodes: Vec<u32> = fs::read_dir(sys.join("devices/system/node"))
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let node = entry
+                    .file_name()
+                    .to_string_lossy()
+                    .strip_prefix("node")?
+                    .parse()
+                    .ok()?;
+                let node_cpus = parse_cpulist(&read(&entry.path().join("cpulist"))?);
+                node_cpus
+                    .iter()
+                    .any(|cpu| job_cpus.contains(cpu))
+                    .then_some(node)
+            })
+            .collect();
+        numa_nodes.sort();
+        let smt = sys.join("devices/system/cpu/smt");
+        Topology {
+            cpus,
+            mems,
+            numa_nodes,
+            smt: read(&smt.join("control")),
+            smt_active: read(&smt.join("active")).map(|active| active == "1"),
+            hwloc: None,
+        }
+    }
+
+    pub fn describe(&self) -> Value {
+        json!({
+            term("cpus"): self.cpus,
+            term("mems"): self.mems,
+            term("numaNodes"): self.numa_nodes,
+            term("smt"): self.smt,
+            term("smtActive"): self.smt_active,
+            term("hwloc"): self.hwloc,
+        })
+    }
+}
+
+// The CPUs of a list like 0-3,8,10-11
+pub fn parse_cpulist(list: &str) -> Vec<u32> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        if let (Ok(first), Ok(last)) = (first.parse::<u32>(), last.parse::<u32>()) {
+            cpus.extend(first..=last);
+        }
+    }
+    cpus
+}
+
+fn hwloc(settings: &Settings) -> Option<String> {
+    match command::run(
+        Command::new(&settings.hwloc_ls).args(["--no-io", "--of", "console"]),
+        settings.command_timeout,
+    ) {
+        Ok(output) => Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+        Err(e) => {
+            debug!("{:#}", e);
+            None
+        }
+    }
+}
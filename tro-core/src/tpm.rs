@@ -0,0 +1,97 @@
+// TPM quotes of the PCRs measuring how a node booted, taken with tpm2_quote
+// and the site's attestation key, so that a verifier holding the key's
+// certificate and the expected PCR values can tell the node that produced
+// the evidence of a TRO was in a trusted state. The quote's qualifying data
+// ties it to that evidence.
+use eyre::{eyre, Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::Path;
+use std::process::Command;
+
+use crate::attachment::base64;
+use crate::command;
+use crate::declaration::term;
+use crate::settings::Settings;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Quote {
+    // PCR selection, e.g. sha256:0,1,2,3,4,5,6,7
+    pub pcrs: String,
+    // qualifying data, hex
+    pub nonce: String,
+    // TPMS_ATTEST, its signature and the PCR values, base64
+    pub message: String,
+    pub signature: String,
+    pub pcr_values: String,
+}
+
+impl Quote {
+    pub fn describe(&self) -> Value {
+        json!({
+            term("pcrSelection"): self.pcrs,
+            term("nonce"): self.nonce,
+            term("message"): self.message,
+            term("signature"): self.signature,
+            term("pcrValues"): self.pcr_values,
+        })
+    }
+}
+
+// A quote of the site's PCR selection with the attestation key `ak`
+pub fn quote(settings: &Settings, ak: &str, nonce: &str) -> Result<Quote, Report> {
+    // tpm2_quote writes there as root: a new directory of its own, not one
+    // someone else made first
+    let dir = std::env::temp_dir().join(format!("spank-tro-quote-{}", random_suffix()?));
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+    let result = quote_in(settings, ak, nonce, &dir);
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+fn random_suffix() -> Result<String, Report> {
+    let mut bytes = [0u8; 8];
+    let read = unsafe { libc::getrandom(bytes.as_mut_ptr() as *mut libc::c_void, bytes.len(), 0) };
+    if read != bytes.len() as isize {
+        return Err(std::io::Error::last_os_error()).wrap_err("getrandom failed");
+    }
+    Ok(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+fn quote_in(settings: &Settings, ak: &str, nonce: &str, dir: &Path) -> Result<Quote, Report> {
+    let (message, signature, pcr_values) = (
+        dir.join("quote.msg"),
+        dir.join("quote.sig"),
+        dir.join("quote.pcrs"),
+    );
+    command::run(
+        Command::new(&settings.tpm2_quote)
+            .args(["--key-context", ak, "--pcr-list", &settings.tpm_pcrs])
+            .args(["--qualification", nonce])
+            .arg("--message")
+            .arg(&message)
+            .arg("--signature")
+            .arg(&signature)
+            .arg("--pcr")
+            .arg(&pcr_values),
+        settings.command_timeout,
+    )
+    .wrap_err("Failed to get a TPM quote")?;
+    let read = |path: &Path| {
+        fs::read(path)
+            .map(|content| base64(&content))
+            .map_err(|e| eyre!("tpm2_quote did not write {}: {e}", path.display()))
+    };
+    Ok(Quote {
+        pcrs: settings.tpm_pcrs.clone(),
+        nonce: nonce.to_string(),
+        message: read(&message)?,
+        signature: read(&signature)?,
+        pcr_values: read(&pcr_values)?,
+    })
+}
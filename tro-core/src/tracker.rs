@@ -0,0 +1,441 @@
+use eyre::{eyre, Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::thread;
+use std::time::Instant;
+use tracing::{debug, info};
+
+use crate::command;
+use crate::job::{now, Job};
+use crate::privilege;
+use crate::settings::Settings;
+use crate::xalt;
+
+// Variable telling spank-tro-wrap where to record the commands of the job
+pub const TRACE_ENV: &str = "SPANK_TRO_TRACE";
+
+// How the programs run by a job are recorded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tracker {
+    // XALT preloaded in the job's tasks
+    #[default]
+    Xalt,
+    // only the times at which the plugin saw the job start and end
+    None,
+    // the tasks are launched through spank-tro-wrap
+    Wrapper,
+    // bpftrace follows the exec and open calls of the job's processes
+    Ebpf,
+    // the tasks are launched through strace, for nodes without eBPF
+    Strace,
+}
+
+impl FromStr for Tracker {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "xalt" => Ok(Tracker::Xalt),
+            "none" => Ok(Tracker::None),
+            "wrapper" => Ok(Tracker::Wrapper),
+            "ebpf" => Ok(Tracker::Ebpf),
+            "strace" => Ok(Tracker::Strace),
+            _ => Err(eyre!("{value} is not one of xalt/none/wrapper/ebpf/strace")),
+        }
+    }
+}
+
+// What the tracker saw of the job's programs, times are unix times
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trace {
+    pub start_time: f64,
+    pub end_time: f64,
+    pub commands: Vec<Vec<String>>,
+    // files opened by the job's programs, when the tracker sees them
+    pub files: Vec<PathBuf>,
+    // programs executed, when the tracker sees them
+    pub executables: Vec<Executable>,
+    // shared libraries the programs loaded, when the tracker sees them
+    pub libraries: Vec<Library>,
+    // seconds on a monotonic clock, when the tracker measured them
+    pub elapsed: Option<f64>,
+}
+
+impl Trace {
+    // Seconds the programs ran, None when the end comes before the start,
+    // e.g. after the node's clock was stepped back
+    pub fn duration(&self) -> Option<f64> {
+        let duration = self.end_time - self.start_time;
+        self.elapsed.or((duration >= 0.0).then_some(duration))
+    }
+
+    pub fn clock_anomaly(&self) -> bool {
+        self.end_time < self.start_time
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Executable {
+    pub path: PathBuf,
+    // the hash XALT computed when the program ran
+    pub xalt_hash: Option<String>,
+}
+
+impl Executable {
+    fn new(path: &str) -> Self {
+        Executable {
+            path: PathBuf::from(path),
+            xalt_hash: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Library {
+    pub path: PathBuf,
+    pub xalt_hash: Option<String>,
+}
+
+// A command run by spank-tro-wrap, one JSON line of its trace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandRecord {
+    pub argv: Vec<String>,
+    pub start_time: f64,
+    pub end_time: f64,
+    pub exit_code: Option<i32>,
+    // seconds on a monotonic clock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elapsed: Option<f64>,
+}
+
+impl CommandRecord {
+    pub fn append(&self, path: &Path) -> Result<(), Report> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+        // a single write keeps the lines of concurrent tasks apart
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .wrap_err_with(|| format!("Failed to record the command in {}", path.display()))
+    }
+}
+
+// Where spank-tro-wrap or bpftrace record the programs of the job
+pub fn trace_path(job: &Job) -> PathBuf {
+    job.workdir
+        .join(".tro")
+        .join(format!("{}.trace", job.key()))
+}
+
+// Start the tracers that run alongside the job rather than in its tasks,
+// returns the pid to hand to stop()
+pub fn start(settings: &Settings, job: &Job) -> Result<Option<u32>, Report> {
+    if settings.tracker != Tracker::Ebpf {
+        return Ok(None);
+    }
+    // bpftrace runs as root, it gets the trace opened as the user rather
+    // than a path in their workdir
+    let path = trace_path(job);
+    let trace = {
+        let _user = privilege::as_user(job);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        privilege::create_nofollow(&path)
+            .wrap_err_with(|| format!("Failed to create {}", path.display()))?
+    };
+    let pid = command::spawn_detached_to(
+        Command::new(&settings.bpftrace)
+            .arg("-e")
+            .arg(bpftrace_program(job.uid)),
+        trace,
+    )
+    .wrap_err_with(|| format!("Failed to start {}", settings.bpftrace.display()))?;
+    info!(
+        "Tracing the programs of job {} with bpftrace ({})",
+        job.jobid, pid
+    );
+    Ok(Some(pid))
+}
+
+// strace in front of a task's command, following its processes and recording
+// their execs in <trace>.<pid>
+pub fn strace_argv(settings: &Settings, job: &Job) -> Result<Vec<String>, Report> {
+    let path = trace_path(job);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let argv = [
+        &settings.strace.to_string_lossy(),
+        // follow forks, one file per process, unix timestamps
+        "-f",
+        "-ff",
+        "-qq",
+        "-ttt",
+        "-e",
+        "trace=execve",
+        "-e",
+        "signal=none",
+        "-o",
+        &path.to_string_lossy(),
+    ];
+    Ok(argv.iter().map(|arg| arg.to_string()).collect())
+}
+
+// Exec and open calls of the user's processes, with their cgroup to tell the
+// job's apart from the user's other jobs on the node
+fn bpftrace_program(uid: u32) -> String {
+    format!(
+        r#"
+tracepoint:syscalls:sys_enter_execve /uid == {uid}/ {{
+    printf("exec\t%s\t%s\t%s\n", strftime("%s", nsecs), cgroup_path(cgroup), str(args->filename));
+}}
+tracepoint:syscalls:sys_enter_openat /uid == {uid}/ {{
+    printf("open\t%s\t%s\t%s\n", strftime("%s", nsecs), cgroup_path(cgroup), str(args->filename));
+}}
+"#
+    )
+}
+
+// The trace of the job's programs, or with `started_after` of those started
+// after that unix time
+pub fn find_trace(
+    tracker: Tracker,
+    job: &Job,
+    started_after: Option<i64>,
+) -> Result<Trace, Report> {
+    match tracker {
+        Tracker::Xalt => read_xalt_trace(job, started_after),
+        Tracker::None => Ok(Trace {
+            start_time: started_after.unwrap_or(job.start_time) as f64,
+            end_time: job.end_time.unwrap_or_else(now) as f64,
+            ..Trace::default()
+        }),
+        Tracker::Wrapper => read_wrapper_trace(&trace_path(job), started_after),
+        Tracker::Ebpf => read_ebpf_trace(&trace_path(job), job, started_after),
+        Tracker::Strace => read_strace_trace(&trace_path(job), job, started_after),
+    }
+}
+
+// The programs of all the XALT records of the job, each MPI rank leaving its
+// own
+fn read_xalt_trace(job: &Job, started_after: Option<i64>) -> Result<Trace, Report> {
+    let mut trace: Option<Trace> = None;
+    for record in xalt::find_records(job, started_after)? {
+        let time = |key: &str| {
+            record["userDT"][key]
+                .as_f64()
+                .ok_or_else(|| eyre!("XALT record of job {} has no {key}", job.jobid))
+        };
+        let (start_time, end_time) = (time("start_time")?, time("end_time")?);
+        let trace = trace.get_or_insert_with(|| Trace {
+            start_time,
+            end_time,
+            ..Trace::default()
+        });
+        trace.start_time = trace.start_time.min(start_time);
+        trace.end_time = trace.end_time.max(end_time);
+        let command: Vec<String> = record["cmdlineA"]
+            .as_array()
+            .map(|argv| {
+                argv.iter()
+                    .filter_map(|arg| arg.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default();
+        if !trace.commands.contains(&command) {
+            trace.commands.push(command);
+        }
+        if let Some(path) = record["userT"]["exec_path"].as_str() {
+            if !trace
+                .executables
+                .iter()
+                .any(|known| known.path == Path::new(path))
+            {
+                trace.executables.push(Executable {
+                    path: PathBuf::from(path),
+                    xalt_hash: record["hash_id"].as_str().map(str::to_string),
+                });
+            }
+        }
+        // [path, hash] pairs
+        for library in record["libA"].as_array().into_iter().flatten() {
+            let Some(path) = library[0].as_str() else {
+                continue;
+            };
+            if !trace
+                .libraries
+                .iter()
+                .any(|known| known.path == Path::new(path))
+            {
+                trace.libraries.push(Library {
+                    path: PathBuf::from(path),
+                    xalt_hash: library[1].as_str().map(str::to_string),
+                });
+            }
+        }
+    }
+    trace.ok_or_else(|| eyre!("No XALT record found for job {}", job.jobid))
+}
+
+// find_trace(), polling for up to trace_timeout as XALT writes its records
+// while the job's processes exit, i.e. often after the job is over
+pub fn wait_for_trace(
+    settings: &Settings,
+    job: &Job,
+    started_after: Option<i64>,
+) -> Result<Trace, Report> {
+    let deadline = Instant::now() + settings.trace_timeout;
+    loop {
+        match find_trace(settings.tracker, job, started_after) {
+            Err(e) if Instant::now() < deadline => {
+                debug!("Waiting for the trace of job {}: {:#}", job.jobid, e);
+                thread::sleep(settings.trace_poll_interval);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn read_wrapper_trace(path: &Path, started_after: Option<i64>) -> Result<Trace, Report> {
+    let file = fs::File::open(path)
+        .wrap_err_with(|| format!("No command recorded in {}", path.display()))?;
+    let mut trace: Option<Trace> = None;
+    for line in BufReader::new(file).lines() {
+        let record: CommandRecord = serde_json::from_str(&line?)
+            .wrap_err_with(|| format!("Invalid record in {}", path.display()))?;
+        // the start time of the step is only known to the second
+        if started_after.is_some_and(|after| record.start_time < (after - 1) as f64) {
+            continue;
+        }
+        let trace = trace.get_or_insert_with(|| Trace {
+            start_time: record.start_time,
+            end_time: record.end_time,
+            elapsed: record.elapsed,
+            ..Trace::default()
+        });
+        // the elapsed times of several commands do not add up to the trace's
+        if !trace.commands.is_empty() {
+            trace.elapsed = None;
+        }
+        trace.start_time = trace.start_time.min(record.start_time);
+        trace.end_time = trace.end_time.max(record.end_time);
+        trace.commands.push(record.argv);
+    }
+    trace.ok_or_else(|| eyre!("No command recorded in {}", path.display()))
+}
+
+// Lines of the bpftrace program: <exec|open> <unix time> <cgroup> <path>
+fn read_ebpf_trace(path: &Path, job: &Job, started_after: Option<i64>) -> Result<Trace, Report> {
+    let file = fs::File::open(path)
+        .wrap_err_with(|| format!("No program traced in {}", path.display()))?;
+    let cgroup = format!("/job_{}/", job.jobid);
+    let mut trace: Option<Trace> = None;
+    let mut files = BTreeSet::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let mut fields = line.splitn(4, '\t');
+        let (Some(event), Some(time), Some(event_cgroup), Some(target)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let Ok(time) = time.parse::<f64>() else {
+            continue;
+        };
+        if !event_cgroup.contains(&cgroup)
+            || started_after.is_some_and(|after| time < (after - 1) as f64)
+        {
+            continue;
+        }
+        let trace = trace.get_or_insert_with(|| Trace {
+            start_time: time,
+            end_time: time,
+            ..Trace::default()
+        });
+        trace.start_time = trace.start_time.min(time);
+        trace.end_time = trace.end_time.max(time);
+        match event {
+            "exec" => {
+                trace.commands.push(vec![target.to_string()]);
+                trace.executables.push(Executable::new(target));
+            }
+            _ => {
+                files.insert(PathBuf::from(target));
+            }
+        }
+    }
+    let mut trace = trace.ok_or_else(|| eyre!("No program of job {} traced", job.jobid))?;
+    // the programs are done when the job's tasks are
+    if let Some(end_time) = job.end_time {
+        trace.end_time = trace.end_time.max(end_time as f64);
+    }
+    trace.files = files.into_iter().collect();
+    Ok(trace)
+}
+
+// The files strace wrote for each process, e.g.
+// 1700000000.123456 execve("/usr/bin/python3", ["python3", "run.py"], 0x7ffd /* 42 vars */) = 0
+fn read_strace_trace(path: &Path, job: &Job, started_after: Option<i64>) -> Result<Trace, Report> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let prefix = format!(
+        "{}.",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let mut execs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_name().to_string_lossy().starts_with(&prefix) {
+            continue;
+        }
+        let file = fs::File::open(entry.path())?;
+        for line in BufReader::new(file).lines() {
+            if let Some(exec) = parse_strace_exec(&line?) {
+                execs.push(exec);
+            }
+        }
+    }
+    // the start time of the step is only known to the second
+    execs.retain(|(time, ..)| started_after.is_none_or(|after| *time >= (after - 1) as f64));
+    execs.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let (Some((start_time, ..)), Some((last_exec, ..))) = (execs.first(), execs.last()) else {
+        return Err(eyre!("No program of job {} traced", job.jobid));
+    };
+    let mut trace = Trace {
+        start_time: *start_time,
+        end_time: job.end_time.map_or(*last_exec, |end_time| end_time as f64),
+        ..Trace::default()
+    };
+    for (_, path, argv) in execs {
+        trace.executables.push(Executable::new(&path));
+        trace.commands.push(argv);
+    }
+    Ok(trace)
+}
+
+// Time, program and argv of a successful execve
+fn parse_strace_exec(line: &str) -> Option<(f64, String, Vec<String>)> {
+    let (time, call) = line.split_once(' ')?;
+    let call = call.strip_prefix("execve(")?;
+    if !call.trim_end().ends_with("= 0") {
+        return None;
+    }
+    let (path, _) = call.strip_prefix('"')?.split_once("\", [")?;
+    let argv = &call[call.find('[')? + 1..call.find("],")?];
+    let argv = argv
+        .split("\", \"")
+        .map(|arg| arg.trim_matches('"').replace("\\\"", "\""))
+        .collect();
+    Some((time.parse().ok()?, path.to_string(), argv))
+}
@@ -0,0 +1,394 @@
+use eyre::{eyre, Report, WrapErr};
+use std::path::Path;
+use std::process::Command;
+use std::time::Instant;
+use tracing::{debug, info};
+
+use crate::command;
+use crate::declaration::{self, Declaration};
+use crate::incremental;
+use crate::job::Job;
+use crate::lockfile::LockFile;
+use crate::merkle;
+use crate::metrics::Metrics;
+use crate::phase::Phase;
+use crate::roots;
+use crate::settings::Settings;
+use crate::signing::SigningKey;
+use crate::workdir::summarize_dir;
+
+// Oldest tro-utils whose command line matches the calls below
+pub const MIN_VERSION: (u32, u32, u32) = (0, 1, 0);
+// First tro-utils that is not known to: before 1.0 a minor release may
+// change the command line
+pub const MAX_VERSION: (u32, u32, u32) = (0, 2, 0);
+
+// The version reported by `tro-utils --version`
+pub fn version(settings: &Settings) -> Result<(u32, u32, u32), Report> {
+    let output = command::run(
+        Command::new(&settings.tro_utils).arg("--version"),
+        settings.command_timeout,
+    )?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    parse_version(&output).ok_or_else(|| eyre!("Unexpected tro-utils version: {}", output.trim()))
+}
+
+// The version of the installed tro-utils, refused outside of the versions
+// whose command line the calls below match rather than left to write empty
+// TROs
+pub fn negotiate(settings: &Settings) -> Result<(u32, u32, u32), Report> {
+    let version = version(settings).wrap_err_with(|| {
+        format!(
+            "Failed to get the version of tro-utils {}",
+            settings.tro_utils.display()
+        )
+    })?;
+    if !supported(version) {
+        return Err(eyre!(
+            "tro-utils {} is not supported, {} up to {} (excluded) is needed",
+            display_version(version),
+            display_version(MIN_VERSION),
+            display_version(MAX_VERSION)
+        ));
+    }
+    debug!("tro-utils {}", display_version(version));
+    Ok(version)
+}
+
+pub fn supported(version: (u32, u32, u32)) -> bool {
+    (MIN_VERSION..MAX_VERSION).contains(&version)
+}
+
+pub fn display_version((major, minor, patch): (u32, u32, u32)) -> String {
+    format!("{major}.{minor}.{patch}")
+}
+
+// e.g. "tro-utils, version 0.1.2"
+fn parse_version(output: &str) -> Option<(u32, u32, u32)> {
+    let version = output.split_whitespace().last()?;
+    let mut parts = version.split('.').map(|part| part.parse().ok());
+    Some((
+        parts.next()??,
+        parts.next()??,
+        parts.next().flatten().unwrap_or(0),
+    ))
+}
+
+// Invokes tro-utils on a job's declaration. In dry-run mode the invocations
+// are only described through `notify`.
+pub struct TroUtils<'a> {
+    settings: &'a Settings,
+    job: &'a Job,
+    metrics: Option<Metrics>,
+    notify: &'a dyn Fn(&str),
+}
+
+impl<'a> TroUtils<'a> {
+    pub fn new(settings: &'a Settings, job: &'a Job, notify: &'a dyn Fn(&str)) -> Self {
+        TroUtils {
+            settings,
+            job,
+            metrics: settings.metrics_dir.as_deref().map(Metrics::new),
+            notify,
+        }
+    }
+
+    pub fn metrics(&self) -> Option<&Metrics> {
+        self.metrics.as_ref().filter(|_| !self.settings.dry_run)
+    }
+
+    // Record the current content of the workdir as a new arrangement, returns
+    // its @id
+    pub fn add_arrangement(&self, comment: &str) -> Result<String, Report> {
+        let workdir = self.job.workdir.to_string_lossy();
+        let args = ["arrangement", "add", "-m", comment, "-i", ".git", &workdir];
+        if self.settings.dry_run {
+            self.report_workdir(comment);
+            self.run(Phase::Arrangement, &args)?;
+            // the id tro-utils would give it
+            let count = match self.job.declaration.exists() {
+                true => Declaration::of(self.job)?.arrangement_count(),
+                false => 0,
+            };
+            return Ok(format!("arrangement/{count}"));
+        }
+        // held until annotated, no other step or node appends in between
+        let lock = self.lock()?;
+        self.run_held(Phase::Arrangement, &args, Some(&lock))?;
+        self.record_hashed();
+        self.annotate_arrangement(Declaration::of_locked(self.job, lock)?)
+    }
+
+    // The final arrangement, incrementally from the initial one with
+    // incremental_arrangement=yes when the declaration allows it
+    pub fn add_final_arrangement(&self, comment: &str) -> Result<String, Report> {
+        if !self.settings.incremental_arrangement || self.settings.dry_run {
+            return self.add_arrangement(comment);
+        }
+        let started = Instant::now();
+        // kept locked until annotated
+        let added = Declaration::of(self.job).and_then(|mut declaration| {
+            let id = incremental::final_arrangement(&mut declaration, self.job, comment)?;
+            Ok(id.map(|_| declaration))
+        });
+        match added {
+            Ok(Some(declaration)) => {
+                self.finish_phase(Phase::Arrangement, started, true);
+                self.annotate_arrangement(declaration)
+            }
+            Ok(None) => self.add_arrangement(comment),
+            Err(e) => {
+                info!("No incremental final arrangement: {:#}", e);
+                self.add_arrangement(comment)
+            }
+        }
+    }
+
+    // What the plugin adds to the arrangement that was just recorded, still
+    // locked, returns its @id
+    fn annotate_arrangement(&self, mut declaration: Declaration) -> Result<String, Report> {
+        let id = declaration
+            .last_arrangement()
+            .ok_or_else(|| eyre!("{} has no arrangement", self.job.declaration.display()))?;
+        let merkle = merkle::describe(&declaration);
+        declaration.annotate_arrangement("merkle", merkle)?;
+        if !self.job.roots.is_empty() {
+            let described = roots::describe(self.settings, &self.job.workdir, &self.job.roots)?;
+            declaration.annotate_arrangement("roots", described)?;
+        }
+        declaration.save()?;
+        Ok(id)
+    }
+
+    pub fn add_performance(
+        &self,
+        comment: &str,
+        start: &str,
+        end: &str,
+        accessed: &str,
+        modified: &str,
+    ) -> Result<String, Report> {
+        let args = [
+            "performance",
+            "add",
+            "-m",
+            comment,
+            "-s",
+            start,
+            "-e",
+            end,
+            "-a",
+            accessed,
+            "-M",
+            modified,
+        ];
+        if self.settings.dry_run {
+            self.run(Phase::Performance, &args)?;
+            return Ok(String::new());
+        }
+        // the @id tro-utils gave it, before another step or node adds theirs
+        let lock = self.lock()?;
+        self.run_held(Phase::Performance, &args, Some(&lock))?;
+        let performances = Declaration::of_locked(self.job, lock)?.performances();
+        match performances.last() {
+            Some((id, _)) => Ok(id.clone()),
+            None => Err(eyre!(
+                "{} has no performance",
+                self.job.declaration.display()
+            )),
+        }
+    }
+
+    pub fn sign(&self, key: &SigningKey) -> Result<(), Report> {
+        if self.settings.dry_run {
+            (self.notify)(&format!(
+                "spank-tro (dry-run): would sign {} with {}",
+                self.job.declaration.display(),
+                key.describe()
+            ));
+        }
+        self.run_with(Phase::Sign, &["sign"], key, None)
+    }
+
+    // Check the signature of the declaration
+    pub fn verify(&self) -> Result<(), Report> {
+        self.run(Phase::Verify, &["verify"])
+    }
+
+    fn run(&self, phase: Phase, subcommand: &[&str]) -> Result<(), Report> {
+        self.run_held(phase, subcommand, None)
+    }
+
+    fn lock(&self) -> Result<LockFile, Report> {
+        declaration::lock(&self.job.declaration)
+            .wrap_err_with(|| format!("Failed to lock {}", self.job.declaration.display()))
+    }
+
+    // With the declaration's lock, `held` by the caller or taken for the call
+    fn run_held(
+        &self,
+        phase: Phase,
+        subcommand: &[&str],
+        held: Option<&LockFile>,
+    ) -> Result<(), Report> {
+        // recording hashes the job's files and writes its declaration, as the
+        // job's user, who gets nothing of the site key. Verifying needs the
+        // site keyring.
+        let key = match phase {
+            Phase::Arrangement | Phase::Performance => SigningKey {
+                owner: Some((self.job.uid, self.job.gid)),
+                ..SigningKey::default()
+            },
+            _ => SigningKey::site(self.settings).unwrap_or_default(),
+        };
+        self.run_with(phase, subcommand, &key, held)
+    }
+
+    // Run tro-utils with the given subcommand and key, as the key's owner if it
+    // belongs to a user, or only report the invocation in dry-run mode
+    fn run_with(
+        &self,
+        phase: Phase,
+        subcommand: &[&str],
+        key: &SigningKey,
+        held: Option<&LockFile>,
+    ) -> Result<(), Report> {
+        let declaration = self.job.declaration.to_string_lossy();
+        let trs_caps = self.settings.trs_caps.to_string_lossy();
+        let mut args = vec!["--declaration", &declaration];
+        // signing and verifying do not need the TRS profile
+        if !matches!(phase, Phase::Sign | Phase::Verify) {
+            args.extend(["--profile", &trs_caps]);
+        }
+        args.extend(["--gpg-fingerprint", &key.fingerprint]);
+        args.extend(subcommand);
+
+        let tro_utils = &self.settings.tro_utils;
+        if self.settings.dry_run {
+            (self.notify)(&format!(
+                "spank-tro (dry-run): would call {} {}",
+                tro_utils.display(),
+                args.join(" ")
+            ));
+            return Ok(());
+        }
+        debug!("Calling {} {}", tro_utils.display(), args.join(" "));
+        let _lock = match held {
+            Some(_) => None,
+            None => Some(self.lock()?),
+        };
+        let started = Instant::now();
+        let result = self.settings.retry.run(phase, || {
+            let mut command = Command::new(tro_utils);
+            // tro-utils' default for --gpg-passphrase, as only root may read
+            // the environment of its processes but anyone their command line
+            if !key.passphrase.is_empty() {
+                command.env("GPG_PASSPHRASE", &key.passphrase);
+            }
+            command::run(
+                key.apply(command.args(args.iter())),
+                self.settings.command_timeout,
+            )
+        });
+        self.finish_phase(phase, started, result.is_ok());
+        let output = result?;
+        debug!("Output: {}", String::from_utf8_lossy(&output.stdout));
+        debug!("Errors: {}", String::from_utf8_lossy(&output.stderr));
+        Ok(())
+    }
+
+    // Structured audit record and metrics of a finished phase
+    pub fn finish_phase(&self, phase: Phase, started: Instant, success: bool) {
+        let duration = started.elapsed();
+        info!(
+            jobid = self.job.jobid,
+            uid = self.job.uid,
+            phase = %phase,
+            duration_ms = duration.as_millis() as u64,
+            success,
+            "TRO {} phase finished",
+            phase
+        );
+        if let Some(metrics) = self.metrics() {
+            if let Err(e) = metrics.record_phase(phase, duration, success) {
+                info!("Failed to update metrics: {}", e);
+            }
+        }
+    }
+
+    // Tell the user how their TRO is coming along, unless they asked for
+    // --tro-quiet
+    pub fn progress(&self, msg: &str) {
+        if !self.job.quiet && !self.settings.dry_run {
+            (self.notify)(&format!("spank-tro: {msg}"));
+        }
+    }
+
+    // Before the final arrangement, which may take a while
+    pub fn report_finalizing(&self) {
+        if self.job.quiet || self.settings.dry_run {
+            return;
+        }
+        let roots = self.job.roots.iter().map(|root| root.path.as_path());
+        let files: u64 = std::iter::once(self.job.workdir.as_path())
+            .chain(roots)
+            .filter_map(|dir| summarize_dir(dir).ok())
+            .map(|(files, _)| files)
+            .sum();
+        self.progress(&format!(
+            "finalizing TRO (hashing {} files)",
+            thousands(files)
+        ));
+    }
+
+    // Account the size of an arrangement's content in the metrics
+    fn record_hashed(&self) {
+        let Some(metrics) = self.metrics() else {
+            return;
+        };
+        if let Err(e) =
+            summarize_dir(&self.job.workdir).and_then(|(_, bytes)| metrics.record_hashed(bytes))
+        {
+            info!("Failed to update metrics: {}", e);
+        }
+    }
+
+    // Print what an arrangement of the workdir and the other roots would
+    // cover
+    fn report_workdir(&self, comment: &str) {
+        let roots = self.job.roots.iter().map(|root| root.path.as_path());
+        for workdir in std::iter::once(self.job.workdir.as_path()).chain(roots) {
+            self.report_dir(comment, workdir);
+        }
+    }
+
+    fn report_dir(&self, comment: &str, workdir: &Path) {
+        match summarize_dir(workdir) {
+            Ok((files, bytes)) => (self.notify)(&format!(
+                "spank-tro (dry-run): {} of {} would cover {} files ({} bytes)",
+                comment,
+                workdir.display(),
+                files,
+                bytes
+            )),
+            Err(e) => (self.notify)(&format!(
+                "spank-tro (dry-run): cannot walk {}: {}",
+                workdir.display(),
+                e
+            )),
+        }
+    }
+}
+
+// 12431 as 12,431
+fn thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
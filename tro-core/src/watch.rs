@@ -0,0 +1,245 @@
+use eyre::{Report, WrapErr};
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, ErrorKind, Seek, SeekFrom};
+use std::os::unix::fs::fchown;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+use crate::changes::FileWatch;
+use crate::command;
+use crate::config::Config;
+use crate::job::Job;
+use crate::privilege;
+use crate::session::Session;
+use crate::settings::Settings;
+use crate::tro_utils::TroUtils;
+
+// Variable telling the job's tasks where to announce their checkpoints, one
+// line with a label per checkpoint
+pub const CHECKPOINT_ENV: &str = "SPANK_TRO_CHECKPOINT";
+
+// The file the job appends its checkpoint labels to
+pub fn checkpoint_path(job: &Job) -> PathBuf {
+    job.workdir
+        .join(".tro")
+        .join(format!("{}.checkpoints", job.key()))
+}
+
+// Whether the job needs spank-tro-watch while it runs
+pub fn needed(settings: &Settings) -> bool {
+    settings.checkpoints || settings.snapshot_interval.is_some() || settings.track_outputs
+}
+
+// Start spank-tro-watch for the job, returns its pid
+pub fn start(config: &Config, job: &Job) -> Result<u32, Report> {
+    let spec = spec_path(job);
+    {
+        // in the user's workdir, where root would follow their symbolic links
+        let _user = privilege::as_user(job);
+        if let Some(dir) = spec.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if config.settings.checkpoints {
+            // the job's tasks write it, the watcher only reads it
+            let checkpoints = checkpoint_path(job);
+            privilege::create_nofollow(&checkpoints)
+                .and_then(|file| fchown(&file, Some(job.uid), Some(job.gid)))
+                .wrap_err_with(|| format!("Failed to create {}", checkpoints.display()))?;
+        }
+        Session::new(&config.settings, job).write(&spec)?;
+    }
+    command::spawn_detached(Command::new(&config.watcher).arg(&spec))
+        .wrap_err_with(|| format!("Failed to start {}", config.watcher.display()))
+}
+
+// What spank-tro-watch keeps track of between polls
+pub struct Watcher {
+    settings: Settings,
+    job: Job,
+    // how much of the checkpoint file was read
+    checkpoint_offset: u64,
+    // DMTCP images already recorded
+    images: BTreeSet<PathBuf>,
+    started: Instant,
+    // when the latest arrangement was recorded, checkpoints included
+    recorded: Instant,
+    snapshots: u32,
+    // writes to the workdir, with track_outputs
+    files: Option<FileWatch>,
+}
+
+impl Watcher {
+    pub fn new(settings: Settings, job: Job) -> Self {
+        // images of earlier runs are part of the initial arrangement
+        let images = fs::read_dir(&job.workdir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| is_dmtcp_image(path))
+                    .collect()
+            })
+            .unwrap_or_default();
+        // without it the final arrangement only lacks the written files
+        let files = match settings.track_outputs {
+            true => FileWatch::new(&job)
+                .inspect_err(|e| warn!("Not tracking outputs: {:#}", e))
+                .ok(),
+            false => None,
+        };
+        Watcher {
+            settings,
+            job,
+            checkpoint_offset: 0,
+            images,
+            started: Instant::now(),
+            recorded: Instant::now(),
+            snapshots: 0,
+            files,
+        }
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        self.settings.checkpoint_poll_interval
+    }
+
+    // Record an arrangement for each checkpoint since the previous poll, and
+    // one more if snapshot_interval passed without any. The last poll, as
+    // the job ends, leaves the latter to the final arrangement.
+    pub fn poll(&mut self, last: bool, notify: &dyn Fn(&str)) -> Result<(), Report> {
+        self.read_files()?;
+        if let Some(files) = &mut self.files {
+            if last {
+                files.open_files();
+            }
+            files.changes.save(&self.job)?;
+        }
+        let mut labels = Vec::new();
+        if self.settings.checkpoints {
+            labels.extend(self.announced()?);
+            labels.extend(self.dmtcp_images()?);
+        }
+        let tro = TroUtils::new(&self.settings, &self.job, notify);
+        for label in labels {
+            let comment = format!(
+                "'Checkpoint: {}{}'",
+                label.replace('\'', ""),
+                self.job.label()
+            );
+            let id = tro.add_arrangement(&comment)?;
+            self.recorded = Instant::now();
+            info!("Recorded {} for checkpoint {}", id, label);
+        }
+        if let Some(interval) = self.settings.snapshot_interval {
+            if !last && self.recorded.elapsed() >= interval {
+                self.snapshot(notify)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Take in the writes to the workdir so far, often enough for the kernel
+    // not to drop any
+    pub fn read_files(&mut self) -> Result<(), Report> {
+        match &mut self.files {
+            Some(files) => files.read(),
+            None => Ok(()),
+        }
+    }
+
+    fn snapshot(&mut self, notify: &dyn Fn(&str)) -> Result<(), Report> {
+        if self.snapshots >= self.settings.snapshot_max {
+            return Ok(());
+        }
+        self.snapshots += 1;
+        // the count goes up even if it fails, not to retry it every poll
+        self.recorded = Instant::now();
+        let minutes = self.started.elapsed().as_secs() / 60;
+        let comment = format!(
+            "'Snapshot: {} of {} after {}m{}'",
+            self.snapshots,
+            self.settings.snapshot_max,
+            minutes,
+            self.job.label()
+        );
+        let tro = TroUtils::new(&self.settings, &self.job, notify);
+        let id = tro.add_arrangement(&comment)?;
+        info!("Recorded {} as snapshot {}", id, self.snapshots);
+        if self.snapshots == self.settings.snapshot_max {
+            info!("Recorded snapshot_max={} snapshots", self.snapshots);
+        }
+        Ok(())
+    }
+
+    // Labels appended to the checkpoint file since the previous poll, only
+    // complete lines
+    fn announced(&mut self) -> Result<Vec<String>, Report> {
+        let path = checkpoint_path(&self.job);
+        let mut file =
+            File::open(&path).wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+        file.seek(SeekFrom::Start(self.checkpoint_offset))?;
+        let mut labels = Vec::new();
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            if !line.ends_with('\n') {
+                break;
+            }
+            self.checkpoint_offset += line.len() as u64;
+            match line.trim() {
+                "" => labels.push("checkpoint".to_string()),
+                label => labels.push(label.to_string()),
+            }
+            line.clear();
+        }
+        Ok(labels)
+    }
+
+    // DMTCP images (ckpt_*.dmtcp) in the workdir that are done being written,
+    // i.e. untouched for a poll interval
+    fn dmtcp_images(&mut self) -> Result<Vec<String>, Report> {
+        let mut labels = Vec::new();
+        let settled = SystemTime::now() - self.poll_interval();
+        for entry in fs::read_dir(&self.job.workdir)? {
+            let path = entry?.path();
+            if !is_dmtcp_image(&path) || self.images.contains(&path) {
+                continue;
+            }
+            let modified = fs::metadata(&path).and_then(|metadata| metadata.modified())?;
+            if modified > settled {
+                continue;
+            }
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            labels.push(format!("DMTCP {name}"));
+            self.images.insert(path);
+        }
+        Ok(labels)
+    }
+}
+
+fn is_dmtcp_image(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.starts_with("ckpt_") && name.ends_with(".dmtcp")
+}
+
+// The settings and job spank-tro-watch is started with
+fn spec_path(job: &Job) -> PathBuf {
+    job.workdir
+        .join(".tro")
+        .join(format!("{}.watch.json", job.key()))
+}
+
+// Stop spank-tro-watch once the job's tasks are done and remove its files
+pub fn stop(pid: u32, job: &Job) {
+    command::stop(pid);
+    for path in [spec_path(job), checkpoint_path(job)] {
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                info!("Failed to remove {}: {}", path.display(), e)
+            }
+            _ => {}
+        }
+    }
+}
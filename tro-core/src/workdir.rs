@@ -0,0 +1,24 @@
+use std::fs::read_dir;
+use std::io;
+use std::path::Path;
+
+// Count files and bytes below dir, skipping .git like tro-utils does
+pub fn summarize_dir(dir: &Path) -> Result<(u64, u64), io::Error> {
+    let (mut files, mut bytes) = (0, 0);
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        let metadata = entry.path().symlink_metadata()?;
+        if metadata.is_dir() {
+            let (sub_files, sub_bytes) = summarize_dir(&entry.path())?;
+            files += sub_files;
+            bytes += sub_bytes;
+        } else {
+            files += 1;
+            bytes += metadata.len();
+        }
+    }
+    Ok((files, bytes))
+}
@@ -0,0 +1,156 @@
+use eyre::{eyre, Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::context::{first_env, JobContext};
+use crate::declaration::{self, term, NAMESPACE};
+use crate::digest;
+use crate::privilege;
+
+// Name of the workflow-level declaration, in the workflow's directory
+pub const AGGREGATE_NAME: &str = "tro-workflow.jsonld";
+
+// Workflow engines that submit a Slurm job per task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    Nextflow,
+    Snakemake,
+}
+
+impl Engine {
+    // Variables the engine leaves in the environment of the jobs it submits
+    fn variables(self) -> &'static [&'static str] {
+        match self {
+            Engine::Nextflow => &["NXF_TASK_WORKDIR", "NXF_WORK", "NXF_HOME", "NXF_VER"],
+            Engine::Snakemake => &["SNAKEMAKE_PROFILE", "SNAKEMAKE_OUTPUT_CACHE"],
+        }
+    }
+
+    // What the engine keeps in the directory it was started from
+    fn marker(self) -> &'static str {
+        match self {
+            Engine::Nextflow => ".nextflow",
+            Engine::Snakemake => ".snakemake",
+        }
+    }
+}
+
+// The workflow run a job is a task of
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Workflow {
+    pub engine: Engine,
+    // where the engine was started, holding the aggregate declaration
+    pub root: PathBuf,
+}
+
+impl Workflow {
+    // The workflow of a job run in `workdir`, if its environment shows it was
+    // submitted by an engine: the closest directory above with the engine's
+    // marker, e.g. the launch directory above Nextflow's work/ab/cdef...
+    pub fn detect(ctx: &impl JobContext, workdir: &Path) -> Result<Option<Self>, Report> {
+        for engine in [Engine::Nextflow, Engine::Snakemake] {
+            if first_env(ctx, engine.variables())?.is_none() {
+                continue;
+            }
+            let root = workdir
+                .ancestors()
+                .find(|dir| dir.join(engine.marker()).is_dir());
+            return Ok(root.map(|root| Workflow {
+                engine,
+                root: root.to_path_buf(),
+            }));
+        }
+        Ok(None)
+    }
+
+    pub fn aggregate_path(&self) -> PathBuf {
+        self.root.join(AGGREGATE_NAME)
+    }
+
+    pub fn describe(&self) -> Value {
+        json!({
+            term("engine"): self.engine,
+            term("root"): self.root,
+        })
+    }
+}
+
+// Add the performances of a task's TRO to the workflow-level declaration at
+// `aggregate`, replacing those of an earlier aggregation of the same TRO.
+// Without a detected `workflow`, it is the one run in the aggregate's
+// directory.
+pub fn aggregate(
+    aggregate: &Path,
+    workflow: Option<&Workflow>,
+    member: &Path,
+) -> Result<(), Report> {
+    let _lock = declaration::lock(aggregate)
+        .wrap_err_with(|| format!("Failed to lock {}", aggregate.display()))?;
+    let mut document = match fs::read(aggregate) {
+        Ok(content) => serde_json::from_slice(&content)
+            .wrap_err_with(|| format!("Invalid declaration {}", aggregate.display()))?,
+        Err(_) => json!({
+            "@context": [{
+                "rdfs": "http://www.w3.org/2000/01/rdf-schema#",
+                "trov": "https://w3id.org/trace/2023/05/trov#",
+                "spank": NAMESPACE,
+            }],
+            "@graph": [{
+                "@id": "tro",
+                "@type": "trov:TransparentResearchObject",
+                "rdfs:comment": "Workflow run",
+                term("workflow"): workflow.map_or_else(
+                    || json!({ term("root"): aggregate.parent() }),
+                    Workflow::describe,
+                ),
+                "trov:hasPerformance": [],
+            }],
+        }),
+    };
+    let content =
+        fs::read(member).wrap_err_with(|| format!("Failed to read {}", member.display()))?;
+    let sha256 = digest::sha256_bytes(&content);
+    let member_document: Value = serde_json::from_slice(&content)
+        .wrap_err_with(|| format!("Invalid declaration {}", member.display()))?;
+    let member_id = member.to_string_lossy().into_owned();
+
+    let performances = document["@graph"][0]["trov:hasPerformance"]
+        .as_array_mut()
+        .ok_or_else(|| eyre!("{} has no performances", aggregate.display()))?;
+    performances.retain(|performance| performance[term("declaration")] != member_id.as_str());
+    let nested = match &member_document["@graph"][0]["trov:hasPerformance"] {
+        Value::Array(nested) => nested.clone(),
+        Value::Null => Vec::new(),
+        performance => vec![performance.clone()],
+    };
+    for mut performance in nested {
+        let id = performance["@id"]
+            .as_str()
+            .unwrap_or("performance")
+            .to_string();
+        if let Some(performance) = performance.as_object_mut() {
+            performance.insert(
+                "@id".to_string(),
+                json!(format!("run/{}/{id}", &sha256[..12])),
+            );
+            performance.insert(term("declaration"), json!(member_id));
+            performance.insert(term("sha256"), json!(sha256));
+        }
+        performances.push(performance);
+    }
+    // readers of the aggregate never see it half written
+    let mut tmp_path = aggregate.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    privilege::create_nofollow(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(&serde_json::to_vec_pretty(&document)?)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, aggregate))
+        .wrap_err_with(|| format!("Failed to write {}", aggregate.display()))
+}
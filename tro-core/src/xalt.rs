@@ -0,0 +1,231 @@
+use eyre::{eyre, Report};
+use serde_json::Value;
+use std::fs::{read_dir, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::job::Job;
+use crate::privilege;
+use crate::settings::parse_bool;
+
+// The first of the `candidates` preload libraries installed in xalt_dir, e.g.
+// lib64/libxalt_init.so or the MPI build
+pub fn preload_library(xalt_dir: &Path, candidates: &[String]) -> Result<PathBuf, Report> {
+    candidates
+        .iter()
+        .map(|candidate| xalt_dir.join(candidate))
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            eyre!(
+                "No XALT preload library in {} (tried {}), check xalt_dir and xalt_preload",
+                xalt_dir.display(),
+                candidates.join(", ")
+            )
+        })
+}
+
+// How XALT records the job's programs, as the user asked with the --tro-xalt-*
+// and --tro-track-gpu options. XALT's own defaults apply to what is not set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Behavior {
+    // XALT's trace of what it does, to the tasks' stderr
+    pub tracing: bool,
+    pub sampling: Option<bool>,
+    pub gpu_tracking: bool,
+}
+
+impl Behavior {
+    fn variables(self) -> Vec<(&'static str, String)> {
+        let yes_no = |value: bool| if value { "yes" } else { "no" }.to_string();
+        let mut variables = vec![("XALT_TRACING", yes_no(self.tracing))];
+        if let Some(sampling) = self.sampling {
+            variables.push(("XALT_SAMPLING", yes_no(sampling)));
+        }
+        if self.gpu_tracking {
+            variables.push(("XALT_GPU_TRACKING", yes_no(true)));
+        }
+        variables
+    }
+}
+
+// An on/off option value
+pub fn parse_switch(value: &str) -> Result<bool, Report> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => parse_bool(value).map_err(|_| eyre!("{value} is not one of on/off")),
+    }
+}
+
+// Where the XALT library goes in an LD_PRELOAD the job already has
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PreloadPolicy {
+    // first, so that XALT sees the programs before anything else does
+    #[default]
+    Prepend,
+    Append,
+    // first, but not at all next to a conflicting library
+    Refuse,
+}
+
+impl FromStr for PreloadPolicy {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "prepend" => Ok(PreloadPolicy::Prepend),
+            "append" => Ok(PreloadPolicy::Append),
+            "refuse" => Ok(PreloadPolicy::Refuse),
+            _ => Err(eyre!("{value} is not one of prepend/append/refuse")),
+        }
+    }
+}
+
+// Preloaded libraries known to intercept what XALT does, e.g. profilers
+pub fn default_conflicts() -> Vec<String> {
+    [
+        "libdarshan",
+        "libhpcrun",
+        "libscorep",
+        "libmap-sampler",
+        "libTAU",
+        "libipm",
+    ]
+    .map(str::to_string)
+    .to_vec()
+}
+
+// LD_PRELOAD of the job's tasks with the XALT `preloader` added to the one
+// they would otherwise get, following `policy` when a library of the job
+// matches one of `conflicts`
+pub fn preload_chain(
+    preloader: &Path,
+    ld_preload: Option<&str>,
+    policy: PreloadPolicy,
+    conflicts: &[String],
+) -> Result<String, Report> {
+    let preloader = preloader.to_string_lossy().into_owned();
+    // ld.so takes colons and spaces
+    let existing: Vec<&str> = ld_preload
+        .unwrap_or_default()
+        .split([':', ' '])
+        .filter(|library| !library.is_empty())
+        .collect();
+    let conflicting = existing.iter().find(|library| {
+        let name = Path::new(library).file_name().unwrap_or_default();
+        conflicts
+            .iter()
+            .any(|conflict| name.to_string_lossy().starts_with(conflict.as_str()))
+    });
+    if let Some(conflicting) = conflicting {
+        if policy == PreloadPolicy::Refuse {
+            return Err(eyre!(
+                "{conflicting} is preloaded, XALT is not preloaded next to it (preload_policy=refuse)"
+            ));
+        }
+        warn!("{conflicting} is preloaded next to XALT, the trace may be incomplete");
+    }
+    let chain = match policy {
+        PreloadPolicy::Append => existing.into_iter().chain([preloader.as_str()]).collect(),
+        PreloadPolicy::Prepend | PreloadPolicy::Refuse => [preloader.as_str()]
+            .into_iter()
+            .chain(existing)
+            .collect::<Vec<_>>(),
+    };
+    Ok(chain.join(":"))
+}
+
+// Environment of the job's tasks making XALT record their programs in
+// `record_dir`, with the `ld_preload` chain that loads it
+pub fn environment(
+    xalt_dir: &Path,
+    ld_preload: String,
+    user: &str,
+    record_dir: &Path,
+    behavior: Behavior,
+) -> Vec<(&'static str, String)> {
+    // It would be super-cool if I could inject those to control XALT...
+    // XALT_RESULT_DIR=/tmp, XALT_RESULT_FILE=foo.run
+    let mut environment = vec![
+        ("XALT_DIR", xalt_dir.to_string_lossy().into_owned()),
+        ("LD_PRELOAD", ld_preload),
+        // Sometimes USER is not set and it trips XALT badly...
+        ("USER", user.to_string()),
+        ("XALT_EXECUTABLE_TRACKING", "yes".to_string()),
+        (
+            "XALT_FILE_PREFIX",
+            record_dir.to_string_lossy().into_owned(),
+        ),
+    ];
+    environment.extend(behavior.variables());
+    environment
+}
+
+// Where XALT writes the records of the job's programs, handed to it with
+// XALT_FILE_PREFIX so that they don't have to be looked for
+pub fn record_dir(job: &Job) -> PathBuf {
+    job.workdir.join(".tro").join("xalt").join(job.key())
+}
+
+// The XALT records of a job, one for each program and MPI rank XALT saw, or
+// with `started_after` of the programs the job started after that unix time.
+// Oldest first.
+pub fn find_records(job: &Job, started_after: Option<i64>) -> Result<Vec<Value>, Report> {
+    let _user = privilege::as_user(job);
+    let record_dir = record_dir(job);
+    let candidates = match record_dir.is_dir() {
+        true => records(&record_dir, None)?,
+        // XALT ignoring XALT_FILE_PREFIX writes to the user's home directory,
+        // only the records written since the job started can be its
+        false => records(
+            &Path::new("/home").join(&job.user).join(".xalt.d"),
+            Some(job.start_time - 1),
+        )?,
+    };
+    let jobid = job.jobid.to_string();
+    let mut found = Vec::new();
+    for path in candidates {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+        let u: Value = serde_json::from_reader(reader)?;
+        if u["userT"]["job_id"].as_str() == Some(&jobid) {
+            let start_time = u["userDT"]["start_time"].as_f64().unwrap_or(0.0);
+            // the start time of the step is only known to the second
+            if started_after.is_none_or(|after| start_time >= (after - 1) as f64) {
+                found.push(u);
+            }
+        }
+    }
+    match found.is_empty() {
+        true => Err(eyre!("No XALT record found for job {}", job.jobid)),
+        false => Ok(found),
+    }
+}
+
+// JSON records below dir, modified after `since` if given, oldest first
+fn records(dir: &Path, since: Option<i64>) -> Result<Vec<PathBuf>, Report> {
+    let mut records = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+                continue;
+            }
+            let modified = metadata.modified()?;
+            let recent = since.is_none_or(|since| {
+                modified >= UNIX_EPOCH + Duration::from_secs(since.max(0) as u64)
+            });
+            if recent && entry.path().extension().is_some_and(|ext| ext == "json") {
+                records.push((modified, entry.path()));
+            }
+        }
+    }
+    records.sort();
+    Ok(records.into_iter().map(|(_, path)| path).collect())
+}
@@ -0,0 +1,29 @@
+use serde_json::json;
+
+use tro_core::attachment;
+use tro_core::settings::Settings;
+
+#[test]
+fn sensitive_content_is_not_attached_in_clear() {
+    let mut settings = Settings::default();
+    let mut described = json!({});
+    attachment::attach(&settings, &mut described, "stdout", "result").unwrap();
+    assert_eq!(described, json!({"spank:content": "result"}));
+
+    settings.set("sensitive", "stdout,batch_script").unwrap();
+    let mut described = json!({});
+    attachment::attach(&settings, &mut described, "stdout", "result").unwrap();
+    assert_eq!(described, json!({}));
+
+    assert!(settings.set("sensitive", "stdin").is_err());
+}
+
+#[test]
+fn base64() {
+    assert_eq!(attachment::base64(b""), "");
+    assert_eq!(attachment::base64(b"f"), "Zg==");
+    assert_eq!(attachment::base64(b"fo"), "Zm8=");
+    assert_eq!(attachment::base64(b"foo"), "Zm9v");
+    assert_eq!(attachment::base64(b"foobar"), "Zm9vYmFy");
+    assert_eq!(attachment::base64(&[0xff, 0xfe]), "//4=");
+}
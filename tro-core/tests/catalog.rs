@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+use tro_core::catalog::{
+    self, Catalog, CatalogBackend, CatalogEntry, PostgresCatalog, SqliteCatalog, Status,
+};
+use tro_core::job::Job;
+use tro_core::report::{self, Format};
+
+fn job() -> Job {
+    Job::new(
+        42,
+        (1000, 1000),
+        "o'brien".to_string(),
+        PathBuf::from("/home/obrien/run"),
+        None,
+        None,
+    )
+}
+
+fn check(catalog: &dyn Catalog) {
+    let job = job();
+    let fingerprints = ["0123ABCD".to_string()];
+    let entry = CatalogEntry::new(&job, Some("gpu"), &fingerprints, Status::Signed);
+    catalog.record(&entry).unwrap();
+    catalog
+        .record(&CatalogEntry::new(&job, None, &[], Status::Failed))
+        .unwrap();
+
+    let found = catalog.find(42).unwrap();
+    assert_eq!(found.len(), 2);
+    assert_eq!(found[0], entry);
+    assert_eq!(found[1].status, Status::Failed);
+    assert!(catalog.find(43).unwrap().is_empty());
+}
+
+#[test]
+fn finalizations_are_indexed() {
+    // the catalog is kept with the sqlite3 shell
+    if Command::new("sqlite3").arg("-version").output().is_err() {
+        return;
+    }
+    let path = std::env::temp_dir().join(format!("catalog-{}.db", std::process::id()));
+    check(&SqliteCatalog::new(PathBuf::from("sqlite3"), path.clone()));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn finalizations_are_indexed_centrally() {
+    // a scratch database to fill, e.g. postgresql://localhost/spank_tro_test
+    let Ok(conninfo) = std::env::var("SPANK_TRO_TEST_POSTGRES") else {
+        return;
+    };
+    let catalog = PostgresCatalog::new(PathBuf::from("psql"), conninfo, None);
+    // jobs of earlier runs are recorded too
+    let before = catalog.find(42).unwrap().len();
+    let job = job();
+    catalog
+        .record(&CatalogEntry::new(&job, None, &[], Status::Unsigned))
+        .unwrap();
+    let found = catalog.find(42).unwrap();
+    assert_eq!(found.len(), before + 1);
+    assert_eq!(found[before].user, "o'brien");
+}
+
+#[test]
+fn backends_are_told_apart() {
+    assert_eq!(
+        "/var/lib/spank-tro/catalog.db"
+            .parse::<CatalogBackend>()
+            .unwrap(),
+        CatalogBackend::Sqlite(PathBuf::from("/var/lib/spank-tro/catalog.db"))
+    );
+    assert_eq!(
+        "sqlite:catalog.db".parse::<CatalogBackend>().unwrap(),
+        CatalogBackend::Sqlite(PathBuf::from("catalog.db"))
+    );
+    assert_eq!(
+        "postgresql://tro@db/catalog"
+            .parse::<CatalogBackend>()
+            .unwrap(),
+        CatalogBackend::Postgres("postgresql://tro@db/catalog".to_string())
+    );
+    assert_eq!(
+        "postgresql:host=db dbname=catalog"
+            .parse::<CatalogBackend>()
+            .unwrap(),
+        CatalogBackend::Postgres("host=db dbname=catalog".to_string())
+    );
+    assert!("sqlite:".parse::<CatalogBackend>().is_err());
+}
+
+#[test]
+fn reports_count_jobs_by_how_they_last_ended() {
+    let job = job();
+    let mut failed = CatalogEntry::new(&job, Some("gpu"), &[], Status::Failed);
+    failed.finalized = "2024-05-01T00:00:00Z".to_string();
+    let signed = CatalogEntry::new(&job, Some("gpu"), &[], Status::Signed);
+    let summaries = report::summarize(&[failed, signed]);
+    assert_eq!(summaries.len(), 1);
+    assert_eq!(
+        (summaries[0].jobs, summaries[0].signed, summaries[0].failed),
+        (1, 1, 0)
+    );
+
+    let table = report::render(&summaries, Format::Table).unwrap();
+    assert_eq!(
+        table,
+        "USER     PARTITION  JOBS  SIGNED  UNSIGNED  FAILED\n\
+         o'brien  gpu        1     1       0         0\n"
+    );
+    assert!(catalog::parse_since("2024-05-01").is_ok());
+    assert!(catalog::parse_since("7d").is_ok());
+    assert!(catalog::parse_since("last week").is_err());
+}
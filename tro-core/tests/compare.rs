@@ -0,0 +1,76 @@
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+
+use tro_core::compare::{self, Aspect};
+use tro_core::declaration::Declaration;
+
+fn write(path: &Path, out_sha256: &str, executable_sha256: &str, run_seconds: i64) {
+    let tro = json!({
+        "@graph": [{
+            "trov:hasComposition": {
+                "trov:hasArtifact": [
+                    { "@id": "composition/1/artifact/0", "trov:sha256": "aa" },
+                    { "@id": "composition/1/artifact/1", "trov:sha256": out_sha256 },
+                ],
+            },
+            "trov:hasArrangement": [{
+                "@id": "arrangement/1",
+                "trov:hasArtifactLocation": [
+                    {
+                        "trov:hasArtifact": { "@id": "composition/1/artifact/0" },
+                        "trov:hasLocation": "run.sh",
+                    },
+                    {
+                        "trov:hasArtifact": { "@id": "composition/1/artifact/1" },
+                        "trov:hasLocation": "out.csv",
+                    },
+                ],
+            }],
+            "trov:hasPerformance": [{
+                "@id": "trp/0",
+                "spank:executables": [
+                    { "spank:path": "/usr/bin/python3", "spank:sha256": executable_sha256 },
+                ],
+                "spank:succeeded": true,
+                "spank:timeline": { "spank:runSeconds": run_seconds },
+            }],
+        }],
+    });
+    fs::write(path, tro.to_string()).unwrap();
+}
+
+#[test]
+fn runs_differ_in_outputs_programs_and_run_time() {
+    let dir = std::env::temp_dir().join(format!("compare-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (left, right, same) = (
+        dir.join("a.jsonld"),
+        dir.join("b.jsonld"),
+        dir.join("c.jsonld"),
+    );
+    write(&left, "bb", "ee", 100);
+    write(&right, "cc", "ff", 300);
+    write(&same, "bb", "ee", 105);
+
+    let left = Declaration::load(&left).unwrap();
+    let differences = compare::compare(&left, &Declaration::load(&right).unwrap());
+    let keys: Vec<(Aspect, &str)> = differences
+        .iter()
+        .map(|difference| (difference.aspect, difference.key.as_str()))
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            (Aspect::Artifact, "out.csv"),
+            (Aspect::Environment, "executable /usr/bin/python3"),
+            (Aspect::Performance, "runSeconds"),
+        ]
+    );
+    assert_eq!(differences[0].left, Some(Value::from("bb")));
+    assert_eq!(differences[0].right, Some(Value::from("cc")));
+
+    // within the run time tolerance
+    assert!(compare::compare(&left, &Declaration::load(&same).unwrap()).is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,591 @@
+use eyre::eyre;
+use std::cell::RefCell;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use tro_core::cas::Cas;
+use tro_core::config::Config;
+use tro_core::context::{
+    self, Context, MockContext, EXEC_DIR_OPTION, GENERATE_OPTION, INPUT_OPTION, NO_GENERATE_OPTION,
+    QUIET_OPTION, ROOT_OPTION, TRACK_GPU_OPTION, XALT_SAMPLING_OPTION, XALT_TRACING_OPTION,
+};
+use tro_core::dependency;
+use tro_core::digest;
+use tro_core::failure::FailurePolicy;
+use tro_core::inputs::{self, Input};
+use tro_core::job::{Job, WorkdirSource};
+use tro_core::phase::Phase;
+use tro_core::roots::{self, Root};
+use tro_core::settings::Settings;
+use tro_core::timeline::{self, Timeline};
+use tro_core::tracker::TRACE_ENV;
+use tro_core::tro_utils::TroUtils;
+use tro_core::workflow::{self, Engine};
+
+fn config(args: &[&str]) -> Config {
+    Config::from_args(args.iter().copied()).unwrap()
+}
+
+// Run context::generate, returning the decision and what the user was told
+fn generate(config: &Config, ctx: &MockContext, in_session: bool) -> (bool, Vec<String>) {
+    let messages = RefCell::new(Vec::new());
+    let notify = |msg: &str| messages.borrow_mut().push(msg.to_string());
+    let enabled = context::enabled(config, ctx).unwrap();
+    let generate = context::generate(config, ctx, enabled, in_session, None, &notify).unwrap();
+    (generate, messages.into_inner())
+}
+
+#[test]
+fn generates_only_when_asked() {
+    let config = config(&[]);
+    let ctx = MockContext::new(Context::Local);
+    assert_eq!(generate(&config, &ctx, false), (false, vec![]));
+    let ctx = ctx.with_option(GENERATE_OPTION);
+    assert_eq!(generate(&config, &ctx, false), (true, vec![]));
+}
+
+#[test]
+fn site_default_can_be_opted_out() {
+    let config = config(&["default_generate=yes", "default_partitions=gpu"]);
+    let ctx = MockContext::new(Context::Allocator).with_env("SBATCH_PARTITION", "gpu");
+    assert!(generate(&config, &ctx, false).0);
+    let ctx = ctx.with_option(NO_GENERATE_OPTION);
+    assert!(!generate(&config, &ctx, false).0);
+    let ctx = MockContext::new(Context::Allocator).with_env("SBATCH_PARTITION", "cpu");
+    assert!(!generate(&config, &ctx, false).0);
+}
+
+#[test]
+fn out_of_scope_requests_are_reported() {
+    let config = config(&["partitions=gpu"]);
+    let ctx = MockContext::new(Context::Remote)
+        .with_env("SLURM_JOB_PARTITION", "debug")
+        .with_option(GENERATE_OPTION);
+    let (generated, messages) = generate(&config, &ctx, false);
+    assert!(!generated);
+    assert_eq!(messages.len(), 1);
+}
+
+#[test]
+fn policy_denies_and_forces() {
+    let config = config(&["deny_users=mallory", "force_accounts=audited"]);
+    let ctx = MockContext::new(Context::Remote)
+        .with_env("SLURM_JOB_USER", "mallory")
+        .with_option(GENERATE_OPTION);
+    let (generated, messages) = generate(&config, &ctx, false);
+    assert!(!generated);
+    assert!(messages[0].contains("mallory"));
+
+    let ctx = MockContext::new(Context::Remote)
+        .with_env("SLURM_JOB_USER", "alice")
+        .with_env("SLURM_JOB_ACCOUNT", "audited")
+        .with_option(NO_GENERATE_OPTION);
+    assert!(generate(&config, &ctx, false).0);
+    // steps of an interactive allocation belong to its TRO
+    assert!(!generate(&config, &ctx, true).0);
+}
+
+#[test]
+fn job_from_the_remote_context() {
+    let mut ctx = MockContext::new(Context::Remote)
+        .with_job(42)
+        .with_env("SLURM_SUBMIT_DIR", "/home/alice/run")
+        .with_env("SLURM_JOB_USER", "alice");
+    ctx.job_stepid = 3;
+    let job = context::job(&config(&[]), &ctx).unwrap();
+    assert_eq!(job.jobid, 42);
+    assert_eq!(job.user, "alice");
+    assert_eq!(
+        job.declaration,
+        PathBuf::from("/home/alice/run/tro-42.jsonld")
+    );
+    assert_eq!(job.key(), "42");
+
+    let job = context::job(&config(&["granularity=step"]), &ctx).unwrap();
+    assert_eq!(job.key(), "42.3");
+
+    let ctx = ctx.with_env("SLURM_RESTART_COUNT", "1");
+    let job = context::job(&config(&[]), &ctx).unwrap();
+    assert_eq!(job.restart, 1);
+    assert_eq!(job.label(), " (attempt 2)");
+}
+
+#[test]
+fn inputs_outside_the_workdir() {
+    let ctx = MockContext::new(Context::Remote)
+        .with_job(42)
+        .with_env("SLURM_SUBMIT_DIR", "/home/alice/run")
+        .with_option_value(
+            INPUT_OPTION,
+            "doi:10.5281/zenodo.123,s3://bucket/data,/scratch/ref.fa",
+        );
+    let job = context::job(&config(&[]), &ctx).unwrap();
+    let parsed: Vec<Input> = job.inputs.iter().map(|i| i.parse().unwrap()).collect();
+    assert_eq!(
+        parsed,
+        vec![
+            Input::Doi("10.5281/zenodo.123".to_string()),
+            Input::Uri("s3://bucket/data".to_string()),
+            Input::Path(PathBuf::from("/scratch/ref.fa")),
+        ]
+    );
+    assert!(inputs::parse("doi:11.1/x").is_err());
+
+    let ctx = ctx.with_option_value(INPUT_OPTION, "doi:nope");
+    assert!(context::job(&config(&[]), &ctx).is_err());
+}
+
+#[test]
+fn het_components_share_the_leader_tro() {
+    let ctx = MockContext::new(Context::Remote)
+        .with_job(43)
+        .with_env("SLURM_SUBMIT_DIR", "/run")
+        .with_env("SLURM_HET_SIZE", "2")
+        .with_env("SLURM_JOB_ID_HET_GROUP_0", "42");
+    let job = context::job(&config(&[]), &ctx).unwrap();
+    assert_eq!(job.declaration, PathBuf::from("/run/tro-42.jsonld"));
+}
+
+#[test]
+fn declarations_follow_the_name_template() {
+    let ctx = MockContext::new(Context::Remote)
+        .with_job(43)
+        .with_env("SLURM_SUBMIT_DIR", "/run")
+        .with_env("SLURM_JOB_USER", "alice")
+        .with_env("SLURM_JOB_NAME", "fit/all")
+        .with_env("SLURM_ARRAY_JOB_ID", "40")
+        .with_env("SLURM_ARRAY_TASK_ID", "3");
+    let config = config(&["tro_name_template=%u-%x-%A_%a-%j%%.jsonld"]);
+    let job = context::job(&config, &ctx).unwrap();
+    assert_eq!(
+        job.declaration,
+        PathBuf::from("/run/alice-fit_all-40_3-43%.jsonld")
+    );
+}
+
+#[test]
+fn dated_declarations_keep_the_date_the_job_started() {
+    // 2024-05-01T23:59:59Z
+    let ctx = MockContext::new(Context::Remote)
+        .with_job(43)
+        .with_env("SLURM_SUBMIT_DIR", "/run")
+        .with_env("SLURM_JOB_START_TIME", "1714607999");
+    let config = config(&["tro_name_template=tro-%d-%j.jsonld"]);
+    let job = context::job(&config, &ctx).unwrap();
+    assert_eq!(
+        job.declaration,
+        PathBuf::from("/run/tro-20240501-43.jsonld")
+    );
+}
+
+#[test]
+fn job_needs_a_submit_dir() {
+    let ctx = MockContext::new(Context::Remote).with_job(42);
+    assert!(context::job(&config(&[]), &ctx).is_err());
+}
+
+#[test]
+fn tracking_preloads_xalt() {
+    let xalt_dir = std::env::temp_dir().join(format!("xalt-{}", std::process::id()));
+    fs::create_dir_all(xalt_dir.join("lib")).unwrap();
+    let xalt_dir_arg = format!("xalt_dir={}", xalt_dir.display());
+    let mut config = config(&[&xalt_dir_arg]);
+    let mut job = Job::new(
+        42,
+        (1000, 1000),
+        "alice".into(),
+        xalt_dir.clone(),
+        None,
+        None,
+    );
+    let mut ctx = MockContext::new(Context::Remote).with_env("LD_PRELOAD", "libfoo.so");
+    // no library, no preloading
+    assert!(context::track(&config, &mut ctx, &mut job, "alice").is_err());
+    assert!(!ctx.env.contains_key("XALT_DIR"));
+
+    fs::write(xalt_dir.join("lib/libxalt_init.so"), "").unwrap();
+    context::track(&config, &mut ctx, &mut job, "alice").unwrap();
+    assert_eq!(ctx.env["USER"], "alice");
+    assert!(ctx.env["LD_PRELOAD"].ends_with("lib/libxalt_init.so:libfoo.so"));
+    assert!(ctx.env["XALT_FILE_PREFIX"].ends_with(".tro/xalt/42"));
+    assert_eq!(ctx.env["XALT_TRACING"], "no");
+    assert!(!ctx.env.contains_key("XALT_SAMPLING"));
+    context::wrap(&config, &mut ctx, &job).unwrap();
+    assert!(ctx.task_argv.is_empty());
+
+    // other preloads: XALT first by default, after them or not at all
+    assert!(job.preload.as_ref().unwrap().ends_with(":libfoo.so"));
+    let mut ctx =
+        MockContext::new(Context::Remote).with_env("LD_PRELOAD", "/opt/darshan/lib/libdarshan.so");
+    context::track(&config, &mut ctx.clone(), &mut job, "alice").unwrap();
+    config.set("preload_policy", "append").unwrap();
+    context::track(&config, &mut ctx, &mut job, "alice").unwrap();
+    assert!(ctx.env["LD_PRELOAD"].starts_with("/opt/darshan/lib/libdarshan.so:"));
+    config.set("preload_policy", "refuse").unwrap();
+    let mut ctx =
+        MockContext::new(Context::Remote).with_env("LD_PRELOAD", "/opt/darshan/lib/libdarshan.so");
+    assert!(context::track(&config, &mut ctx, &mut job, "alice").is_err());
+    config.set("preload_policy", "prepend").unwrap();
+
+    let mut ctx = MockContext::new(Context::Remote)
+        .with_option(XALT_TRACING_OPTION)
+        .with_option(TRACK_GPU_OPTION)
+        .with_option_value(XALT_SAMPLING_OPTION, "off");
+    context::track(&config, &mut ctx, &mut job, "alice").unwrap();
+    assert_eq!(ctx.env["XALT_TRACING"], "yes");
+    assert_eq!(ctx.env["XALT_SAMPLING"], "no");
+    assert_eq!(ctx.env["XALT_GPU_TRACKING"], "yes");
+    let mut ctx = MockContext::new(Context::Remote).with_option_value(XALT_SAMPLING_OPTION, "some");
+    assert!(context::track(&config, &mut ctx, &mut job, "alice").is_err());
+
+    config.set("xalt_preload", "lib/libxalt_mpi.so").unwrap();
+    assert!(context::track(&config, &mut ctx, &mut job, "alice").is_err());
+    fs::remove_dir_all(&xalt_dir).unwrap();
+}
+
+#[test]
+fn tracking_without_xalt() {
+    let config = config(&["tracker=none"]);
+    let mut ctx = MockContext::new(Context::Remote);
+    context::track(&config, &mut ctx, &mut Job::default(), "alice").unwrap();
+    assert!(ctx.env.is_empty());
+
+    let config = self::config(&["tracker=wrapper", "wrapper=/opt/wrap"]);
+    let mut job = Job::new(42, (1000, 1000), "alice".into(), "/run".into(), None, None);
+    context::track(&config, &mut ctx, &mut job, "alice").unwrap();
+    assert_eq!(ctx.env[TRACE_ENV], "/run/.tro/42.trace");
+    ctx.task_argv = vec!["a.out".to_string()];
+    context::wrap(&config, &mut ctx, &job).unwrap();
+    assert_eq!(ctx.task_argv, ["/opt/wrap", "a.out"]);
+}
+
+#[test]
+fn site_environment_of_the_job() {
+    let config = config(&["tracker=none", "job_env=OMP_PROC_BIND=true,TRO_SITE=a=b"]);
+    let mut ctx = MockContext::new(Context::Remote);
+    context::track(&config, &mut ctx, &mut Job::default(), "alice").unwrap();
+    assert_eq!(ctx.env["OMP_PROC_BIND"], "true");
+    assert_eq!(ctx.env["TRO_SITE"], "a=b");
+
+    assert!(Config::from_args(["plugin_env=GNUPGHOME"]).is_err());
+    assert!(Config::from_args(["plugin_env=1GPG=/x"]).is_err());
+    assert!(Config::from_args(["job_env=A=1,A=2"]).is_err());
+}
+
+#[test]
+fn allocation_jobid() {
+    let ctx = MockContext::new(Context::Allocator);
+    assert_eq!(context::allocation_jobid(&ctx).unwrap(), None);
+    let ctx = ctx.with_env("SLURM_JOB_ID", "42");
+    assert_eq!(context::allocation_jobid(&ctx).unwrap(), Some(42));
+    let ctx = ctx.with_env("SLURM_JOB_ID", "nope");
+    assert!(context::allocation_jobid(&ctx).is_err());
+}
+
+#[test]
+fn snapshot_interval_is_limited() {
+    let config = config(&["snapshot_interval=30m", "snapshot_max=4"]);
+    assert_eq!(
+        config.settings.snapshot_interval,
+        Some(std::time::Duration::from_secs(1800))
+    );
+    assert_eq!(config.settings.snapshot_max, 4);
+    assert!(Config::from_args(["snapshot_interval=10s"]).is_err());
+    assert!(Config::from_args(["snapshot_interval=0"])
+        .unwrap()
+        .settings
+        .snapshot_interval
+        .is_none());
+}
+
+#[test]
+fn dependencies_of_a_pipeline() {
+    let dependencies = dependency::parse("afterok:12:13_2,after:14+10?singleton").unwrap();
+    let jobids: Vec<&str> = dependencies.iter().map(|d| d.jobid.as_str()).collect();
+    assert_eq!(jobids, ["12", "13_2", "14"]);
+    assert_eq!(dependencies[2].kind, "after");
+    assert!(dependency::parse("afterok:abc").is_err());
+
+    let ctx = MockContext::new(Context::Remote)
+        .with_job(42)
+        .with_env("SLURM_SUBMIT_DIR", "/home/alice/run")
+        .with_env("SLURM_JOB_DEPENDENCY", "afterok:41");
+    let job = context::job(&config(&[]), &ctx).unwrap();
+    assert_eq!(job.dependency.as_deref(), Some("afterok:41"));
+}
+
+#[test]
+fn dependencies_are_found_in_the_declaration_dir() {
+    let dir = std::env::temp_dir().join(format!("dependency-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // stands for scontrol, which still knows job 41
+    let scontrol = dir.join("scontrol");
+    fs::write(
+        &scontrol,
+        "#!/bin/sh\necho 'JobId=41 UserId=alice(1000) JobName=prep WorkDir=/home/alice/run'\n",
+    )
+    .unwrap();
+    fs::set_permissions(&scontrol, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut config = config(&[
+        "declaration_dir=/archive/%u",
+        "tro_name_template=%x-%j.jsonld",
+    ]);
+    config.settings.scontrol = scontrol;
+    let dependency = dependency::parse("afterok:41").unwrap().remove(0);
+    assert_eq!(
+        dependency.declaration(&config, &dir),
+        PathBuf::from("/archive/alice/prep-41.jsonld")
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn workflow_tasks_are_aggregated() {
+    let root = std::env::temp_dir().join(format!("nextflow-{}", std::process::id()));
+    let task = root.join("work/ab/cdef");
+    fs::create_dir_all(root.join(".nextflow")).unwrap();
+    fs::create_dir_all(&task).unwrap();
+    let ctx = MockContext::new(Context::Remote)
+        .with_job(42)
+        .with_env("SLURM_SUBMIT_DIR", &task.to_string_lossy())
+        .with_env("NXF_HOME", "/home/alice/.nextflow");
+    let job = context::job(&config(&[]), &ctx).unwrap();
+    let workflow = job.workflow.unwrap();
+    assert_eq!(workflow.engine, Engine::Nextflow);
+    assert_eq!(workflow.root, root);
+
+    let member = task.join("tro-42.jsonld");
+    let tro = r#"{"@graph": [{"trov:hasPerformance": [{"@id": "trp/0"}]}]}"#;
+    fs::write(&member, tro).unwrap();
+    let aggregate = workflow.aggregate_path();
+    for _ in 0..2 {
+        workflow::aggregate(&aggregate, Some(&workflow), &member).unwrap();
+    }
+    let document: serde_json::Value =
+        serde_json::from_slice(&fs::read(&aggregate).unwrap()).unwrap();
+    let performances = document["@graph"][0]["trov:hasPerformance"]
+        .as_array()
+        .unwrap();
+    assert_eq!(performances.len(), 1);
+    assert_eq!(
+        performances[0]["spank:declaration"],
+        *member.to_string_lossy()
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn timeline_from_the_scheduler() {
+    let timeline = Timeline {
+        submitted: Some(100),
+        eligible: Some(160),
+        started: Some(400),
+        ended: Some(1000),
+    };
+    assert_eq!(timeline.queued(), Some(240));
+    let described = timeline.describe();
+    assert_eq!(described["spank:runSeconds"], 600);
+    assert_eq!(described["spank:started"], "1970-01-01T00:06:40Z");
+    assert!(timeline::parse_slurm_time("Unknown").is_none());
+    assert!(timeline::parse_slurm_time("2024-05-01T12:00:00").is_some());
+}
+
+#[test]
+fn workdir_without_submit_dir() {
+    let ctx = MockContext::new(Context::Remote)
+        .with_job(42)
+        .with_env("SLURM_JOB_USER", "alice");
+    let config = config(&["scontrol=/nonexistent/scontrol"]);
+    assert!(context::job(&config, &ctx).is_err());
+
+    let config = self::config(&[
+        "scontrol=/nonexistent/scontrol",
+        "fallback_workdir=/var/spool/tro",
+    ]);
+    let job = context::job(&config, &ctx).unwrap();
+    assert_eq!(job.workdir, PathBuf::from("/var/spool/tro"));
+    assert_eq!(job.workdir_source, WorkdirSource::Fallback);
+    assert_eq!(
+        job.declaration,
+        PathBuf::from("/var/spool/tro/tro-42.jsonld")
+    );
+
+    let ctx = ctx.with_env("SLURM_SUBMIT_DIR", "/home/alice/run");
+    let job = context::job(&config, &ctx).unwrap();
+    assert_eq!(job.workdir_source, WorkdirSource::Submit);
+    assert!(Config::from_args(["fallback_workdir=spool"]).is_err());
+}
+
+#[test]
+fn execution_apart_from_declaration() {
+    let ctx = MockContext::new(Context::Remote)
+        .with_job(42)
+        .with_env("SLURM_SUBMIT_DIR", "/home/alice/run")
+        .with_env("SLURM_JOB_USER", "alice");
+    let config = config(&["declaration_dir=/archive/%u", "exec_dir=/scratch/%u/%j"]);
+    let job = context::job(&config, &ctx).unwrap();
+    assert_eq!(job.workdir, PathBuf::from("/scratch/alice/42"));
+    assert_eq!(job.workdir_source, WorkdirSource::Exec);
+    assert_eq!(
+        job.declaration,
+        PathBuf::from("/archive/alice/tro-42.jsonld")
+    );
+
+    let ctx = ctx.with_option_value(EXEC_DIR_OPTION, "/scratch/alice/mine");
+    let job = context::job(&config, &ctx).unwrap();
+    assert_eq!(job.workdir, PathBuf::from("/scratch/alice/mine"));
+
+    let ctx = ctx.with_option_value(EXEC_DIR_OPTION, "mine");
+    assert!(context::job(&config, &ctx).is_err());
+    assert!(Config::from_args(["declaration_dir=archive"]).is_err());
+}
+
+#[test]
+fn roots_of_the_arrangements() {
+    let ctx = MockContext::new(Context::Remote)
+        .with_job(42)
+        .with_env("SLURM_SUBMIT_DIR", "/home/alice/run")
+        .with_env("SLURM_JOB_USER", "alice")
+        .with_option_value(ROOT_OPTION, "results=/results/alice/42");
+    let config = config(&["roots=data=/data/%u"]);
+    let job = context::job(&config, &ctx).unwrap();
+    let roots: Vec<(&str, PathBuf)> = job
+        .roots
+        .iter()
+        .map(|root| (root.label.as_str(), root.path.clone()))
+        .collect();
+    assert_eq!(
+        roots,
+        [
+            ("data", PathBuf::from("/data/alice")),
+            ("results", PathBuf::from("/results/alice/42"))
+        ]
+    );
+
+    let ctx = ctx.with_option_value(ROOT_OPTION, "data=/elsewhere");
+    assert!(context::job(&config, &ctx).is_err());
+    assert!(roots::parse("workdir=/run").is_err());
+    assert!(roots::parse("data=relative").is_err());
+    assert!(roots::parse("data=/a,data=/b").is_err());
+
+    let dir = std::env::temp_dir().join(format!("roots-{}", std::process::id()));
+    fs::create_dir_all(dir.join(".git")).unwrap();
+    fs::write(dir.join(".git").join("HEAD"), "ref").unwrap();
+    fs::write(dir.join("input.csv"), "a,b\n").unwrap();
+    let root: Root = format!("data={}", dir.display()).parse().unwrap();
+    let described = root.describe(&Settings::default()).unwrap();
+    let artifacts = described["spank:artifacts"].as_array().unwrap();
+    assert_eq!(artifacts.len(), 1);
+    assert_eq!(artifacts[0]["spank:location"], "input.csv");
+    assert_eq!(artifacts[0]["spank:contentSize"], 4);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn large_inputs_are_fingerprinted() {
+    let dir = std::env::temp_dir().join(format!("large-{}", std::process::id()));
+    fs::create_dir_all(dir.join("data")).unwrap();
+    fs::write(dir.join("data").join("small"), "a").unwrap();
+    fs::write(dir.join("data").join("large"), "0123456789").unwrap();
+    let mut settings = Settings::default();
+    settings.set("max_hash_size", "5").unwrap();
+    let job = Job::new(42, (1000, 1000), "alice".into(), dir.clone(), None, None);
+    let input = Input::Path(dir.join("data").join("large"));
+    let described = input.describe(&settings, &job, None).unwrap();
+    assert!(described.get("spank:sha256").is_none());
+    assert!(described["spank:modified"].is_string());
+    assert_eq!(described["spank:contentSize"], 10);
+
+    settings.set("large_files", "head").unwrap();
+    let described = input.describe(&settings, &job, None).unwrap();
+    assert_eq!(
+        described["spank:headSha256"],
+        digest::sha256_bytes(b"0123456789")
+    );
+
+    let described = Input::Path(dir.join("data"))
+        .describe(&settings, &job, None)
+        .unwrap();
+    assert_eq!(described["spank:partiallyHashedFiles"], 1);
+    assert_eq!(described["spank:contentSize"], 11);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn inputs_are_stored_once() {
+    let dir = std::env::temp_dir().join(format!("cas-{}", std::process::id()));
+    fs::create_dir_all(dir.join("ref")).unwrap();
+    fs::write(dir.join("ref").join("genome.fa"), ">chr1\nACGT\n").unwrap();
+    let mut settings = Settings::default();
+    settings
+        .set("cas_dir", &dir.join("cas").to_string_lossy())
+        .unwrap();
+    let job = Job::new(42, (1000, 1000), "alice".into(), dir.clone(), None, None);
+    let cas = Cas::of(&settings, &job).unwrap();
+
+    let input = Input::Path(dir.join("ref").join("genome.fa"));
+    let first = input.describe(&settings, &job, Some(&cas)).unwrap();
+    let second = input.describe(&settings, &job, Some(&cas)).unwrap();
+    assert_eq!(first["spank:casPath"], second["spank:casPath"]);
+    let object = PathBuf::from(first["spank:casPath"].as_str().unwrap());
+    assert_eq!(fs::read_to_string(&object).unwrap(), ">chr1\nACGT\n");
+    let sha256 = first["spank:sha256"].as_str().unwrap();
+    assert_eq!(object, cas.path(sha256));
+    assert!(object.starts_with(dir.join("cas").join("sha256").join(&sha256[..2])));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn quiet_jobs_get_no_progress_messages() {
+    let ctx = MockContext::new(Context::Remote)
+        .with_job(42)
+        .with_env("SLURM_SUBMIT_DIR", "/home/alice/run");
+    let config = config(&[]);
+    let messages = RefCell::new(Vec::new());
+    let notify = |msg: &str| messages.borrow_mut().push(msg.to_string());
+
+    let job = context::job(&config, &ctx).unwrap();
+    TroUtils::new(&config.settings, &job, &notify).progress("TRO initialized");
+    assert_eq!(*messages.borrow(), ["spank-tro: TRO initialized"]);
+
+    let job = context::job(&config, &ctx.with_option(QUIET_OPTION)).unwrap();
+    assert!(job.quiet);
+    TroUtils::new(&config.settings, &job, &notify).progress("TRO initialized");
+    assert_eq!(messages.borrow().len(), 1);
+}
+
+#[test]
+fn failure_policy_per_phase() {
+    let site = config(&[
+        "on_failure=warn",
+        "fail_on=sign",
+        "warn_on=xalt,arrangement",
+    ]);
+    let on_failure = &site.settings.on_failure;
+    assert_eq!(on_failure.policy(Phase::Sign), FailurePolicy::Fail);
+    assert_eq!(on_failure.policy(Phase::Trace), FailurePolicy::Warn);
+    assert_eq!(on_failure.policy(Phase::Performance), FailurePolicy::Warn);
+    assert!(on_failure
+        .check(Phase::Sign, Err::<(), _>(eyre!("no key")))
+        .is_err());
+    assert_eq!(
+        on_failure
+            .check(Phase::Trace, Err::<(), _>(eyre!("late")))
+            .unwrap(),
+        None
+    );
+
+    // a phase in both lists goes by the last one
+    let site = config(&["on_failure=fail", "warn_on=sign", "fail_on=sign"]);
+    assert_eq!(
+        site.settings.on_failure.policy(Phase::Sign),
+        FailurePolicy::Fail
+    );
+    assert_eq!(
+        site.settings.on_failure.policy(Phase::Verify),
+        FailurePolicy::Fail
+    );
+    assert!(Config::from_args(["fail_on=upload"]).is_err());
+}
@@ -0,0 +1,63 @@
+use serde_json::{json, Value};
+use std::fs;
+
+use tro_core::cwlprov;
+
+#[test]
+fn research_object_of_a_declaration() {
+    let dir = std::env::temp_dir().join(format!("cwlprov-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let declaration = dir.join("tro-42.jsonld");
+    let tro = json!({
+        "@graph": [{
+            "@id": "tro",
+            "trov:hasComposition": {
+                "trov:hasArtifact": [{ "@id": "composition/1/artifact/0", "trov:sha256": "ab12" }],
+            },
+            "trov:hasArrangement": [
+                {
+                    "@id": "arrangement/0",
+                    "rdfs:comment": "Initial arrangement",
+                    "trov:hasLocus": [{
+                        "trov:hasArtifact": { "@id": "composition/1/artifact/0" },
+                        "trov:hasLocation": "input.csv",
+                    }],
+                },
+                { "@id": "arrangement/1", "rdfs:comment": "Final arrangement" },
+            ],
+            "trov:hasPerformance": {
+                "@id": "trp/0",
+                "rdfs:comment": "Run magic",
+                "trov:accessedArrangement": { "@id": "arrangement/0" },
+                "trov:contributedToArrangement": {
+                    "@id": "trp/0/binding_0",
+                    "trov:arrangement": { "@id": "arrangement/1" },
+                },
+            },
+        }],
+    });
+    fs::write(&declaration, tro.to_string()).unwrap();
+    let ro = cwlprov::default_dir(&declaration);
+    cwlprov::export(&declaration, &ro).unwrap();
+
+    assert!(ro.join("data/tro-42.jsonld").exists());
+    let manifest = fs::read_to_string(ro.join("manifest-sha256.txt")).unwrap();
+    assert!(manifest.ends_with("  data/tro-42.jsonld\n"));
+    let provenance: Value = serde_json::from_slice(
+        &fs::read(ro.join("metadata/provenance/primary.cwlprov.json")).unwrap(),
+    )
+    .unwrap();
+    assert!(provenance["entity"]["data:ab12"].is_object());
+    assert!(provenance["activity"]["tro:trp/0"].is_object());
+    let used: Vec<&Value> = provenance["used"].as_object().unwrap().values().collect();
+    assert_eq!(used[0]["prov:entity"], "tro:arrangement/0");
+    let generated: Vec<&Value> = provenance["wasGeneratedBy"]
+        .as_object()
+        .unwrap()
+        .values()
+        .collect();
+    assert_eq!(generated[0]["prov:entity"], "tro:arrangement/1");
+    // an existing research object is left alone
+    assert!(cwlprov::export(&declaration, &ro).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
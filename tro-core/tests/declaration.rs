@@ -0,0 +1,43 @@
+use serde_json::json;
+use std::fs;
+
+use tro_core::declaration::Declaration;
+use tro_core::job::Job;
+
+#[test]
+fn the_jobs_performance_is_annotated_rather_than_the_latest() {
+    let dir = std::env::temp_dir().join(format!("declaration-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut job = Job::new(42, (0, 0), "root".into(), dir.clone(), None, None);
+    let performances = json!([{ "@id": "performance/0" }, { "@id": "performance/1" }]);
+    let tro = json!({ "@graph": [{ "trov:hasPerformance": performances }] });
+    fs::write(&job.declaration, tro.to_string()).unwrap();
+
+    // another step added performance/1 since
+    job.performance = "performance/0".to_string();
+    let mut declaration = Declaration::of(&job).unwrap();
+    declaration.annotate_performance("exit", json!(0)).unwrap();
+    declaration.save().unwrap();
+    drop(declaration);
+    let content: serde_json::Value =
+        serde_json::from_slice(&fs::read(&job.declaration).unwrap()).unwrap();
+    let performances = &content["@graph"][0]["trov:hasPerformance"];
+    assert_eq!(performances[0]["spank:exit"], 0);
+    assert!(performances[1].get("spank:exit").is_none());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reading_does_not_lock() {
+    let dir = std::env::temp_dir().join(format!("declaration-read-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("tro.jsonld");
+    fs::write(&path, json!({ "@graph": [{}] }).to_string()).unwrap();
+    // e.g. spank-tro compare of a TRO with itself
+    let (_left, _right) = (
+        Declaration::load(&path).unwrap(),
+        Declaration::load(&path).unwrap(),
+    );
+    assert!(!dir.join("tro.jsonld.lock").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,27 @@
+use tro_core::diff::Diff;
+
+fn locations(files: &[(&str, &str)]) -> Vec<(String, String)> {
+    files
+        .iter()
+        .map(|(location, sha256)| (location.to_string(), sha256.to_string()))
+        .collect()
+}
+
+#[test]
+fn files_are_added_removed_and_modified() {
+    let initial = locations(&[("run.sh", "aa"), ("data.csv", "bb"), ("tmp.txt", "cc")]);
+    let last = locations(&[
+        ("run.sh", "aa"),
+        ("data.csv", "dd"),
+        ("results/out.csv", "ee"),
+        ("log.txt", "ff"),
+    ]);
+    let diff = Diff::between(&initial, &last);
+    assert_eq!(diff.added, ["log.txt", "results/out.csv"]);
+    assert_eq!(diff.removed, ["tmp.txt"]);
+    assert_eq!(diff.modified, ["data.csv"]);
+    assert_eq!(
+        diff.to_json("arrangement/0")["spank:added"][1],
+        "results/out.csv"
+    );
+}
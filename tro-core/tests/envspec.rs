@@ -0,0 +1,73 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use tro_core::config::Config;
+use tro_core::context::{Context, MockContext};
+use tro_core::envspec::{Environment, Kind};
+use tro_core::job::Job;
+
+#[test]
+fn environments_are_detected_from_the_job_environment() {
+    let ctx = MockContext::new(Context::Remote)
+        .with_env("CONDA_PREFIX", "/home/user/miniconda3/envs/analysis")
+        .with_env("CONDA_EXE", "/home/user/miniconda3/bin/conda")
+        .with_env("VIRTUAL_ENV", "/home/user/project/.venv");
+    let environments = Environment::detect(&ctx).unwrap();
+    assert_eq!(environments.len(), 2);
+    assert_eq!(environments[0].kind, Kind::Conda);
+    assert_eq!(
+        environments[0].conda,
+        Some(PathBuf::from("/home/user/miniconda3/bin/conda"))
+    );
+    assert_eq!(environments[1].kind, Kind::Virtualenv);
+    let ctx = MockContext::new(Context::Remote).with_env("CONDA_PREFIX", "");
+    assert!(Environment::detect(&ctx).unwrap().is_empty());
+}
+
+#[test]
+fn a_virtualenv_is_specified_by_pip_freeze() {
+    let dir = std::env::temp_dir().join(format!("envspec-{}", std::process::id()));
+    let bin = dir.join("venv/bin");
+    fs::create_dir_all(&bin).unwrap();
+    // stands for the virtualenv's python
+    fs::write(
+        bin.join("python"),
+        "#!/bin/sh\necho \"$*\"\necho numpy==1.26.4\n",
+    )
+    .unwrap();
+    fs::set_permissions(bin.join("python"), fs::Permissions::from_mode(0o755)).unwrap();
+    let settings = Config::from_args(["capture_environment=yes"])
+        .unwrap()
+        .settings;
+    let job = Job::new(42, (0, 0), "root".into(), dir.clone(), None, None);
+    let environment = Environment {
+        kind: Kind::Virtualenv,
+        prefix: dir.join("venv"),
+        conda: None,
+        loaded: Vec::new(),
+    };
+    let specification = environment.specification(&settings, &job).unwrap();
+    assert_eq!(specification, "-m pip freeze --all\nnumpy==1.26.4\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn spack_environments_come_with_their_loaded_packages() {
+    let ctx = MockContext::new(Context::Remote)
+        .with_env("SPACK_ENV", "/home/user/project")
+        .with_env("SPACK_LOADED_HASHES", "abcdefg:hijklmn");
+    let environments = Environment::detect(&ctx).unwrap();
+    assert_eq!(environments[0].kind, Kind::Spack);
+    assert_eq!(environments[0].prefix, PathBuf::from("/home/user/project"));
+    assert_eq!(environments[0].loaded, ["abcdefg", "hijklmn"]);
+
+    // without an environment, what was loaded comes from the Spack instance
+    let ctx = MockContext::new(Context::Remote)
+        .with_env("SPACK_ROOT", "/opt/spack")
+        .with_env("SPACK_LOADED_HASHES", "abcdefg");
+    let environments = Environment::detect(&ctx).unwrap();
+    assert_eq!(environments[0].prefix, PathBuf::from("/opt/spack"));
+    let ctx = MockContext::new(Context::Remote).with_env("SPACK_ROOT", "/opt/spack");
+    assert!(Environment::detect(&ctx).unwrap().is_empty());
+}
@@ -0,0 +1,29 @@
+use std::path::{Path, PathBuf};
+
+use tro_core::filesystems::{mount_of, parse_mountinfo};
+
+const MOUNTINFO: &str = "\
+22 1 253:0 / / rw,relatime shared:1 - xfs /dev/mapper/root rw,attr2
+40 22 0:50 / /scratch rw,nosuid shared:20 - lustre 10.0.0.1@o2ib:/scratch rw,flock,lazystatfs
+41 22 0:51 / /home rw,relatime shared:21 - nfs4 nas:/export/home rw,vers=4.2
+42 41 0:52 / /home/my\\040projects rw,relatime shared:22 - gpfs projects rw
+43 22 0:53 / /scratch rw,nosuid shared:23 - lustre 10.0.0.2@o2ib:/scratch2 rw,flock
+";
+
+#[test]
+fn paths_are_on_the_deepest_mount_above_them() {
+    let mounts = parse_mountinfo(MOUNTINFO);
+    assert_eq!(mounts.len(), 5);
+    let mount = |path: &str| mount_of(&mounts, Path::new(path)).unwrap();
+    let scratch = mount("/scratch/user/run");
+    assert_eq!(scratch.fstype, "lustre");
+    // mounted over the first one
+    assert_eq!(scratch.source, "10.0.0.2@o2ib:/scratch2");
+    assert_eq!(scratch.options, "rw,nosuid");
+    assert_eq!(scratch.super_options, "rw,flock");
+    assert_eq!(mount("/home/user").fstype, "nfs4");
+    let projects = mount("/home/my projects/a");
+    assert_eq!(projects.mount_point, PathBuf::from("/home/my projects"));
+    assert_eq!(projects.fstype, "gpfs");
+    assert_eq!(mount("/scratchpad").fstype, "xfs");
+}
@@ -0,0 +1,28 @@
+use tro_core::config::Config;
+use tro_core::generator::{self, parse_xalt_version, slurm_version};
+
+#[test]
+fn versions() {
+    assert_eq!(slurm_version((23 << 16) | (2 << 8) | 7), "23.02.7");
+    let report = "\
+*------------------------------------------------------------------------------*
+                      XALT Configuration Report
+*------------------------------------------------------------------------------*
+
+Today's DATE:                    Fri Oct 16 10:00:00 2026
+XALT_VERSION:                    3.0.2
+XALT_GIT_VERSION:                @git@
+";
+    assert_eq!(parse_xalt_version(report).as_deref(), Some("3.0.2"));
+    assert_eq!(parse_xalt_version(""), None);
+}
+
+#[test]
+fn the_generator_is_described() {
+    let mut config = Config::from_args(["tro_utils=/nonexistent/tro-utils"]).unwrap();
+    config.slurm_version = Some((24 << 16) | (11 << 8));
+    let described = generator::describe(&config);
+    assert_eq!(described["spank:version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(described["spank:slurmVersion"], "24.11.0");
+    assert!(described["spank:troUtilsVersion"].is_null());
+}
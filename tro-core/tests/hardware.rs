@@ -0,0 +1,50 @@
+use std::fs;
+
+use tro_core::hardware;
+
+#[test]
+fn physical_cores_are_counted_across_sockets() {
+    let thread = |socket: u32, core: u32| {
+        format!("processor\t: 0\nmodel name\t: AMD EPYC 7763 64-Core Processor\nphysical id\t: {socket}\ncore id\t\t: {core}\n\n")
+    };
+    let cpuinfo: String = [(0, 0), (0, 0), (0, 1), (1, 0), (1, 1)]
+        .into_iter()
+        .map(|(socket, core)| thread(socket, core))
+        .collect();
+    let (model, sockets, cores) = hardware::cpu(&cpuinfo);
+    assert_eq!(model.as_deref(), Some("AMD EPYC 7763 64-Core Processor"));
+    assert_eq!((sockets, cores), (Some(2), Some(4)));
+    assert_eq!(hardware::cpu("processor\t: 0\n"), (None, None, None));
+}
+
+#[test]
+fn interconnects_are_told_by_their_devices() {
+    let sys = std::env::temp_dir().join(format!("hardware-{}", std::process::id()));
+    let port = sys.join("infiniband/mlx5_0/ports/1");
+    fs::create_dir_all(&port).unwrap();
+    fs::write(port.join("link_layer"), "InfiniBand\n").unwrap();
+    fs::write(port.join("rate"), "200 Gb/sec (4X HDR)\n").unwrap();
+    let port = sys.join("infiniband/mlx5_1/ports/1");
+    fs::create_dir_all(&port).unwrap();
+    fs::write(port.join("link_layer"), "Ethernet\n").unwrap();
+    fs::create_dir_all(sys.join("cxi/cxi0")).unwrap();
+
+    let interconnects = hardware::interconnects(&sys);
+    let kinds: Vec<(&str, &str)> = interconnects
+        .iter()
+        .map(|interconnect| (interconnect.device.as_str(), interconnect.kind.as_str()))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            ("mlx5_0", "InfiniBand"),
+            ("mlx5_1", "RoCE"),
+            ("cxi0", "Slingshot")
+        ]
+    );
+    assert_eq!(
+        interconnects[0].rate.as_deref(),
+        Some("200 Gb/sec (4X HDR)")
+    );
+    fs::remove_dir_all(&sys).unwrap();
+}
@@ -0,0 +1,46 @@
+use eyre::eyre;
+use serde_json::json;
+use std::fs;
+
+use tro_core::config::Config;
+use tro_core::declaration::Declaration;
+use tro_core::incident;
+use tro_core::job::Job;
+use tro_core::phase::Phase;
+
+#[test]
+fn warned_failures_end_up_in_the_tro() {
+    let dir = std::env::temp_dir().join(format!("incident-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // uid 0 is not switched to
+    let mut job = Job::new(42, (0, 0), "root".into(), dir.clone(), None, None);
+    job.declaration = dir.join("tro-42.jsonld");
+    let tro = json!({ "@graph": [{ "spank:incidents": [{ "spank:phase": "sign" }] }] });
+    fs::write(&job.declaration, tro.to_string()).unwrap();
+
+    let config = Config::from_args(["fail_on=sign"]).unwrap();
+    let settings = &config.settings;
+    let missing = incident::check(
+        settings,
+        &job,
+        Phase::Trace,
+        Err::<(), _>(eyre!("no trace")),
+    );
+    assert_eq!(missing.unwrap(), None);
+    assert!(incident::check(settings, &job, Phase::Sign, Err::<(), _>(eyre!("no key"))).is_err());
+    assert!(incident::check(settings, &job, Phase::Performance, Ok(())).is_ok());
+    let incidents = incident::load(&job).unwrap();
+    assert_eq!(incidents.len(), 1);
+    assert_eq!(incidents[0].phase, Phase::Trace);
+
+    incident::annotate(settings, &job).unwrap();
+    assert!(!incident::incidents_path(&job).exists());
+    let declaration = Declaration::load(&job.declaration).unwrap();
+    let recorded = declaration.tro_term("incidents").unwrap();
+    // after those of the previous attempt
+    assert_eq!(recorded[0]["spank:phase"], "sign");
+    assert_eq!(recorded[1]["spank:phase"], "trace");
+    assert_eq!(recorded[1]["spank:error"], "no trace");
+    drop(declaration);
+    fs::remove_dir_all(&dir).unwrap();
+}
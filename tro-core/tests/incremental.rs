@@ -0,0 +1,81 @@
+use serde_json::{json, Value};
+use std::fs;
+
+use tro_core::declaration::Declaration;
+use tro_core::incremental;
+use tro_core::job::Job;
+
+#[test]
+fn unchanged_files_are_carried_over() {
+    let dir = std::env::temp_dir().join(format!("incremental-{}", std::process::id()));
+    let workdir = dir.join("run");
+    fs::create_dir_all(&workdir).unwrap();
+    fs::write(workdir.join("input.csv"), "1,2\n").unwrap();
+    fs::write(workdir.join("output.csv"), "").unwrap();
+    // uid 0 is not switched to
+    let mut job = Job::new(42, (0, 0), "root".into(), workdir.clone(), None, None);
+    job.declaration = dir.join("tro-42.jsonld");
+    job.initial_arrangement = "arrangement/0".to_string();
+    incremental::snapshot(&job).unwrap();
+
+    let sha256 = tro_core::digest::sha256_file(&workdir.join("input.csv")).unwrap();
+    let artifact =
+        |i: usize| json!({ "@id": format!("composition/1/artifact/{i}"), "trov:sha256": sha256 });
+    let locus = |i: usize, location: &str| {
+        json!({
+            "@id": format!("arrangement/0/location/{i}"),
+            "trov:hasArtifact": { "@id": format!("composition/1/artifact/{i}") },
+            "trov:hasLocation": location,
+        })
+    };
+    let tro = json!({
+        "@graph": [{
+            "trov:hasComposition": { "trov:hasArtifact": [artifact(0), artifact(1)] },
+            "trov:hasArrangement": [{
+                "@id": "arrangement/0",
+                "@type": "trov:ArtifactArrangement",
+                "trov:hasLocus": [locus(0, "input.csv"), locus(1, "output.csv")],
+            }],
+        }],
+    });
+    fs::write(&job.declaration, tro.to_string()).unwrap();
+
+    fs::write(workdir.join("output.csv"), "3\n").unwrap();
+    let id = incremental::final_arrangement(
+        &mut Declaration::of(&job).unwrap(),
+        &job,
+        "Final arrangement",
+    )
+    .unwrap();
+    assert_eq!(id.as_deref(), Some("arrangement/1"));
+
+    let mut declaration = Declaration::load(&job.declaration).unwrap();
+    let tro = declaration.tro().unwrap();
+    let last = &tro["trov:hasArrangement"][1];
+    assert_eq!(last["rdfs:comment"], "Final arrangement");
+    assert_eq!(last["spank:incremental"]["spank:carriedOver"], 1);
+    let recomputed = last["spank:incremental"]["spank:recomputed"]
+        .as_array()
+        .unwrap();
+    assert!(recomputed.contains(&Value::from("output.csv")));
+    assert!(!recomputed.contains(&Value::from("input.csv")));
+    let loci = last["trov:hasLocus"].as_array().unwrap();
+    let input = loci
+        .iter()
+        .find(|locus| locus["trov:hasLocation"] == "input.csv")
+        .unwrap();
+    assert_eq!(input["trov:hasArtifact"]["@id"], "composition/1/artifact/0");
+    assert!(input["@id"]
+        .as_str()
+        .unwrap()
+        .starts_with("arrangement/1/location/"));
+    drop(declaration);
+
+    // without a snapshot, tro-utils does it all
+    fs::remove_file(incremental::stats_path(&job)).unwrap();
+    assert_eq!(
+        incremental::final_arrangement(&mut Declaration::of(&job).unwrap(), &job, "Again").unwrap(),
+        None
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
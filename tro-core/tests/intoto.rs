@@ -0,0 +1,61 @@
+use serde_json::{json, Value};
+use std::fs;
+
+use tro_core::intoto;
+
+#[test]
+fn materials_are_the_initial_arrangement_and_products_the_final_one() {
+    let dir = std::env::temp_dir().join(format!("intoto-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let declaration = dir.join("tro-1.jsonld");
+    let location = |artifact: &str, location: &str| {
+        json!({
+            "trov:hasArtifact": { "@id": artifact },
+            "trov:hasLocation": location,
+        })
+    };
+    let tro = json!({
+        "@graph": [{
+            "trov:hasComposition": {
+                "trov:hasArtifact": [
+                    { "@id": "composition/1/artifact/0", "trov:sha256": "aa" },
+                    { "@id": "composition/1/artifact/1", "trov:sha256": "bb" },
+                ],
+            },
+            "trov:hasArrangement": [
+                {
+                    "@id": "arrangement/0",
+                    "trov:hasArtifactLocation": [location("composition/1/artifact/0", "run.sh")],
+                },
+                {
+                    "@id": "arrangement/1",
+                    "trov:hasArtifactLocation": [
+                        location("composition/1/artifact/0", "run.sh"),
+                        location("composition/1/artifact/1", "out.csv"),
+                    ],
+                },
+            ],
+            "trov:hasPerformance": [{
+                "@id": "trp/0",
+                "spank:commands": [["/bin/bash", "run.sh"]],
+            }],
+        }],
+    });
+    fs::write(&declaration, tro.to_string()).unwrap();
+
+    let path = intoto::default_path(&declaration);
+    assert_eq!(path, dir.join("tro-1.intoto.json"));
+    intoto::export(&declaration, &path).unwrap();
+    let statement: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    assert_eq!(statement["_type"], intoto::STATEMENT_TYPE);
+    assert_eq!(statement["subject"][1]["name"], "out.csv");
+    assert_eq!(statement["subject"][1]["digest"]["sha256"], "bb");
+    let predicate = &statement["predicate"];
+    assert_eq!(
+        predicate["materials"],
+        json!([{ "name": "run.sh", "digest": { "sha256": "aa" } }])
+    );
+    assert_eq!(predicate["command"], json!(["/bin/bash", "run.sh"]));
+    assert_eq!(predicate["byproducts"]["tro"]["name"], "tro-1.jsonld");
+    fs::remove_dir_all(&dir).unwrap();
+}
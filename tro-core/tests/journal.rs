@@ -0,0 +1,74 @@
+use eyre::eyre;
+use serde_json::json;
+use std::fs::{self, File};
+use std::path::Path;
+
+use tro_core::job::Job;
+use tro_core::journal::{journal_path, Journal};
+use tro_core::spool;
+
+fn write_tro(path: &Path, arrangements: usize) {
+    let arrangements: Vec<_> = (0..arrangements)
+        .map(|i| json!({ "@id": format!("arrangement/{i}") }))
+        .collect();
+    let tro = json!({ "@graph": [{ "trov:hasArrangement": arrangements }] });
+    fs::write(path, tro.to_string()).unwrap();
+}
+
+#[test]
+fn an_interrupted_finalization_does_not_add_twice() {
+    let dir = std::env::temp_dir().join(format!("journal-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // uid 0 is not switched to
+    let mut job = Job::new(42, (0, 0), "root".into(), dir.clone(), None, None);
+    job.declaration = dir.join("tro-42.jsonld");
+    write_tro(&job.declaration, 1);
+    let path = journal_path(&dir, &job.key());
+
+    // the finalizer dies once tro-utils added the arrangement
+    let mut journal = Journal::open(&path).unwrap();
+    assert!(!journal.resumed());
+    let result = journal.add_arrangement(&job, || {
+        write_tro(&job.declaration, 2);
+        Err(eyre!("killed"))
+    });
+    assert!(result.is_err());
+
+    let mut journal = Journal::open(&path).unwrap();
+    assert!(journal.resumed());
+    let id = journal
+        .add_arrangement(&job, || panic!("added twice"))
+        .unwrap();
+    assert_eq!(id, "arrangement/1");
+    journal.set_final_arrangement(&id).unwrap();
+    assert_eq!(
+        Journal::open(&path).unwrap().final_arrangement(),
+        Some("arrangement/1")
+    );
+
+    journal.remove().unwrap();
+    assert!(!path.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn specs_of_dead_finalizers_are_requeued() {
+    let dir = std::env::temp_dir().join(format!("journal-spool-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("1.json.running"), "{}").unwrap();
+    fs::write(dir.join("2.json.running"), "{}").unwrap();
+    // a finalizer still works on job 2
+    let claimed = File::open(dir.join("2.json.running")).unwrap();
+    claimed.lock().unwrap();
+
+    let requeued = spool::requeue_abandoned(&dir).unwrap();
+    assert_eq!(requeued, [dir.join("1.json")]);
+    assert!(dir.join("2.json.running").exists());
+    assert!(spool::requeue(&dir.join("2.json.running")).is_err());
+    drop(claimed);
+    assert_eq!(
+        spool::requeue(&dir.join("2.json.running")).unwrap(),
+        dir.join("2.json")
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,68 @@
+use serde_json::json;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use tro_core::config::Config;
+use tro_core::libraries::{self, ReverseMap};
+use tro_core::tracker::{Library, Trace};
+
+#[test]
+fn the_deepest_module_directory_provides_a_library() {
+    let map = ReverseMap::from_json(&json!({ "reverseMapT": {
+        "/opt/apps/gcc/12": "gcc/12",
+        "/opt/apps/gcc/12/openblas/0.3.21": "openblas/0.3.21",
+        "/opt/apps/gcc/12/mkl": { "pkg": "mkl/2023" },
+    }}));
+    let module = |path: &str| map.module(Path::new(path));
+    assert_eq!(
+        module("/opt/apps/gcc/12/openblas/0.3.21/lib/libopenblas.so.0"),
+        Some("openblas/0.3.21")
+    );
+    assert_eq!(
+        module("/opt/apps/gcc/12/mkl/lib/libmkl_rt.so"),
+        Some("mkl/2023")
+    );
+    assert_eq!(
+        module("/opt/apps/gcc/12/lib64/libgfortran.so.5"),
+        Some("gcc/12")
+    );
+    assert_eq!(module("/usr/lib64/libc.so.6"), None);
+}
+
+#[test]
+fn libraries_are_resolved_to_their_module_and_build() {
+    let dir = std::env::temp_dir().join(format!("libraries-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let spack = dir.join("spack");
+    fs::write(
+        &spack,
+        "#!/bin/sh\necho 'openblas 0.3.21 h4sh /opt/spack/openblas-0.3.21-h4sh'\n",
+    )
+    .unwrap();
+    fs::set_permissions(&spack, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut settings = Config::from_args(["library_provenance=yes"])
+        .unwrap()
+        .settings;
+    settings.spack = spack;
+    settings.rpm = dir.join("missing");
+    settings.dpkg_query = dir.join("missing");
+
+    let library = Library {
+        path: PathBuf::from("/opt/spack/openblas-0.3.21-h4sh/lib/libopenblas.so.0"),
+        xalt_hash: Some("x4lt".to_string()),
+    };
+    let trace = Trace {
+        libraries: vec![library.clone(), library],
+        ..Trace::default()
+    };
+    let modules =
+        ReverseMap::from_json(&json!({ "/opt/spack/openblas-0.3.21-h4sh": "openblas/0.3.21" }));
+    let resolved = libraries::resolve(&settings, &trace, &modules);
+    assert_eq!(resolved.len(), 1);
+    assert_eq!(resolved[0]["spank:module"], "openblas/0.3.21");
+    assert_eq!(resolved[0]["spank:package"], "pkg:spack/openblas@0.3.21");
+    assert_eq!(resolved[0]["spank:buildHash"], "h4sh");
+    assert_eq!(resolved[0]["spank:xaltHash"], "x4lt");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,39 @@
+use std::fs;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use tro_core::catalog::hostname;
+use tro_core::lockfile::LockFile;
+
+#[test]
+fn a_lock_is_exclusive_until_dropped() {
+    let dir = std::env::temp_dir().join(format!("lockfile-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("tro.jsonld.lock");
+
+    let lock = LockFile::acquire(&path).unwrap();
+    let (sender, receiver) = mpsc::channel();
+    let waiter = {
+        let path = path.clone();
+        thread::spawn(move || {
+            let _lock = LockFile::acquire(&path).unwrap();
+            sender.send(()).unwrap();
+        })
+    };
+    assert!(receiver.recv_timeout(Duration::from_millis(500)).is_err());
+    drop(lock);
+    receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    waiter.join().unwrap();
+    assert!(!path.exists());
+
+    // left by a process of this node that is gone
+    let holder = format!("{} {} 0", hostname().unwrap_or_default(), i32::MAX);
+    fs::write(&path, holder).unwrap();
+    let lock = LockFile::acquire(&path).unwrap();
+    assert!(fs::read_to_string(&path)
+        .unwrap()
+        .contains(&std::process::id().to_string()));
+    drop(lock);
+    fs::remove_dir_all(&dir).unwrap();
+}
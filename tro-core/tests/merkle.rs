@@ -0,0 +1,64 @@
+use serde_json::json;
+use std::fs;
+
+use tro_core::declaration::Declaration;
+use tro_core::digest;
+use tro_core::merkle;
+
+fn locations(files: &[(&str, &str)]) -> Vec<(String, String)> {
+    files
+        .iter()
+        .map(|(location, sha256)| (location.to_string(), sha256.to_string()))
+        .collect()
+}
+
+#[test]
+fn directories_roll_up_to_the_root() {
+    let digests = merkle::directories(&locations(&[
+        ("run.sh", "aa"),
+        ("results/run1/out.csv", "bb"),
+        ("results/run1/log.txt", "cc"),
+    ]));
+    let dirs: Vec<&str> = digests.keys().map(String::as_str).collect();
+    assert_eq!(dirs, [".", "results", "results/run1"]);
+    let run1 = digest::sha256_bytes(b"cc  log.txt\nbb  out.csv\n");
+    assert_eq!(digests["results/run1"], run1);
+    let results = digest::sha256_bytes(format!("{run1}  run1/\n").as_bytes());
+    assert_eq!(digests["results"], results);
+    let root = digest::sha256_bytes(format!("{results}  results/\naa  run.sh\n").as_bytes());
+    assert_eq!(digests["."], root);
+}
+
+#[test]
+fn a_subdirectory_is_checked_on_its_own() {
+    let dir = std::env::temp_dir().join(format!("merkle-{}", std::process::id()));
+    fs::create_dir_all(dir.join("results")).unwrap();
+    fs::write(dir.join("results").join("out.csv"), "1,2\n").unwrap();
+    let sha256 = digest::sha256_file(&dir.join("results").join("out.csv")).unwrap();
+    let declaration = dir.join("tro-42.jsonld");
+    let tro = json!({
+        "@graph": [{
+            "trov:hasComposition": {
+                "trov:hasArtifact": [{ "@id": "composition/1/artifact/0", "trov:sha256": sha256 }],
+            },
+            "trov:hasArrangement": [{
+                "@id": "arrangement/0",
+                "trov:hasArtifactLocation": [{
+                    "trov:hasArtifact": { "@id": "composition/1/artifact/0" },
+                    "trov:hasLocation": "results/out.csv",
+                }],
+            }],
+        }],
+    });
+    fs::write(&declaration, tro.to_string()).unwrap();
+
+    let mut loaded = Declaration::load(&declaration).unwrap();
+    assert!(merkle::verify_dir(&loaded, &dir, "results").is_err());
+    let tree = merkle::describe(&loaded);
+    loaded.annotate_arrangement("merkle", tree).unwrap();
+    merkle::verify_dir(&loaded, &dir, "results").unwrap();
+    merkle::verify_dir(&loaded, &dir, "results/").unwrap();
+    assert!(merkle::verify_dir(&loaded, &dir, "missing").is_err());
+    drop(loaded);
+    fs::remove_dir_all(&dir).unwrap();
+}
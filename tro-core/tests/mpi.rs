@@ -0,0 +1,35 @@
+use serde_json::json;
+
+use tro_core::mpi;
+
+#[test]
+fn the_records_of_each_rank_are_summarized_by_program() {
+    let record = |path: &str, run_time: f64| {
+        json!({
+            "userT": { "exec_path": path, "num_tasks": "4" },
+            "userDT": { "start_time": 100.0, "end_time": 100.0 + run_time, "run_time": run_time },
+            "libA": [["/opt/openmpi/4.1.6/lib/libmpi.so.40", "abc"]],
+            "envT": { "SLURM_PROCID": "0" },
+        })
+    };
+    let records = [
+        record("/opt/bin/solver", 10.0),
+        record("/opt/bin/solver", 20.0),
+        record("/opt/bin/post", 1.0),
+    ];
+    let summary = mpi::summarize(&records).unwrap();
+    assert_eq!(summary.ranks, 4);
+    assert_eq!(summary.programs.len(), 2);
+    let solver = &summary.programs[0];
+    assert_eq!((solver.records, solver.ranks), (2, 4));
+    assert_eq!(
+        (solver.min_runtime, solver.max_runtime, solver.mean_runtime),
+        (10.0, 20.0, 15.0)
+    );
+    assert_eq!(summary.implementation, Some("Open MPI"));
+    assert_eq!(summary.launcher, Some("srun"));
+
+    // serial programs are no MPI job
+    let serial = json!({ "userT": { "exec_path": "/bin/true" }, "userDT": { "num_tasks": 1 } });
+    assert!(mpi::summarize(&[serial]).is_none());
+}
@@ -0,0 +1,86 @@
+use serde_json::json;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use tro_core::config::Config;
+use tro_core::declaration::Declaration;
+use tro_core::digest;
+use tro_core::job::Job;
+use tro_core::nodes::{self, NodeEvidence};
+use tro_core::xalt;
+
+#[test]
+fn the_head_node_merges_the_evidence_of_every_node() {
+    let dir = std::env::temp_dir().join(format!("nodes-{}", std::process::id()));
+    let submit_dir = dir.join("submit");
+    fs::create_dir_all(&submit_dir).unwrap();
+    // uid 0 is not switched to
+    let mut head = Job::new(42, (0, 0), "root".into(), submit_dir.clone(), None, None);
+    head.node_count = 2;
+    let tro = json!({ "@graph": [{ "trov:hasPerformance": [{ "@id": "trp/0" }] }] });
+    fs::write(&head.declaration, tro.to_string()).unwrap();
+    let settings = &Config::from_args(Vec::<&str>::new()).unwrap().settings;
+
+    // the other node runs in a node-local directory, its XALT records with it
+    let mut other = head.clone();
+    other.node_id = 1;
+    other.workdir = dir.join("local");
+    let record_dir = xalt::record_dir(&other);
+    fs::create_dir_all(&record_dir).unwrap();
+    let record = json!({ "userT": { "exec_path": "/opt/bin/solver" } });
+    fs::write(record_dir.join("run.json"), record.to_string()).unwrap();
+    let evidence = NodeEvidence {
+        node: "node2".into(),
+        node_id: 1,
+        cpus: Some(64),
+        ..NodeEvidence::default()
+    };
+    nodes::deposit(settings, &other, evidence).unwrap();
+    let evidence = NodeEvidence {
+        node: "node1".into(),
+        ..nodes::collect(&head)
+    };
+    nodes::deposit(settings, &head, evidence).unwrap();
+    assert_eq!(nodes::load(&head).unwrap().len(), 2);
+
+    nodes::merge(settings, &head).unwrap();
+    assert!(!nodes::evidence_dir(&head).exists());
+    let declaration = Declaration::load(&head.declaration).unwrap();
+    let merged = declaration.last_performance_term("nodes").unwrap();
+    assert_eq!(merged[0]["spank:hostname"], "node1");
+    assert_eq!(merged[1]["spank:hostname"], "node2");
+    assert_eq!(merged[1]["spank:cpus"], 64);
+    assert_eq!(merged[1]["spank:executables"], json!(["/opt/bin/solver"]));
+    drop(declaration);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn evidence_is_attested_with_munge() {
+    let dir = std::env::temp_dir().join(format!("nodes-munge-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let job = Job::new(42, (0, 0), "root".into(), dir.clone(), None, None);
+    // stands for munge, whose credentials carry their payload
+    let munge = dir.join("munge");
+    fs::write(&munge, "#!/bin/sh\nprintf 'MUNGE:%s:' \"$(cat)\"\n").unwrap();
+    fs::set_permissions(&munge, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut config = Config::from_args(["munge_attestation=yes"]).unwrap();
+    config.settings.munge = munge;
+    let settings = &config.settings;
+    assert!(nodes::needed(settings, &job));
+
+    nodes::deposit(settings, &job, nodes::collect(&job)).unwrap();
+    let evidence = nodes::load(&job).unwrap().remove(0);
+    let attestation = evidence.attestation.clone().unwrap();
+    let payload = serde_json::to_vec(&NodeEvidence {
+        attestation: None,
+        ..evidence
+    })
+    .unwrap();
+    assert_eq!(attestation.payload_sha256, digest::sha256_bytes(&payload));
+    assert_eq!(
+        attestation.credential,
+        format!("MUNGE:{}:", attestation.payload_sha256)
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
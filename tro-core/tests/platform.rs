@@ -0,0 +1,26 @@
+use tro_core::platform::{microcode, os_release_field, Platform};
+
+#[test]
+fn os_release_fields_are_unquoted() {
+    let os_release = "NAME=\"Rocky Linux\"\nVERSION_ID=\"9.4\"\nID=rocky\nPRETTY_NAME=\"Rocky Linux 9.4 (Blue Onyx)\"\n";
+    assert_eq!(
+        os_release_field(os_release, "PRETTY_NAME").as_deref(),
+        Some("Rocky Linux 9.4 (Blue Onyx)")
+    );
+    assert_eq!(os_release_field(os_release, "ID").as_deref(), Some("rocky"));
+    assert_eq!(
+        os_release_field(os_release, "VERSION_ID").as_deref(),
+        Some("9.4")
+    );
+    assert_eq!(os_release_field(os_release, "VERSION"), None);
+}
+
+#[test]
+fn the_platform_of_this_node() {
+    let cpuinfo =
+        "processor\t: 0\nmicrocode\t: 0xd0003d1\n\nprocessor\t: 1\nmicrocode\t: 0xd0003d1\n";
+    assert_eq!(microcode(cpuinfo).as_deref(), Some("0xd0003d1"));
+    let platform = Platform::collect();
+    assert!(platform.kernel.is_some());
+    assert!(platform.glibc.is_some());
+}
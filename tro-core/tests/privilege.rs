@@ -0,0 +1,54 @@
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+use tro_core::job::Job;
+use tro_core::privilege;
+
+#[test]
+fn files_are_accessed_as_the_user() {
+    // only root has someone else to be
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+    let dir = std::env::temp_dir().join(format!("privilege-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+    let user = privilege::as_owner((65534, 65534));
+    assert!(fs::write(dir.join("tro-1.jsonld"), "{}").is_err());
+    drop(user);
+    fs::write(dir.join("tro-1.jsonld"), "{}").unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn symlinked_destinations_are_refused() {
+    let dir = std::env::temp_dir().join(format!("privilege-symlink-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let target = dir.join("target");
+    fs::write(&target, "root's").unwrap();
+    let link = dir.join("tro-1.jsonld");
+    std::os::unix::fs::symlink(&target, &link).unwrap();
+    assert!(privilege::refuse_symlink(&link).is_err());
+    assert!(privilege::create_nofollow(&link).is_err());
+    assert!(privilege::refuse_symlink(&target).is_ok());
+    assert!(privilege::refuse_symlink(&dir.join("missing")).is_ok());
+    assert_eq!(fs::read_to_string(&target).unwrap(), "root's");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn tro_files_are_handed_over() {
+    let dir = std::env::temp_dir().join(format!("privilege-owner-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let declaration = dir.join("tro-1.jsonld");
+    fs::write(&declaration, "{}").unwrap();
+    fs::set_permissions(&declaration, fs::Permissions::from_mode(0o600)).unwrap();
+    let job = Job::new(42, (4242, 4343), "alice".into(), dir.clone(), None, None);
+    privilege::hand_over(&job, &[&declaration, &dir.join("tro-1.sig")]).unwrap();
+    let metadata = fs::metadata(&declaration).unwrap();
+    assert_eq!(metadata.mode() & 0o777, privilege::TRO_MODE);
+    if unsafe { libc::geteuid() } == 0 {
+        assert_eq!((metadata.uid(), metadata.gid()), (4242, 4343));
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
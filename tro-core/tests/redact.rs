@@ -0,0 +1,62 @@
+use tro_core::redact::{self, default_patterns, REDACTED};
+
+fn argv(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn secret_names() {
+    let patterns = default_patterns();
+    assert!(redact::is_secret(&patterns, "GITHUB_TOKEN"));
+    assert!(redact::is_secret(&patterns, "db-password"));
+    assert!(redact::is_secret(&patterns, "AWS_SECRET_ACCESS_KEY"));
+    assert!(!redact::is_secret(&patterns, "SLURM_JOB_ID"));
+    assert!(redact::is_secret(&["AWS_*".to_string()], "aws_region"));
+}
+
+#[test]
+fn command_lines() {
+    let patterns = default_patterns();
+    let redacted = redact::argv(
+        &patterns,
+        &argv(&[
+            "env",
+            "API_TOKEN=abc",
+            "./run",
+            "--password",
+            "hunter2",
+            "--api-key=xyz",
+            "--steps",
+            "10",
+        ]),
+    );
+    assert_eq!(
+        redacted,
+        argv(&[
+            "env",
+            &format!("API_TOKEN={REDACTED}"),
+            "./run",
+            "--password",
+            REDACTED,
+            &format!("--api-key={REDACTED}"),
+            "--steps",
+            "10",
+        ])
+    );
+}
+
+#[test]
+fn scripts() {
+    let patterns = default_patterns();
+    let script = "#!/bin/bash\n\
+                  export HF_TOKEN=hf_123; echo ok\n\
+                  DB_PASSWORD='a b c' ./load\n\
+                  MY_TOKEN_COUNT=3\n\
+                  NSTEPS=10 ./run\n";
+    let redacted = redact::text(&patterns, script);
+    assert!(!redacted.contains("hf_123"));
+    assert!(!redacted.contains("a b c"));
+    assert!(redacted.contains(&format!("export HF_TOKEN={REDACTED}; echo ok")));
+    assert!(redacted.contains(&format!("DB_PASSWORD={REDACTED} ./load")));
+    assert!(redacted.contains("NSTEPS=10 ./run"));
+}
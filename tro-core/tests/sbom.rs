@@ -0,0 +1,66 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use tro_core::config::Config;
+use tro_core::sbom;
+use tro_core::tracker::{Executable, Library, Trace};
+
+#[test]
+fn files_are_listed_under_the_packages_that_installed_them() {
+    let dir = std::env::temp_dir().join(format!("sbom-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // stand for rpm, which owns what is under /usr, and Spack
+    let rpm = dir.join("rpm");
+    fs::write(
+        &rpm,
+        "#!/bin/sh\ncase \"$4\" in /usr/*) echo 'glibc 2.34-100.el9' ;; *) exit 1 ;; esac\n",
+    )
+    .unwrap();
+    let spack = dir.join("spack");
+    fs::write(
+        &spack,
+        "#!/bin/sh\necho 'openmpi 4.1.6 abcdefg /opt/spack/openmpi-4.1.6-abc'\n",
+    )
+    .unwrap();
+    for program in [&rpm, &spack] {
+        fs::set_permissions(program, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let mut settings = Config::from_args(["sbom=yes"]).unwrap().settings;
+    settings.rpm = rpm;
+    settings.spack = spack;
+    settings.dpkg_query = dir.join("missing");
+
+    let trace = Trace {
+        executables: vec![Executable {
+            path: PathBuf::from("/home/user/a.out"),
+            xalt_hash: None,
+        }],
+        libraries: [
+            "/usr/lib64/libc.so.6",
+            "/usr/lib64/libm.so.6",
+            "/opt/spack/openmpi-4.1.6-abc/lib/libmpi.so.40",
+        ]
+        .into_iter()
+        .map(|path| Library {
+            path: PathBuf::from(path),
+            xalt_hash: None,
+        })
+        .collect(),
+        ..Trace::default()
+    };
+    let entries = sbom::entries(&settings, &trace);
+    assert_eq!(entries.len(), 4);
+    assert!(entries[0].executable && entries[0].package.is_none());
+
+    let document = sbom::document(&entries);
+    assert_eq!(document["bomFormat"], "CycloneDX");
+    let components = document["components"].as_array().unwrap();
+    assert_eq!(components.len(), 3);
+    assert_eq!(components[0]["type"], "file");
+    assert_eq!(components[0]["name"], "/home/user/a.out");
+    assert_eq!(components[1]["purl"], "pkg:rpm/glibc@2.34-100.el9");
+    assert_eq!(components[1]["components"].as_array().unwrap().len(), 2);
+    assert_eq!(components[2]["purl"], "pkg:spack/openmpi@4.1.6");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,43 @@
+use serde_json::json;
+use std::fs;
+use tro_core::declaration::Declaration;
+use tro_core::schema::{self, VERSION};
+
+#[test]
+fn versions_are_read() {
+    assert_eq!(schema::version(&json!({"@graph": [{}]})).unwrap(), 1);
+    let versioned = json!({"@graph": [{"spank:schemaVersion": 7}]});
+    assert_eq!(schema::version(&versioned).unwrap(), 7);
+    let invalid = json!({"@graph": [{"spank:schemaVersion": "one"}]});
+    assert!(schema::version(&invalid).is_err());
+}
+
+#[test]
+fn older_declarations_are_upgraded_and_newer_ones_left_alone() {
+    let dir = std::env::temp_dir().join(format!("schema-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("tro.jsonld");
+
+    fs::write(
+        &path,
+        json!({"@context": [{}], "@graph": [{"@id": "tro"}]}).to_string(),
+    )
+    .unwrap();
+    let declaration = Declaration::load(&path).unwrap();
+    assert_eq!(declaration.schema_version(), 1);
+    declaration.save().unwrap();
+    drop(declaration);
+    let saved: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    assert_eq!(saved["@graph"][0]["spank:schemaVersion"], VERSION);
+
+    let newer = json!({"@graph": [{"@id": "tro", "spank:schemaVersion": VERSION + 1}]});
+    fs::write(&path, newer.to_string()).unwrap();
+    let declaration = Declaration::load(&path).unwrap();
+    assert_eq!(declaration.schema_version(), VERSION + 1);
+    assert!(declaration.save().is_err());
+    drop(declaration);
+    let kept: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    assert_eq!(kept, newer);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,205 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+use tro_core::secrets::PassphraseSource;
+use tro_core::settings::Settings;
+use tro_core::signing::{self, SigningKey, SiteKey};
+use tro_core::sigstore;
+
+fn settings(gpg_keys: &str) -> Settings {
+    let mut settings = Settings::default();
+    settings.set("gpg_fingerprint", "OLD").unwrap();
+    settings.set("gpg_keys", gpg_keys).unwrap();
+    settings
+}
+
+#[test]
+fn site_key_windows() {
+    let key: SiteKey = "ABCD:2025-01-01:2025-12-31".parse().unwrap();
+    assert!(key.is_valid_on("2025-01-01"));
+    assert!(key.is_valid_on("2025-12-31"));
+    assert!(!key.is_valid_on("2026-01-01"));
+    let key: SiteKey = "ABCD::2025-12-31".parse().unwrap();
+    assert!(key.is_valid_on("1970-01-01"));
+    assert!("ABCD:2025-13-01".parse::<SiteKey>().is_err());
+    assert!(":2025-01-01".parse::<SiteKey>().is_err());
+}
+
+#[test]
+fn the_newest_valid_key_signs() {
+    let key = SigningKey::site(&settings("")).unwrap();
+    assert_eq!(key.fingerprint, "OLD");
+    assert_eq!(key.expires, None);
+
+    let key = SigningKey::site(&settings(
+        "A:2000-01-01:2999-12-31,B:2001-01-01,C:2998-01-01",
+    ))
+    .unwrap();
+    assert_eq!(key.fingerprint, "B");
+    let key = SigningKey::site(&settings("A:2000-01-01:2999-12-31,C:2998-01-01")).unwrap();
+    assert_eq!(key.expires.as_deref(), Some("2999-12-31"));
+
+    assert!(SigningKey::site(&settings("A::2000-01-01")).is_err());
+}
+
+#[test]
+fn passphrase_sources() {
+    assert_eq!(
+        "config".parse::<PassphraseSource>().unwrap(),
+        PassphraseSource::Config
+    );
+    assert_eq!(
+        "vault:secret/tro#key".parse::<PassphraseSource>().unwrap(),
+        PassphraseSource::Vault {
+            path: "secret/tro".to_string(),
+            field: "key".to_string()
+        }
+    );
+    let source: PassphraseSource = "vault:secret/tro".parse().unwrap();
+    assert!(matches!(source, PassphraseSource::Vault { field, .. } if field == "passphrase"));
+    assert!("vault:".parse::<PassphraseSource>().is_err());
+    assert_eq!(
+        "keyring:tro-site".parse::<PassphraseSource>().unwrap(),
+        PassphraseSource::Keyring("tro-site".to_string())
+    );
+}
+
+#[test]
+fn unusable_keys() {
+    let sec = |validity: &str, capabilities: &str| {
+        format!("sec:{validity}:255:22:0123456789ABCDEF:1700000000:::u:::{capabilities}:::+:::23::0:\nfpr:::::::::0123:\n")
+    };
+    assert_eq!(signing::unusable(&sec("u", "scSC")), None);
+    assert_eq!(signing::unusable(&sec("e", "scSC")), Some("has expired"));
+    assert_eq!(signing::unusable(&sec("r", "scSC")), Some("is revoked"));
+    assert_eq!(
+        signing::unusable(&sec("u", "eE")),
+        Some("has no signing capability")
+    );
+    assert_eq!(signing::unusable(""), Some("has no secret key"));
+}
+
+// A new GnuPG home with an ed25519 signing key, and its fingerprint
+fn generate_key(name: &str, passphrase: &str) -> (std::path::PathBuf, String) {
+    let home = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    fs::create_dir_all(&home).unwrap();
+    fs::set_permissions(&home, fs::Permissions::from_mode(0o700)).unwrap();
+    let generated = Command::new("gpg")
+        .env("GNUPGHOME", &home)
+        .args([
+            "--batch",
+            "--pinentry-mode",
+            "loopback",
+            "--passphrase",
+            passphrase,
+            "--quick-gen-key",
+            "spank-tro test <test@example.org>",
+            "ed25519",
+            "sign",
+            "never",
+        ])
+        .output()
+        .unwrap();
+    assert!(generated.status.success());
+    let listed = Command::new("gpg")
+        .env("GNUPGHOME", &home)
+        .args(["--batch", "--with-colons", "--list-secret-keys"])
+        .output()
+        .unwrap();
+    let fingerprint = String::from_utf8_lossy(&listed.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("fpr:::::::::"))
+        .map(|rest| rest.trim_end_matches(':').to_string())
+        .unwrap();
+    (home, fingerprint)
+}
+
+fn remove_home(home: &Path) {
+    let _ = Command::new("gpgconf")
+        .env("GNUPGHOME", home)
+        .args(["--kill", "gpg-agent"])
+        .output();
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn keys_are_checked_with_a_test_signature() {
+    if Command::new("gpg").arg("--version").output().is_err() {
+        return;
+    }
+    let (home, fingerprint) = generate_key("gnupg", "");
+
+    let mut settings = Settings::default();
+    settings.set("gpg_home", &home.to_string_lossy()).unwrap();
+    settings.set("gpg_fingerprint", &fingerprint).unwrap();
+    signing::check_site_key(&settings).unwrap();
+    settings.set("gpg_fingerprint", "0123456789ABCDEF").unwrap();
+    assert!(signing::check_site_key(&settings).is_err());
+    remove_home(&home);
+}
+
+#[test]
+fn keys_with_a_passphrase() {
+    if Command::new("gpg").arg("--version").output().is_err() {
+        return;
+    }
+    let (home, fingerprint) = generate_key("gnupg-passphrase", "secret");
+    let key = SigningKey {
+        gpg_home: home.clone(),
+        fingerprint,
+        passphrase: "secret".to_string(),
+        ..SigningKey::default()
+    };
+    let declaration = home.join("tro-1.jsonld");
+    fs::write(&declaration, "{}").unwrap();
+    let mut settings = Settings::default();
+    settings.set("gpg_home", &home.to_string_lossy()).unwrap();
+    // without the passphrase the key generation left with the agent
+    let _ = Command::new("gpgconf")
+        .env("GNUPGHOME", &home)
+        .args(["--kill", "gpg-agent"])
+        .output();
+    let wrong = SigningKey {
+        passphrase: "wrong".to_string(),
+        ..key.clone()
+    };
+    assert!(wrong.check(&settings).is_err());
+    key.check(&settings).unwrap();
+    let signature = signing::detached_signature(&declaration, None);
+    signing::detach_sign(&settings, &key, &declaration, &signature).unwrap();
+    let verified = Command::new("gpg")
+        .env("GNUPGHOME", &home)
+        .arg("--verify")
+        .arg(&signature)
+        .arg(&declaration)
+        .output()
+        .unwrap();
+    assert!(verified.status.success());
+
+    remove_home(&home);
+}
+
+#[test]
+fn keyless_signing_needs_an_identity_token() {
+    let mut settings = Settings::default();
+    settings.set("signer", "sigstore").unwrap();
+    assert!(signing::keys(&settings, (1000, 1000)).unwrap().is_empty());
+    assert!(sigstore::identity_token(&settings).is_err());
+
+    let token = std::env::temp_dir().join(format!("oidc-token-{}", std::process::id()));
+    fs::write(&token, "eyJhbGciOi.payload.sig\n").unwrap();
+    settings
+        .set("sigstore_token_file", &token.to_string_lossy())
+        .unwrap();
+    assert_eq!(
+        sigstore::identity_token(&settings).unwrap(),
+        "eyJhbGciOi.payload.sig"
+    );
+    assert_eq!(
+        sigstore::bundle_path(Path::new("/work/tro-1.jsonld"), Some("attempt-1")),
+        Path::new("/work/tro-1.jsonld.attempt-1.sigstore.json")
+    );
+    fs::remove_file(&token).unwrap();
+}
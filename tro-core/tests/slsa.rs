@@ -0,0 +1,63 @@
+use serde_json::{json, Value};
+use std::fs;
+
+use tro_core::slsa::{self, Builder};
+
+#[test]
+fn provenance_names_the_builder_invocation_and_inputs() {
+    let dir = std::env::temp_dir().join(format!("slsa-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let declaration = dir.join("tro-7.jsonld");
+    let tro = json!({
+        "@graph": [{
+            "spank:slurmJob": { "cluster": "perlmutter", "partition": "debug" },
+            "trov:hasArrangement": [{
+                "@id": "arrangement/0",
+                "spank:batchScript": { "spank:sha256": "cc" },
+                "spank:inputs": [
+                    { "spank:doi": "10.5281/zenodo.1" },
+                    { "spank:path": "/data/in.h5", "spank:sha256": "dd" },
+                ],
+            }],
+            "trov:hasPerformance": [{
+                "@id": "trp/0",
+                "spank:commands": [["/bin/bash", "job.sh"]],
+                "spank:timeline": { "spank:started": "2024-05-01T12:00:00Z" },
+            }],
+        }],
+    });
+    fs::write(&declaration, tro.to_string()).unwrap();
+
+    let path = slsa::default_path(&declaration);
+    let known = Builder {
+        partition: Some("regular".into()),
+        nodes: vec!["nid001".into()],
+        ..Builder::default()
+    };
+    slsa::export(&declaration, &path, Some(&known)).unwrap();
+    let statement: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    assert_eq!(statement["predicateType"], slsa::PROVENANCE_PREDICATE);
+    // the TRO is the subject of a run leaving no file
+    assert_eq!(statement["subject"][0]["name"], "tro-7.jsonld");
+    let definition = &statement["predicate"]["buildDefinition"];
+    assert_eq!(
+        definition["externalParameters"]["batchScript"]["digest"]["sha256"],
+        "cc"
+    );
+    assert_eq!(
+        definition["resolvedDependencies"],
+        json!([
+            { "uri": "https://doi.org/10.5281/zenodo.1" },
+            { "uri": "file:///data/in.h5", "digest": { "sha256": "dd" } },
+        ])
+    );
+    let run = &statement["predicate"]["runDetails"];
+    assert_eq!(
+        run["builder"]["id"],
+        "urn:spank-tro:slurm:perlmutter:regular"
+    );
+    assert_eq!(run["builder"]["builderDependencies"][0]["name"], "nid001");
+    assert_eq!(run["metadata"]["startedOn"], "2024-05-01T12:00:00Z");
+    assert_eq!(run["metadata"]["invocationId"], "tro-7.jsonld#trp/0");
+    fs::remove_dir_all(&dir).unwrap();
+}
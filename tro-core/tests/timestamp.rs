@@ -0,0 +1,38 @@
+use tro_core::timestamp;
+
+#[test]
+fn unix_times_are_rfc3339_in_utc() {
+    assert_eq!(timestamp::from_unix(0).unwrap(), "1970-01-01T00:00:00Z");
+    assert_eq!(
+        timestamp::from_unix(1714564800).unwrap(),
+        "2024-05-01T12:00:00Z"
+    );
+    assert!(timestamp::now().ends_with('Z'));
+}
+
+#[test]
+fn out_of_range_times_are_errors() {
+    assert!(timestamp::from_unix(i64::MAX).is_err());
+    assert!(timestamp::from_unix(i64::MIN).is_err());
+}
+
+#[test]
+fn offsets_are_converted_to_utc() {
+    let time = timestamp::parse("2024-05-01T14:00:00+02:00").unwrap();
+    assert_eq!(timestamp::format(&time), "2024-05-01T12:00:00Z");
+    assert!(timestamp::parse("2024-05-01 12:00:00").is_err());
+}
+
+#[test]
+fn fractional_seconds_are_kept() {
+    assert_eq!(
+        timestamp::from_unix_f64(1714564800.25).unwrap(),
+        "2024-05-01T12:00:00.250Z"
+    );
+    assert_eq!(
+        timestamp::from_unix_f64(1714564800.0).unwrap(),
+        "2024-05-01T12:00:00Z"
+    );
+    assert!(timestamp::from_unix_f64(f64::NAN).is_err());
+    assert!(timestamp::from_unix_f64(1e300).is_err());
+}
@@ -0,0 +1,39 @@
+use std::fs;
+
+use tro_core::topology::{parse_cpulist, Topology};
+
+#[test]
+fn cpu_lists() {
+    assert_eq!(parse_cpulist("0-3,8,10-11\n"), [0, 1, 2, 3, 8, 10, 11]);
+    assert!(parse_cpulist("").is_empty());
+}
+
+#[test]
+fn the_numa_nodes_of_the_job_come_from_its_cpuset() {
+    let sys = std::env::temp_dir().join(format!("topology-{}", std::process::id()));
+    let write = |path: &str, content: &str| {
+        let path = sys.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    };
+    write("devices/system/node/node0/cpulist", "0-7\n");
+    write("devices/system/node/node1/cpulist", "8-15\n");
+    write("devices/system/node/node2/cpulist", "16-23\n");
+    write("devices/system/cpu/smt/control", "on\n");
+    write("devices/system/cpu/smt/active", "1\n");
+    write("fs/cgroup/job_42/cpuset.cpus.effective", "6-9\n");
+    write("fs/cgroup/job_42/cpuset.mems.effective", "0-1\n");
+
+    let topology = Topology::from_sysfs(&sys, Some(&sys.join("fs/cgroup/job_42")));
+    assert_eq!(topology.cpus.as_deref(), Some("6-9"));
+    assert_eq!(topology.mems.as_deref(), Some("0-1"));
+    assert_eq!(topology.numa_nodes, [0, 1]);
+    assert_eq!(topology.smt.as_deref(), Some("on"));
+    assert_eq!(topology.smt_active, Some(true));
+
+    // outside of a cgroup, what the node has is still told
+    let topology = Topology::from_sysfs(&sys, None);
+    assert!(topology.numa_nodes.is_empty());
+    assert_eq!(topology.smt_active, Some(true));
+    fs::remove_dir_all(&sys).unwrap();
+}
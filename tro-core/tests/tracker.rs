@@ -0,0 +1,202 @@
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use tro_core::job::Job;
+use tro_core::settings::Settings;
+use tro_core::tracker::{find_trace, trace_path, wait_for_trace, CommandRecord, Tracker};
+use tro_core::xalt;
+
+fn job(name: &str) -> Job {
+    let workdir = std::env::temp_dir().join(format!("tracker-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&workdir);
+    Job::new(42, (1000, 1000), "alice".into(), workdir, None, None)
+}
+
+fn record(argv: &[&str], start_time: f64, end_time: f64) -> CommandRecord {
+    CommandRecord {
+        argv: argv.iter().map(|arg| arg.to_string()).collect(),
+        start_time,
+        end_time,
+        exit_code: Some(0),
+        elapsed: None,
+    }
+}
+
+#[test]
+fn none_uses_the_plugin_clocks() {
+    let mut job = job("none");
+    job.start_time = 100;
+    job.end_time = Some(200);
+    let trace = find_trace(Tracker::None, &job, None).unwrap();
+    assert_eq!((trace.start_time, trace.end_time), (100.0, 200.0));
+    assert!(trace.commands.is_empty());
+}
+
+#[test]
+fn wrapper_spans_the_recorded_commands() {
+    let job = job("wrapper");
+    let path = trace_path(&job);
+    record(&["prep"], 110.0, 120.0).append(&path).unwrap();
+    record(&["solve", "-n", "4"], 120.0, 180.0)
+        .append(&path)
+        .unwrap();
+    let trace = find_trace(Tracker::Wrapper, &job, None).unwrap();
+    assert_eq!((trace.start_time, trace.end_time), (110.0, 180.0));
+    assert_eq!(trace.commands, [vec!["prep"], vec!["solve", "-n", "4"]]);
+
+    // a step only gets the commands started with it
+    let trace = find_trace(Tracker::Wrapper, &job, Some(150)).unwrap_err();
+    assert!(trace.to_string().contains("No command"));
+    let trace = find_trace(Tracker::Wrapper, &job, Some(121)).unwrap();
+    assert_eq!(trace.commands.len(), 1);
+    fs::remove_dir_all(&job.workdir).unwrap();
+}
+
+#[test]
+fn durations_come_from_the_monotonic_clock_first() {
+    let job = job("elapsed");
+    let path = trace_path(&job);
+    let mut solve = record(&["solve"], 110.25, 110.5);
+    solve.elapsed = Some(0.3);
+    solve.append(&path).unwrap();
+    let trace = find_trace(Tracker::Wrapper, &job, None).unwrap();
+    assert_eq!(trace.duration(), Some(0.3));
+
+    // the elapsed times of several commands are not the trace's
+    record(&["post"], 111.0, 112.0).append(&path).unwrap();
+    let trace = find_trace(Tracker::Wrapper, &job, None).unwrap();
+    assert_eq!(trace.duration(), Some(112.0 - 110.25));
+    fs::remove_dir_all(&job.workdir).unwrap();
+}
+
+#[test]
+fn a_stepped_clock_is_flagged() {
+    let job = job("stepped");
+    // the clock went back while the command ran
+    record(&["solve"], 120.0, 100.0)
+        .append(&trace_path(&job))
+        .unwrap();
+    let trace = find_trace(Tracker::Wrapper, &job, None).unwrap();
+    assert!(trace.clock_anomaly());
+    assert_eq!(trace.duration(), None);
+    fs::remove_dir_all(&job.workdir).unwrap();
+}
+
+#[test]
+fn wrapper_without_commands() {
+    let job = job("empty");
+    assert!(find_trace(Tracker::Wrapper, &job, None).is_err());
+    assert_eq!(
+        trace_path(&job),
+        PathBuf::from(&job.workdir).join(".tro/42.trace")
+    );
+}
+
+#[test]
+fn ebpf_keeps_the_job_cgroup() {
+    let mut job = job("ebpf");
+    job.end_time = Some(300);
+    let path = trace_path(&job);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let lines = [
+        "exec\t100\t/system.slice/slurmstepd.scope/job_42/step_0/user/task_0\t/usr/bin/python3",
+        "open\t101\t/system.slice/slurmstepd.scope/job_42/step_0/user/task_0\t/data/in.csv",
+        "open\t102\t/system.slice/slurmstepd.scope/job_42/step_0/user/task_0\t/data/in.csv",
+        "exec\t105\t/system.slice/slurmstepd.scope/job_43/step_0/user/task_0\t/bin/other",
+        "Attaching 2 probes...",
+    ];
+    fs::write(&path, lines.join("\n")).unwrap();
+    let trace = find_trace(Tracker::Ebpf, &job, None).unwrap();
+    assert_eq!((trace.start_time, trace.end_time), (100.0, 300.0));
+    assert_eq!(trace.commands, [vec!["/usr/bin/python3"]]);
+    assert_eq!(trace.files, [PathBuf::from("/data/in.csv")]);
+    fs::remove_dir_all(&job.workdir).unwrap();
+}
+
+#[test]
+fn strace_follows_the_execs() {
+    let mut job = job("strace");
+    job.end_time = Some(400);
+    let path = trace_path(&job);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let process = |pid: u32| path.with_extension(format!("trace.{pid}"));
+    fs::write(
+        process(100),
+        "300.500000 execve(\"/bin/bash\", [\"bash\", \"job.sh\"], 0x7ffd /* 20 vars */) = 0\n\
+         301.000000 +++ exited with 0 +++\n",
+    )
+    .unwrap();
+    fs::write(
+        process(101),
+        "302.250000 execve(\"/usr/bin/nope\", [\"nope\"], 0x7ffd /* 20 vars */) = -1 ENOENT (No such file or directory)\n\
+         302.500000 execve(\"/usr/bin/python3\", [\"python3\", \"-c\", \"print(\\\"hi\\\")\"], 0x7ffd /* 20 vars */) = 0\n",
+    )
+    .unwrap();
+    let trace = find_trace(Tracker::Strace, &job, None).unwrap();
+    assert_eq!((trace.start_time, trace.end_time), (300.5, 400.0));
+    assert_eq!(
+        trace.commands,
+        [
+            vec!["bash", "job.sh"],
+            vec!["python3", "-c", "print(\"hi\")"]
+        ]
+    );
+    let executables: Vec<_> = trace.executables.iter().map(|e| &e.path).collect();
+    assert_eq!(executables, ["/bin/bash", "/usr/bin/python3"]);
+    fs::remove_dir_all(&job.workdir).unwrap();
+}
+
+#[test]
+fn waits_for_a_late_trace() {
+    let job = job("late");
+    let mut settings = Settings {
+        tracker: Tracker::Wrapper,
+        trace_timeout: Duration::ZERO,
+        trace_poll_interval: Duration::from_millis(10),
+        ..Settings::default()
+    };
+    assert!(wait_for_trace(&settings, &job, None).is_err());
+
+    settings.trace_timeout = Duration::from_secs(5);
+    let path = trace_path(&job);
+    let writer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        record(&["late"], 100.0, 101.0).append(&path).unwrap();
+    });
+    let trace = wait_for_trace(&settings, &job, None).unwrap();
+    assert_eq!(trace.commands, [vec!["late"]]);
+    writer.join().unwrap();
+    fs::remove_dir_all(&job.workdir).unwrap();
+}
+
+#[test]
+fn xalt_records_of_the_job() {
+    let job = job("xalt");
+    let dir = xalt::record_dir(&job).join("2026");
+    fs::create_dir_all(&dir).unwrap();
+    let record = |jobid: &str, start_time: f64| {
+        format!(
+            r#"{{"userT": {{"job_id": "{jobid}", "exec_path": "/opt/a.out"}}, "hash_id": "abc", "userDT": {{"start_time": {start_time}, "end_time": {}}}, "cmdlineA": ["./a.out", "-v"], "libA": [["/usr/lib64/libm.so.6", "def"]]}}"#,
+            start_time + 10.0
+        )
+    };
+    fs::write(dir.join("run.a.json"), record("41", 100.0)).unwrap();
+    fs::write(dir.join("run.b.json"), record("42", 200.0)).unwrap();
+    // another rank of the same program
+    fs::write(dir.join("run.c.json"), record("42", 205.0)).unwrap();
+    let trace = find_trace(Tracker::Xalt, &job, None).unwrap();
+    assert_eq!((trace.start_time, trace.end_time), (200.0, 215.0));
+    assert_eq!(trace.commands, [vec!["./a.out", "-v"]]);
+    assert_eq!(trace.executables.len(), 1);
+    assert_eq!(trace.executables[0].path, PathBuf::from("/opt/a.out"));
+    assert_eq!(trace.executables[0].xalt_hash.as_deref(), Some("abc"));
+    assert_eq!(
+        trace.libraries[0].path,
+        PathBuf::from("/usr/lib64/libm.so.6")
+    );
+    assert_eq!(trace.libraries[0].xalt_hash.as_deref(), Some("def"));
+    assert!(find_trace(Tracker::Xalt, &job, Some(300)).is_err());
+    fs::remove_dir_all(&job.workdir).unwrap();
+}